@@ -0,0 +1,54 @@
+//! Record which build this is, for `rusty-axe version --verbose` to say: the commit
+//! it's built from (and whether there were changes on top of it), when, and for what
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = git(&["rev-parse", "HEAD"]);
+    let dirty = match &commit {
+        Some(_) => {
+            git(&["status", "--porcelain", "--untracked-files=no"]).map_or("unknown", |changes| {
+                match changes.is_empty() {
+                    true => "false",
+                    false => "true",
+                }
+            })
+        }
+        None => "unknown",
+    };
+    println!(
+        "cargo:rustc-env=RUSTY_AXE_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rustc-env=RUSTY_AXE_DIRTY={}", dirty);
+
+    // SOURCE_DATE_EPOCH, for a reproducible build, or else now
+    let built = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        });
+    println!("cargo:rustc-env=RUSTY_AXE_BUILT={}", built);
+    println!(
+        "cargo:rustc-env=RUSTY_AXE_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+}
+
+/// What git prints when run with `args`, trimmed, if it could be run here
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
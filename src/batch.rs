@@ -0,0 +1,268 @@
+//! Splitting events into batches small enough for a single PutLogEvents call
+
+use crate::events::DAY;
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+
+/// The most events PutLogEvents accepts in one call
+pub const MAX_BATCH_EVENTS: usize = 10_000;
+
+/// The largest batch PutLogEvents accepts, counting each event's overhead
+pub const MAX_BATCH_SIZE: usize = 1_048_576;
+
+/// What each event adds to the size of a batch, on top of its message
+pub const EVENT_OVERHEAD: usize = 26;
+
+/// The longest span of time PutLogEvents accepts in one batch, in milliseconds: the
+/// newest event has to be less than a day after the oldest
+pub const MAX_BATCH_SPAN: i64 = DAY;
+
+/// How much an event adds to the size of a batch
+fn event_size(event: &InputLogEvent) -> usize {
+    event.message().map_or(0, str::len) + EVENT_OVERHEAD
+}
+
+/// The size of a batch as PutLogEvents counts it: the UTF-8 bytes of every message,
+/// plus 26 bytes for each event
+///
+/// A batch is accepted if this is at most [`MAX_BATCH_SIZE`].
+pub fn batch_size(events: &[InputLogEvent]) -> usize {
+    events.iter().map(event_size).sum()
+}
+
+/// Split events into batches that each fit in a single PutLogEvents call
+///
+/// The events keep their order, and each batch is filled as far as it can be before
+/// the next is started, its size kept as [`batch_size`] would count it.  An event too
+/// big for any batch (which the oversize policy should have already dealt with) is
+/// put in a batch of its own, to be rejected without taking anything else with it.
+/// A batch is also ended once an event comes [`MAX_BATCH_SPAN`] or more after its
+/// first one.
+pub fn make_batches(events: Vec<InputLogEvent>) -> Vec<Vec<InputLogEvent>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut size = 0;
+
+    for event in events {
+        let added = event_size(&event);
+        if is_full(batch.len(), size, added, span(batch.first(), &event)) {
+            batches.push(std::mem::take(&mut batch));
+            size = 0;
+        }
+        size += added;
+        batch.push(event);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+/// How many batches [`make_batches`] would split the events into
+pub fn count_batches(events: &[InputLogEvent]) -> usize {
+    let mut count = 0;
+    let (mut len, mut size, mut first) = (0, 0, None);
+
+    for event in events {
+        let added = event_size(event);
+        if is_full(len, size, added, span(first, event)) {
+            count += 1;
+            (len, size) = (0, 0);
+        }
+        if len == 0 {
+            first = Some(event);
+        }
+        len += 1;
+        size += added;
+    }
+
+    count + usize::from(len != 0)
+}
+
+/// How long after the first event in a batch another event comes
+fn span(first: Option<&InputLogEvent>, event: &InputLogEvent) -> i64 {
+    let first = first.and_then(|first| first.timestamp).unwrap_or_default();
+    event.timestamp.unwrap_or_default() - first
+}
+
+/// Whether a batch of `len` events, `size` in all, has to be sent before another
+/// event of `added`, `span` after the batch's first, can go in one
+fn is_full(len: usize, size: usize, added: usize, span: i64) -> bool {
+    len != 0 && (len == MAX_BATCH_EVENTS || size + added > MAX_BATCH_SIZE || span >= MAX_BATCH_SPAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(count: usize, message_len: usize) -> Vec<InputLogEvent> {
+        (0..count)
+            .map(|i| {
+                InputLogEvent::builder()
+                    .timestamp(i as i64)
+                    .message("x".repeat(message_len))
+                    .build()
+            })
+            .collect()
+    }
+
+    fn lens(batches: &[Vec<InputLogEvent>]) -> Vec<usize> {
+        batches.iter().map(Vec::len).collect()
+    }
+
+    fn event(message: &str) -> InputLogEvent {
+        InputLogEvent::builder()
+            .timestamp(0)
+            .message(message)
+            .build()
+    }
+
+    #[test]
+    fn test_batch_size() {
+        assert_eq!(batch_size(&[]), 0);
+        assert_eq!(batch_size(&[event("hello")]), 5 + 26);
+        assert_eq!(batch_size(&[event("a"), event("bc")]), 3 + 2 * 26);
+        // Bytes, not characters
+        assert_eq!(batch_size(&[event("é")]), 2 + 26);
+        assert_eq!(batch_size(&[event("日本")]), 6 + 26);
+        assert_eq!(batch_size(&[event("🦀")]), 4 + 26);
+        assert_eq!(batch_size(&[InputLogEvent::builder().build()]), 26);
+    }
+
+    #[test]
+    fn test_blank_line_size() {
+        let options = crate::events::EventOptions::default();
+        let mut summary = crate::uploader::UploadSummary::default();
+        let events = crate::events::line_events(0, String::new(), &options, &mut summary);
+        assert_eq!(batch_size(&events), 1 + 26);
+    }
+
+    #[test]
+    fn test_batches_within_limits() {
+        // Multi-byte messages that only just fit if bytes are counted right
+        let message = "é".repeat((MAX_BATCH_SIZE / 2 - EVENT_OVERHEAD) / 2);
+        let input: Vec<_> = (0..5).map(|_| event(&message)).collect();
+        let batches = make_batches(input);
+        assert_eq!(lens(&batches), [2, 2, 1]);
+        assert_eq!(batch_size(&batches[0]), MAX_BATCH_SIZE);
+        assert!(batches
+            .iter()
+            .all(|batch| batch_size(batch) <= MAX_BATCH_SIZE));
+    }
+
+    #[test]
+    fn test_largest_event() {
+        // A message this big fills a batch by itself, overhead included
+        let message = "x".repeat(MAX_BATCH_SIZE - EVENT_OVERHEAD);
+        assert_eq!(message.len(), 1_048_550);
+        let batches = make_batches(vec![event("before"), event(&message), event("after")]);
+        assert_eq!(lens(&batches), [1, 1, 1]);
+        assert_eq!(batch_size(&batches[1]), MAX_BATCH_SIZE);
+
+        // Though a line that long never gets that far in one piece
+        let options = crate::events::EventOptions {
+            oversize: crate::events::Oversize::Drop,
+            ..Default::default()
+        };
+        let mut summary = crate::uploader::UploadSummary::default();
+        assert!(crate::events::line_events(0, message.clone(), &options, &mut summary).is_empty());
+        let options = crate::events::EventOptions::default();
+        let parts = crate::events::line_events(0, message, &options, &mut summary);
+        assert!(parts.len() > 1);
+        let batches = make_batches(parts);
+        assert!(batches
+            .iter()
+            .all(|batch| batch_size(batch) <= MAX_BATCH_SIZE));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(make_batches(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_event_count_limit() {
+        assert_eq!(
+            lens(&make_batches(events(MAX_BATCH_EVENTS, 1))),
+            [MAX_BATCH_EVENTS]
+        );
+        assert_eq!(
+            lens(&make_batches(events(MAX_BATCH_EVENTS + 1, 1))),
+            [MAX_BATCH_EVENTS, 1]
+        );
+        assert_eq!(
+            lens(&make_batches(events(2 * MAX_BATCH_EVENTS, 1))),
+            [MAX_BATCH_EVENTS, MAX_BATCH_EVENTS]
+        );
+    }
+
+    #[test]
+    fn test_size_limit() {
+        // Four of these fill a batch exactly, overhead included
+        let fits = MAX_BATCH_SIZE / 4 - EVENT_OVERHEAD;
+        assert_eq!(lens(&make_batches(events(4, fits))), [4]);
+        assert_eq!(lens(&make_batches(events(5, fits))), [4, 1]);
+        // A byte more each, and only three fit
+        assert_eq!(lens(&make_batches(events(4, fits + 1))), [3, 1]);
+    }
+
+    #[test]
+    fn test_giant_event() {
+        let mut input = events(1, 10);
+        input.extend(events(1, MAX_BATCH_SIZE));
+        input.extend(events(1, 10));
+        assert_eq!(lens(&make_batches(input)), [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_order_kept() {
+        let batches = make_batches(events(MAX_BATCH_EVENTS + 5, 1));
+        let timestamps: Vec<_> = batches
+            .iter()
+            .flatten()
+            .map(|event| event.timestamp.unwrap())
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] + 1 == pair[1]));
+        assert_eq!(timestamps.len(), MAX_BATCH_EVENTS + 5);
+    }
+
+    fn at(timestamps: &[i64]) -> Vec<InputLogEvent> {
+        timestamps
+            .iter()
+            .map(|&timestamp| {
+                InputLogEvent::builder()
+                    .timestamp(timestamp)
+                    .message("x")
+                    .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_span_limit() {
+        // Just under a day fits in one batch, a day exactly doesn't
+        assert_eq!(lens(&make_batches(at(&[0, DAY - 1]))), [2]);
+        assert_eq!(lens(&make_batches(at(&[0, DAY]))), [1, 1]);
+        // The span's counted from the first event of each batch, not the last
+        let hour = DAY / 24;
+        let input = at(&[0, 12 * hour, DAY - 1, DAY, DAY + 12 * hour, 2 * DAY]);
+        assert_eq!(lens(&make_batches(input)), [3, 2, 1]);
+        assert_eq!(lens(&make_batches(at(&[0, 30 * DAY, 31 * DAY]))), [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_count_batches() {
+        let fits = MAX_BATCH_SIZE / 4 - EVENT_OVERHEAD;
+        for input in [
+            Vec::new(),
+            events(1, 10),
+            events(5, fits),
+            events(4, fits + 1),
+            events(2 * MAX_BATCH_EVENTS + 1, 1),
+            at(&[0, DAY - 1, DAY, 3 * DAY]),
+            at(&[0, 12, DAY + 11, DAY + 12]),
+        ] {
+            assert_eq!(count_batches(&input), make_batches(input).len());
+        }
+    }
+}
@@ -0,0 +1,1933 @@
+//! The command line: the options each command takes, and running the command they
+//! give
+
+use crate::error::Error;
+use crate::events::{
+    collect_events, expand_directories, expand_globs, input_size, read_files, BinaryPolicy,
+    BlankLines, ControlChars, Encoding, EncodingErrors, EpochUnit, EventOptions, Format, LineRange,
+    OnInvalid, OutOfOrder, OutOfRange, Oversize, STDIN_PATH,
+};
+#[cfg(feature = "journald")]
+use crate::journal;
+#[cfg(feature = "otlp")]
+use crate::otlp;
+use crate::uploader::{
+    upload, ClientOptions, OnRejected, Output, Sink, StreamEvents, UploadOptions, UploadSummary,
+};
+use crate::{
+    completions, config, credentials, endpoints, environment, exit, filter, follow, group, guard,
+    kinesis, list, logging, man, metadata, metric, naming, notify, profile, rate, redact, region,
+    retry, s3, splunk, state, syslog, template, timestamp, verify, version, zone,
+};
+
+use aws_sdk_cloudwatchlogs::Endpoint;
+use aws_types::credentials::SharedCredentialsProvider;
+use aws_types::region::Region;
+
+use chrono::{DateTime, Utc};
+use clap::{ArgMatches, CommandFactory, ErrorKind, Parser, ValueHint};
+use std::ffi::OsString;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "journald")]
+use tracing::error;
+use tracing::{debug, warn};
+
+/// Quickly shove a file into CloudWatch Logs
+///
+/// Without a command, the arguments are taken to be `push`'s.
+///
+/// Some times you need to keep a little bit of log data for debugging purposes
+/// or perhaps you need to document why an EC2 instance keeps crashing.  This is
+/// where I come in.  Call me right before the instance goes down and I'll do my
+/// best to jam as much (or as little) information into CloudWatch Logs as I can.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None, after_help = exit::HELP)]
+pub struct Cli {
+    #[clap(flatten)]
+    global: Global,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Send files to CloudWatch Logs
+    Push(Box<Args>),
+    Groups(list::GroupsArgs),
+    Streams(list::StreamsArgs),
+    Completions(completions::CompletionsArgs),
+    /// Print the version, and with --verbose (or --output json) which build it is: the
+    /// commit it was built from, when, for what target, and with which version of the
+    /// CloudWatch Logs SDK
+    Version,
+    #[clap(hide = true)]
+    Man(man::ManArgs),
+}
+
+/// The options every command takes, before its name or after it
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct Global {
+    /// The named profile in the AWS config files to call AWS with, like prod-logging,
+    /// as when this isn't running on EC2 (AWS_PROFILE does the same).  The instance
+    /// metadata isn't asked for anything then
+    #[clap(long, env = "RUSTY_AXE_PROFILE", global = true)]
+    pub(crate) profile: Option<String>,
+
+    /// The AWS region to call, like us-west-2, rather than the one AWS_REGION, the
+    /// AWS config files or the instance metadata say (or else us-east-1)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_REGION",
+        global = true,
+        value_parser = region::parse_region
+    )]
+    pub(crate) region: Option<Region>,
+
+    /// How what's asked for is printed on stdout: push's summary of the run (or with
+    /// --dry-run, what would be sent), or the log groups or streams listed.  As JSON,
+    /// it's all that's printed on stdout, and push's summary isn't said on stderr
+    #[clap(
+        long,
+        env = "RUSTY_AXE_OUTPUT",
+        global = true,
+        value_enum,
+        default_value_t
+    )]
+    pub(crate) output: Output,
+
+    #[clap(flatten)]
+    pub(crate) verbosity: logging::Verbosity,
+}
+
+/// Arguments for the command line, with `push` put in front of them if they're the
+/// ones from before there were commands
+fn with_command(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<_> = args.into_iter().collect();
+    if command_position(&args).is_none() {
+        args.insert(1, OsString::from("push"));
+    }
+    args
+}
+
+/// Where the arguments name their command, after any global options, or else ask
+/// for help or the version; None if they do neither, as before there were commands
+fn command_position(args: &[OsString]) -> Option<usize> {
+    let command = Cli::command();
+    let named = |arg: &str, global: &clap::Arg| match arg.strip_prefix("--") {
+        Some(long) => global.get_long() == long.split('=').next(),
+        None => arg
+            .strip_prefix('-')
+            .and_then(|short| short.chars().next())
+            .is_some_and(|short| global.get_short() == Some(short)),
+    };
+    let mut rest = args
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .enumerate()
+        .skip(1);
+    while let Some((position, arg)) = rest.next() {
+        // -h is --head, unless it's all there is
+        if ["help", "--help", "-V", "--version"].contains(&&*arg)
+            || (arg == "-h" && args.len() == 2)
+        {
+            return Some(position);
+        }
+        match command.get_arguments().find(|global| named(&arg, global)) {
+            Some(global)
+                if global.is_takes_value_set()
+                    && global.get_action().takes_values()
+                    && !arg.contains('=') =>
+            {
+                rest.next();
+            }
+            Some(_) => (),
+            None => return command.find_subcommand(&*arg).map(|_| position),
+        }
+    }
+    // Nothing but global options
+    Some(args.len())
+}
+
+/// The command the arguments name, where they name it, and what clap makes of them,
+/// leaving anything wrong with them for later
+///
+/// The global options are in the matches, and the command's own in its
+/// subcommand's.
+pub(crate) fn given_command(args: &[OsString]) -> Option<(usize, String, ArgMatches)> {
+    let position = command_position(args)?;
+    let name = args.get(position)?.to_str()?;
+    let command = Cli::command();
+    command.find_subcommand(name)?;
+    let matches = command
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .ok()?;
+    matches.subcommand_matches(name)?;
+    Some((position, name.to_string(), matches))
+}
+
+/// Send files to CloudWatch Logs
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None, after_help = exit::HELP)]
+// Its own help flag, rather than the one `Command` hands down, as -h is --head's
+#[clap(mut_arg("help", |arg| arg.help("Print help information (-h is short for --head)")))]
+pub(crate) struct Args {
+    /// Path of the file(s) to process ("-" or omitted reads from a piped stdin)
+    #[clap(short, long, env = "RUSTY_AXE_FILENAME", value_hint = ValueHint::FilePath)]
+    pub(crate) filename: Vec<String>,
+
+    /// CloudWatchLogs group to write messages to.  Can be given more than once, to
+    /// send the same messages to each
+    #[clap(
+        short,
+        long,
+        env = "RUSTY_AXE_GROUP",
+        value_hint = ValueHint::Other,
+        required_unless_present_any = DESTINATION_ARGS,
+        conflicts_with_all = DESTINATION_ARGS
+    )]
+    pub(crate) group: Vec<String>,
+
+    /// Where to send the messages: CloudWatch Logs, a Kinesis data stream, a Firehose
+    /// delivery stream, an S3 bucket (a whole object a file), a syslog server, a Splunk
+    /// HTTP Event Collector, an OpenTelemetry collector (if built with the otlp
+    /// feature), or a local file (the last four without calling AWS at all)
+    #[clap(long, env = "RUSTY_AXE_DESTINATION", value_enum, default_value_t)]
+    pub(crate) destination: Destination,
+
+    /// The Kinesis data stream to put messages into, each as a record of its own, with
+    /// --destination kinesis
+    #[clap(
+        long,
+        env = "RUSTY_AXE_KINESIS_STREAM",
+        required_if_eq("destination", "kinesis")
+    )]
+    pub(crate) kinesis_stream: Option<String>,
+
+    /// The partition key for records put into Kinesis (by default, the instance id,
+    /// as {instance_id} has it)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_PARTITION_KEY",
+        requires = "kinesis-stream",
+        conflicts_with = "group",
+        value_parser = kinesis::parse_partition_key
+    )]
+    pub(crate) partition_key: Option<String>,
+
+    /// The Firehose delivery stream to put messages into, each as a line of its own,
+    /// with --destination firehose
+    #[clap(
+        long,
+        env = "RUSTY_AXE_DELIVERY_STREAM",
+        required_if_eq("destination", "firehose"),
+        conflicts_with = "kinesis-stream"
+    )]
+    pub(crate) delivery_stream: Option<String>,
+
+    /// The S3 bucket to put each file in with --destination s3, as an object of its own
+    /// (in parts, if it's over 8 MiB)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_BUCKET",
+        required_if_eq("destination", "s3"),
+        conflicts_with_all = &["kinesis-stream", "delivery-stream"]
+    )]
+    pub(crate) bucket: Option<String>,
+
+    /// What the key of every object put in the --bucket starts with, like incidents/
+    #[clap(
+        long,
+        env = "RUSTY_AXE_KEY_PREFIX",
+        default_value = "",
+        hide_default_value = true,
+        requires = "bucket"
+    )]
+    pub(crate) key_prefix: String,
+
+    /// How to name the objects put in the --bucket, after the --key-prefix, with the
+    /// same variables as --stream-template; the default is
+    /// '{instance_id}/{date}/{filename}'
+    #[clap(
+        long,
+        env = "RUSTY_AXE_KEY_TEMPLATE",
+        value_parser = naming::parse_template,
+        requires = "bucket"
+    )]
+    pub(crate) key_template: Option<template::Template>,
+
+    /// How S3 encrypts the objects put in the --bucket, if not as the bucket does by
+    /// default.  With aws:kms, the key is --kms-key-arn (or else S3's default key)
+    #[clap(long, env = "RUSTY_AXE_SSE", value_enum, requires = "bucket")]
+    pub(crate) sse: Option<s3::Sse>,
+
+    /// Put each file in the --bucket as it is, rather than the lines taken from it
+    /// (after --grep, --head and the rest)
+    #[clap(long, env = "RUSTY_AXE_RAW_OBJECT", requires = "bucket")]
+    pub(crate) raw_object: bool,
+
+    /// The file to write events to with --destination file, a JSON object per line with
+    /// each event's timestamp and message, and the number of the batch it would be
+    /// sent in.  It's replaced if it's there already
+    #[clap(
+        long,
+        env = "RUSTY_AXE_OUT",
+        value_hint = ValueHint::FilePath,
+        required_if_eq("destination", "file"),
+        conflicts_with_all = &["kinesis-stream", "delivery-stream", "bucket"]
+    )]
+    pub(crate) out: Option<PathBuf>,
+
+    /// The syslog server to send messages to with --destination syslog, as HOST[:PORT]
+    /// (514 by default, or 6514 for TLS), each as an RFC 5424 message
+    #[clap(
+        long,
+        env = "RUSTY_AXE_SYSLOG_SERVER",
+        value_parser = syslog::Server::parse,
+        required_if_eq("destination", "syslog"),
+        conflicts_with_all = &["kinesis-stream", "delivery-stream", "bucket", "out"]
+    )]
+    pub(crate) syslog_server: Option<syslog::Server>,
+
+    /// How to send messages to the --syslog-server.  Over TLS, its certificate has to
+    /// be one the system trusts
+    #[clap(
+        long,
+        env = "RUSTY_AXE_SYSLOG_PROTO",
+        value_enum,
+        default_value_t,
+        requires = "syslog-server",
+        conflicts_with = "group"
+    )]
+    pub(crate) syslog_proto: syslog::Protocol,
+
+    /// The Splunk HTTP Event Collector to post messages to with --destination splunk,
+    /// like https://http-inputs-acme.splunkcloud.com (or its
+    /// /services/collector/event endpoint)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_URL",
+        value_hint = ValueHint::Url,
+        value_parser = splunk::parse_url,
+        required_if_eq("destination", "splunk"),
+        conflicts_with_all = &["kinesis-stream", "delivery-stream", "bucket", "out", "syslog-server"]
+    )]
+    pub(crate) hec_url: Option<String>,
+
+    /// The environment variable holding the HEC token (which can't be given on the
+    /// command line itself)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_TOKEN_ENV",
+        value_name = "VAR",
+        value_parser = splunk::Token::from_env,
+        requires = "hec-url",
+        conflicts_with_all = &["group", "hec-token-file"]
+    )]
+    pub(crate) hec_token_env: Option<splunk::Token>,
+
+    /// The file holding the HEC token, on its first line
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_TOKEN_FILE",
+        value_hint = ValueHint::FilePath,
+        value_name = "PATH",
+        value_parser = splunk::Token::from_file,
+        requires = "hec-url",
+        conflicts_with = "group"
+    )]
+    pub(crate) hec_token_file: Option<splunk::Token>,
+
+    /// The sourcetype of events sent to the HEC (by default, _json with --format jsonl
+    /// or csv, or else rusty_axe)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_SOURCETYPE",
+        requires = "hec-url",
+        conflicts_with = "group"
+    )]
+    pub(crate) hec_sourcetype: Option<String>,
+
+    /// The host events sent to the HEC are from (by default, this one's name)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_HOST",
+        requires = "hec-url",
+        conflicts_with = "group"
+    )]
+    pub(crate) hec_host: Option<String>,
+
+    /// The source of events sent to the HEC (by default, the file each is from)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_HEC_SOURCE",
+        requires = "hec-url",
+        conflicts_with = "group"
+    )]
+    pub(crate) hec_source: Option<String>,
+
+    /// The OpenTelemetry collector to export messages to with --destination otlp, as
+    /// the URL logs are sent under (to /v1/logs) over OTLP/HTTP, like
+    /// http://collector:4318
+    #[cfg(feature = "otlp")]
+    #[clap(
+        long,
+        env = "RUSTY_AXE_OTLP_ENDPOINT",
+        value_hint = ValueHint::Url,
+        value_parser = otlp::parse_endpoint,
+        required_if_eq("destination", "otlp"),
+        conflicts_with_all = &["kinesis-stream", "delivery-stream", "bucket", "out", "syslog-server"]
+    )]
+    pub(crate) otlp_endpoint: Option<String>,
+
+    /// The log stream to write to, rather than a new one named after the instance and
+    /// the time.  A stream that already exists is added to
+    #[clap(long, env = "RUSTY_AXE_STREAM", value_parser = parse_stream_name, conflicts_with = "stream-per-file")]
+    pub(crate) stream: Option<String>,
+
+    /// How to name new log streams, like '{hostname}/{filename}/{date}'.  Can use
+    /// {instance_id}, {hostname}, {az}, {region}, {cluster} (of the ECS task this runs
+    /// in), {tag:<name>} (of the instance, like {tag:Name}, if it allows its tags in
+    /// the instance metadata), {filename} (of the file sent), {date}, {epoch} and
+    /// {timestamp}; the default
+    /// is '{instance_id}-{timestamp}', with '-{filename}' on the end for
+    /// --stream-per-file.  Off EC2, {instance_id} is the ECS task's id, or else the
+    /// hostname
+    #[clap(long, env = "RUSTY_AXE_STREAM_TEMPLATE", value_parser = naming::parse_template, conflicts_with = "stream")]
+    pub(crate) stream_template: Option<template::Template>,
+
+    /// Add to the log stream if it's there already, rather than starting a new one
+    /// every run.  Unless named by --stream or --stream-template, the stream is named
+    /// after the instance alone (and the file, with --stream-per-file)
+    #[clap(long, env = "RUSTY_AXE_APPEND")]
+    pub(crate) append: bool,
+
+    /// Succeed without uploading anything when a glob pattern matches no files
+    #[clap(long, env = "RUSTY_AXE_ALLOW_EMPTY_GLOB")]
+    pub(crate) allow_empty_glob: bool,
+
+    /// Upload every file found beneath directories given as --filename (or matched by
+    /// one of its glob patterns)
+    #[clap(short, long, env = "RUSTY_AXE_RECURSIVE")]
+    pub(crate) recursive: bool,
+
+    /// Skip files matching this glob pattern while walking directories
+    #[clap(long, env = "RUSTY_AXE_EXCLUDE", value_parser = glob::Pattern::new)]
+    pub(crate) exclude: Vec<glob::Pattern>,
+
+    /// Process the first lines of the file
+    #[clap(short, long, env = "RUSTY_AXE_HEAD", default_value_t = 0)]
+    pub(crate) head: usize,
+
+    /// Process the last lines of the file
+    #[clap(short, long, env = "RUSTY_AXE_TAIL", default_value_t = 0)]
+    pub(crate) tail: usize,
+
+    /// Only upload lines matching this regular expression (or any of them, if given
+    /// more than once).  Lines are filtered first, so --head, --tail and --lines pick
+    /// from the lines that matched
+    #[clap(long, env = "RUSTY_AXE_GREP")]
+    pub(crate) grep: Vec<String>,
+
+    /// Leave out lines matching this regular expression (or any of them, if given more
+    /// than once), even if they match --grep
+    #[clap(long, env = "RUSTY_AXE_GREP_V")]
+    pub(crate) grep_v: Vec<String>,
+
+    /// Match --grep and --grep-v patterns regardless of case
+    #[clap(short, long, env = "RUSTY_AXE_IGNORE_CASE")]
+    pub(crate) ignore_case: bool,
+
+    /// Treat --grep and --grep-v patterns as plain text rather than regular expressions
+    #[clap(long, env = "RUSTY_AXE_FIXED_STRINGS")]
+    pub(crate) fixed_strings: bool,
+
+    /// Only upload lines logged at or after this time, either a timestamp like
+    /// 2024-05-01T14:25:00Z or a time ago like 30m, 2h or 1d.  Lines without a timestamp
+    /// count as logged at the same time as the line before
+    #[clap(long, env = "RUSTY_AXE_SINCE", value_parser = timestamp::parse_bound)]
+    pub(crate) since: Option<DateTime<Utc>>,
+
+    /// Only upload lines logged at or before this time, given the same way as --since
+    #[clap(long, env = "RUSTY_AXE_UNTIL", value_parser = timestamp::parse_bound)]
+    pub(crate) until: Option<DateTime<Utc>>,
+
+    /// Group lines into records, each starting with a line matching this regular
+    /// expression, so a stack trace is sent as one event.  --head, --tail and --lines
+    /// then count records rather than lines
+    #[clap(long, env = "RUSTY_AXE_MULTILINE_START", value_parser = regex::bytes::Regex::new)]
+    pub(crate) multiline_start: Option<regex::bytes::Regex>,
+
+    /// How the lines of the file are laid out
+    #[clap(long, env = "RUSTY_AXE_FORMAT", value_enum, default_value_t)]
+    pub(crate) format: Format,
+
+    /// Read the first row of a CSV file as data, naming the columns col1, col2...
+    #[clap(long, env = "RUSTY_AXE_CSV_NO_HEADER")]
+    pub(crate) csv_no_header: bool,
+
+    /// Take each event's timestamp from this field of the JSON object (with dots
+    /// between the names of nested fields), rather than the time it was read
+    #[clap(long, env = "RUSTY_AXE_TIMESTAMP_FIELD")]
+    pub(crate) timestamp_field: Option<String>,
+
+    /// Where to take event timestamps from, rather than the time lines were read: auto
+    /// looks for a common layout, like RFC 3339 or syslog, at the start of each line,
+    /// mtime uses the time the file was last modified, or give a time as RFC 3339 (like
+    /// 2024-04-30T22:15:00Z) or milliseconds since the epoch.  Lines are a millisecond
+    /// apart from there, and with --timestamp-format only those without a timestamp of
+    /// their own get it
+    #[clap(long, env = "RUSTY_AXE_TIMESTAMP", value_parser = timestamp::parse_source)]
+    pub(crate) timestamp: Option<timestamp::Source>,
+
+    /// Take each event's timestamp from its line, in this layout (chrono's strftime
+    /// syntax, like "%Y-%m-%d %H:%M:%S", or "epoch" for a number of seconds since the
+    /// epoch), rather than the time it was read.  Lines without one are given the
+    /// timestamp of the line before
+    #[clap(long, env = "RUSTY_AXE_TIMESTAMP_FORMAT", value_parser = timestamp::parse_format, conflicts_with = "timestamp-field")]
+    pub(crate) timestamp_format: Option<String>,
+
+    /// Where in each line to find the timestamp for --timestamp-format (the first group,
+    /// if the pattern has one), when it isn't at the start of the line
+    #[clap(long, env = "RUSTY_AXE_TIMESTAMP_REGEX", value_parser = regex::Regex::new, requires = "timestamp-format")]
+    pub(crate) timestamp_regex: Option<regex::Regex>,
+
+    /// The time zone of timestamps logged without one, as a name from the zone
+    /// database (like Australia/Sydney) or "local".  Times that happened twice as the
+    /// clocks went back are taken as the earlier, and times skipped as they went
+    /// forward are moved on by the gap
+    #[clap(long, env = "RUSTY_AXE_TIMEZONE", value_parser = zone::Zone::parse, requires = "timestamp-format")]
+    pub(crate) timezone: Option<zone::Zone>,
+
+    /// The unit of timestamps read with --timestamp-format epoch.  auto takes numbers
+    /// too big to be seconds as milliseconds, and those too big for that as microseconds
+    #[clap(
+        long,
+        env = "RUSTY_AXE_EPOCH_UNIT",
+        value_enum,
+        default_value_t,
+        requires = "timestamp-format"
+    )]
+    pub(crate) epoch_unit: EpochUnit,
+
+    /// What to do when timestamps taken from the lines aren't in order, since CloudWatch
+    /// Logs only accepts events in time order
+    #[clap(long, env = "RUSTY_AXE_OUT_OF_ORDER", value_enum, default_value_t)]
+    pub(crate) out_of_order: OutOfOrder,
+
+    /// Send events timestamped from their lines in the order they were read, rather
+    /// than sorting them by time (CloudWatch Logs may reject a batch that's out of order)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_NO_SORT_EVENTS",
+        conflicts_with = "out-of-order"
+    )]
+    pub(crate) no_sort_events: bool,
+
+    /// What to do with events logged at times CloudWatch Logs won't accept: more than
+    /// 14 days ago (or longer ago than the log group keeps events), or more than 2 hours
+    /// ahead
+    #[clap(long, env = "RUSTY_AXE_OUT_OF_RANGE", value_enum, default_value_t)]
+    pub(crate) out_of_range: OutOfRange,
+
+    /// Create the log group if it doesn't exist yet
+    #[clap(long, env = "RUSTY_AXE_CREATE_GROUP")]
+    pub(crate) create_group: bool,
+
+    /// How many days a log group created by --create-group keeps events for (one of
+    /// 1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365, 400, 545, 731, 1096, 1827, 2192,
+    /// 2557, 2922, 3288 or 3653), rather than forever
+    #[clap(long, env = "RUSTY_AXE_RETENTION_DAYS", value_parser = group::parse_retention_days)]
+    pub(crate) retention_days: Option<i32>,
+
+    /// Set --retention-days on the log group even if it already exists
+    #[clap(long, env = "RUSTY_AXE_FORCE_RETENTION", requires = "retention-days")]
+    pub(crate) force_retention: bool,
+
+    /// Tag a log group created by --create-group, like team=infra.  Values can use
+    /// {instance_id}, {filename} (of the file sent) and {version} (of rusty_axe).  Can
+    /// be given more than once
+    #[clap(long, env = "RUSTY_AXE_TAG", value_parser = group::Tag::parse)]
+    pub(crate) tag: Vec<group::Tag>,
+
+    /// Add the --tag tags to the log group even if it already exists
+    #[clap(long, env = "RUSTY_AXE_FORCE_TAGS", requires = "tag")]
+    pub(crate) force_tags: bool,
+
+    /// The ARN of a KMS key to encrypt a log group created by --create-group with.  A
+    /// group that already exists without it is warned about.  With --destination s3,
+    /// it's the key objects are encrypted with, for --sse aws:kms
+    #[clap(long, env = "RUSTY_AXE_KMS_KEY_ARN", value_parser = group::parse_kms_key_arn)]
+    pub(crate) kms_key_arn: Option<String>,
+
+    /// Associate --kms-key-arn with the log group if it already exists without it
+    #[clap(long, env = "RUSTY_AXE_FORCE_KMS", requires = "kms-key-arn")]
+    pub(crate) force_kms: bool,
+
+    /// What to do with events CloudWatch Logs rejects for their time all the same (as
+    /// when this instance's clock is off)
+    #[clap(long, env = "RUSTY_AXE_ON_REJECTED", value_enum, default_value_t)]
+    pub(crate) on_rejected: OnRejected,
+
+    /// What to do with lines that don't fit the --format
+    #[clap(long, env = "RUSTY_AXE_ON_INVALID", value_enum, default_value_t)]
+    pub(crate) on_invalid: OnInvalid,
+
+    /// Only upload every Nth line (starting with the first) of those picked out
+    #[clap(long, env = "RUSTY_AXE_SAMPLE", conflicts_with = "sample-random")]
+    pub(crate) sample: Option<usize>,
+
+    /// Only upload a random sample of the lines picked out, each kept with this chance
+    #[clap(long, env = "RUSTY_AXE_SAMPLE_RANDOM", value_parser = parse_rate)]
+    pub(crate) sample_random: Option<f64>,
+
+    /// Seed for --sample-random, to pick the same sample every time
+    #[clap(long, env = "RUSTY_AXE_SAMPLE_SEED", requires = "sample-random")]
+    pub(crate) sample_seed: Option<u64>,
+
+    /// Don't add an event saying how many lines were left out between the head and tail
+    #[clap(long, env = "RUSTY_AXE_NO_OMISSION_MARKER")]
+    pub(crate) no_omission_marker: bool,
+
+    /// Process a range of lines, like 120:180 or 500: (1-based and inclusive)
+    #[clap(long, env = "RUSTY_AXE_LINES", value_parser = LineRange::parse, conflicts_with_all = &["head", "tail"])]
+    pub(crate) lines: Vec<LineRange>,
+
+    /// Send at most this many bytes of messages from each file, like 5MB (KB and MB are
+    /// 1024 and 1024² bytes).  The latest lines picked out (by --head, --tail or
+    /// --lines, if given) are kept, and the rest left out
+    #[clap(long, env = "RUSTY_AXE_MAX_BYTES", value_parser = parse_max_bytes, conflicts_with = "follow")]
+    pub(crate) max_bytes: Option<usize>,
+
+    /// Start reading this many bytes into the file (moving on to the next line if that's
+    /// part way through one), such as the final offset reported by an earlier run
+    #[clap(long, env = "RUSTY_AXE_START_OFFSET", default_value_t = 0)]
+    pub(crate) start_offset: u64,
+
+    /// Remember how far into each file this run got, and start from there next time
+    #[clap(long, env = "RUSTY_AXE_STATE_FILE", value_hint = ValueHint::FilePath, conflicts_with_all = &["start-offset", "follow"])]
+    pub(crate) state_file: Option<PathBuf>,
+
+    /// Read compressed files as raw bytes instead of decompressing them
+    #[clap(long, env = "RUSTY_AXE_NO_DECOMPRESS")]
+    pub(crate) no_decompress: bool,
+
+    /// The character encoding of the input files, if they aren't UTF-8 (UTF-16 files
+    /// starting with a byte order mark are recognised without it)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_ENCODING",
+        value_enum,
+        conflicts_with = "follow"
+    )]
+    pub(crate) encoding: Option<Encoding>,
+
+    /// What to do with lines that aren't valid UTF-8 (or can't be decoded)
+    #[clap(long, env = "RUSTY_AXE_ENCODING_ERRORS", value_enum, default_value_t)]
+    pub(crate) encoding_errors: EncodingErrors,
+
+    /// What to do with files that look binary (contain NUL bytes)
+    #[clap(long, env = "RUSTY_AXE_BINARY", value_enum, default_value_t)]
+    pub(crate) binary: BinaryPolicy,
+
+    /// What to do with lines too long for a single CloudWatch Logs event
+    #[clap(long, env = "RUSTY_AXE_OVERSIZE", value_enum, default_value_t)]
+    pub(crate) oversize: Oversize,
+
+    /// What to send in place of blank lines, which CloudWatch Logs won't accept as is
+    #[clap(long, env = "RUSTY_AXE_BLANK_LINES", value_enum, default_value_t)]
+    pub(crate) blank_lines: BlankLines,
+
+    /// Remove terminal escape sequences, like colours, which CloudWatch Logs shows as
+    /// garbage
+    #[clap(long, env = "RUSTY_AXE_STRIP_ANSI")]
+    pub(crate) strip_ansi: bool,
+
+    /// Expand tabs to spaces, with a tab stop every N columns
+    #[clap(long, env = "RUSTY_AXE_TAB_WIDTH", value_parser = parse_tab_width)]
+    pub(crate) tab_width: Option<usize>,
+
+    /// Make control characters, like NUL, printable: escaped as \u{0000} or replaced
+    /// with \u{FFFD}.  Tabs are expanded too (see --tab-width)
+    #[clap(
+        long,
+        env = "RUSTY_AXE_NORMALIZE_CONTROL_CHARS",
+        value_enum,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "escape"
+    )]
+    pub(crate) normalize_control_chars: Option<ControlChars>,
+
+    /// Replace text matching this regular expression with [REDACTED] before it's sent
+    /// (or with something else, given as PATTERN=>REPLACEMENT, which can use $1 for
+    /// groups in the pattern).  Can be given more than once
+    #[clap(long, env = "RUSTY_AXE_REDACT", value_parser = redact::Rule::parse)]
+    pub(crate) redact: Vec<redact::Rule>,
+
+    /// Mask well-known kinds of sensitive text, like email or ipv4, keeping enough of
+    /// it to be useful (use --mask help to list them all)
+    #[clap(long, env = "RUSTY_AXE_MASK", value_delimiter = ',')]
+    pub(crate) mask: Vec<String>,
+
+    /// Read entries from the systemd journal instead of a file
+    #[cfg(feature = "journald")]
+    #[clap(
+        long,
+        env = "RUSTY_AXE_JOURNAL",
+        conflicts_with_all = &[
+            "filename",
+            "follow",
+            "state-file",
+            "multiline-start",
+            "encoding",
+            "timestamp",
+            "timestamp-format",
+            "max-bytes"
+        ]
+    )]
+    pub(crate) journal: bool,
+
+    /// Only read journal entries for this systemd unit
+    #[cfg(feature = "journald")]
+    #[clap(long, env = "RUSTY_AXE_UNIT", requires = "journal")]
+    pub(crate) unit: Vec<String>,
+
+    /// Only read journal entries from this boot (0 is the current boot, -1 the one before)
+    #[cfg(feature = "journald")]
+    #[clap(
+        long,
+        env = "RUSTY_AXE_BOOT",
+        requires = "journal",
+        allow_hyphen_values = true
+    )]
+    pub(crate) boot: Option<String>,
+
+    /// Map files into memory instead of reading them, which is faster for a small
+    /// head/tail of a very large file
+    #[clap(long, env = "RUSTY_AXE_MMAP")]
+    pub(crate) mmap: bool,
+
+    /// Keep uploading lines as they are appended to the file, like `tail -f`
+    #[clap(long, env = "RUSTY_AXE_FOLLOW")]
+    pub(crate) follow: bool,
+
+    /// Seconds to wait between checks for new lines when following a file
+    #[clap(long, env = "RUSTY_AXE_FOLLOW_INTERVAL", default_value_t = 5)]
+    pub(crate) follow_interval: u64,
+
+    /// The ARN of a role to assume and send with, like
+    /// arn:aws:iam::123456789012:role/central-logs-writer, for log groups in another
+    /// account.  The instance's own credentials are used to assume it
+    #[clap(long, env = "RUSTY_AXE_ROLE_ARN", value_parser = credentials::parse_role_arn)]
+    pub(crate) role_arn: Option<String>,
+
+    /// The external id the --role-arn's trust policy asks for
+    #[clap(long, env = "RUSTY_AXE_EXTERNAL_ID", requires = "role-arn")]
+    pub(crate) external_id: Option<String>,
+
+    /// What to call the --role-arn session, as CloudTrail shows it (by default
+    /// rusty-axe- and the time it started)
+    #[clap(long, env = "RUSTY_AXE_SESSION_NAME", requires = "role-arn", value_parser = credentials::parse_session_name)]
+    pub(crate) session_name: Option<String>,
+
+    /// Send to CloudWatch Logs, Kinesis or Firehose (and STS, for --role-arn) at this
+    /// URL, like LocalStack's or a VPC endpoint's, rather than AWS's usual endpoint.
+    /// AWS_ENDPOINT_URL_LOGS (or _KINESIS, _FIREHOSE, or AWS_ENDPOINT_URL) does the same
+    #[clap(long, env = "RUSTY_AXE_ENDPOINT_URL", value_hint = ValueHint::Url, value_parser = endpoints::parse_endpoint_url)]
+    pub(crate) endpoint_url: Option<Endpoint>,
+
+    /// Call CloudWatch Logs (and STS) at their FIPS endpoints in the region, as
+    /// AWS_USE_FIPS_ENDPOINT=true does too
+    #[clap(long, env = "RUSTY_AXE_USE_FIPS", conflicts_with = "endpoint-url")]
+    pub(crate) use_fips: bool,
+
+    /// Call CloudWatch Logs (and STS) at their dual-stack endpoints in the region,
+    /// which can be reached over IPv6, as AWS_USE_DUALSTACK_ENDPOINT=true does too
+    #[clap(long, env = "RUSTY_AXE_USE_DUALSTACK", conflicts_with = "endpoint-url")]
+    pub(crate) use_dualstack: bool,
+
+    /// Don't ask the EC2 instance metadata for anything (the instance id and
+    /// availability zone for stream names, or the region and credentials), nor an ECS
+    /// task's, as when this isn't running on either; streams are named by the hostname
+    /// instead.  AWS_EC2_METADATA_DISABLED=true does the same for the EC2 one
+    #[clap(long, env = "RUSTY_AXE_NO_IMDS")]
+    pub(crate) no_imds: bool,
+
+    /// Seconds to wait for the instance metadata (or an ECS task's) before naming
+    /// streams by the hostname instead.  In a container, the instance's metadata hop
+    /// limit has to be 2 for it to answer at all
+    #[clap(long, env = "RUSTY_AXE_IMDS_TIMEOUT", value_parser = parse_seconds, default_value = "1", conflicts_with = "no-imds")]
+    pub(crate) imds_timeout: Duration,
+
+    /// How many times to retry a call to CloudWatch Logs that's throttled or fails for
+    /// some other passing reason, before giving up
+    #[clap(long, env = "RUSTY_AXE_MAX_RETRIES", default_value_t = 5)]
+    pub(crate) max_retries: u32,
+
+    /// Milliseconds to wait before the first retry, doubling for each one after (with
+    /// some randomness, so many instances don't retry in step)
+    #[clap(long, env = "RUSTY_AXE_RETRY_BASE_DELAY", default_value_t = 200)]
+    pub(crate) retry_base_delay: u64,
+
+    /// How many times to try each call to CloudWatch Logs in all, the first time
+    /// included (the same as --max-retries one less)
+    #[clap(long, env = "RUSTY_AXE_ATTEMPTS", value_parser = parse_attempts, conflicts_with = "max-retries")]
+    pub(crate) attempts: Option<u32>,
+
+    /// Seconds to wait for a connection to CloudWatch Logs before giving up on it
+    #[clap(long, env = "RUSTY_AXE_CONNECT_TIMEOUT", value_parser = parse_seconds, default_value = "3")]
+    pub(crate) connect_timeout: Duration,
+
+    /// Seconds any one call to CloudWatch Logs can take before it's given up on (and
+    /// retried)
+    #[clap(long, env = "RUSTY_AXE_OPERATION_TIMEOUT", value_parser = parse_seconds, default_value = "30")]
+    pub(crate) operation_timeout: Duration,
+
+    /// Read the events back once they're sent, and fail unless they all landed
+    #[clap(long, env = "RUSTY_AXE_VERIFY", conflicts_with_all = &["follow", "append"])]
+    pub(crate) verify: bool,
+
+    /// Seconds to keep looking for events --verify hasn't found yet, as they can take
+    /// a while to show up
+    #[clap(long, env = "RUSTY_AXE_VERIFY_TIMEOUT", value_parser = parse_seconds, default_value = "30")]
+    pub(crate) verify_timeout: Duration,
+
+    /// With --verify, check the first and last events read back are the ones sent
+    #[clap(long, env = "RUSTY_AXE_VERIFY_CONTENT", requires = "verify")]
+    pub(crate) verify_content: bool,
+
+    /// The most PutLogEvents calls to make per second (retries included), for when
+    /// many instances share the account's limit.  Short bursts within a second's
+    /// allowance aren't held up
+    #[clap(long, env = "RUSTY_AXE_RATE_LIMIT", value_parser = rate::parse_requests)]
+    pub(crate) rate_limit: Option<f64>,
+
+    /// The most bytes of events to send per second, like 500KB/s (KB and MB are 1024
+    /// and 1024² bytes)
+    #[clap(long, env = "RUSTY_AXE_RATE_LIMIT_BYTES", value_parser = rate::parse_bytes)]
+    pub(crate) rate_limit_bytes: Option<f64>,
+
+    /// Send each file to a log stream of its own (named after the file too), rather
+    /// than all of them to one
+    #[clap(long, env = "RUSTY_AXE_STREAM_PER_FILE", conflicts_with = "follow")]
+    pub(crate) stream_per_file: bool,
+
+    /// How many log streams to upload to at once, when there's more than one
+    #[clap(long, env = "RUSTY_AXE_CONCURRENCY", value_parser = parse_concurrency, default_value_t = 1)]
+    pub(crate) concurrency: usize,
+
+    /// Print the events that would be sent, and how many bytes and batches would go to
+    /// which log stream, without sending them (or calling AWS at all).  The instance
+    /// metadata is still asked what stream names need from it, unless --no-imds says
+    /// not to
+    #[clap(long, env = "RUSTY_AXE_DRY_RUN", conflicts_with_all = &["follow", "verify"])]
+    pub(crate) dry_run: bool,
+
+    /// Don't send a file larger than this, like 1GB or 500MB, without asking first (or
+    /// at all, when there's no one at the terminal to ask), for what ingesting it would
+    /// cost.  Standard input isn't checked, as its size isn't known
+    #[clap(long, env = "RUSTY_AXE_MAX_FILE_SIZE", value_parser = guard::parse_max_file_size, default_value = guard::DEFAULT_MAX_FILE_SIZE)]
+    pub(crate) max_file_size: u64,
+
+    /// Send files over --max-file-size without asking
+    #[clap(long, env = "RUSTY_AXE_FORCE")]
+    pub(crate) force: bool,
+
+    /// Once every event is sent, publish how many there were (LinesShipped) and how
+    /// many bytes their messages came to (BytesShipped) as CloudWatch metrics, for
+    /// dashboards and alarms.  Not being able to is only warned about
+    #[clap(long, env = "RUSTY_AXE_EMIT_METRIC", conflicts_with_all = &["follow", "dry-run"])]
+    pub(crate) emit_metric: bool,
+
+    /// The namespace of the --emit-metric metrics
+    #[clap(long, env = "RUSTY_AXE_METRIC_NAMESPACE", default_value = metric::DEFAULT_NAMESPACE, requires = "emit-metric")]
+    pub(crate) metric_namespace: String,
+
+    /// A dimension for the --emit-metric metrics, like Environment=prod, in place of
+    /// the usual LogGroup={group} and InstanceId={instance_id}.  Values can use {group}
+    /// and {instance_id}.  Can be given more than once
+    #[clap(long, env = "RUSTY_AXE_METRIC_DIMENSION", value_parser = metric::Dimension::parse, requires = "emit-metric")]
+    pub(crate) metric_dimension: Vec<metric::Dimension>,
+
+    /// Publish how the run went to this SNS topic, like
+    /// arn:aws:sns:us-east-1:123456789012:on-call, whether it succeeded or not: the
+    /// log groups (with links), the streams, how many events and bytes were sent, and
+    /// what failed.  Not being able to is only warned about
+    #[clap(
+        long,
+        env = "RUSTY_AXE_NOTIFY_SNS",
+        value_parser = notify::parse_topic_arn,
+        conflicts_with_all = &["follow", "dry-run"]
+    )]
+    pub(crate) notify_sns: Option<String>,
+
+    /// How the --notify-sns message is laid out
+    #[clap(
+        long,
+        env = "RUSTY_AXE_NOTIFY_FORMAT",
+        value_enum,
+        default_value_t,
+        requires = "notify-sns"
+    )]
+    pub(crate) notify_format: Output,
+
+    /// Read options from this TOML file, rather than the first of ./rusty_axe.toml and
+    /// /etc/rusty_axe/config.toml that's there.  Options given here, or by their
+    /// RUSTY_AXE_ variables, win over the file's; a variable for an option that can be
+    /// given more than once holds a comma-separated list
+    #[clap(long, env = "RUSTY_AXE_CONFIG", value_hint = ValueHint::FilePath, value_parser, value_name = "PATH")]
+    pub(crate) config: Option<PathBuf>,
+
+    /// Use the options in the config file's [profile.NAME] table, on top of the ones
+    /// outside any profile
+    #[clap(
+        long,
+        env = "RUSTY_AXE_CONFIG_PROFILE",
+        value_parser,
+        value_name = "NAME"
+    )]
+    pub(crate) config_profile: Option<String>,
+
+    /// Don't say how far reading and sending have got (as a bar when stderr is a
+    /// terminal, or else a line every --progress-interval seconds)
+    #[clap(long, env = "RUSTY_AXE_NO_PROGRESS")]
+    pub(crate) no_progress: bool,
+
+    /// Seconds between lines saying how far reading and sending have got, when
+    /// stderr isn't a terminal
+    #[clap(
+        long,
+        env = "RUSTY_AXE_PROGRESS_INTERVAL",
+        value_parser = parse_seconds,
+        default_value = "10"
+    )]
+    pub(crate) progress_interval: Duration,
+}
+
+/// How far apart tab stops are when control characters are normalized
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// The arguments that say where events go, other than to log groups
+const DESTINATION_ARGS: &[&str] = &[
+    "kinesis-stream",
+    "delivery-stream",
+    "bucket",
+    "out",
+    "syslog-server",
+    "hec-url",
+    #[cfg(feature = "otlp")]
+    "otlp-endpoint",
+];
+
+/// Parse a chance between 0 and 1, as given to --sample-random
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{:?} isn't a number between 0 and 1", s)),
+    }
+}
+
+/// Parse how many log streams to upload to at once, as given to --concurrency
+fn parse_concurrency(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{:?} isn't a positive number of streams", s)),
+    }
+}
+
+/// Parse how many times to try each call, as given to --attempts
+fn parse_attempts(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(attempts) if attempts > 0 => Ok(attempts),
+        _ => Err(format!("{:?} isn't a positive number of attempts", s)),
+    }
+}
+
+/// Parse a size in bytes, as given to --max-bytes
+fn parse_max_bytes(s: &str) -> Result<usize, String> {
+    rate::parse_size(s)
+        .map(|bytes| bytes as usize)
+        .ok_or_else(|| format!("{:?} isn't a size like 5MB or 500KB", s))
+}
+
+/// Parse a timeout in seconds, which can be fractional, like 0.5
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("{:?} isn't a positive number of seconds", s)),
+    }
+}
+
+/// Parse the distance between tab stops, as given to --tab-width
+fn parse_tab_width(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(format!("{:?} isn't a positive number of columns", s)),
+    }
+}
+
+/// Whether `args` give push's options without naming the command, as they did before
+/// there were commands
+pub fn is_legacy(args: &[OsString]) -> bool {
+    command_position(args).is_none()
+}
+
+/// The command line `args`, naming push if it's only implied, with the options the
+/// RUSTY_AXE_ variables and then the config file give that it doesn't
+pub fn with_defaults(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    environment::with_lists(with_command(args), |name| std::env::var_os(name))
+        .and_then(|args| config::with_config(args, config::SEARCH_PATHS))
+}
+
+/// Run the command `cli` gives
+///
+/// `legacy` is whether it was given the way [`is_legacy`] says is deprecated, and
+/// `started` when the program was, for the summary to say how long it took.  Never
+/// exits itself: an error it returns says what went wrong, and [`Error::failure`]
+/// the status (as `--help` lists them) for the program to exit with.
+pub async fn run(cli: Cli, legacy: bool, started: Instant) -> Result<(), Error> {
+    let Cli { global, command } = cli;
+    logging::init(global.verbosity);
+    let args = match command {
+        Command::Push(args) => *args,
+        Command::Groups(args) => return list::groups(args, &global).await,
+        Command::Streams(args) => return list::streams(args, &global).await,
+        Command::Completions(args) => return completions::print(args),
+        Command::Man(args) => return man::print(args),
+        Command::Version => {
+            version::print(&global);
+            return Ok(());
+        }
+    };
+    if legacy {
+        warn!(
+            "Giving push's options without the push command is deprecated, and won't \
+             work in a later version: run rusty-axe push with them instead"
+        );
+    }
+    if let Some(path) = &args.config {
+        match &args.config_profile {
+            Some(profile) => debug!("Options read from {} ({})", path.display(), profile),
+            None => debug!("Options read from {}", path.display()),
+        }
+    }
+
+    let patterns = filter::PatternOptions {
+        ignore_case: args.ignore_case,
+        fixed_strings: args.fixed_strings,
+    };
+    let filter = match filter::LineFilter::new(&args.grep, &args.grep_v, patterns) {
+        Ok(filter) => filter,
+        Err(e) => return Err(usage(ErrorKind::ValueValidation, e)),
+    };
+    if args.kinesis_stream.is_some() && args.destination != Destination::Kinesis {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--kinesis-stream can only be used with --destination kinesis",
+        ));
+    }
+    if args.delivery_stream.is_some() && args.destination != Destination::Firehose {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--delivery-stream can only be used with --destination firehose",
+        ));
+    }
+    // clap lets --raw-object through without --bucket when -g conflicts with the bucket
+    if (args.bucket.is_some() || args.raw_object) && args.destination != Destination::S3 {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--bucket and --raw-object can only be used with --destination s3",
+        ));
+    }
+    if args.destination == Destination::S3
+        && args.kms_key_arn.is_some()
+        && args.sse != Some(s3::Sse::AwsKms)
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--kms-key-arn with --destination s3 needs --sse aws:kms",
+        ));
+    }
+    if args.out.is_some() && args.destination != Destination::File {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--out can only be used with --destination file",
+        ));
+    }
+    if args.syslog_server.is_some() && args.destination != Destination::Syslog {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--syslog-server can only be used with --destination syslog",
+        ));
+    }
+    if args.hec_url.is_some() && args.destination != Destination::Splunk {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--hec-url can only be used with --destination splunk",
+        ));
+    }
+    if args.destination == Destination::Splunk
+        && args.hec_token_env.is_none()
+        && args.hec_token_file.is_none()
+    {
+        return Err(usage(
+            ErrorKind::MissingRequiredArgument,
+            "--destination splunk needs the token, from --hec-token-env or \
+                 --hec-token-file",
+        ));
+    }
+    #[cfg(feature = "otlp")]
+    if args.otlp_endpoint.is_some() && args.destination != Destination::Otlp {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--otlp-endpoint can only be used with --destination otlp",
+        ));
+    }
+    if args.destination != Destination::CloudwatchLogs
+        && (args.follow || args.verify || args.dry_run || args.emit_metric)
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--follow, --verify, --dry-run and --emit-metric can only be used with \
+                 --destination cloudwatch-logs",
+        ));
+    }
+    if args.timestamp_field.is_some() && args.format != Format::Jsonl {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--timestamp-field can only be used with --format jsonl",
+        ));
+    }
+    if args.timestamp == Some(timestamp::Source::Auto)
+        && (args.timestamp_format.is_some() || args.timestamp_field.is_some())
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--timestamp auto can't be used with --timestamp-format or --timestamp-field",
+        ));
+    }
+    if args.epoch_unit != EpochUnit::Auto && args.timestamp_format.as_deref() != Some("epoch") {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--epoch-unit can only be used with --timestamp-format epoch",
+        ));
+    }
+    if args.timestamp == Some(timestamp::Source::Mtime) && args.follow {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--timestamp mtime can't be used with --follow",
+        ));
+    }
+    if args.format == Format::Csv && (args.follow || args.multiline_start.is_some()) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--format csv can't be used with --follow or --multiline-start",
+        ));
+    }
+    #[cfg(feature = "journald")]
+    if args.format == Format::Csv && args.journal {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--format csv can't be used with --journal",
+        ));
+    }
+    if args.mask.iter().any(|preset| preset == "help") {
+        println!("Presets for --mask:");
+        for preset in redact::PRESETS {
+            println!("  {:<16}{}", preset.name, preset.description);
+        }
+        return Ok(());
+    }
+    let mut redact: Vec<_> = match args.mask.iter().map(|p| redact::Rule::preset(p)).collect() {
+        Ok(rules) => rules,
+        Err(e) => return Err(usage(ErrorKind::InvalidValue, e)),
+    };
+    redact.extend(args.redact);
+    // Following never finishes, so there's no telling how far it's got
+    let progress = logging::progress(
+        global.verbosity,
+        !args.no_progress && !args.follow,
+        args.progress_interval,
+    );
+    let options = EventOptions {
+        filter,
+        since: args.since,
+        until: args.until,
+        multiline_start: args.multiline_start,
+        format: args.format,
+        csv_no_header: args.csv_no_header,
+        timestamp_field: args.timestamp_field,
+        timestamp: args.timestamp,
+        timestamp_format: args.timestamp_format.map(|format| match format.as_str() {
+            "epoch" => timestamp::LineFormat::epoch(args.epoch_unit, args.timestamp_regex),
+            _ => timestamp::LineFormat::new(&format, args.timestamp_regex, args.timezone),
+        }),
+        out_of_order: args.out_of_order,
+        no_sort_events: args.no_sort_events,
+        base_timestamp: match args.timestamp {
+            Some(timestamp::Source::At(millis)) => Some(millis),
+            _ => None,
+        },
+        on_invalid: args.on_invalid,
+        head: args.head,
+        tail: args.tail,
+        sample: args.sample,
+        sample_random: args.sample_random,
+        sample_seed: args.sample_seed,
+        no_omission_marker: args.no_omission_marker,
+        lines: args.lines,
+        max_bytes: args.max_bytes,
+        start_offset: args.start_offset,
+        no_decompress: args.no_decompress,
+        encoding: args.encoding,
+        encoding_errors: args.encoding_errors,
+        binary: args.binary,
+        oversize: args.oversize,
+        blank_lines: args.blank_lines,
+        strip_ansi: args.strip_ansi,
+        tab_width: args
+            .tab_width
+            .or(args.normalize_control_chars.map(|_| DEFAULT_TAB_WIDTH)),
+        control_chars: args.normalize_control_chars,
+        redact,
+        mmap: args.mmap,
+        progress: progress.clone(),
+    };
+
+    let variant = endpoints::Variant {
+        fips: args.use_fips,
+        dualstack: args.use_dualstack,
+    };
+    let profile = profile::selected(global.profile);
+    // Nor does the SDK look for the region and credentials there, with --no-imds
+    let imds = !args.no_imds;
+    // Sending anywhere but AWS, or a dry run, needs neither, and mustn't go looking for
+    // them
+    let (region, credentials) = if !args.destination.is_aws() || args.dry_run {
+        (None, None)
+    } else {
+        if let Some(profile) = &profile {
+            profile::check(profile).await.map_err(Error::Profile)?;
+        }
+        let region = region::resolve(global.region.as_ref(), profile.as_deref(), imds).await;
+        let source = match (args.role_arn, &profile) {
+            (Some(role_arn), _) => credentials::Source::Role {
+                role: credentials::AssumeRole {
+                    role_arn,
+                    external_id: args.external_id,
+                    session_name: args.session_name,
+                },
+                region: region.clone(),
+                endpoint: endpoints::Resolver::new("sts", args.endpoint_url.as_ref(), variant),
+                profile: profile.clone(),
+                imds,
+            },
+            (None, Some(profile)) => credentials::Source::Profile(profile.clone()),
+            (None, None) => credentials::Source::Default { imds },
+        };
+        let credentials = credentials::load(source)
+            .await
+            .map_err(Error::Credentials)?;
+        (Some(region), Some(credentials))
+    };
+    let upload_options = UploadOptions {
+        sink: match args.destination {
+            Destination::CloudwatchLogs => Sink::CloudWatchLogs,
+            Destination::Kinesis => Sink::Kinesis(kinesis::KinesisOptions {
+                stream: args.kinesis_stream.unwrap_or_default(),
+                partition_key: args.partition_key,
+            }),
+            Destination::Firehose => Sink::Firehose(args.delivery_stream.unwrap_or_default()),
+            Destination::S3 => Sink::S3(s3::S3Options {
+                bucket: args.bucket.unwrap_or_default(),
+                key_prefix: args.key_prefix,
+                key_template: args.key_template,
+                sse: args.sse,
+                kms_key: args.kms_key_arn.clone(),
+                raw: args.raw_object,
+            }),
+            Destination::File => Sink::File(args.out.unwrap_or_default()),
+            Destination::Syslog => Sink::Syslog(syslog::SyslogOptions {
+                server: args.syslog_server.expect("required for syslog"),
+                protocol: args.syslog_proto,
+            }),
+            Destination::Splunk => Sink::Splunk(splunk::SplunkOptions {
+                url: args.hec_url.unwrap_or_default(),
+                token: args
+                    .hec_token_env
+                    .or(args.hec_token_file)
+                    .expect("required for splunk"),
+                sourcetype: args.hec_sourcetype.unwrap_or_else(|| match args.format {
+                    Format::Text => String::from(splunk::DEFAULT_SOURCETYPE),
+                    Format::Jsonl | Format::Csv => String::from("_json"),
+                }),
+                host: args.hec_host,
+                source: args.hec_source,
+            }),
+            #[cfg(feature = "otlp")]
+            Destination::Otlp => Sink::Otlp(args.otlp_endpoint.unwrap_or_default()),
+        },
+        group: args.group.first().cloned().unwrap_or_default(),
+        more_groups: args.group.iter().skip(1).cloned().collect(),
+        stream: args.stream,
+        stream_template: args.stream_template.or_else(|| {
+            args.append
+                .then(|| naming::append_template(args.stream_per_file))
+        }),
+        append: args.append,
+        out_of_range: args.out_of_range,
+        on_rejected: args.on_rejected,
+        group_setup: group::GroupSetup {
+            create: args.create_group,
+            retention_days: args.retention_days,
+            force_retention: args.force_retention,
+            tags: Default::default(),
+            force_tags: args.force_tags,
+            kms_key: args.kms_key_arn,
+            force_kms: args.force_kms,
+        },
+        tags: args.tag,
+        imds: metadata::ImdsOptions {
+            enabled: !args.no_imds,
+            timeout: args.imds_timeout,
+        },
+        retry: retry::RetryPolicy {
+            max_retries: args
+                .attempts
+                .map_or(args.max_retries, |attempts| attempts - 1),
+            base_delay: Duration::from_millis(args.retry_base_delay),
+            credentials: credentials.clone(),
+        },
+        client: ClientOptions {
+            timeouts: retry::Timeouts {
+                connect: args.connect_timeout,
+                operation: args.operation_timeout,
+            },
+            credentials: credentials.map(SharedCredentialsProvider::new),
+            region,
+            profile,
+            endpoint: args.endpoint_url,
+            variant,
+            no_imds: args.no_imds,
+        },
+        rate_limit: Arc::new(rate::RateLimit::new(args.rate_limit, args.rate_limit_bytes)),
+        concurrency: args.concurrency,
+        verify: args.verify.then(|| verify::VerifyOptions {
+            timeout: args.verify_timeout,
+            content: args.verify_content,
+            ..Default::default()
+        }),
+        dry_run: args.dry_run.then_some(global.output),
+        output: global.output,
+        metric: args
+            .emit_metric
+            .then(|| metric::MetricOptions::new(args.metric_namespace, args.metric_dimension)),
+        notify: args.notify_sns.map(|topic_arn| notify::NotifyOptions {
+            topic_arn,
+            format: args.notify_format,
+        }),
+        progress,
+    };
+
+    #[cfg(feature = "journald")]
+    if args.journal {
+        let journal = journal::JournalOptions {
+            units: args.unit,
+            boot: args.boot,
+        };
+        let mut summary = UploadSummary::default();
+        let events = match journal::get_events(&journal, &options, &mut summary).await {
+            Ok(events) => {
+                summary.files_read.push(String::from("journal"));
+                events
+            }
+            Err(e) => {
+                error!("Couldn't read the journal: {}", e);
+                summary
+                    .files_failed
+                    .push((String::from("journal"), e.to_string()));
+                Vec::new()
+            }
+        };
+        return upload(
+            &upload_options,
+            vec![StreamEvents::merged(events)],
+            summary,
+            None,
+            started,
+        )
+        .await;
+    }
+
+    let mut filenames = args.filename;
+    if filenames.is_empty() {
+        if io::stdin().is_terminal() {
+            return Err(usage(
+                ErrorKind::MissingRequiredArgument,
+                "--filename is required unless input is piped to stdin",
+            ));
+        }
+        filenames.push(String::from(STDIN_PATH));
+    }
+    let mut summary = UploadSummary::default();
+    let expanded = expand_globs(
+        &filenames,
+        args.allow_empty_glob,
+        args.recursive,
+        &mut summary,
+    );
+    let filenames = match expanded {
+        Ok(filenames) => filenames,
+        Err(e) => return Err(usage(ErrorKind::ValueValidation, e)),
+    };
+
+    if args.follow {
+        if filenames.len() != 1 || filenames[0] == STDIN_PATH {
+            return Err(usage(
+                ErrorKind::ArgumentConflict,
+                "--follow needs exactly one file (not stdin)",
+            ));
+        }
+        if !upload_options.more_groups.is_empty() {
+            return Err(usage(
+                ErrorKind::ArgumentConflict,
+                "--follow can only send to one --group",
+            ));
+        }
+        for (path, reason) in &summary.files_failed {
+            warn!("Couldn't read {}: {}", path, reason);
+        }
+        check_sizes(&filenames, args.max_file_size, args.force, &options)?;
+        let interval = Duration::from_secs(args.follow_interval.max(1));
+        return follow::follow_file(&filenames[0], &options, &upload_options, interval).await;
+    }
+
+    let mut state = match args.state_file.as_deref().map(state::StateFile::open) {
+        Some(Ok(state)) => Some(state),
+        Some(Err(e)) => return Err(Error::Other(format!("Couldn't open the state file: {}", e))),
+        None => None,
+    };
+
+    let filenames = expand_directories(&filenames, args.recursive, &args.exclude, &mut summary);
+    // A dry run sends nothing, so costs nothing to let through
+    if !args.dry_run {
+        check_sizes(&filenames, args.max_file_size, args.force, &options)?;
+    }
+    let streams = if args.stream_per_file || args.destination.sends_file() {
+        read_files(&filenames, &options, state.as_mut(), &mut summary)
+            .await
+            .into_iter()
+            .map(|(path, events)| StreamEvents {
+                file: Some(path),
+                events,
+            })
+            .collect()
+    } else {
+        let events = collect_events(&filenames, &options, state.as_mut(), &mut summary).await;
+        vec![StreamEvents::merged(events)]
+    };
+
+    upload(&upload_options, streams, summary, state, started).await
+}
+
+/// A usage error found after the arguments were parsed, worded as clap words its own
+fn usage(kind: ErrorKind, message: impl std::fmt::Display) -> Error {
+    Args::command().error(kind, message).into()
+}
+
+/// Stop the run unless every file is under --max-file-size, or sending the ones that
+/// aren't is meant
+fn check_sizes(
+    filenames: &[String],
+    max_file_size: u64,
+    force: bool,
+    options: &EventOptions,
+) -> Result<(), Error> {
+    let files: Vec<_> = filenames
+        .iter()
+        .map(|path| (path.clone(), input_size(path)))
+        .collect();
+    let mut ask = guard::ask_terminal;
+    let ask: Option<guard::Ask> = match io::stdin().is_terminal() {
+        true => Some(&mut ask),
+        false => None,
+    };
+    guard::check(&files, max_file_size, force, options, ask)
+}
+
+/// Where events are sent
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Destination {
+    /// Log streams in CloudWatch Logs
+    #[default]
+    CloudwatchLogs,
+    /// A Kinesis data stream (--kinesis-stream)
+    Kinesis,
+    /// A Firehose delivery stream (--delivery-stream)
+    Firehose,
+    /// An S3 bucket, a whole object a file (--bucket)
+    S3,
+    /// A syslog server (--syslog-server)
+    Syslog,
+    /// A Splunk HTTP Event Collector (--hec-url)
+    Splunk,
+    /// An OpenTelemetry collector (--otlp-endpoint)
+    #[cfg(feature = "otlp")]
+    Otlp,
+    /// A local file, as JSON lines (--out)
+    File,
+}
+
+impl Destination {
+    /// Whether it's in AWS, needing credentials and a region to send to
+    fn is_aws(self) -> bool {
+        match self {
+            Destination::Syslog | Destination::Splunk | Destination::File => false,
+            #[cfg(feature = "otlp")]
+            Destination::Otlp => false,
+            _ => true,
+        }
+    }
+
+    /// Whether events are sent with the file they're from, so each file's are sent on
+    /// their own
+    fn sends_file(self) -> bool {
+        match self {
+            Destination::Splunk | Destination::S3 => true,
+            #[cfg(feature = "otlp")]
+            Destination::Otlp => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parse a --stream, which has to be a name CloudWatch Logs allows: 1 to 512
+/// characters, none of them `:` or `*`
+fn parse_stream_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.chars().count() > 512 {
+        Err(format!("{:?} isn't 1 to 512 characters long", s))
+    } else if s.contains([':', '*']) {
+        Err(format!(
+            "{:?} has a : or * in it, which log stream names can't",
+            s
+        ))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// push's options, and the global ones, from a command line that names push
+    pub(crate) fn parse_push(args: Vec<OsString>) -> Result<(Global, Box<Args>), String> {
+        let Cli { global, command } = Cli::try_parse_from(args).map_err(|e| e.to_string())?;
+        match command {
+            Command::Push(args) => Ok((global, args)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_with_command() {
+        let parse = |args: &[&str]| {
+            let args = with_command(args.iter().map(OsString::from));
+            Cli::try_parse_from(args).map(|cli| cli.command)
+        };
+        let legacy = |args: &[&str]| {
+            let args: Vec<_> = args.iter().map(OsString::from).collect();
+            command_position(&args).is_none()
+        };
+        // Without a command, it's push
+        assert!(matches!(
+            parse(&["rusty-axe", "-g", "g", "-f", "x"]),
+            Ok(Command::Push(args)) if args.group == ["g"]
+        ));
+        assert!(legacy(&["rusty-axe", "-g", "g", "-f", "x"]));
+        assert!(matches!(
+            parse(&["rusty-axe", "push", "-g", "g"]),
+            Ok(Command::Push(args)) if args.group == ["g"]
+        ));
+        assert!(!legacy(&["rusty-axe", "push", "-g", "g"]));
+        assert!(matches!(
+            parse(&[
+                "rusty-axe",
+                "groups",
+                "--prefix",
+                "/ec2",
+                "--output",
+                "json"
+            ]),
+            Ok(Command::Groups(_))
+        ));
+        assert!(matches!(
+            parse(&["rusty-axe", "streams", "-g", "crash-logs", "--limit", "10"]),
+            Ok(Command::Streams(_))
+        ));
+        assert!(matches!(
+            parse(&["rusty-axe", "completions", "zsh"]),
+            Ok(Command::Completions(_))
+        ));
+        assert!(matches!(
+            parse(&["rusty-axe", "version", "-v"]),
+            Ok(Command::Version)
+        ));
+        // -h is --head, as it always was
+        assert!(matches!(
+            parse(&["rusty-axe", "-h", "5", "-g", "g"]),
+            Ok(Command::Push(args)) if args.head == 5
+        ));
+        // Asking for help is left alone
+        let err = parse(&["rusty-axe", "--help"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DisplayHelp);
+        assert!(err.to_string().contains("groups"));
+        assert!(!legacy(&["rusty-axe", "-h"]));
+        assert!(!legacy(&["rusty-axe", "help", "push"]));
+        assert!(!legacy(&["rusty-axe", "--version"]));
+    }
+
+    #[test]
+    fn test_global_options() {
+        let parse = |args: &[&str]| {
+            let args = with_command(args.iter().map(OsString::from));
+            Cli::try_parse_from(args).map(|cli| cli.global)
+        };
+        // Before the command's name or after it, with or without one
+        for args in [
+            &["rusty-axe", "--region", "eu-west-1", "-vv", "groups"][..],
+            &["rusty-axe", "groups", "--region=eu-west-1", "-vv"],
+            &[
+                "rusty-axe",
+                "--region",
+                "eu-west-1",
+                "streams",
+                "-g",
+                "g",
+                "-vv",
+            ],
+            &[
+                "rusty-axe",
+                "-vv",
+                "push",
+                "-g",
+                "g",
+                "--region",
+                "eu-west-1",
+            ],
+            &["rusty-axe", "--region", "eu-west-1", "-vv", "-g", "g"],
+        ] {
+            let global = parse(args).unwrap();
+            assert_eq!(global.region, Some(Region::new("eu-west-1")), "{:?}", args);
+            assert_eq!(global.verbosity.verbose, 2, "{:?}", args);
+        }
+        let global = parse(&[
+            "rusty-axe",
+            "--profile",
+            "prod",
+            "groups",
+            "--output",
+            "json",
+        ]);
+        let global = global.unwrap();
+        assert_eq!(global.profile.as_deref(), Some("prod"));
+        assert_eq!(global.output, Output::Json);
+        // As the listing commands had it
+        let global = parse(&["rusty-axe", "groups", "--output", "table"]).unwrap();
+        assert_eq!(global.output, Output::Text);
+        assert!(parse(&["rusty-axe", "groups", "-q", "-v"]).is_err());
+        assert!(parse(&["rusty-axe", "--region", "Frankfurt", "groups"]).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        assert_eq!(parse(&[]).unwrap().concurrency, 1);
+        assert_eq!(parse(&["--concurrency", "4"]).unwrap().concurrency, 4);
+        assert!(parse(&["--concurrency", "0"]).is_err());
+        assert!(parse(&["--stream-per-file", "--follow"]).is_err());
+    }
+
+    #[test]
+    fn test_max_bytes_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        assert_eq!(
+            parse(&["--max-bytes", "5MB"]).unwrap().max_bytes,
+            Some(5 << 20)
+        );
+        assert_eq!(
+            parse(&["--max-bytes", "1000"]).unwrap().max_bytes,
+            Some(1000)
+        );
+        assert!(parse(&["--max-bytes", "5GB"]).is_err());
+        assert!(parse(&["--max-bytes", "5MB", "--follow"]).is_err());
+    }
+
+    #[test]
+    fn test_stream_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        assert_eq!(parse(&[]).unwrap().stream, None);
+        assert_eq!(
+            parse(&["--stream", "incident-4821/syslog"]).unwrap().stream,
+            Some(String::from("incident-4821/syslog"))
+        );
+        assert!(parse(&["--stream", ""]).is_err());
+        assert!(parse(&["--stream", "a:b"]).is_err());
+        assert!(parse(&["--stream", "a*"]).is_err());
+        assert!(parse(&["--stream", &"x".repeat(513)]).is_err());
+        assert!(parse(&["--stream", &"x".repeat(512)]).is_ok());
+        assert!(parse(&["--stream", "s", "--stream-per-file"]).is_err());
+    }
+
+    #[test]
+    fn test_verify_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let args = parse(&["--verify", "--verify-timeout", "5", "--verify-content"]).unwrap();
+        assert!(args.verify && args.verify_content);
+        assert_eq!(args.verify_timeout, Duration::from_secs(5));
+        assert!(parse(&["--verify-content"]).is_err());
+        assert!(parse(&["--verify", "--append"]).is_err());
+    }
+
+    #[test]
+    fn test_metric_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let args = parse(&["--emit-metric", "--metric-dimension", "Env=prod"]).unwrap();
+        assert!(args.emit_metric);
+        assert_eq!(args.metric_namespace, metric::DEFAULT_NAMESPACE);
+        assert_eq!(args.metric_dimension[0].name, "Env");
+        assert!(parse(&["--metric-namespace", "Crashes"]).is_err());
+        assert!(parse(&["--emit-metric", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn test_notify_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let topic = "arn:aws:sns:us-east-1:123456789012:on-call";
+        let args = parse(&["--notify-sns", topic, "--notify-format", "json"]).unwrap();
+        assert_eq!(args.notify_sns.as_deref(), Some(topic));
+        assert_eq!(args.notify_format, Output::Json);
+        assert!(parse(&["--notify-format", "json"]).is_err());
+        assert!(parse(&["--notify-sns", "on-call"]).is_err());
+    }
+
+    #[test]
+    fn test_destination_args() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["rusty-axe"], args].concat());
+        let args = parse(&["-g", "g"]).unwrap();
+        assert_eq!(args.destination, Destination::CloudwatchLogs);
+        let args = parse(&[
+            "--destination",
+            "kinesis",
+            "--kinesis-stream",
+            "logs",
+            "--partition-key",
+            "web",
+        ])
+        .unwrap();
+        assert_eq!(args.destination, Destination::Kinesis);
+        assert_eq!(args.kinesis_stream.as_deref(), Some("logs"));
+        assert_eq!(args.partition_key.as_deref(), Some("web"));
+        // Kinesis needs a stream, and has no use for a log group
+        assert!(parse(&["--destination", "kinesis"]).is_err());
+        assert!(parse(&["--kinesis-stream", "logs", "-g", "g"]).is_err());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["-g", "g", "--partition-key", "web"]).is_err());
+
+        let args = parse(&["--destination", "firehose", "--delivery-stream", "to-s3"]).unwrap();
+        assert_eq!(args.destination, Destination::Firehose);
+        assert_eq!(args.delivery_stream.as_deref(), Some("to-s3"));
+        assert!(parse(&["--destination", "firehose"]).is_err());
+        assert!(parse(&["--delivery-stream", "to-s3", "-g", "g"]).is_err());
+
+        let args = parse(&[
+            "--destination",
+            "s3",
+            "--bucket",
+            "incidents",
+            "--key-prefix",
+            "crash/",
+            "--sse",
+            "aws:kms",
+        ])
+        .unwrap();
+        assert_eq!(args.destination, Destination::S3);
+        assert_eq!(args.bucket.as_deref(), Some("incidents"));
+        assert_eq!(args.key_prefix, "crash/");
+        assert_eq!(args.sse, Some(s3::Sse::AwsKms));
+        assert!(parse(&["--destination", "s3"]).is_err());
+        assert!(parse(&["--bucket", "incidents", "-g", "g"]).is_err());
+
+        let args = parse(&["--destination", "file", "--out", "/tmp/events.jsonl"]).unwrap();
+        assert_eq!(args.destination, Destination::File);
+        assert_eq!(args.out, Some(PathBuf::from("/tmp/events.jsonl")));
+        assert!(parse(&["--destination", "file"]).is_err());
+        assert!(parse(&["--out", "/tmp/events.jsonl", "-g", "g"]).is_err());
+
+        let args = parse(&["--destination", "syslog", "--syslog-server", "relay:1514"]).unwrap();
+        assert_eq!(args.destination, Destination::Syslog);
+        assert_eq!(args.syslog_server.unwrap().port, Some(1514));
+        assert_eq!(args.syslog_proto, syslog::Protocol::Udp);
+        let args = parse(&["--syslog-server", "relay", "--syslog-proto", "tls"]).unwrap();
+        assert_eq!(args.syslog_proto, syslog::Protocol::Tls);
+        assert!(parse(&["--destination", "syslog"]).is_err());
+        assert!(parse(&["--syslog-proto", "tcp", "-g", "g"]).is_err());
+    }
+
+    #[test]
+    fn test_splunk_args() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["rusty-axe"], args].concat());
+        let dir = tempfile::tempdir().unwrap();
+        let token = dir.path().join("token");
+        fs::write(&token, "0000-secret\n").unwrap();
+        let token = token.to_str().unwrap();
+        let args = parse(&[
+            "--destination",
+            "splunk",
+            "--hec-url",
+            "https://splunk:8088/",
+            "--hec-token-file",
+            token,
+            "--hec-source",
+            "app",
+        ])
+        .unwrap();
+        assert_eq!(args.destination, Destination::Splunk);
+        assert_eq!(args.hec_url.as_deref(), Some("https://splunk:8088"));
+        assert!(args.hec_token_file.is_some());
+        assert_eq!(args.hec_source.as_deref(), Some("app"));
+        assert!(!format!("{:?}", args).contains("0000-secret"));
+        assert!(args.destination.sends_file());
+        assert!(parse(&["--destination", "splunk"]).is_err());
+        assert!(parse(&["--hec-url", "https://splunk:8088", "-g", "g"]).is_err());
+        assert!(parse(&["--hec-token-file", token, "-g", "g"]).is_err());
+        let missing = dir.path().join("missing");
+        let missing = missing.to_str().unwrap();
+        assert!(parse(&[
+            "--hec-url",
+            "https://splunk:8088",
+            "--hec-token-file",
+            missing
+        ])
+        .is_err());
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_otlp_args() {
+        let parse = |args: &[&str]| Args::try_parse_from([&["rusty-axe"], args].concat());
+        let args = parse(&[
+            "--destination",
+            "otlp",
+            "--otlp-endpoint",
+            "http://collector:4318/",
+        ])
+        .unwrap();
+        assert_eq!(args.destination, Destination::Otlp);
+        assert_eq!(args.otlp_endpoint.as_deref(), Some("http://collector:4318"));
+        assert!(!args.destination.is_aws());
+        assert!(parse(&["--destination", "otlp"]).is_err());
+        assert!(parse(&["--otlp-endpoint", "http://collector:4318", "-g", "g"]).is_err());
+        assert!(parse(&["--otlp-endpoint", "collector:4318"]).is_err());
+    }
+
+    #[test]
+    fn test_timeout_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.connect_timeout, Duration::from_secs(3));
+        assert_eq!(args.operation_timeout, Duration::from_secs(30));
+        let args = parse(&["--connect-timeout", "0.5", "--attempts", "2"]).unwrap();
+        assert_eq!(args.connect_timeout, Duration::from_millis(500));
+        assert_eq!(args.attempts, Some(2));
+        assert!(parse(&["--operation-timeout", "0"]).is_err());
+        assert!(parse(&["--attempts", "0"]).is_err());
+        assert!(parse(&["--attempts", "2", "--max-retries", "1"]).is_err());
+
+        assert_eq!(args.imds_timeout, Duration::from_secs(1));
+        let args = parse(&["--imds-timeout", "0.2"]).unwrap();
+        assert_eq!(args.imds_timeout, Duration::from_millis(200));
+        assert!(parse(&["--no-imds"]).unwrap().no_imds);
+        assert!(parse(&["--no-imds", "--imds-timeout", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_lines_conflict_with_head_and_tail() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        assert!(parse(&["--lines", "1:2", "--lines", "5:"]).is_ok());
+        assert!(parse(&["--lines", "1:2", "--head", "5"]).is_err());
+        assert!(parse(&["--lines", "1:2", "--tail", "5"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("0.01"), Ok(0.01));
+        assert_eq!(parse_rate("1"), Ok(1.0));
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("-0.1").is_err());
+        assert!(parse_rate("often").is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let args = Args::try_parse_from([
+            "rusty-axe",
+            "-f",
+            "app.jsonl",
+            "-g",
+            "group",
+            "--format",
+            "jsonl",
+            "--on-invalid",
+            "skip",
+        ])
+        .unwrap();
+        assert_eq!(
+            (args.format, args.on_invalid),
+            (Format::Jsonl, OnInvalid::Skip)
+        );
+    }
+
+    #[test]
+    fn test_parse_normalize_control_chars() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["rusty-axe", "-f", "app.log", "-g", "group"];
+            argv.extend(extra);
+            Args::try_parse_from(argv)
+        };
+        assert_eq!(
+            parse(&["--normalize-control-chars"])
+                .unwrap()
+                .normalize_control_chars,
+            Some(ControlChars::Escape)
+        );
+        let args = parse(&["--normalize-control-chars=placeholder", "--tab-width", "4"]).unwrap();
+        assert_eq!(
+            args.normalize_control_chars,
+            Some(ControlChars::Placeholder)
+        );
+        assert_eq!(args.tab_width, Some(4));
+        assert!(parse(&["--tab-width", "0"]).is_err());
+    }
+}
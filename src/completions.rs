@@ -0,0 +1,91 @@
+//! Tab completion scripts, for the `completions` command
+
+use crate::cli::Cli;
+use crate::error::Error;
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use std::io::{self, Write};
+
+/// Print a script for a shell to complete rusty-axe's commands and options with
+///
+/// Put it where the shell looks for completions, like
+/// ~/.local/share/bash-completion/completions/rusty-axe for bash,
+/// _rusty-axe in a directory on zsh's $fpath, or
+/// ~/.config/fish/completions/rusty-axe.fish for fish.
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    /// The shell to complete in
+    #[clap(value_enum)]
+    shell: Shell,
+}
+
+pub fn print(args: CompletionsArgs) -> Result<(), Error> {
+    let mut stdout = io::stdout().lock();
+    write(args.shell, &mut stdout);
+    stdout
+        .flush()
+        .map_err(|e| Error::Other(format!("Couldn't print the completions: {}", e)))
+}
+
+/// Write the script for `shell` to `out`
+fn write(shell: Shell, out: &mut dyn Write) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(shell: Shell) -> String {
+        let mut out = Vec::new();
+        write(shell, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_scripts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let script = script(shell);
+            // Every command, and the options of each
+            for word in [
+                "push",
+                "groups",
+                "streams",
+                "completions",
+                "retention-days",
+                "stream-template",
+                "config-profile",
+                "prefix",
+                "limit",
+            ] {
+                assert!(
+                    script.contains(word),
+                    "{:?} isn't in {}'s script",
+                    word,
+                    shell
+                );
+            }
+            assert!(script.contains("rusty-axe"), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn test_value_hints() {
+        let zsh = script(Shell::Zsh);
+        // Files for --filename, and nothing to go on for --group but its name
+        let filename = zsh
+            .lines()
+            .find(|line| line.contains("--filename="))
+            .unwrap();
+        assert!(filename.ends_with(":_files' \\"), "{}", filename);
+        let group = zsh.lines().find(|line| line.contains("--group=")).unwrap();
+        assert!(group.ends_with(":GROUP:( )' \\"), "{}", group);
+        // The shells a script can be written for
+        for shell in ["bash", "zsh", "fish", "powershell", "elvish"] {
+            assert!(zsh.contains(shell), "{}", shell);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Options kept in a TOML file, so a long command line doesn't have to be given (or
+//! kept in a script) every time
+//!
+//! Each key is the long name of one of `push`'s options, with the value it would be
+//! given: a string or a number for an option that takes one, an array for an option
+//! that can be given more than once, and `true` for a flag (or a number, for -v).
+//! The options in a `[profile.NAME]` table are used on top of the others with
+//! `--config-profile NAME`.  Whatever the command line or a RUSTY_AXE_ variable gives
+//! wins over the file, and the file wins over the defaults.
+//!
+//! ```toml
+//! group = "crash-logs"
+//! stream-template = "{instance_id}-{filename}"
+//! grep = ["ERROR", "FATAL"]
+//! create-group = true
+//!
+//! [profile.crash-dump]
+//! filename = ["/var/crash/*.log"]
+//! retention-days = 30
+//! ```
+
+use crate::cli::Cli;
+
+use clap::{ArgAction, ArgMatches, CommandFactory, ValueSource};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// Where the config file is looked for, in order, when --config doesn't say
+pub const SEARCH_PATHS: &[&str] = &["rusty_axe.toml", "/etc/rusty_axe/config.toml"];
+
+/// Options that only say where the options are, so can't be in the file
+const NOT_IN_FILE: &[&str] = &["config", "config-profile", "help", "version"];
+
+/// The command line, with any options the config file gives `push` added to it
+///
+/// The file is the one --config names, or else the first of `search` that's there.
+/// An option given on the command line, or by its variable, leaves the file's value
+/// for it out.  When the file was found by looking for it, --config is added naming
+/// it.
+pub fn with_config(mut args: Vec<OsString>, search: &[&str]) -> Result<Vec<OsString>, String> {
+    // Anything wrong with the command line is for clap to say once the file's added
+    // (--group, for one, can be missing when it's in the file)
+    let (position, matches) = match crate::cli::given_command(&args) {
+        Some((position, name, matches)) if name == "push" => (position, matches),
+        _ => return Ok(args),
+    };
+    let push = matches.subcommand_matches("push").expect("just given");
+
+    let profile = push.get_one::<String>("config-profile");
+    let mut added = Vec::new();
+    let path = match push.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None => match search.iter().map(Path::new).find(|path| path.is_file()) {
+            Some(path) => {
+                let mut config = OsString::from("--config=");
+                config.push(path);
+                added.push(config);
+                path.to_path_buf()
+            }
+            None if profile.is_some() => {
+                return Err(format!(
+                    "--config-profile needs a config file, and there's none at {}",
+                    search.join(" or ")
+                ))
+            }
+            None => return Ok(args),
+        },
+    };
+
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let file: Table = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let options = options(file, profile).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for (key, value) in &options {
+        let arguments = arguments(key, value, &matches);
+        added.extend(arguments.map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+
+    args.splice(position + 1..position + 1, added);
+    Ok(args)
+}
+
+/// The options in the file, with those of `profile` (if one's chosen) in place of
+/// any outside it
+///
+/// Every key is checked, in every profile, so a typo doesn't go unnoticed until that
+/// profile is used.
+fn options(mut file: Table, profile: Option<&String>) -> Result<Table, String> {
+    let profiles = match file.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => {
+            return Err(String::from(
+                "profile has to be a table of profiles, like [profile.crash-dump]",
+            ))
+        }
+        None => Table::new(),
+    };
+    check_keys(&file, "")?;
+    for (name, options) in &profiles {
+        match options {
+            Value::Table(options) => check_keys(options, &format!(" in [profile.{}]", name))?,
+            _ => return Err(format!("profile.{} has to be a table of options", name)),
+        }
+    }
+
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(Value::Table(options)) => file.extend(options.clone()),
+            _ => return Err(format!("there's no [profile.{}]", name)),
+        }
+    }
+    Ok(file)
+}
+
+/// Check every key names an option that can be in the file
+fn check_keys(options: &Table, place: &str) -> Result<(), String> {
+    for key in options.keys() {
+        if find(key).is_none() {
+            return Err(format!(
+                "there's no option {:?}{} (options are named as they are on the command \
+                 line, like stream-template)",
+                key, place
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The option `key` names, if it's one that can be in the file: one of `push`'s, or
+/// a global one
+fn find(key: &str) -> Option<clap::Arg<'static>> {
+    let command = Cli::command();
+    let push = command
+        .find_subcommand("push")
+        .expect("there's a push command");
+    let long = key.replace('_', "-");
+    let arg = command
+        .get_arguments()
+        .chain(push.get_arguments())
+        .filter(|arg| !NOT_IN_FILE.contains(&arg.get_id()))
+        .find(|arg| arg.get_long() == Some(long.as_str()));
+    arg.cloned()
+}
+
+/// The arguments that give the option `key` names the file's `value`, which are none
+/// if it's already on the command line or given by its variable
+fn arguments(key: &str, value: &Value, matches: &ArgMatches) -> Result<Vec<OsString>, String> {
+    let arg = find(key).expect("checked already");
+    // A global option is in the matches, and one of push's in its subcommand's
+    let global = Cli::command()
+        .get_arguments()
+        .any(|global| global.get_id() == arg.get_id());
+    let matches = match global {
+        true => matches,
+        false => matches.subcommand_matches("push").expect("push was given"),
+    };
+    if let Some(ValueSource::CommandLine | ValueSource::EnvVariable) =
+        matches.value_source(arg.get_id())
+    {
+        return Ok(Vec::new());
+    }
+
+    let long = format!("--{}", arg.get_long().expect("found by it"));
+    let values = match value {
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    let mut args = Vec::new();
+    for value in values {
+        let flag = !arg.is_takes_value_set();
+        let value = match value {
+            Value::Boolean(set) if flag => {
+                args.extend(set.then(|| long.clone()));
+                continue;
+            }
+            Value::Integer(count) if matches!(arg.get_action(), ArgAction::Count) => {
+                let count = usize::try_from(*count).unwrap_or(0);
+                args.extend(std::iter::repeat_n(long.clone(), count));
+                continue;
+            }
+            _ if flag => return Err(format!("{} is a flag, so can only be true or false", key)),
+            Value::String(value) => value.clone(),
+            Value::Integer(value) => value.to_string(),
+            Value::Float(value) => value.to_string(),
+            Value::Boolean(value) => value.to_string(),
+            _ => return Err(format!("{} has to be a string, a number or an array", key)),
+        };
+        args.push(format!("{}={}", long, value));
+    }
+    Ok(args.into_iter().map(OsString::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::tests::parse_push;
+    use crate::cli::{Args, Global};
+    use std::io::Write;
+
+    /// A config file holding `toml`
+    fn config(toml: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(toml.as_bytes()).unwrap();
+        file
+    }
+
+    /// Parse `push`'s arguments, with the options in `file`
+    fn parse(file: &tempfile::NamedTempFile, args: &[&str]) -> Result<(Global, Box<Args>), String> {
+        let path = file.path().to_str().unwrap();
+        let search = [path];
+        let args = ["rusty-axe", "push"].iter().chain(args).map(OsString::from);
+        let args = with_config(args.collect(), &search)?;
+        parse_push(args)
+    }
+
+    const FILE: &str = r#"
+        group = "from-file"
+        stream-template = "{instance_id}-{filename}"
+        grep = ["ERROR", "FATAL"]
+        create-group = true
+        retention-days = 30
+        max_retries = 7
+        verbose = 2
+        region = "eu-west-1"
+
+        [profile.crash-dump]
+        group = ["crash-dumps", "security"]
+        retention-days = 90
+        tail = 500
+    "#;
+
+    #[test]
+    fn test_file_over_defaults() {
+        let file = config(FILE);
+        let (global, args) = parse(&file, &[]).unwrap();
+        assert_eq!(args.group, ["from-file"]);
+        assert!(args.stream_template.is_some());
+        assert_eq!(args.grep, ["ERROR", "FATAL"]);
+        assert!(args.create_group);
+        assert_eq!(args.retention_days, Some(30));
+        assert_eq!(args.max_retries, 7);
+        assert_eq!(global.verbosity.verbose, 2);
+        assert_eq!(global.region.unwrap().as_ref(), "eu-west-1");
+        // What the file doesn't give keeps its default
+        assert_eq!(args.tail, 0);
+        // It was looked for, and found
+        assert_eq!(args.config.as_deref(), Some(file.path()));
+    }
+
+    #[test]
+    fn test_command_line_over_file() {
+        let file = config(FILE);
+        let (_, args) = parse(
+            &file,
+            &["-g", "from-cli", "--grep", "WARN", "--retention-days", "7"],
+        )
+        .unwrap();
+        // Lists are replaced, not added to
+        assert_eq!(args.group, ["from-cli"]);
+        assert_eq!(args.grep, ["WARN"]);
+        assert_eq!(args.retention_days, Some(7));
+        assert_eq!(args.max_retries, 7);
+
+        // Global options count before the command's name as well as after it
+        let path = file.path().to_str().unwrap();
+        let args = ["rusty-axe", "--region", "us-east-2", "push"].map(OsString::from);
+        let (global, args) = parse_push(with_config(args.to_vec(), &[path]).unwrap()).unwrap();
+        assert_eq!(global.region.unwrap().as_ref(), "us-east-2");
+        assert_eq!(args.group, ["from-file"]);
+    }
+
+    #[test]
+    fn test_profile() {
+        let file = config(FILE);
+        let (_, args) = parse(&file, &["--config-profile", "crash-dump"]).unwrap();
+        assert_eq!(args.group, ["crash-dumps", "security"]);
+        assert_eq!(args.retention_days, Some(90));
+        assert_eq!(args.tail, 500);
+        // The rest of the file still counts
+        assert_eq!(args.grep, ["ERROR", "FATAL"]);
+        // And the command line wins over the profile
+        let (_, args) = parse(&file, &["--config-profile", "crash-dump", "--tail", "5"]).unwrap();
+        assert_eq!(args.tail, 5);
+
+        let err = parse(&file, &["--config-profile", "nightly"]).unwrap_err();
+        assert!(err.ends_with("there's no [profile.nightly]"), "{}", err);
+    }
+
+    #[test]
+    fn test_explicit_file() {
+        let file = config("group = \"named\"");
+        let path = file.path().to_str().unwrap();
+        let args = ["rusty-axe", "push", "--config", path].map(OsString::from);
+        // Found without looking
+        let args = with_config(args.to_vec(), &[]).unwrap();
+        assert!(matches!(parse_push(args), Ok((_, args)) if args.group == ["named"]));
+        let args = ["rusty-axe", "push", "--config", "/nonexistent.toml"].map(OsString::from);
+        assert!(with_config(args.to_vec(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_no_file() {
+        let args = ["rusty-axe", "push", "-g", "g"]
+            .map(OsString::from)
+            .to_vec();
+        assert_eq!(
+            with_config(args.clone(), &["/nonexistent.toml"]).unwrap(),
+            args
+        );
+        let args = ["rusty-axe", "push", "--config-profile", "p"].map(OsString::from);
+        assert!(with_config(args.to_vec(), &["/nonexistent.toml"]).is_err());
+        // Other commands don't take options from the file
+        let args = ["rusty-axe", "groups"].map(OsString::from).to_vec();
+        let file = config("bogus = 1");
+        let search = [file.path().to_str().unwrap()];
+        assert_eq!(with_config(args.clone(), &search).unwrap(), args);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let unknown = |toml| parse(&config(toml), &[]).unwrap_err();
+        let err = unknown("group = \"g\"\nstream-templat = \"x\"");
+        assert!(
+            err.contains("there's no option \"stream-templat\""),
+            "{}",
+            err
+        );
+        // Even in a profile that isn't used
+        let err = unknown("group = \"g\"\n[profile.p]\nretention = 30");
+        assert!(err.contains("\"retention\" in [profile.p]"), "{}", err);
+        let err = unknown("config = \"other.toml\"");
+        assert!(err.contains("there's no option \"config\""), "{}", err);
+    }
+
+    #[test]
+    fn test_bad_values() {
+        let bad = |toml| parse(&config(toml), &[]).unwrap_err();
+        assert!(bad("group = \"g\"\ncreate-group = \"yes\"").contains("is a flag"));
+        assert!(bad("group = { name = \"g\" }").contains("has to be a string"));
+        assert!(bad("group = \"g\"\nretention-days = 31").contains("retention"));
+        let file = config("group = \"g");
+        let err = parse(&file, &[]).unwrap_err();
+        assert!(err.starts_with(file.path().to_str().unwrap()), "{}", err);
+    }
+}
@@ -0,0 +1,419 @@
+//! The credentials to send with: this instance's own, a profile's, or those of a
+//! role assumed with them (as for log groups in another account), refreshed as they
+//! expire
+
+use crate::endpoints::Resolver;
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::meta::credentials::LazyCachingCredentialsProvider;
+use aws_sdk_sts::Client as STS_Client;
+use aws_types::credentials::{
+    self, future, CredentialsError, ProvideCredentials, SharedCredentialsProvider,
+};
+use aws_types::region::Region;
+use aws_types::Credentials;
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the credentials to send with come from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Found the usual way: the environment, the AWS config files, then the
+    /// instance metadata (unless not `imds`)
+    Default { imds: bool },
+    /// A profile in the AWS config files
+    Profile(String),
+    /// A role, assumed in `region` with the credentials of a profile, or else those
+    /// found the usual way
+    Role {
+        role: AssumeRole,
+        region: Region,
+        endpoint: Option<Resolver>,
+        profile: Option<String>,
+        imds: bool,
+    },
+}
+
+impl Source {
+    /// A new provider of the credentials, which keeps them until shortly before
+    /// they expire
+    async fn provider(&self) -> SharedCredentialsProvider {
+        match self {
+            Source::Default { imds } => found(*imds).await,
+            Source::Profile(name) => crate::profile::credentials(name),
+            Source::Role {
+                role,
+                region,
+                endpoint,
+                profile,
+                imds,
+            } => {
+                let own = match profile {
+                    Some(profile) => crate::profile::credentials(profile),
+                    None => found(*imds).await,
+                };
+                role.provider(own, region.clone(), endpoint.clone())
+            }
+        }
+    }
+}
+
+/// The credentials found the usual way, the instance metadata only being asked for
+/// them if `imds`
+async fn found(imds: bool) -> SharedCredentialsProvider {
+    let chain = DefaultCredentialsChain::builder()
+        .configure(crate::metadata::provider_config(imds))
+        .build()
+        .await;
+    SharedCredentialsProvider::new(chain)
+}
+
+/// Credentials that can be refreshed before they're due to expire, for when they're
+/// turned down as expired all the same, by making their provider afresh
+///
+/// Clones share the one provider, so refreshing one refreshes them all.
+#[derive(Debug, Clone)]
+pub struct Refreshing {
+    source: Source,
+    current: Arc<Mutex<Current>>,
+}
+
+/// The provider credentials come from for now, and how many times it's been made
+/// afresh
+#[derive(Debug)]
+struct Current {
+    generation: usize,
+    provider: SharedCredentialsProvider,
+}
+
+impl Refreshing {
+    pub async fn new(source: Source) -> Refreshing {
+        let provider = source.provider().await;
+        Refreshing {
+            source,
+            current: Arc::new(Mutex::new(Current {
+                generation: 0,
+                provider,
+            })),
+        }
+    }
+
+    /// How many times the credentials have been refreshed
+    pub fn generation(&self) -> usize {
+        self.current.lock().unwrap().generation
+    }
+
+    /// Make the provider afresh, so the credentials are got again next time they're
+    /// asked for, unless that's been done since `generation` (as when many calls are
+    /// turned down at once, and the first to be refreshes them for all)
+    pub async fn refresh(&self, generation: usize) {
+        let provider = self.source.provider().await;
+        let mut current = self.current.lock().unwrap();
+        if current.generation == generation {
+            *current = Current {
+                generation: generation + 1,
+                provider,
+            };
+        }
+    }
+}
+
+impl ProvideCredentials for Refreshing {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        let provider = self.current.lock().unwrap().provider.clone();
+        future::ProvideCredentials::new(async move { provider.provide_credentials().await })
+    }
+}
+
+/// The credentials from `source`, checked first if they're a role's, so a role that
+/// can't be assumed is known about before anything's read or sent
+pub async fn load(source: Source) -> Result<Refreshing, String> {
+    let credentials = Refreshing::new(source).await;
+    if let Source::Role { role, .. } = &credentials.source {
+        check(&credentials, &role.role_arn).await?;
+    }
+    Ok(credentials)
+}
+
+/// A role to assume, and how
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssumeRole {
+    pub role_arn: String,
+    /// The external id the role's trust policy asks for, if it asks for one
+    pub external_id: Option<String>,
+    /// What to call the session, which shows up in CloudTrail (by default
+    /// `rusty-axe-` and when it started)
+    pub session_name: Option<String>,
+}
+
+impl AssumeRole {
+    /// A provider of the role's credentials, got with `own` (a profile's, or else
+    /// this instance's own)
+    ///
+    /// The credentials are kept until shortly before they expire, then the role is
+    /// assumed again, so an upload that outlasts a session carries on.  STS is
+    /// called where `endpoint` says, if given, rather than at its usual endpoint.
+    pub fn provider(
+        &self,
+        own: SharedCredentialsProvider,
+        region: Region,
+        endpoint: Option<Resolver>,
+    ) -> SharedCredentialsProvider {
+        let mut config = aws_sdk_sts::Config::builder()
+            .credentials_provider(own)
+            .region(region);
+        if let Some(endpoint) = endpoint {
+            config = config.endpoint_resolver(endpoint);
+        }
+        let loader = Loader {
+            sts: STS_Client::from_conf(config.build()),
+            role: self.clone(),
+            session_name: self
+                .session_name
+                .clone()
+                .unwrap_or_else(default_session_name),
+        };
+        let cache = LazyCachingCredentialsProvider::builder()
+            .load(loader)
+            .build();
+        SharedCredentialsProvider::new(cache)
+    }
+}
+
+/// Assumes the role each time it's asked for credentials (which the cache in front
+/// of it only does when the last ones are about to expire)
+#[derive(Debug)]
+struct Loader {
+    sts: STS_Client,
+    role: AssumeRole,
+    session_name: String,
+}
+
+impl Loader {
+    async fn credentials(&self) -> credentials::Result {
+        let resp = self
+            .sts
+            .assume_role()
+            .role_arn(&self.role.role_arn)
+            .set_external_id(self.role.external_id.clone())
+            .role_session_name(&self.session_name)
+            .send()
+            .await
+            .map_err(CredentialsError::provider_error)?;
+        let assumed = resp
+            .credentials
+            .ok_or_else(|| CredentialsError::unhandled("STS didn't return any credentials"))?;
+        let expiration = assumed
+            .expiration
+            .and_then(|time| SystemTime::try_from(time).ok());
+        Ok(Credentials::new(
+            assumed.access_key_id.unwrap_or_default(),
+            assumed.secret_access_key.unwrap_or_default(),
+            assumed.session_token,
+            expiration,
+            "AssumeRole",
+        ))
+    }
+}
+
+impl ProvideCredentials for Loader {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.credentials())
+    }
+}
+
+/// Check that `provider` can come up with credentials for the role, saying why not
+/// if it can't
+async fn check(provider: &impl ProvideCredentials, role_arn: &str) -> Result<(), String> {
+    let e = match provider.provide_credentials().await {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    // The reason is usually a few errors down
+    let mut reason = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        reason = format!("{}: {}", reason, e);
+        source = e.source();
+    }
+    let mut message = format!("Couldn't assume role {}: {}", role_arn, reason);
+    if reason.contains("AccessDenied") || reason.contains("not authorized") {
+        message.push_str(
+            " (the role's trust policy has to let this instance's role assume it, with the \
+             --external-id it asks for if it asks for one, and this instance's role has to be \
+             allowed sts:AssumeRole on it)",
+        );
+    }
+    Err(message)
+}
+
+/// `rusty-axe-` and the time in seconds, to tell one run's session from another's
+fn default_session_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("rusty-axe-{}", now.as_secs())
+}
+
+/// Parse a --role-arn, like `arn:aws:iam::123456789012:role/central-logs-writer`
+pub fn parse_role_arn(s: &str) -> Result<String, String> {
+    let parts: Vec<_> = s.splitn(6, ':').collect();
+    let valid = match parts[..] {
+        ["arn", partition, "iam", "", account, resource] => {
+            partition.starts_with("aws")
+                && account.len() == 12
+                && account.bytes().all(|b| b.is_ascii_digit())
+                && resource
+                    .strip_prefix("role/")
+                    .is_some_and(|name| !name.is_empty())
+        }
+        _ => false,
+    };
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "{:?} isn't the ARN of an IAM role, like arn:aws:iam::123456789012:role/name",
+            s
+        ))
+    }
+}
+
+/// Parse a --session-name, which STS wants to be 2 to 64 letters, digits or any of
+/// `_+=,.@-`
+pub fn parse_session_name(s: &str) -> Result<String, String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "_+=,.@-".contains(c);
+    if (2..=64).contains(&s.len()) && s.chars().all(allowed) {
+        Ok(s.to_string())
+    } else {
+        Err(String::from(
+            "a session name has to be 2 to 64 letters, digits or any of _+=,.@-",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails to provide credentials, the way STS turning the role down does
+    #[derive(Debug)]
+    struct Refused;
+
+    impl ProvideCredentials for Refused {
+        fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+        where
+            Self: 'a,
+        {
+            let cause = "AccessDenied: User: arn:aws:sts::111111111111:assumed-role/web/i-0123 \
+                         is not authorized to perform: sts:AssumeRole";
+            future::ProvideCredentials::ready(Err(CredentialsError::provider_error(cause)))
+        }
+    }
+
+    #[test]
+    fn test_parse_role_arn() {
+        let arn = "arn:aws:iam::123456789012:role/central-logs-writer";
+        assert_eq!(parse_role_arn(arn).unwrap(), arn);
+        assert!(parse_role_arn("arn:aws-us-gov:iam::123456789012:role/a/b").is_ok());
+        for bad in [
+            "central-logs-writer",
+            "arn:aws:iam::1234:role/writer",
+            "arn:aws:iam::123456789012:user/writer",
+            "arn:aws:iam::123456789012:role/",
+            "arn:aws:s3:::bucket",
+        ] {
+            assert!(parse_role_arn(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_session_name() {
+        assert!(parse_session_name("web-1@prod").is_ok());
+        assert!(parse_session_name("a").is_err());
+        assert!(parse_session_name("no spaces").is_err());
+        assert!(parse_session_name(&"x".repeat(65)).is_err());
+        assert!(parse_session_name(&default_session_name()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let arn = "arn:aws:iam::123456789012:role/central-logs-writer";
+        let own = Credentials::new("AKID", "secret", None, None, "test");
+        assert!(check(&own, arn).await.is_ok());
+
+        let err = check(&Refused, arn).await.unwrap_err();
+        assert!(
+            err.starts_with("Couldn't assume role arn:aws:iam::"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("not authorized to perform: sts:AssumeRole"),
+            "{}",
+            err
+        );
+        assert!(err.contains("trust policy"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_provider() {
+        // The role's provider asks STS with the credentials it's given, so what it
+        // gets refused with is the reason it couldn't assume the role
+        let role = AssumeRole {
+            role_arn: String::from("arn:aws:iam::123456789012:role/central-logs-writer"),
+            external_id: Some(String::from("logs")),
+            session_name: Some(String::from("test")),
+        };
+        let provider = role.provider(
+            SharedCredentialsProvider::new(Refused),
+            Region::new("us-east-1"),
+            None,
+        );
+        let err = check(&provider, &role.role_arn).await.unwrap_err();
+        assert!(err.contains("not authorized"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let credentials = Refreshing::new(Source::Default { imds: true }).await;
+        let copy = credentials.clone();
+        assert_eq!(credentials.generation(), 0);
+        credentials.refresh(0).await;
+        assert_eq!(copy.generation(), 1);
+        // Refreshed already by someone else, since it was seen at generation 0
+        copy.refresh(0).await;
+        assert_eq!(credentials.generation(), 1);
+    }
+
+    /// Assumes the role in `RUSTY_AXE_TEST_ROLE_ARN` with this machine's credentials
+    #[cfg(feature = "integration")]
+    #[tokio::test]
+    async fn test_assume_role() {
+        let role_arn = match std::env::var("RUSTY_AXE_TEST_ROLE_ARN") {
+            Ok(role_arn) => role_arn,
+            Err(_) => {
+                return eprintln!("RUSTY_AXE_TEST_ROLE_ARN isn't set, so not assuming a role")
+            }
+        };
+        let role = AssumeRole {
+            role_arn,
+            external_id: std::env::var("RUSTY_AXE_TEST_EXTERNAL_ID").ok(),
+            session_name: None,
+        };
+        let source = Source::Role {
+            role,
+            region: crate::region::resolve(None, None, true).await,
+            endpoint: Resolver::new("sts", None, Default::default()),
+            profile: None,
+            imds: true,
+        };
+        load(source).await.unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+//! Writing events to a local file instead of sending them anywhere, to see what a run
+//! would send
+//!
+//! The file has a JSON object per line, `{"batch":..,"message":..,"timestamp":..}`,
+//! each event in the order it would be sent and numbered by the PutLogEvents batch it
+//! would go in (counting on from one stream's batches to the next).
+
+use crate::batch;
+use crate::uploader::{StreamEvents, UploadSummary};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use tracing::error;
+
+/// Write each set of events to the file at `path`, replacing whatever was there
+///
+/// The summary and what's returned are as for [`send_logs`](crate::uploader::send_logs), each set
+/// counted as a stream; if the file can't be written, they all fail.
+pub fn send_streams(
+    path: &Path,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Vec<Option<String>> {
+    let labels: Vec<_> = streams
+        .iter()
+        .map(|stream| match &stream.file {
+            Some(file) => (
+                format!("{} in {}", file, path.display()),
+                stream.events.len(),
+            ),
+            None => (path.display().to_string(), stream.events.len()),
+        })
+        .collect();
+    let files: Vec<_> = streams.iter().map(|stream| stream.file.clone()).collect();
+    let bytes: usize = streams
+        .iter()
+        .map(|s| crate::events::message_bytes(&s.events))
+        .sum();
+    let written = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out, streams.into_iter().map(|stream| stream.events))?;
+        out.flush()
+    });
+    match written {
+        Ok(()) => {
+            summary.streams_sent.extend(labels);
+            summary.bytes_sent += bytes;
+            Vec::new()
+        }
+        Err(e) => {
+            let reason = e.to_string();
+            error!("Couldn't write events to {}: {}", path.display(), reason);
+            for (label, _) in labels {
+                summary.streams_failed.push((label, reason.clone()));
+            }
+            files
+        }
+    }
+}
+
+/// Write every set of events, a line each, split into batches as they'd be sent
+pub fn write(
+    out: &mut impl Write,
+    streams: impl IntoIterator<Item = Vec<InputLogEvent>>,
+) -> io::Result<()> {
+    let batches = streams.into_iter().flat_map(batch::make_batches);
+    for (index, batch) in batches.enumerate() {
+        for event in batch {
+            writeln!(out, "{}", line(index, &event))?;
+        }
+    }
+    Ok(())
+}
+
+/// An event as a line of the file
+fn line(batch: usize, event: &InputLogEvent) -> serde_json::Value {
+    json!({
+        "timestamp": event.timestamp(),
+        "message": event.message(),
+        "batch": batch,
+    })
+}
+
+/// Read the events back from a file written by [`write`], each with its batch (only
+/// the tests do, so far)
+#[cfg(test)]
+pub fn read(from: impl io::BufRead) -> io::Result<Vec<(usize, InputLogEvent)>> {
+    let invalid = |line: usize, what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {} {}", line, what),
+        )
+    };
+    let mut events = Vec::new();
+    for (number, line) in from.lines().enumerate() {
+        let line = line?;
+        let value: serde_json::Value =
+            serde_json::from_str(&line).map_err(|e| invalid(number + 1, &e.to_string()))?;
+        let batch = value["batch"]
+            .as_u64()
+            .ok_or_else(|| invalid(number + 1, "has no batch"))?;
+        let event = InputLogEvent::builder()
+            .set_timestamp(value["timestamp"].as_i64())
+            .set_message(value["message"].as_str().map(String::from))
+            .build();
+        events.push((batch as usize, event));
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn events(messages: &[&str]) -> Vec<InputLogEvent> {
+        messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                InputLogEvent::builder()
+                    .timestamp(1_700_000_000_000 + i as i64)
+                    .message(*message)
+                    .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let first = events(&["started", "with \"quotes\" and \u{1F980}", ""]);
+        let second = events(&["stopped"]);
+        let mut out = Vec::new();
+        write(&mut out, vec![first.clone(), second.clone()]).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap().lines().next(),
+            Some(r#"{"batch":0,"message":"started","timestamp":1700000000000}"#)
+        );
+
+        let read = read(&out[..]).unwrap();
+        let (batches, read): (Vec<_>, Vec<_>) = read.into_iter().unzip();
+        // Each stream's events start a batch of their own
+        assert_eq!(batches, [0, 0, 0, 1]);
+        assert_eq!(read, [first, second].concat());
+    }
+
+    #[test]
+    fn test_batches() {
+        let big = "x".repeat(batch::MAX_BATCH_SIZE / 2);
+        let mut out = Vec::new();
+        write(&mut out, vec![events(&[&big, &big, "after"])]).unwrap();
+        let batches: Vec<_> = read(&out[..])
+            .unwrap()
+            .into_iter()
+            .map(|(b, _)| b)
+            .collect();
+        assert_eq!(batches, [0, 1, 1]);
+    }
+
+    #[test]
+    fn test_send_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        fs::write(&path, "left over\n").unwrap();
+        let streams = vec![
+            StreamEvents {
+                file: Some(String::from("a.log")),
+                events: events(&["a"]),
+            },
+            StreamEvents {
+                file: Some(String::from("b.log")),
+                events: events(&["b", "c"]),
+            },
+        ];
+        let mut summary = UploadSummary::default();
+        assert!(send_streams(&path, streams, &mut summary).is_empty());
+        assert_eq!(summary.streams_sent.len(), 2);
+        assert_eq!(summary.streams_sent[1].1, 2);
+        let read = read(io::BufReader::new(File::open(&path).unwrap())).unwrap();
+        let messages: Vec<_> = read.iter().map(|(_, e)| e.message().unwrap()).collect();
+        assert_eq!(messages, ["a", "b", "c"]);
+
+        let streams = vec![StreamEvents::merged(events(&["a"]))];
+        let missing = dir.path().join("missing").join("events.jsonl");
+        assert_eq!(send_streams(&missing, streams, &mut summary), [None]);
+        assert_eq!(summary.streams_failed.len(), 1);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(read(&b"not json\n"[..]).is_err());
+        assert!(read(&br#"{"message":"no batch"}"#[..]).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Where calls to AWS go, when it isn't a service's usual endpoint: one given as a
+//! URL, or a FIPS or dual-stack (IPv4 and IPv6) one in the client's region
+
+use aws_sdk_cloudwatchlogs::{Endpoint, Region};
+use aws_types::endpoint::{AwsEndpoint, BoxError, ResolveAwsEndpoint};
+use tracing::warn;
+
+/// Which of a service's endpoints to call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Variant {
+    /// One validated under FIPS 140-2
+    pub fips: bool,
+    /// One that can be reached over IPv6 as well as IPv4
+    pub dualstack: bool,
+}
+
+impl Variant {
+    /// The variant asked for, by flag or else by `AWS_USE_FIPS_ENDPOINT` and
+    /// `AWS_USE_DUALSTACK_ENDPOINT` as the AWS CLI has them
+    pub fn with_env(self) -> Variant {
+        let set = |var| std::env::var(var).is_ok_and(|value| value.eq_ignore_ascii_case("true"));
+        Variant {
+            fips: self.fips || set("AWS_USE_FIPS_ENDPOINT"),
+            dualstack: self.dualstack || set("AWS_USE_DUALSTACK_ENDPOINT"),
+        }
+    }
+
+    /// What to call it, as said to auditors
+    pub fn describe(&self) -> &'static str {
+        match (self.fips, self.dualstack) {
+            (false, false) => "standard",
+            (true, false) => "FIPS",
+            (false, true) => "dual-stack",
+            (true, true) => "FIPS dual-stack",
+        }
+    }
+}
+
+/// Where a client's calls go
+#[derive(Debug, Clone)]
+pub enum Resolver {
+    /// An endpoint given as a URL, whatever the region
+    Url(Endpoint),
+    /// The variant of the service's endpoint (like `logs`) in the client's region
+    Variant(&'static str, Variant),
+}
+
+impl Resolver {
+    /// Where `service` (like `logs`) is to be called, if not at its usual endpoint:
+    /// at `url` if given, or else where the environment says, or else at its
+    /// `variant` endpoint if that isn't the standard one
+    pub fn new(
+        service: &'static str,
+        url: Option<&Endpoint>,
+        variant: Variant,
+    ) -> Option<Resolver> {
+        if let Some(url) = url.cloned().or_else(|| env_endpoint(service)) {
+            return Some(Resolver::Url(url));
+        }
+        let variant = variant.with_env();
+        (variant != Variant::default()).then_some(Resolver::Variant(service, variant))
+    }
+
+    /// The URL calls go to in `region`
+    pub fn url(&self, region: &Region) -> Result<String, BoxError> {
+        let resolved = self.resolve_endpoint(region)?;
+        let mut uri = "/".parse()?;
+        resolved.endpoint().set_endpoint(&mut uri, None);
+        Ok(uri.to_string().trim_end_matches('/').to_string())
+    }
+
+    /// What kind of endpoint it is, as said to auditors
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Resolver::Url(_) => "given",
+            Resolver::Variant(_, variant) => variant.describe(),
+        }
+    }
+}
+
+impl ResolveAwsEndpoint for Resolver {
+    fn resolve_endpoint(&self, region: &Region) -> Result<AwsEndpoint, BoxError> {
+        let endpoint = match self {
+            Resolver::Url(endpoint) => endpoint.clone(),
+            Resolver::Variant(service, variant) => {
+                let url = variant_url(service, region.as_ref(), *variant)?;
+                Endpoint::immutable(url.parse()?)
+            }
+        };
+        Ok(AwsEndpoint::new(endpoint, Default::default()))
+    }
+}
+
+/// The URL of the `variant` of `service`'s endpoint in `region`
+///
+/// GovCloud's standard endpoints are already FIPS ones, and China's regions have
+/// none.
+pub fn variant_url(service: &str, region: &str, variant: Variant) -> Result<String, String> {
+    let china = region.starts_with("cn-");
+    let govcloud = region.starts_with("us-gov-");
+    if china && variant.fips {
+        return Err(format!("{} has no FIPS endpoints", region));
+    }
+    // GovCloud's dual-stack endpoints still have FIPS ones of their own
+    let host = match variant.fips && (variant.dualstack || !govcloud) {
+        true => format!("{}-fips", service),
+        false => service.to_string(),
+    };
+    let domain = match (china, variant.dualstack) {
+        (false, false) => "amazonaws.com",
+        (false, true) => "api.aws",
+        (true, false) => "amazonaws.com.cn",
+        (true, true) => "api.amazonwebservices.com.cn",
+    };
+    Ok(format!("https://{}.{}.{}", host, region, domain))
+}
+
+/// Parse an --endpoint-url, which has to be a whole http or https URL
+pub fn parse_endpoint_url(s: &str) -> Result<Endpoint, String> {
+    if !s.starts_with("https://") && !s.starts_with("http://") {
+        return Err(format!("{:?} isn't an http:// or https:// URL", s));
+    }
+    match s.parse() {
+        Ok(uri) => Ok(Endpoint::immutable(uri)),
+        Err(e) => Err(format!("{:?} isn't a URL: {}", s, e)),
+    }
+}
+
+/// The endpoint the environment says to call `service` (like `logs`) at, from
+/// `AWS_ENDPOINT_URL_<SERVICE>` or else `AWS_ENDPOINT_URL`, as the AWS CLI has it
+///
+/// One that isn't a URL is warned about and left out.
+pub fn env_endpoint(service: &str) -> Option<Endpoint> {
+    let service_var = format!("AWS_ENDPOINT_URL_{}", service.to_uppercase());
+    let var = [service_var, String::from("AWS_ENDPOINT_URL")]
+        .into_iter()
+        .find(|var| std::env::var_os(var).is_some())?;
+    let url = std::env::var(&var).unwrap_or_default();
+    match parse_endpoint_url(&url) {
+        Ok(endpoint) => Some(endpoint),
+        Err(e) => {
+            warn!("Ignoring {}: {}", var, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where `resolver` sends calls in `region`
+    fn resolve(resolver: &Resolver, region: &'static str) -> String {
+        resolver.url(&Region::new(region)).unwrap()
+    }
+
+    #[test]
+    fn test_variant_url() {
+        let url = |region, fips, dualstack| {
+            variant_url("logs", region, Variant { fips, dualstack }).unwrap()
+        };
+        assert_eq!(
+            url("us-east-1", false, false),
+            "https://logs.us-east-1.amazonaws.com"
+        );
+        assert_eq!(
+            url("us-east-1", true, false),
+            "https://logs-fips.us-east-1.amazonaws.com"
+        );
+        assert_eq!(
+            url("eu-west-1", false, true),
+            "https://logs.eu-west-1.api.aws"
+        );
+        assert_eq!(
+            url("us-west-2", true, true),
+            "https://logs-fips.us-west-2.api.aws"
+        );
+        assert_eq!(
+            url("us-gov-west-1", true, false),
+            "https://logs.us-gov-west-1.amazonaws.com"
+        );
+        assert_eq!(
+            url("us-gov-east-1", true, true),
+            "https://logs-fips.us-gov-east-1.api.aws"
+        );
+        assert_eq!(
+            url("cn-north-1", false, true),
+            "https://logs.cn-north-1.api.amazonwebservices.com.cn"
+        );
+        let fips = Variant {
+            fips: true,
+            dualstack: false,
+        };
+        assert!(variant_url("logs", "cn-north-1", fips).is_err());
+    }
+
+    #[test]
+    fn test_resolver() {
+        let fips = Variant {
+            fips: true,
+            dualstack: false,
+        };
+        let resolver = Resolver::new("rustyaxetest", None, fips).unwrap();
+        assert_eq!(
+            resolve(&resolver, "us-east-2"),
+            "https://rustyaxetest-fips.us-east-2.amazonaws.com"
+        );
+
+        // A URL that's given wins out
+        let url = parse_endpoint_url("http://localhost:4566").unwrap();
+        let resolver = Resolver::new("rustyaxetest", Some(&url), fips).unwrap();
+        assert_eq!(resolve(&resolver, "us-east-2"), "http://localhost:4566");
+
+        assert!(Resolver::new("rustyaxetest", None, Variant::default()).is_none());
+    }
+
+    #[test]
+    fn test_env_endpoint() {
+        assert!(parse_endpoint_url("localhost:4566").is_err());
+        assert!(parse_endpoint_url("https://bad host").is_err());
+        std::env::set_var("AWS_ENDPOINT_URL_RUSTYAXEENV", "http://localhost:4566");
+        assert!(env_endpoint("rustyaxeenv").is_some());
+        std::env::set_var("AWS_ENDPOINT_URL_RUSTYAXEENV", "localhost");
+        assert!(env_endpoint("rustyaxeenv").is_none());
+    }
+}
@@ -0,0 +1,296 @@
+//! Options given as RUSTY_AXE_ variables, as systemd's Environment= lines and
+//! containers set them
+//!
+//! Every option has a variable named after it: --retention-days is
+//! RUSTY_AXE_RETENTION_DAYS, for one.  clap reads most of them itself, which is the
+//! `[env: ...]` --help shows.  An option that can be given more than once takes a
+//! comma-separated list in its variable, and -v the number of times it'd be given,
+//! neither of which clap makes anything of, so those are put on the command line here.
+//! A comma a value has in it, like the one in a pattern such as `\d{1,3}`, is given
+//! as `\,`.
+//! The command line wins over a variable, and a variable wins over the config file.
+
+use crate::cli::Cli;
+
+use clap::{ArgAction, CommandFactory, ValueSource};
+use std::ffi::{OsStr, OsString};
+
+/// The command line, with the lists and counts its command's variables give added
+/// to it
+///
+/// `vars` looks a variable up.  An option the command line gives leaves its variable
+/// out.
+pub fn with_lists(
+    mut args: Vec<OsString>,
+    vars: impl Fn(&OsStr) -> Option<OsString>,
+) -> Result<Vec<OsString>, String> {
+    let (position, name, matches) = match crate::cli::given_command(&args) {
+        Some(given) => given,
+        None => return Ok(args),
+    };
+    let command = Cli::command();
+    let subcommand = command.find_subcommand(&name).expect("just given");
+    let submatches = matches.subcommand_matches(&name).expect("just given");
+    let globals = command.get_arguments().map(|arg| (arg, &matches));
+    let own = subcommand.get_arguments().map(|arg| (arg, submatches));
+
+    let mut added = Vec::new();
+    for (arg, matches) in globals.chain(own) {
+        let (name, long) = match (arg.get_env(), arg.get_long()) {
+            (Some(name), Some(long)) => (name, long),
+            _ => continue,
+        };
+        let counted = matches!(arg.get_action(), ArgAction::Count);
+        let listed =
+            arg.is_multiple_occurrences_set() || matches!(arg.get_action(), ArgAction::Append);
+        if !(counted || listed)
+            || matches.value_source(arg.get_id()) == Some(ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let value = match vars(name) {
+            Some(value) => value,
+            None => continue,
+        };
+        let value = value
+            .to_str()
+            .ok_or_else(|| format!("{} isn't UTF-8: {:?}", name.to_string_lossy(), value))?;
+
+        if counted {
+            let count: usize = value.trim().parse().map_err(|_| {
+                format!(
+                    "{} has to be the number of times --{} would be given, not {:?}",
+                    name.to_string_lossy(),
+                    long,
+                    value
+                )
+            })?;
+            added.extend(std::iter::repeat_n(
+                OsString::from(format!("--{}", long)),
+                count,
+            ));
+        } else {
+            let values = split_list(value).into_iter();
+            let values = values.filter(|value| !value.is_empty());
+            added.extend(values.map(|value| OsString::from(format!("--{}={}", long, value))));
+        }
+    }
+
+    args.splice(position + 1..position + 1, added);
+    Ok(args)
+}
+
+/// The values of a comma-separated list, where `\,` is a comma in a value
+///
+/// Any other backslash is kept as it is, so a pattern's `\d` or `\.` needs no more
+/// escaping than it would on the command line.  `\\` is kept as well, and a comma
+/// after it still ends the value.
+fn split_list(list: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut chars = list.chars();
+    while let Some(c) = chars.next() {
+        let value = values.last_mut().expect("never empty");
+        match c {
+            ',' => values.push(String::new()),
+            '\\' => match chars.next() {
+                Some(',') => value.push(','),
+                Some(next) => {
+                    value.push('\\');
+                    value.push(next);
+                }
+                None => value.push('\\'),
+            },
+            c => value.push(c),
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::tests::parse_push;
+    use crate::cli::{Args, Global};
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    /// Parse `push`'s arguments, with the lists `vars` give
+    fn parse(args: &[&str], vars: &[(&str, &str)]) -> Result<(Global, Box<Args>), String> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        parse_with(args, |name| {
+            vars.get(name.to_str().unwrap()).map(OsString::from)
+        })
+    }
+
+    fn parse_with(
+        args: &[&str],
+        vars: impl Fn(&OsStr) -> Option<OsString>,
+    ) -> Result<(Global, Box<Args>), String> {
+        let args = ["rusty-axe", "push"].iter().chain(args).map(OsString::from);
+        parse_push(with_lists(args.collect(), vars)?)
+    }
+
+    /// The variable that gives the option with this long name
+    fn var(long: &str) -> String {
+        format!("RUSTY_AXE_{}", long.replace('-', "_").to_uppercase())
+    }
+
+    /// Run the test named `name` again, in a process of its own with `vars` set so no
+    /// other test sees them, and say whether this is that process
+    fn in_own_process(name: &str, vars: &[(&str, &str)]) -> bool {
+        const OWN_PROCESS: &str = "ENVIRONMENT_TEST_OWN_PROCESS";
+        if std::env::var_os(OWN_PROCESS).is_some() {
+            return true;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([name, "--exact", "--nocapture"])
+            .env(OWN_PROCESS, "1")
+            .envs(vars.iter().copied())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}{}", stdout, stderr);
+        assert!(
+            stdout.contains("1 passed"),
+            "{} wasn't run: {}",
+            name,
+            stdout
+        );
+        false
+    }
+
+    #[test]
+    fn test_every_option_has_a_variable() {
+        let command = Cli::command();
+        for subcommand in command.get_subcommands() {
+            for arg in command.get_arguments().chain(subcommand.get_arguments()) {
+                if ["help", "version"].contains(&arg.get_id()) || arg.is_positional() {
+                    continue;
+                }
+                let long = arg.get_long().unwrap();
+                assert_eq!(
+                    arg.get_env().and_then(OsStr::to_str),
+                    Some(var(long).as_str()),
+                    "--{} of {}",
+                    long,
+                    subcommand.get_name()
+                );
+            }
+        }
+        assert_eq!(var("retention-days"), "RUSTY_AXE_RETENTION_DAYS");
+    }
+
+    #[test]
+    fn test_lists() {
+        let vars = [
+            ("RUSTY_AXE_FILENAME", "/var/log/a.log,/var/log/b.log"),
+            ("RUSTY_AXE_GREP", "ERROR,,FATAL"),
+            ("RUSTY_AXE_GROUP", "from-env"),
+            ("RUSTY_AXE_MASK", "password,token"),
+            ("RUSTY_AXE_TAG", "team=platform,env=prod"),
+        ];
+        let (_, args) = parse(&["-g", "from-cli"], &vars).unwrap();
+        assert_eq!(args.filename, ["/var/log/a.log", "/var/log/b.log"]);
+        assert_eq!(args.grep, ["ERROR", "FATAL"]);
+        assert_eq!(args.mask, ["password", "token"]);
+        assert_eq!(args.tag.len(), 2);
+        // The command line's list is used in place of the variable's
+        assert_eq!(args.group, ["from-cli"]);
+        let (_, args) = parse(&["-g", "g", "--grep", "WARN"], &vars).unwrap();
+        assert_eq!(args.grep, ["WARN"]);
+
+        let (_, args) = parse(&[], &[("RUSTY_AXE_GROUP", "team,security")]).unwrap();
+        assert_eq!(args.group, ["team", "security"]);
+    }
+
+    #[test]
+    fn test_escaped_commas() {
+        assert_eq!(split_list(r"a\,b,c"), ["a,b", "c"]);
+        assert_eq!(split_list(r"\d+\.\d+,x"), [r"\d+\.\d+", "x"]);
+        assert_eq!(split_list(r"a\\,b\"), [r"a\\", r"b\"]);
+        assert_eq!(split_list(""), [""]);
+
+        let vars = [
+            ("RUSTY_AXE_GREP", r"^\d{1\,3}$,WARN"),
+            ("RUSTY_AXE_REDACT", r"(\w+)@example\.com=>$1@\,,secret"),
+        ];
+        let (_, args) = parse(&["-g", "g"], &vars).unwrap();
+        assert_eq!(args.grep, [r"^\d{1,3}$", "WARN"]);
+        assert_eq!(args.redact.len(), 2);
+    }
+
+    #[test]
+    fn test_counts() {
+        let (global, _) = parse(&["-g", "g"], &[("RUSTY_AXE_VERBOSE", "2")]).unwrap();
+        assert_eq!(global.verbosity.verbose, 2);
+        let (global, _) = parse(&["-g", "g", "-v"], &[("RUSTY_AXE_VERBOSE", "2")]).unwrap();
+        assert_eq!(global.verbosity.verbose, 1);
+        // Given before the command's name, as a global option can be
+        let args = ["rusty-axe", "-v", "push", "-g", "g"].map(OsString::from);
+        let vars = |name: &OsStr| (name == "RUSTY_AXE_VERBOSE").then(|| OsString::from("2"));
+        let (global, _) = parse_push(with_lists(args.to_vec(), vars).unwrap()).unwrap();
+        assert_eq!(global.verbosity.verbose, 1);
+        let err = parse(&["-g", "g"], &[("RUSTY_AXE_VERBOSE", "lots")]).unwrap_err();
+        assert!(err.contains("RUSTY_AXE_VERBOSE has to be"), "{}", err);
+    }
+
+    #[test]
+    fn test_other_commands() {
+        let vars = |name: &OsStr| (name == "RUSTY_AXE_GROUP").then(|| OsString::from("a,b"));
+        // A command without lists is left as it is
+        let args = ["rusty-axe", "streams", "--limit", "5"].map(OsString::from);
+        assert_eq!(with_lists(args.to_vec(), vars).unwrap(), args);
+        let args = ["rusty-axe", "--version"].map(OsString::from);
+        assert_eq!(with_lists(args.to_vec(), vars).unwrap(), args);
+    }
+
+    #[test]
+    fn test_variables() {
+        let vars = [
+            ("RUSTY_AXE_GROUP", "team,security"),
+            ("RUSTY_AXE_TAIL", "5"),
+            ("RUSTY_AXE_REGION", "eu-west-1"),
+            ("RUSTY_AXE_CREATE_GROUP", "true"),
+            ("RUSTY_AXE_NO_IMDS", "false"),
+            ("RUSTY_AXE_FORMAT", "jsonl"),
+            ("RUSTY_AXE_CONNECT_TIMEOUT", "7"),
+            ("RUSTY_AXE_MAX_RETRIES", "2"),
+        ];
+        if !in_own_process("environment::tests::test_variables", &vars) {
+            return;
+        }
+        let parse = |args: &[&str]| parse_with(args, |name| std::env::var_os(name)).unwrap();
+        let (global, args) = parse(&[]);
+        assert_eq!(args.group, ["team", "security"]);
+        assert_eq!(args.tail, 5);
+        assert_eq!(global.region.unwrap().as_ref(), "eu-west-1");
+        assert!(args.create_group);
+        assert!(!args.no_imds);
+        assert!(matches!(args.format, crate::events::Format::Jsonl));
+        assert_eq!(args.connect_timeout, std::time::Duration::from_secs(7));
+        // What no variable gives keeps its default
+        assert_eq!(args.head, 0);
+
+        // The command line wins over a variable
+        let (_, args) = parse(&["--tail", "3", "-g", "cli"]);
+        assert_eq!(args.tail, 3);
+        assert_eq!(args.group, ["cli"]);
+
+        // And a variable over the config file, which wins over the defaults
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "tail = 9\nmax-retries = 4\nretention-days = 30").unwrap();
+        let path = file.path().to_str().unwrap();
+        let args = ["rusty-axe", "push", "--config", path, "--max-retries", "1"];
+        let args = with_lists(args.map(OsString::from).to_vec(), |name| {
+            std::env::var_os(name)
+        })
+        .unwrap();
+        let args = crate::config::with_config(args, &[]).unwrap();
+        let (_, args) = parse_push(args).unwrap();
+        assert_eq!(args.max_retries, 1);
+        assert_eq!(args.tail, 5);
+        assert_eq!(args.retention_days, Some(30));
+        assert_eq!(args.group, ["team", "security"]);
+    }
+}
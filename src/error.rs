@@ -0,0 +1,139 @@
+//! What can go wrong reading input and sending it, as one error to match on, along
+//! with the status the program exits with for each
+
+use crate::exit::Failure;
+use crate::retry;
+
+use std::io;
+use std::time::SystemTimeError;
+
+/// Why reading an input, or sending its events, failed
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An input couldn't be read, like a file that isn't there
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// A line wasn't valid UTF-8, with --encoding-errors fail
+    #[error("line {line} isn't valid UTF-8")]
+    Encoding {
+        /// Which line, counting from 1
+        line: usize,
+    },
+    /// A line didn't fit the --format, with --on-invalid fail
+    #[error("line {line} {reason}")]
+    InvalidLine {
+        /// Which line, counting from 1
+        line: usize,
+        /// What was wrong with it
+        reason: &'static str,
+    },
+    /// The input looked binary, with --binary refuse
+    #[error("looks like a binary file (use --binary hexdump or --binary force to upload it)")]
+    Binary,
+    /// A timestamp couldn't be used, like one out of order with --out-of-order fail,
+    /// or one CloudWatch Logs won't accept with --out-of-range fail
+    #[error("{0}")]
+    InvalidTimestamp(String),
+    /// The system clock is set before the epoch, so nothing can be timestamped
+    #[error("the system clock is set before 1970")]
+    Clock(#[from] SystemTimeError),
+    /// A call to CloudWatch Logs failed
+    #[error("{}", retry::describe(.0))]
+    AwsSdk(#[from] aws_sdk_cloudwatchlogs::Error),
+    /// CloudWatch Logs rejected events for their time, with --on-rejected fail
+    #[error("CloudWatch Logs rejected {0} events for their time")]
+    Rejected(usize),
+    /// The instance metadata (or an ECS task's) couldn't be asked
+    #[error("{0}")]
+    Imds(String),
+    /// An input file was over --max-file-size, and sending it anyway wasn't meant
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// The command line was wrong, in a way only found after clap parsed it
+    #[error("{0}")]
+    Usage(#[from] clap::Error),
+    /// The --profile isn't one that can be used, like one not in the config
+    #[error("{0}")]
+    Profile(String),
+    /// No credentials could be had, like when a role couldn't be assumed
+    #[error("{0}")]
+    Credentials(String),
+    /// Not everything was sent, as the summary already says
+    #[error("not everything was sent")]
+    Unsent(Failure),
+    /// Anything not told apart above
+    #[error("{0}")]
+    Other(String),
+}
+
+impl Error {
+    /// Which failure this amounts to, for the status the program exits with
+    pub fn failure(&self) -> Failure {
+        match self {
+            Error::Io(_)
+            | Error::Encoding { .. }
+            | Error::InvalidLine { .. }
+            | Error::Binary
+            | Error::InvalidTimestamp(_) => Failure::Input,
+            Error::AwsSdk(e) => Failure::of(e),
+            Error::Rejected(_) => Failure::Partial,
+            Error::PayloadTooLarge(_) => Failure::TooLarge,
+            Error::Usage(_) | Error::Profile(_) => Failure::Usage,
+            Error::Credentials(_) => Failure::Denied,
+            Error::Unsent(failure) => *failure,
+            Error::Clock(_) | Error::Imds(_) | Error::Other(_) => Failure::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudwatchlogs::error::ResourceNotFoundException;
+
+    #[test]
+    fn test_failure() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        assert_eq!(Error::from(missing).failure(), Failure::Input);
+        assert_eq!(Error::Encoding { line: 3 }.failure(), Failure::Input);
+        let not_found = aws_sdk_cloudwatchlogs::Error::ResourceNotFoundException(
+            ResourceNotFoundException::builder()
+                .message("The specified log group does not exist.")
+                .build(),
+        );
+        let e = Error::from(not_found);
+        assert_eq!(e.failure(), Failure::NotFound);
+        assert!(e.to_string().contains("--create-group"), "{}", e);
+        let too_large = Error::PayloadTooLarge(String::from("big.log is 2.0 GB"));
+        assert_eq!(too_large.failure(), Failure::TooLarge);
+        assert_eq!(
+            Error::Imds(String::from("no answer")).failure(),
+            Failure::Other
+        );
+        let usage = clap::Command::new("rusty-axe").error(clap::ErrorKind::ArgumentConflict, "no");
+        assert_eq!(Error::from(usage).failure(), Failure::Usage);
+        assert_eq!(
+            Error::Credentials(String::from("couldn't assume the role")).failure(),
+            Failure::Denied
+        );
+        assert_eq!(Error::Unsent(Failure::Partial).failure(), Failure::Partial);
+        assert_eq!(Error::Rejected(3).failure(), Failure::Partial);
+    }
+
+    #[test]
+    fn test_single_line() {
+        assert_eq!(
+            Error::InvalidLine {
+                line: 2,
+                reason: "isn't valid JSON"
+            }
+            .to_string(),
+            "line 2 isn't valid JSON"
+        );
+        assert_eq!(
+            Error::Encoding { line: 7 }.to_string(),
+            "line 7 isn't valid UTF-8"
+        );
+    }
+}
@@ -0,0 +1,3606 @@
+//! Reading events from input files: which files to read, which of their lines to
+//! keep, and the event each line becomes
+
+use crate::error::Error;
+use crate::uploader::{RangeCounts, UploadSummary};
+use crate::{filter, format, input, message, multiline, progress, redact, state, timestamp};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// Filename that means "read from standard input"
+pub const STDIN_PATH: &str = "-";
+
+/// Expand any glob patterns in the list of filenames
+///
+/// Plain paths are passed through untouched (so a missing file is still reported as
+/// a read failure later on).  Matches for each pattern are sorted so runs are
+/// deterministic, and hidden files only match when the pattern itself spells out
+/// the leading dot.  Directories only match when `recursive` is set, to be walked
+/// by [`expand_directories`], and one that can't be read while matching is recorded
+/// as a failure.
+///
+/// # Arguments
+///
+/// * `patterns` - Filenames and/or glob patterns from the command line
+/// * `allow_empty` - Whether a pattern matching nothing is acceptable
+/// * `recursive` - Whether to keep directories that match
+/// * `summary` - Where to record paths that couldn't be read
+///
+pub fn expand_globs(
+    patterns: &[String],
+    allow_empty: bool,
+    recursive: bool,
+    summary: &mut UploadSummary,
+) -> Result<Vec<String>, String> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let mut filenames = Vec::new();
+
+    for pattern in patterns {
+        if pattern == STDIN_PATH || !pattern.contains(['*', '?', '[']) {
+            filenames.push(pattern.to_string());
+            continue;
+        }
+
+        // `glob_with` doesn't apply `require_literal_leading_dot` while walking
+        // directories, so hidden paths are filtered out by matching them again
+        let matcher = glob::Pattern::new(pattern)
+            .map_err(|e| format!("Invalid glob pattern {:?}: {}", pattern, e))?;
+        let paths = glob::glob(pattern)
+            .map_err(|e| format!("Invalid glob pattern {:?}: {}", pattern, e))?;
+        let mut matches = Vec::new();
+        let failed = summary.files_failed.len();
+        for path in paths {
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    let path = e.path().display().to_string();
+                    summary.files_failed.push((path, e.error().to_string()));
+                    continue;
+                }
+            };
+            let wanted = path.is_file() || (recursive && path.is_dir());
+            if wanted && matcher.matches_path_with(&path, options) {
+                matches.push(path.display().to_string());
+            }
+        }
+        matches.sort();
+
+        // What couldn't be read might have matched, so it's reported as failing instead
+        if matches.is_empty() && summary.files_failed.len() == failed && !allow_empty {
+            return Err(format!(
+                "{:?} didn't match any files (use --allow-empty-glob to carry on anyway)",
+                pattern
+            ));
+        }
+        filenames.extend(matches);
+    }
+
+    Ok(filenames)
+}
+
+/// Replace any directories in the list of filenames with the files inside them
+///
+/// Directories are only walked when `recursive` is set, otherwise they are recorded
+/// as failures.  Symlinked directories are followed, but each real directory is only
+/// visited once so a link back up the tree can't send us round in circles.
+///
+/// # Arguments
+///
+/// * `paths` - Filenames from the command line (after glob expansion)
+/// * `recursive` - Whether to walk directories
+/// * `excludes` - Patterns matching the names (or paths) of files to skip
+/// * `summary` - Where to record skipped and failed paths
+///
+pub fn expand_directories(
+    paths: &[String],
+    recursive: bool,
+    excludes: &[glob::Pattern],
+    summary: &mut UploadSummary,
+) -> Vec<String> {
+    let mut filenames = Vec::new();
+    let mut visited = HashSet::new();
+
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            filenames.push(path.to_string());
+        } else if recursive {
+            walk_directory(
+                Path::new(path),
+                excludes,
+                &mut visited,
+                &mut filenames,
+                summary,
+            );
+        } else {
+            let reason = String::from("is a directory (use --recursive to upload its contents)");
+            summary.files_failed.push((path.to_string(), reason));
+        }
+    }
+
+    filenames
+}
+
+/// Collect every regular file beneath `dir`, in sorted order
+fn walk_directory(
+    dir: &Path,
+    excludes: &[glob::Pattern],
+    visited: &mut HashSet<PathBuf>,
+    filenames: &mut Vec<String>,
+    summary: &mut UploadSummary,
+) {
+    let display = dir.display().to_string();
+
+    match dir.canonicalize() {
+        Ok(real) => {
+            if !visited.insert(real) {
+                summary
+                    .files_skipped
+                    .push((display, String::from("directory already visited")));
+                return;
+            }
+        }
+        Err(e) => {
+            summary.files_failed.push((display, e.to_string()));
+            return;
+        }
+    }
+
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).collect(),
+        Err(e) => {
+            summary.files_failed.push((display, e.to_string()));
+            return;
+        }
+    };
+    entries.sort();
+
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if excludes
+            .iter()
+            .any(|p| p.matches(&name) || p.matches_path(&path))
+        {
+            summary
+                .files_skipped
+                .push((path.display().to_string(), String::from("excluded")));
+        } else if path.is_dir() {
+            walk_directory(&path, excludes, visited, filenames, summary);
+        } else if path.is_file() {
+            filenames.push(path.display().to_string());
+        } else {
+            summary.files_skipped.push((
+                path.display().to_string(),
+                String::from("not a regular file"),
+            ));
+        }
+    }
+}
+
+/// How lines should be picked out of each input file
+#[derive(Debug, Clone, Default)]
+pub struct EventOptions {
+    /// Which lines are worth uploading at all, before any are picked out
+    pub filter: filter::LineFilter,
+    /// Leave out lines logged before this time
+    pub since: Option<DateTime<Utc>>,
+    /// Leave out lines logged after this time
+    pub until: Option<DateTime<Utc>>,
+    /// Lines matching this start a new record, and any others carry on the one before
+    pub multiline_start: Option<regex::bytes::Regex>,
+    /// How the lines are laid out
+    pub format: Format,
+    /// Read the first CSV row as data rather than the names of the columns
+    pub csv_no_header: bool,
+    /// The JSON field holding each line's timestamp
+    pub timestamp_field: Option<String>,
+    /// Where event timestamps come from, if not a format given for the lines
+    pub timestamp: Option<timestamp::Source>,
+    /// How to read each line's timestamp from the line itself
+    pub timestamp_format: Option<timestamp::LineFormat>,
+    /// What to do with timestamps read from the lines that aren't in order
+    pub out_of_order: OutOfOrder,
+    /// Whether to leave events timestamped from their lines in the order they were read
+    pub no_sort_events: bool,
+    /// When lines without a timestamp of their own were logged, if not when they're read
+    pub base_timestamp: Option<i64>,
+    /// What to do with lines that don't fit the format
+    pub on_invalid: OnInvalid,
+    /// The number of lines to read from the beginning of the file
+    pub head: usize,
+    /// The number of lines to read from the end of the file
+    pub tail: usize,
+    /// Keep every Nth line of those picked out
+    pub sample: Option<usize>,
+    /// Keep each line picked out with this chance
+    pub sample_random: Option<f64>,
+    /// Seed for the random sample, so it's the same each time
+    pub sample_seed: Option<u64>,
+    /// Leave out the event saying how many lines were skipped between head and tail
+    pub no_omission_marker: bool,
+    /// Ranges of lines to read, instead of a head and tail
+    pub lines: Vec<LineRange>,
+    /// The most bytes of messages to keep from each file, keeping the latest
+    pub max_bytes: Option<usize>,
+    /// How many bytes into the file to start reading
+    pub start_offset: u64,
+    /// Read compressed files as raw bytes instead of decompressing them
+    pub no_decompress: bool,
+    /// The character encoding of the input, unless it's to be worked out
+    pub encoding: Option<Encoding>,
+    /// What to do with lines that aren't valid UTF-8
+    pub encoding_errors: EncodingErrors,
+    /// What to do with files that look binary
+    pub binary: BinaryPolicy,
+    /// What to do with lines too long for a single event
+    pub oversize: Oversize,
+    /// What to send in place of blank lines
+    pub blank_lines: BlankLines,
+    /// Remove terminal escape sequences from each line
+    pub strip_ansi: bool,
+    /// Expand tabs to spaces, with tab stops this far apart
+    pub tab_width: Option<usize>,
+    /// How to make control characters printable, if at all
+    pub control_chars: Option<ControlChars>,
+    /// Patterns to scrub out of every message, --mask presets first
+    pub redact: Vec<redact::Rule>,
+    /// Map files into memory rather than reading them
+    pub mmap: bool,
+    /// Where to say how much of the input has been read
+    pub progress: progress::Progress,
+}
+
+impl EventOptions {
+    /// Can lines be taken straight from the file's bytes, without decoding them?
+    fn is_utf8(&self) -> bool {
+        self.encoding
+            .is_none_or(|encoding| encoding == Encoding::Utf8)
+    }
+
+    /// Does every line need looking at to decide whether (or how) it's uploaded?
+    fn filters_lines(&self) -> bool {
+        !self.filter.is_empty()
+            || self.since.is_some()
+            || self.until.is_some()
+            || self.multiline_start.is_some()
+            || self.format == Format::Csv
+    }
+
+    /// The timestamp for lines without one of their own
+    fn base_timestamp(&self) -> i64 {
+        self.base_timestamp.unwrap_or_else(now_millis)
+    }
+
+    /// Can reading stop short of the end of the input, once the --head or every
+    /// --lines range is done with?
+    pub(crate) fn stops_early(&self) -> bool {
+        if !self.lines.is_empty() {
+            return self.lines.iter().all(|range| range.end.is_some());
+        }
+        self.head != 0 && self.tail == 0
+    }
+
+    /// Are events timestamped from their lines, rather than with when they were read?
+    fn reads_timestamps(&self) -> bool {
+        self.timestamp == Some(timestamp::Source::Auto)
+            || self.timestamp_format.is_some()
+            || self.timestamp_field.is_some()
+    }
+}
+
+/// What to do with files that look binary rather than text
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BinaryPolicy {
+    /// Don't upload the file
+    #[default]
+    Refuse,
+    /// Upload an `xxd`-style hex dump of the file instead
+    Hexdump,
+    /// Upload the file as if it were text anyway
+    Force,
+}
+
+/// How the lines of a file are laid out
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Plain text, sent as is
+    #[default]
+    Text,
+    /// A JSON value on each line (JSON Lines), sent as is so CloudWatch Logs can find
+    /// its fields
+    Jsonl,
+    /// Comma-separated values, each row sent as a JSON object named by the header row
+    Csv,
+}
+
+/// What to do with lines that don't fit the --format
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnInvalid {
+    /// Send the line as plain text
+    #[default]
+    Raw,
+    /// Leave the line out
+    Skip,
+    /// Give up on the file
+    Fail,
+}
+
+/// Character encodings input files can be decoded from
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, which input is taken to be unless it says otherwise
+    #[clap(name = "utf-8", alias = "utf8")]
+    Utf8,
+    /// ISO-8859-1
+    #[clap(alias = "iso-8859-1")]
+    Latin1,
+    /// UTF-16, little-endian
+    #[clap(name = "utf-16le", alias = "utf16le")]
+    Utf16le,
+    /// UTF-16, big-endian
+    #[clap(name = "utf-16be", alias = "utf16be")]
+    Utf16be,
+}
+
+/// The unit of timestamps given as a number since the epoch
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EpochUnit {
+    /// Work it out from how big the number is
+    #[default]
+    Auto,
+    /// Seconds
+    S,
+    /// Milliseconds
+    Ms,
+    /// Microseconds
+    Us,
+}
+
+/// What to do when timestamps read from the lines go back in time
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutOfOrder {
+    /// Sort the events into time order (lines logged at the same time keep their order)
+    #[default]
+    Sort,
+    /// Stop without uploading anything from the file
+    Fail,
+}
+
+/// What to do with events that CloudWatch Logs would reject for their time
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// Give them the nearest timestamp that will be accepted
+    #[default]
+    Clamp,
+    /// Leave them out
+    Skip,
+    /// Stop without sending anything
+    Fail,
+}
+
+/// What to do with lines that aren't valid UTF-8
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodingErrors {
+    /// Swap the invalid bytes for U+FFFD replacement characters
+    #[default]
+    Replace,
+    /// Leave the line out
+    Skip,
+    /// Give up on the file
+    Fail,
+}
+
+/// What to do with lines too long for a single CloudWatch Logs event
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Oversize {
+    /// Send the line as several events, each marked with which part it is
+    #[default]
+    Split,
+    /// Cut the end off the line, noting how much was cut
+    Truncate,
+    /// Leave the line out
+    Drop,
+}
+
+/// What to send in place of blank lines
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlankLines {
+    /// A single space
+    #[default]
+    Space,
+    /// Nothing at all; the line is left out
+    Skip,
+    /// The text "(blank)", so it's obvious where the blank lines were
+    Placeholder,
+}
+
+/// How control characters are made printable
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlChars {
+    /// An escape naming the character, like \u{0007}
+    Escape,
+    /// The replacement character, \u{FFFD}
+    Placeholder,
+}
+
+/// Create a single vector of InputLogEvents from every input file
+///
+/// When more than one file is given, each file's events are preceded by a header
+/// event naming the file so they can be told apart in the log stream.  A file that
+/// can't be read is recorded in the summary and skipped; it doesn't stop the others.
+///
+/// # Arguments
+///
+/// * `paths` - The input files to process
+/// * `options` - How to pick lines out of each file
+/// * `state` - Where earlier runs got to in each file, if reading should resume there
+/// * `summary` - Where to record which files were read or failed
+///
+pub async fn collect_events(
+    paths: &[String],
+    options: &EventOptions,
+    state: Option<&mut state::StateFile>,
+    summary: &mut UploadSummary,
+) -> Vec<InputLogEvent> {
+    let files = read_files(paths, options, state, summary).await;
+    let mut events = Vec::new();
+
+    for (path, file_events) in files {
+        if paths.len() > 1 {
+            let timestamp = file_events
+                .first()
+                .and_then(|event| event.timestamp)
+                .unwrap_or_else(now_millis);
+            events.push(build_event(timestamp, format!("===== {} =====", path)));
+        }
+        events.extend(file_events);
+    }
+
+    // Each file is in order by now, but their timestamps can still overlap
+    if options.reads_timestamps() {
+        if !options.no_sort_events {
+            events.sort_by_key(|event| event.timestamp);
+        }
+    } else {
+        space_out_timestamps(&mut events, now_millis(), summary);
+    }
+
+    events
+}
+
+/// Read the events out of each input file, keeping every file's events apart
+///
+/// A file that can't be read is recorded in the summary and left out; it doesn't
+/// stop the others.  Takes the same arguments as [`collect_events`].
+pub async fn read_files(
+    paths: &[String],
+    options: &EventOptions,
+    mut state: Option<&mut state::StateFile>,
+    summary: &mut UploadSummary,
+) -> Vec<(String, Vec<InputLogEvent>)> {
+    let mut files = Vec::new();
+    let sizes: Vec<_> = paths.iter().map(|path| input_size(path)).collect();
+    options.progress.reading(&sizes);
+
+    for (path, size) in paths.iter().zip(sizes) {
+        let resumed;
+        let options = match state.as_deref_mut() {
+            Some(state) if path != STDIN_PATH => match state.start_offset(path) {
+                Ok(start_offset) => {
+                    resumed = EventOptions {
+                        start_offset,
+                        ..options.clone()
+                    };
+                    &resumed
+                }
+                Err(e) => {
+                    error!("Couldn't read {:?}: {}", path, e);
+                    summary.files_failed.push((path.to_string(), e.to_string()));
+                    continue;
+                }
+            },
+            _ => options,
+        };
+
+        options.progress.start_file(size);
+        match get_events(path.to_string(), options, summary).await {
+            Ok(events) => {
+                files.push((path.to_string(), events));
+                summary.files_read.push(path.to_string());
+            }
+            Err(e) => {
+                error!("Couldn't read {:?}: {}", path, e);
+                summary.files_failed.push((path.to_string(), e.to_string()));
+            }
+        }
+        options.progress.finish_file();
+    }
+
+    files
+}
+
+/// How many bytes there are to read from an input file, if that can be known
+/// beforehand (it can't for standard input)
+pub(crate) fn input_size(path: &str) -> Option<u64> {
+    match path {
+        STDIN_PATH => None,
+        _ => fs::metadata(path).ok().map(|metadata| metadata.len()),
+    }
+}
+
+/// Create a vector of InputLogEvents from an input file
+///
+/// # Arguments
+///
+/// * `path` - An input file to process ("-" reads from standard input)
+/// * `options` - How to pick lines out of the file
+/// * `summary` - Where to count anything noteworthy about the lines read
+///
+/// ```
+/// use rusty_axe::events::{self, EventOptions};
+/// use rusty_axe::uploader::UploadSummary;
+/// use std::io::Write;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), rusty_axe::error::Error> {
+/// let mut file = tempfile::NamedTempFile::new()?;
+/// writeln!(file, "starting up\nlistening on :8080\nshutting down")?;
+/// let path = file.path().to_str().unwrap().to_string();
+///
+/// // Only the last two lines
+/// let options = EventOptions {
+///     tail: 2,
+///     ..Default::default()
+/// };
+/// let mut summary = UploadSummary::default();
+/// let events = events::get_events(path, &options, &mut summary).await?;
+/// let messages: Vec<_> = events.iter().filter_map(|event| event.message()).collect();
+/// assert_eq!(messages.last(), Some(&"shutting down"));
+/// # Ok(())
+/// # }
+/// ```
+pub async fn get_events(
+    path: String,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<Vec<InputLogEvent>, Error> {
+    info!("Reading {:?}...", path);
+
+    let started = Instant::now();
+    let events = match options.timestamp {
+        Some(timestamp::Source::Mtime) => modified_millis(&path).and_then(|modified| {
+            let options = EventOptions {
+                base_timestamp: Some(modified),
+                ..options.clone()
+            };
+            read_input(&path, &options, summary)
+        }),
+        _ => read_input(&path, options, summary),
+    };
+    summary.read_time += started.elapsed();
+
+    let mut events = events?;
+    arrange_events(&mut events, options, clock_millis()?, summary)?;
+    Ok(events)
+}
+
+/// Keep only the latest events whose messages fit in `max_bytes`, leaving out the
+/// earlier ones
+///
+/// This applies to whatever was picked out of the file, so with --head and --tail
+/// the tail is kept before any of the head.  The events left out are replaced by an
+/// omission marker counting them (along with the lines of any marker among them),
+/// and the marker's own bytes don't count towards the limit.  `markers` are where
+/// the markers already among the events are, and how many lines each stands in for.
+fn cap_bytes(
+    events: &mut Vec<InputLogEvent>,
+    markers: &[(usize, usize)],
+    max_bytes: usize,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) {
+    fn message(event: &InputLogEvent) -> &str {
+        event.message().unwrap_or_default()
+    }
+    let marker = |index: usize| {
+        markers
+            .iter()
+            .find(|&&(at, _)| at == index)
+            .map(|&(_, omitted)| omitted)
+    };
+    let mut kept = 0;
+    let mut start = match events.iter().enumerate().rposition(|(index, event)| {
+        if marker(index).is_none() {
+            kept += message(event).len();
+        }
+        kept > max_bytes
+    }) {
+        Some(last_left_out) => last_left_out + 1,
+        None => return,
+    };
+    // A marker the cut leaves at the start would only sit next to the new one
+    while start < events.len() && marker(start).is_some() {
+        start += 1;
+    }
+
+    let mut omitted = 0;
+    for (index, event) in events[..start].iter().enumerate() {
+        match marker(index) {
+            Some(count) => omitted += count,
+            None => {
+                omitted += 1;
+                summary.lines_capped += 1;
+                summary.bytes_capped += message(event).len();
+            }
+        }
+    }
+    let timestamp = events[start - 1].timestamp.unwrap_or_else(now_millis);
+    events.drain(..start);
+    if !options.no_omission_marker {
+        events.insert(0, build_event(timestamp, message::omission_marker(omitted)));
+    }
+}
+
+/// When a file was last modified, in milliseconds since the epoch
+fn modified_millis(path: &str) -> Result<i64, Error> {
+    if path == STDIN_PATH {
+        return Err(Error::InvalidTimestamp(String::from(
+            "standard input has no modification time for --timestamp mtime",
+        )));
+    }
+    let modified = fs::metadata(path)?.modified()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).map_err(|_| {
+        Error::InvalidTimestamp(format!("{:?} was last modified before 1970", path))
+    })?;
+    Ok(since_epoch.as_millis() as i64)
+}
+
+/// Read the events out of an input file, whichever way suits the file best
+fn read_input(
+    path: &str,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<Vec<InputLogEvent>, Error> {
+    // Ranges can be anywhere in the file, so mapping it wouldn't save anything, and
+    // nor would mapping it only to skip to an offset or to filter every line
+    if options.mmap
+        && options.lines.is_empty()
+        && options.start_offset == 0
+        && !options.filters_lines()
+        && options.is_utf8()
+    {
+        match input::map_file(path) {
+            Ok(Some(map)) => {
+                let compressed = input::Compression::sniff(&map).is_some();
+                // Anything that needs decoding first goes the buffered route below
+                if !input::looks_binary(&map) && (options.no_decompress || !compressed) {
+                    let (events, offset) = read_mapped_events(&map, options, summary)?;
+                    summary.final_offsets.push((path.to_string(), offset));
+                    return Ok(events);
+                }
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Couldn't map {:?}, reading it normally: {}", path, e),
+        }
+    }
+
+    // A tail on its own can be found by reading backwards from the end of the file,
+    // though line numbers for failing on invalid UTF-8 would mean counting every line
+    if options.head == 0
+        && options.tail != 0
+        && options.lines.is_empty()
+        && options.start_offset == 0
+        && !options.filters_lines()
+        && options.is_utf8()
+        && options.encoding_errors != EncodingErrors::Fail
+    {
+        if let Some(tail) = input::read_tail(path, options.tail, !options.no_decompress)? {
+            summary.bytes_read += tail.bytes_read;
+            summary.final_offsets.push((path.to_string(), tail.len));
+
+            let timestamp = options.base_timestamp();
+            let mut processor = LineProcessor::new(options);
+            for line in tail.lines {
+                processor.push(timestamp, 0, &line, summary)?;
+            }
+            return Ok(processor.finish(summary));
+        }
+    }
+
+    // Pipes (and decompressors) can't be rewound, so the input is only read once
+    let (reader, start) = input::open_input_at(path, !options.no_decompress, options.start_offset)?;
+    let start = match start {
+        Some(start) => start,
+        None => {
+            warn!(
+                "{:?} is shorter than the start offset of {} bytes",
+                path, options.start_offset
+            );
+            summary
+                .final_offsets
+                .push((path.to_string(), options.start_offset));
+            return Ok(Vec::new());
+        }
+    };
+    let mut reader = input::CountingReader::new(progress::Reading::new(reader, &options.progress));
+    let (events, binary) = match input::sniff_encoding(&mut reader, options.encoding)? {
+        Encoding::Utf8 => read_text(&mut reader, options, summary)?,
+        encoding => {
+            let decoder = input::Decoder::new(&mut reader, encoding);
+            read_text(BufReader::new(decoder), options, summary)?
+        }
+    };
+    summary.bytes_read += reader.count();
+    summary
+        .final_offsets
+        .push((path.to_string(), start + reader.count()));
+    if binary {
+        summary
+            .binary_files
+            .push((path.to_string(), options.binary, reader.count()));
+    }
+
+    Ok(events)
+}
+
+/// Create a vector of InputLogEvents from text, or from something that looks binary
+/// if the binary policy allows it
+///
+/// Returns the events along with whether the input looked binary.
+fn read_text<R: BufRead>(
+    mut reader: R,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<(Vec<InputLogEvent>, bool), Error> {
+    if !input::looks_binary(reader.fill_buf()?) {
+        return Ok((read_events(reader, options, summary)?, false));
+    }
+
+    let events = match options.binary {
+        BinaryPolicy::Refuse => {
+            return Err(Error::Binary);
+        }
+        BinaryPolicy::Hexdump => {
+            let hex = input::HexDump::new(reader);
+            read_events(BufReader::new(hex), options, summary)?
+        }
+        BinaryPolicy::Force => read_events(reader, options, summary)?,
+    };
+
+    Ok((events, true))
+}
+
+/// Create a vector of InputLogEvents from a file mapped into memory
+///
+/// Only the pages holding the head and tail lines are read, which is what makes
+/// this quicker than reading a very large file front to back.  Returns the events
+/// along with the offset just past the last line taken.
+fn read_mapped_events(
+    data: &[u8],
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<(Vec<InputLogEvent>, u64), Error> {
+    let timestamp = options.base_timestamp();
+    let lines = input::mapped_lines(data, options.head, options.tail);
+    let mut processor = LineProcessor::new(options);
+
+    // Only a head leaves anything unread at the end of the file
+    let end = match lines.last() {
+        Some(&(offset, line)) if options.tail == 0 => input::line_end(data, offset, line),
+        _ => data.len(),
+    };
+
+    // Line numbers are only needed for error messages, and finding them for the tail
+    // means counting every line, so don't bother unless they might be used
+    let needs_index = options.encoding_errors == EncodingErrors::Fail;
+
+    // Counting the lines between the head and the tail means reading them, but only
+    // to look for line endings
+    let omitted = match (lines.get(options.head.max(1) - 1), lines.get(options.head)) {
+        (Some(&(offset, line)), Some(&(tail_start, _))) if options.head != 0 => {
+            input::count_lines(&data[input::line_end(data, offset, line)..tail_start])
+        }
+        _ => 0,
+    };
+
+    for (i, (offset, line)) in lines.into_iter().enumerate() {
+        if i == options.head {
+            processor.mark_omitted(omitted, timestamp);
+        }
+        let index = if needs_index {
+            memchr::memchr_iter(b'\n', &data[..offset]).count()
+        } else {
+            0
+        };
+        summary.bytes_read += line.len() as u64 + 1;
+        processor.push(timestamp, index, line, summary)?;
+    }
+
+    Ok((processor.finish(summary), end as u64))
+}
+
+/// Create a vector of InputLogEvents from anything that yields lines
+///
+/// Head lines (or every line, when no limits are given) are turned into events as
+/// they are read.  Tail lines are kept in a ring buffer holding the last `tail`
+/// lines seen after the head, which is appended once the input is exhausted.  When
+/// lines are grouped into multiline records, it's records that are counted instead.
+///
+/// # Arguments
+///
+/// * `reader` - The input to process
+/// * `options` - How to pick lines out of the input
+/// * `summary` - Where to count anything noteworthy about the lines read
+///
+pub(crate) fn read_events<R: BufRead>(
+    reader: R,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<Vec<InputLogEvent>, Error> {
+    let timestamp = options.base_timestamp();
+
+    let mut processor = LineProcessor::new(options);
+    let mut selector = Selector::new(options.head, options.tail, &options.lines);
+    let mut window = timestamp::TimeWindow::new(options.since, options.until);
+    let mut records = match options.format {
+        Format::Csv => multiline::Records::csv_rows(),
+        _ => multiline::Records::new(options.multiline_start.clone()),
+    };
+    let mut line = Vec::new();
+
+    // Create a set of log events from the input contents, a record at a time (which
+    // is a line at a time unless lines are being grouped)
+    let mut index = 0;
+    let mut reader = input::LineReader::new(reader);
+    loop {
+        let more = reader.read_line(&mut line)?;
+        let record = if more {
+            index += 1;
+            records.push(index - 1, std::mem::take(&mut line))
+        } else {
+            records.flush()
+        };
+
+        if let Some((index, record)) = record {
+            if processor.wants_header() {
+                processor.set_header(index, &record)?;
+            } else if window.keep(&record, summary) && options.filter.keep(&record, summary) {
+                if let Some((index, record)) = selector.offer((index, record)) {
+                    processor.push(timestamp, index, &record, summary)?;
+                }
+                if selector.is_done() {
+                    if more {
+                        reader.finish_line()?;
+                    }
+                    break;
+                }
+            }
+        }
+        if !more {
+            break;
+        }
+    }
+
+    processor.mark_omitted(selector.omitted(), timestamp);
+    for (index, line) in selector.finish() {
+        processor.push(timestamp, index, &line, summary)?;
+    }
+
+    Ok(processor.finish(summary))
+}
+
+/// Turns the lines picked out of an input into events, one line at a time
+///
+/// Anything that carries over from one line to the next, like how far through the
+/// sample it's got, lasts for as long as the input does.
+pub(crate) struct LineProcessor<'a> {
+    options: &'a EventOptions,
+    sampler: Sampler,
+    /// The names of the CSV columns, once the header has been read
+    columns: Option<Vec<String>>,
+    /// The layout of the lines' timestamps, for --timestamp auto
+    auto_format: Option<timestamp::AutoFormat>,
+    /// The timestamp read from the last line that had one
+    last_timestamp: Option<i64>,
+    events: Vec<InputLogEvent>,
+    /// Where omission markers are among the events, and how many lines each stands
+    /// in for
+    markers: Vec<(usize, usize)>,
+}
+
+impl<'a> LineProcessor<'a> {
+    pub(crate) fn new(options: &'a EventOptions) -> LineProcessor<'a> {
+        LineProcessor {
+            options,
+            sampler: Sampler::new(options),
+            columns: None,
+            auto_format: (options.timestamp == Some(timestamp::Source::Auto))
+                .then(timestamp::AutoFormat::default),
+            last_timestamp: None,
+            events: Vec::new(),
+            markers: Vec::new(),
+        }
+    }
+
+    /// Turn a line's raw bytes into events, unless it's sampled out or can't be decoded
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - When the line was logged (or read)
+    /// * `index` - Where the line sits in the input (0-based), for error messages
+    /// * `bytes` - The line itself
+    /// * `summary` - Where to count anything noteworthy about the line
+    ///
+    pub(crate) fn push(
+        &mut self,
+        timestamp: i64,
+        index: usize,
+        bytes: &[u8],
+        summary: &mut UploadSummary,
+    ) -> Result<(), Error> {
+        summary.lines_selected += 1;
+        if !self.sampler.keep() {
+            summary.lines_sampled_out += 1;
+            return Ok(());
+        }
+
+        let options = self.options;
+        let mut line = match decode_line(bytes, index, options.encoding_errors, summary)? {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        if options.strip_ansi {
+            message::strip_ansi(&mut line);
+        }
+        // Before anything measures the line, since escapes make it longer
+        if let Some(width) = options.tab_width {
+            message::expand_tabs(&mut line, width);
+        }
+        if let Some(control_chars) = options.control_chars {
+            let placeholder = match control_chars {
+                ControlChars::Escape => None,
+                ControlChars::Placeholder => Some(char::REPLACEMENT_CHARACTER),
+            };
+            message::escape_control_chars(&mut line, placeholder);
+        }
+
+        let mut found = match (&mut self.auto_format, &options.timestamp_format) {
+            (Some(auto), _) => auto.parse(line.as_bytes()),
+            (None, Some(layout)) => layout.parse(&line, summary),
+            (None, None) => None,
+        }
+        .map(|time| time.timestamp_millis());
+
+        // Blank lines are left to the blank line policy, whatever the format
+        let invalid = match options.format {
+            _ if line.is_empty() => None,
+            Format::Text => None,
+            Format::Jsonl => {
+                match format::parse_json_line(&line, options.timestamp_field.as_deref()) {
+                    Some(timestamp) => {
+                        found = timestamp;
+                        None
+                    }
+                    None => Some("isn't valid JSON"),
+                }
+            }
+            Format::Csv => match format::parse_csv_row(&line) {
+                Some(fields) => match format::csv_to_json(self.columns.as_deref(), &fields) {
+                    Some(object) => {
+                        line = object;
+                        None
+                    }
+                    None => Some("doesn't have as many fields as the CSV header"),
+                },
+                None => Some("isn't a valid CSV row"),
+            },
+        };
+
+        if let Some(reason) = invalid {
+            summary.lines_invalid += 1;
+            match options.on_invalid {
+                OnInvalid::Raw => (),
+                OnInvalid::Skip => return Ok(()),
+                OnInvalid::Fail => {
+                    return Err(Error::InvalidLine {
+                        line: index + 1,
+                        reason,
+                    })
+                }
+            }
+        }
+
+        let timestamp = if options.reads_timestamps() {
+            self.line_timestamp(found, timestamp, summary)
+        } else {
+            timestamp
+        };
+
+        // Last of all, so nothing sensitive survives into the message however it's shaped
+        redact::redact(&mut line, &options.redact, summary);
+        self.events
+            .extend(line_events(timestamp, line, options, summary));
+        Ok(())
+    }
+
+    /// The timestamp for a line: the one read from it, or failing that, the last one
+    /// read from a line before it
+    ///
+    /// Lines before the first timestamp can only be given that one, once it's found.
+    fn line_timestamp(
+        &mut self,
+        found: Option<i64>,
+        read_at: i64,
+        summary: &mut UploadSummary,
+    ) -> i64 {
+        match found {
+            Some(found) => {
+                if self.last_timestamp.is_none() {
+                    for event in &mut self.events {
+                        event.timestamp = Some(found);
+                    }
+                }
+                self.last_timestamp = Some(found);
+            }
+            None => summary.lines_without_timestamp += 1,
+        }
+        self.last_timestamp.unwrap_or(read_at)
+    }
+
+    /// Does the next line need to be read as the CSV header, rather than pushed?
+    fn wants_header(&self) -> bool {
+        self.options.format == Format::Csv && !self.options.csv_no_header && self.columns.is_none()
+    }
+
+    /// Take the names of the columns from the CSV header
+    fn set_header(&mut self, index: usize, bytes: &[u8]) -> Result<(), Error> {
+        let header =
+            format::parse_csv_row(&String::from_utf8_lossy(bytes)).ok_or(Error::InvalidLine {
+                line: index + 1,
+                reason: "isn't a valid CSV header",
+            })?;
+        self.columns = Some(header);
+        Ok(())
+    }
+
+    /// Add an event saying how many lines were left out between the head and tail
+    ///
+    /// The marker shares the timestamp of the event before it so the events stay in
+    /// order, falling back on `timestamp` when there isn't one.
+    pub(crate) fn mark_omitted(&mut self, omitted: usize, timestamp: i64) {
+        if omitted == 0 || self.options.no_omission_marker {
+            return;
+        }
+        let timestamp = self
+            .events
+            .last()
+            .and_then(|event| event.timestamp)
+            .unwrap_or(timestamp);
+        self.markers.push((self.events.len(), omitted));
+        self.events
+            .push(build_event(timestamp, message::omission_marker(omitted)));
+    }
+
+    /// The events made from the lines pushed since the last call, for inputs that
+    /// never finish
+    pub(crate) fn take(&mut self) -> Vec<InputLogEvent> {
+        self.markers.clear();
+        std::mem::take(&mut self.events)
+    }
+
+    /// The events made from every line pushed, only the latest of them if there's
+    /// more than --max-bytes
+    pub(crate) fn finish(self, summary: &mut UploadSummary) -> Vec<InputLogEvent> {
+        let mut events = self.events;
+        if let Some(max_bytes) = self.options.max_bytes {
+            cap_bytes(&mut events, &self.markers, max_bytes, self.options, summary);
+        }
+        events
+    }
+}
+
+/// Keeps a sample of the lines offered to it
+enum Sampler {
+    /// Every line
+    All,
+    /// Every `n`th line, starting with the first
+    Every { n: usize, seen: usize },
+    /// Each line with the same chance of being kept
+    Random { rate: f64, rng: fastrand::Rng },
+}
+
+impl Sampler {
+    pub(crate) fn new(options: &EventOptions) -> Sampler {
+        match (options.sample, options.sample_random) {
+            (_, Some(rate)) => Sampler::Random {
+                rate,
+                rng: options
+                    .sample_seed
+                    .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            },
+            (Some(n), None) if n > 1 => Sampler::Every { n, seen: 0 },
+            _ => Sampler::All,
+        }
+    }
+
+    /// Should the next line be kept?
+    fn keep(&mut self) -> bool {
+        match self {
+            Sampler::All => true,
+            Sampler::Every { n, seen } => {
+                let keep = *seen % *n == 0;
+                *seen += 1;
+                keep
+            }
+            Sampler::Random { rate, rng } => rng.f64() < *rate,
+        }
+    }
+}
+
+/// Picks the first `head` and last `tail` items out of a stream in a single pass
+///
+/// Items in the head (or every item, when neither limit is set) are handed straight
+/// back as they're offered.  The tail is a ring buffer of the last `tail` items seen
+/// after the head, which is collected once the stream is exhausted.  When ranges are
+/// given they replace the head and tail, and items inside any of them are handed
+/// straight back.
+pub(crate) struct Selector<T> {
+    head: usize,
+    tail: usize,
+    ranges: Vec<LineRange>,
+    /// How many items have been offered so far
+    seen: usize,
+    tail_items: VecDeque<T>,
+}
+
+impl<T> Selector<T> {
+    pub(crate) fn new(head: usize, tail: usize, ranges: &[LineRange]) -> Selector<T> {
+        Selector {
+            head,
+            tail,
+            ranges: ranges.to_vec(),
+            seen: 0,
+            tail_items: VecDeque::with_capacity(tail),
+        }
+    }
+
+    /// Offer the next item, getting it straight back if it's part of the head
+    pub(crate) fn offer(&mut self, item: T) -> Option<T> {
+        let index = self.seen;
+        self.seen += 1;
+
+        if !self.ranges.is_empty() {
+            return self
+                .ranges
+                .iter()
+                .any(|range| range.contains(index + 1))
+                .then_some(item);
+        }
+        // Log all the items, or the first item(s)
+        if (self.head == 0 && self.tail == 0) || index < self.head {
+            return Some(item);
+        }
+        // Remember the last item(s)
+        if self.tail != 0 {
+            if self.tail_items.len() == self.tail {
+                self.tail_items.pop_front();
+            }
+            self.tail_items.push_back(item);
+        }
+
+        None
+    }
+
+    /// Will every item offered from now on be ignored?
+    pub(crate) fn is_done(&self) -> bool {
+        if !self.ranges.is_empty() {
+            return self
+                .ranges
+                .iter()
+                .all(|range| range.end.is_some_and(|end| self.seen >= end));
+        }
+        self.tail == 0 && self.head != 0 && self.seen >= self.head
+    }
+
+    /// How many items fell between the head and the tail
+    ///
+    /// Only known when there's both a head and a tail, since otherwise offering stops
+    /// early or there's no gap to speak of.
+    pub(crate) fn omitted(&self) -> usize {
+        if self.head == 0 || self.tail == 0 || !self.ranges.is_empty() {
+            return 0;
+        }
+        self.seen.saturating_sub(self.head + self.tail_items.len())
+    }
+
+    /// The tail items, once there's nothing left to offer
+    pub(crate) fn finish(self) -> VecDeque<T> {
+        self.tail_items
+    }
+}
+
+/// A range of line numbers given with `--lines`, 1-based and inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    /// The first line in the range
+    pub start: usize,
+    /// The last line in the range, or None to carry on to the end of the file
+    pub end: Option<usize>,
+}
+
+impl LineRange {
+    /// Parse `START:END`, where either end may be left off (`500:` or `:200`)
+    ///
+    /// ```
+    /// use rusty_axe::events::LineRange;
+    ///
+    /// let range = LineRange::parse("500:").unwrap();
+    /// assert_eq!((range.start, range.end), (500, None));
+    /// assert_eq!(LineRange::parse(":200").unwrap().start, 1);
+    /// assert!(LineRange::parse("200:100").is_err());
+    /// ```
+    pub fn parse(s: &str) -> Result<LineRange, String> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| format!("{:?} isn't a range like 120:180", s))?;
+        let number = |n: &str| {
+            n.parse::<usize>()
+                .map_err(|_| format!("{:?} isn't a line number", n))
+        };
+
+        let start = if start.is_empty() { 1 } else { number(start)? };
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(number(end)?)
+        };
+
+        if start == 0 {
+            return Err(String::from("line numbers start at 1"));
+        }
+        if end.is_some_and(|end| end < start) {
+            return Err(format!("{:?} ends before it starts", s));
+        }
+
+        Ok(LineRange { start, end })
+    }
+
+    /// Is the (1-based) line number inside the range?
+    fn contains(&self, line: usize) -> bool {
+        line >= self.start && self.end.is_none_or(|end| line <= end)
+    }
+}
+
+/// Turn a line's raw bytes into text, following the policy for invalid UTF-8
+///
+/// Returns None if the line should be left out altogether.
+///
+/// # Arguments
+///
+/// * `bytes` - The line to decode
+/// * `index` - Where the line sits in the file (0-based), for error messages
+/// * `policy` - What to do when the line isn't valid UTF-8
+/// * `summary` - Where to count lines that were mangled or skipped
+///
+fn decode_line(
+    bytes: &[u8],
+    index: usize,
+    policy: EncodingErrors,
+    summary: &mut UploadSummary,
+) -> Result<Option<String>, Error> {
+    if let Ok(line) = std::str::from_utf8(bytes) {
+        return Ok(Some(line.to_string()));
+    }
+
+    match policy {
+        EncodingErrors::Replace => {
+            summary.lines_replaced += 1;
+            Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+        }
+        EncodingErrors::Skip => {
+            summary.lines_skipped_encoding += 1;
+            Ok(None)
+        }
+        EncodingErrors::Fail => Err(Error::Encoding { line: index + 1 }),
+    }
+}
+
+/// The current time as milliseconds since the epoch, which is what CloudWatch Logs expects
+///
+/// A clock set before the epoch reads as the epoch itself, which is too long ago for
+/// anything sent then to be accepted; [`clock_millis`] says so instead.
+pub fn now_millis() -> i64 {
+    clock_millis().unwrap_or_default()
+}
+
+/// The current time as milliseconds since the epoch, unless the clock is set before it
+pub fn clock_millis() -> Result<i64, Error> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(i64::try_from(since_epoch.as_millis()).unwrap_or(i64::MAX))
+}
+
+/// How old an event can be (in milliseconds) before CloudWatch Logs rejects it
+pub(crate) const MAX_EVENT_AGE: i64 = 14 * 24 * 60 * 60 * 1000;
+
+/// How far inside the age limit events are kept, leaving time for the upload itself
+pub(crate) const EVENT_AGE_MARGIN: i64 = 10 * 60 * 1000;
+
+/// A day in milliseconds, the unit log group retention periods are given in
+pub(crate) const DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// The times CloudWatch Logs will accept events at, as of some moment
+pub(crate) struct AcceptedTimes {
+    /// The oldest, leaving time for the upload itself
+    pub(crate) oldest: i64,
+    /// The oldest the log group keeps, if it doesn't keep events forever
+    pub(crate) retained: Option<i64>,
+    /// The latest, leaving time for the upload itself
+    pub(crate) latest: i64,
+}
+
+impl AcceptedTimes {
+    pub(crate) fn new(now: i64, retention_days: Option<i32>) -> AcceptedTimes {
+        AcceptedTimes {
+            oldest: now - MAX_EVENT_AGE + EVENT_AGE_MARGIN,
+            retained: retention_days.map(|days| now - i64::from(days) * DAY + EVENT_AGE_MARGIN),
+            latest: now + MAX_EVENT_LEAD - EVENT_AGE_MARGIN,
+        }
+    }
+
+    /// The oldest time accepted, whichever limit it comes from
+    pub(crate) fn earliest(&self) -> i64 {
+        self.retained
+            .map_or(self.oldest, |retained| retained.max(self.oldest))
+    }
+
+    /// Which limit (if any) `time` is outside, as its count in `counts`, along with
+    /// the nearest time that's accepted
+    fn outside<'a>(&self, time: i64, counts: &'a mut RangeCounts) -> Option<(&'a mut usize, i64)> {
+        let earliest = self.earliest();
+        if time < self.oldest {
+            Some((&mut counts.too_old, earliest))
+        } else if time < earliest {
+            Some((&mut counts.past_retention, earliest))
+        } else if time > self.latest {
+            Some((&mut counts.too_new, self.latest))
+        } else {
+            None
+        }
+    }
+}
+
+/// Deal with events CloudWatch Logs would reject for their time, by the out-of-range
+/// policy
+///
+/// Those are events older than 14 days, older than the log group's retention period
+/// (if it has one) or more than 2 hours ahead of `now`.  Clamping moves them to the
+/// nearest time that's accepted, which keeps them in order.  Failing leaves the
+/// events alone and returns how many were outside each limit.
+pub(crate) fn check_event_times(
+    events: &mut Vec<InputLogEvent>,
+    policy: OutOfRange,
+    now: i64,
+    retention_days: Option<i32>,
+    summary: &mut UploadSummary,
+) -> Result<(), Error> {
+    let accepted = AcceptedTimes::new(now, retention_days);
+
+    match policy {
+        OutOfRange::Clamp => {
+            for event in events.iter_mut() {
+                let outside = event
+                    .timestamp
+                    .and_then(|time| accepted.outside(time, &mut summary.events_clamped));
+                if let Some((count, nearest)) = outside {
+                    *count += 1;
+                    event.timestamp = Some(nearest);
+                }
+            }
+        }
+        OutOfRange::Skip => events.retain(|event| {
+            let outside = event
+                .timestamp
+                .and_then(|time| accepted.outside(time, &mut summary.events_skipped));
+            match outside {
+                Some((count, _)) => {
+                    *count += 1;
+                    false
+                }
+                None => true,
+            }
+        }),
+        OutOfRange::Fail => {
+            let mut counts = RangeCounts::default();
+            for time in events.iter().filter_map(|event| event.timestamp) {
+                if let Some((count, _)) = accepted.outside(time, &mut counts) {
+                    *count += 1;
+                }
+            }
+            let outside: Vec<_> = counts
+                .by_limit()
+                .into_iter()
+                .filter(|(count, _)| *count > 0)
+                .map(|(count, limit)| format!("{} {}", count, limit))
+                .collect();
+            if !outside.is_empty() {
+                return Err(Error::InvalidTimestamp(format!(
+                    "Some events are logged at times CloudWatch Logs won't accept ({}), so nothing was sent",
+                    outside.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How far ahead of now (in milliseconds) an event can be before CloudWatch Logs rejects it
+pub(crate) const MAX_EVENT_LEAD: i64 = 2 * 60 * 60 * 1000;
+
+/// Move events sharing a timestamp a millisecond apart, so they keep their order
+///
+/// CloudWatch Logs shows events logged in the same millisecond in any order it
+/// likes, which shuffles the lines of a stack trace.  Each event is moved to at
+/// least a millisecond after the one before it, but never so far ahead of `now`
+/// that it would be rejected; past that point they're left sharing a timestamp.
+fn space_out_timestamps(events: &mut [InputLogEvent], now: i64, summary: &mut UploadSummary) {
+    let latest = now + MAX_EVENT_LEAD - EVENT_AGE_MARGIN;
+    let mut previous: Option<i64> = None;
+
+    for event in events {
+        let mut timestamp = event.timestamp.unwrap_or(now);
+        if let Some(previous) = previous.filter(|&previous| timestamp <= previous) {
+            if previous < latest {
+                timestamp = previous + 1;
+            } else {
+                timestamp = previous;
+                summary.events_sharing_timestamp += 1;
+            }
+        }
+        event.timestamp = Some(timestamp);
+        previous = Some(timestamp);
+    }
+}
+
+/// Get a batch of events ready to send, in time order with distinct timestamps
+///
+/// Events timestamped from their lines are sorted (unless --no-sort-events says not
+/// to), while those timestamped with when they were read are spaced out.  Only the
+/// events given are rearranged, so when a file is sent in several batches, each is
+/// sorted on its own and nothing moves between a batch and any sent before it.
+pub(crate) fn arrange_events(
+    events: &mut [InputLogEvent],
+    options: &EventOptions,
+    now: i64,
+    summary: &mut UploadSummary,
+) -> Result<(), Error> {
+    if !options.reads_timestamps() {
+        space_out_timestamps(events, now, summary);
+    } else if !options.no_sort_events {
+        order_events(events, options.out_of_order)?;
+    }
+    Ok(())
+}
+
+/// Put events in the time order CloudWatch Logs insists on, unless told to give up
+///
+/// The sort is stable, so events logged at the same time keep their order.
+fn order_events(events: &mut [InputLogEvent], policy: OutOfOrder) -> Result<(), Error> {
+    let backwards = events
+        .windows(2)
+        .find(|pair| pair[1].timestamp < pair[0].timestamp);
+    match (backwards, policy) {
+        (None, _) => Ok(()),
+        (Some(_), OutOfOrder::Sort) => {
+            events.sort_by_key(|event| event.timestamp);
+            Ok(())
+        }
+        (Some(pair), OutOfOrder::Fail) => {
+            let time = |event: &InputLogEvent| {
+                let millis = event.timestamp.unwrap_or_default();
+                Utc.timestamp_millis_opt(millis)
+                    .single()
+                    .map_or_else(|| millis.to_string(), |time| time.to_rfc3339())
+            };
+            Err(Error::InvalidTimestamp(format!(
+                "a line logged at {} comes after one logged at {} (use --out-of-order sort to upload it anyway)",
+                time(&pair[1]),
+                time(&pair[0])
+            )))
+        }
+    }
+}
+
+/// Turn a decoded line into the event(s) that will carry it
+///
+/// CloudWatch Logs doesn't accept blank messages, so blank lines are swapped for
+/// something else or dropped.  Lines too long for a single event are split or
+/// truncated to fit, depending on the oversize policy, so this may hand back more
+/// than one event.
+pub(crate) fn line_events(
+    timestamp: i64,
+    mut line: String,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Vec<InputLogEvent> {
+    if line.is_empty() {
+        match options.blank_lines {
+            BlankLines::Space => line.push(' '),
+            BlankLines::Skip => return Vec::new(),
+            BlankLines::Placeholder => line.push_str("(blank)"),
+        }
+    }
+
+    if line.len() <= message::MAX_MESSAGE_SIZE {
+        return vec![build_event(timestamp, line)];
+    }
+
+    match options.oversize {
+        Oversize::Split => {
+            summary.lines_split += 1;
+            message::split(line)
+                .into_iter()
+                .map(|part| build_event(timestamp, part))
+                .collect()
+        }
+        Oversize::Truncate => {
+            summary.lines_truncated += 1;
+            message::truncate(&mut line, message::MAX_MESSAGE_SIZE);
+            vec![build_event(timestamp, line)]
+        }
+        Oversize::Drop => {
+            summary.lines_dropped_oversize += 1;
+            Vec::new()
+        }
+    }
+}
+
+/// Turn a single line into an InputLogEvent
+pub fn build_event(timestamp: i64, line: String) -> InputLogEvent {
+    InputLogEvent::builder()
+        .timestamp(timestamp)
+        .message(line)
+        .build()
+}
+
+/// The bytes of the events' messages
+pub(crate) fn message_bytes(events: &[InputLogEvent]) -> usize {
+    events
+        .iter()
+        .map(|event| event.message().map_or(0, str::len))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use clap::Parser;
+    use std::io;
+    use std::time::Duration;
+
+    use std::fs::File;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_get_first_line() {
+        let events = vec![InputLogEvent::builder()
+            .timestamp(0)
+            .message(
+                "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor",
+            )
+            .build()];
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &options(1, 0),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[tokio::test]
+    async fn test_get_first_5_lines() {
+        let mut events = Vec::new();
+
+        let message = [
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor",
+            "incididunt ut labore et dolore magna aliqua. Morbi tempus iaculis urna id",
+            "volutpat. Neque viverra justo nec ultrices dui sapien eget. Cras semper auctor",
+            "neque vitae. Nam aliquam sem et tortor consequat id. Sit amet cursus sit amet",
+            "dictum sit amet justo. Lobortis elementum nibh tellus molestie nunc non. Nam",
+        ];
+
+        for message in message {
+            events.push(
+                InputLogEvent::builder()
+                    .timestamp(0)
+                    .message(message)
+                    .build(),
+            );
+        }
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &options(5, 0),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[tokio::test]
+    async fn test_get_last_line() {
+        let events = vec![InputLogEvent::builder()
+            .timestamp(0)
+            .message("massa massa. Vitae proin sagittis nisl rhoncus mattis rhoncus urna.")
+            .build()];
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &options(0, 1),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[tokio::test]
+    async fn test_get_last_5_lines() {
+        let mut events = Vec::new();
+
+        let message = [
+            "egestas integer eget aliquet nibh. Porttitor rhoncus dolor purus non. Fermentum",
+            "dui faucibus in ornare quam viverra. Lectus magna fringilla urna porttitor",
+            "rhoncus dolor purus. Varius duis at consectetur lorem donec. Urna duis",
+            "convallis convallis tellus id. Egestas sed tempus urna et pharetra pharetra",
+            "massa massa. Vitae proin sagittis nisl rhoncus mattis rhoncus urna.",
+        ];
+
+        for message in message {
+            events.push(
+                InputLogEvent::builder()
+                    .timestamp(0)
+                    .message(message)
+                    .build(),
+            );
+        }
+
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &options(0, 5),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[tokio::test]
+    async fn test_get_first_and_last_5_lines() {
+        let mut events = Vec::new();
+
+        let message = [
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor",
+            "incididunt ut labore et dolore magna aliqua. Morbi tempus iaculis urna id",
+            "volutpat. Neque viverra justo nec ultrices dui sapien eget. Cras semper auctor",
+            "neque vitae. Nam aliquam sem et tortor consequat id. Sit amet cursus sit amet",
+            "dictum sit amet justo. Lobortis elementum nibh tellus molestie nunc non. Nam",
+            "----- 45 lines omitted -----",
+            "egestas integer eget aliquet nibh. Porttitor rhoncus dolor purus non. Fermentum",
+            "dui faucibus in ornare quam viverra. Lectus magna fringilla urna porttitor",
+            "rhoncus dolor purus. Varius duis at consectetur lorem donec. Urna duis",
+            "convallis convallis tellus id. Egestas sed tempus urna et pharetra pharetra",
+            "massa massa. Vitae proin sagittis nisl rhoncus mattis rhoncus urna.",
+        ];
+
+        for message in message {
+            events.push(
+                InputLogEvent::builder()
+                    .timestamp(0)
+                    .message(message)
+                    .build(),
+            );
+        }
+
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &options(5, 5),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[test]
+    fn test_read_last_lines_without_seek() {
+        // A Cursor stands in for a pipe: it is only ever read front to back
+        let input = io::Cursor::new("one\ntwo\n\nfour\nfive\n");
+
+        let events = [" ", "four", "five"]
+            .map(|m| InputLogEvent::builder().timestamp(0).message(m).build())
+            .to_vec();
+        assert_eq!(
+            events,
+            reset_timestamp(
+                read_events(input, &options(0, 3), &mut UploadSummary::default()).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_head_and_tail_overlap() {
+        let input = io::Cursor::new("one\ntwo\nthree\n");
+
+        let events = ["one", "two", "three"]
+            .map(|m| InputLogEvent::builder().timestamp(0).message(m).build())
+            .to_vec();
+        assert_eq!(
+            events,
+            reset_timestamp(
+                read_events(input, &options(2, 2), &mut UploadSummary::default()).unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_file() {
+        let path = "tests/fixtures/does-not-exist.txt".to_string();
+        let mut summary = UploadSummary::default();
+        let err = get_events(path, &options(1, 1), &mut summary)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Io(e) if e.kind() == io::ErrorKind::NotFound),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.failure(), crate::exit::Failure::Input);
+    }
+
+    #[tokio::test]
+    async fn test_collect_multiple_files() {
+        let paths = [
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            "tests/fixtures/does-not-exist.txt".to_string(),
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+        ];
+        let mut summary = UploadSummary::default();
+
+        let ret = collect_events(&paths, &options(1, 1), None, &mut summary).await;
+
+        // Limits apply per file, and each file gets a header
+        let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(messages.len(), 8);
+        assert_eq!(messages[0], "===== tests/fixtures/lorem-ipsum-5.txt =====");
+        assert_eq!(messages[2], "----- 53 lines omitted -----");
+        assert_eq!(messages[..4], messages[4..]);
+
+        assert_eq!(summary.files_read.len(), 2);
+        assert_eq!(summary.files_failed.len(), 1);
+        assert_eq!(
+            summary.files_failed[0].0,
+            "tests/fixtures/does-not-exist.txt"
+        );
+        assert!(!summary.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_read_files_apart() {
+        let paths = [
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            "tests/fixtures/does-not-exist.txt".to_string(),
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+        ];
+        let mut summary = UploadSummary::default();
+
+        let files = read_files(&paths, &options(1, 1), None, &mut summary).await;
+
+        // No headers, as each file's events are kept to themselves
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "tests/fixtures/lorem-ipsum-5.txt");
+        let messages = |events: &[InputLogEvent]| -> Vec<_> {
+            events.iter().map(|e| e.message.clone().unwrap()).collect()
+        };
+        assert_eq!(files[0].1.len(), 3);
+        assert_eq!(messages(&files[0].1), messages(&files[1].1));
+        assert_eq!(summary.files_failed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_files_progress() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for i in 0..10_000 {
+            writeln!(
+                file,
+                "line {} of a file big enough to report on as it's read",
+                i
+            )
+            .unwrap();
+        }
+        let paths = [
+            file.path().to_str().unwrap().to_string(),
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+        ];
+        let total = paths
+            .iter()
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum();
+        let (progress, updates) = progress::tests::recorded();
+        let options = EventOptions {
+            progress,
+            ..options(0, 0)
+        };
+
+        read_files(&paths, &options, None, &mut UploadSummary::default()).await;
+
+        let reads = progress::tests::reads(&updates.lock().unwrap());
+        assert!(reads.len() > 4, "{:?}", reads);
+        assert!(reads.iter().all(|&(_, of)| of == Some(total)));
+        assert!(progress::tests::increasing(reads.iter().map(|read| read.0)));
+        assert_eq!(reads.last(), Some(&(total, Some(total))));
+    }
+
+    #[test]
+    fn test_cap_bytes() {
+        let events = || -> Vec<_> {
+            ["aaaa", "bbbb", "cccc", "dddd"]
+                .iter()
+                .enumerate()
+                .map(|(i, message)| build_event(i as i64, message.to_string()))
+                .collect()
+        };
+        let messages = |events: &[InputLogEvent]| -> Vec<_> {
+            events.iter().map(|e| e.message.clone().unwrap()).collect()
+        };
+        let options = EventOptions::default();
+
+        let mut summary = UploadSummary::default();
+        let mut capped = events();
+        cap_bytes(&mut capped, &[], 100, &options, &mut summary);
+        assert_eq!(capped, events());
+
+        // The latest lines that fit are kept
+        let mut capped = events();
+        cap_bytes(&mut capped, &[], 9, &options, &mut summary);
+        assert_eq!(
+            messages(&capped),
+            ["----- 2 lines omitted -----", "cccc", "dddd"]
+        );
+        assert_eq!(capped[0].timestamp, Some(1));
+        assert_eq!((summary.lines_capped, summary.bytes_capped), (2, 8));
+
+        let mut capped = events();
+        cap_bytes(&mut capped, &[], 3, &options, &mut summary);
+        assert_eq!(messages(&capped), ["----- 4 lines omitted -----"]);
+
+        let options = EventOptions {
+            no_omission_marker: true,
+            ..Default::default()
+        };
+        let mut capped = events();
+        cap_bytes(&mut capped, &[], 4, &options, &mut summary);
+        assert_eq!(messages(&capped), ["dddd"]);
+    }
+
+    #[test]
+    fn test_cap_bytes_with_head_and_tail() {
+        let head_and_tail = || -> Vec<_> {
+            ["h1", "h2", &message::omission_marker(50), "t1", "t2"]
+                .iter()
+                .map(|message| build_event(0, message.to_string()))
+                .collect()
+        };
+        let messages = |events: &[InputLogEvent]| -> Vec<_> {
+            events.iter().map(|e| e.message.clone().unwrap()).collect()
+        };
+        let options = EventOptions::default();
+        let mut summary = UploadSummary::default();
+
+        // The tail is kept first, then as much of the end of the head as fits (the
+        // marker between them doesn't count)
+        let mut capped = head_and_tail();
+        cap_bytes(&mut capped, &[(2, 50)], 6, &options, &mut summary);
+        assert_eq!(
+            messages(&capped),
+            [
+                "----- 1 line omitted -----",
+                "h2",
+                "----- 50 lines omitted -----",
+                "t1",
+                "t2"
+            ]
+        );
+
+        // Leaving none of the head, the markers become one
+        let mut capped = head_and_tail();
+        cap_bytes(&mut capped, &[(2, 50)], 4, &options, &mut summary);
+        assert_eq!(
+            messages(&capped),
+            ["----- 52 lines omitted -----", "t1", "t2"]
+        );
+        assert_eq!((summary.lines_capped, summary.bytes_capped), (3, 6));
+
+        // A line that only reads like a marker is left out as a line of its own
+        let mut capped: Vec<_> = [&message::omission_marker(7), "t1"]
+            .iter()
+            .map(|message| build_event(0, message.to_string()))
+            .collect();
+        cap_bytes(&mut capped, &[], 2, &options, &mut summary);
+        assert_eq!(messages(&capped), ["----- 1 line omitted -----", "t1"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes_with_head_and_tail() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "h1\nh2\nm1\nm2\nm3\nm4\nm5\nt1\nt2").unwrap();
+        let path = file.path().to_str().unwrap();
+        for mmap in [false, true] {
+            let options = EventOptions {
+                head: 2,
+                tail: 2,
+                max_bytes: Some(4),
+                mmap,
+                ..Default::default()
+            };
+            let mut summary = UploadSummary::default();
+            let events = get_events(path.to_string(), &options, &mut summary)
+                .await
+                .unwrap();
+            let messages: Vec<_> = events.iter().filter_map(|e| e.message()).collect();
+            assert_eq!(messages, ["----- 7 lines omitted -----", "t1", "t2"]);
+            assert_eq!(summary.lines_capped, 2);
+        }
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let mut summary = UploadSummary::default();
+        let mut expand_globs = |patterns: &[String], allow_empty| {
+            expand_globs(patterns, allow_empty, false, &mut summary)
+        };
+        let patterns = [
+            "tests/fixtures/glob/*.log".to_string(),
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+        ];
+        assert_eq!(
+            expand_globs(&patterns, false).unwrap(),
+            [
+                "tests/fixtures/glob/a.log",
+                "tests/fixtures/glob/b.log",
+                "tests/fixtures/lorem-ipsum-5.txt",
+            ]
+        );
+
+        // Hidden files need to be asked for explicitly
+        let patterns = ["tests/fixtures/glob/.*.log".to_string()];
+        assert_eq!(
+            expand_globs(&patterns, false).unwrap(),
+            ["tests/fixtures/glob/.hidden.log"]
+        );
+    }
+
+    #[test]
+    fn test_expand_globs_without_matches() {
+        let mut summary = UploadSummary::default();
+        let mut expand_globs = |patterns: &[String], allow_empty| {
+            expand_globs(patterns, allow_empty, false, &mut summary)
+        };
+        let patterns = ["tests/fixtures/glob/*.gz".to_string()];
+        assert!(expand_globs(&patterns, false)
+            .unwrap_err()
+            .contains("--allow-empty-glob"));
+        assert!(expand_globs(&patterns, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expand_globs_directories() {
+        let patterns = ["tests/fixtures/tr*".to_string()];
+        let mut summary = UploadSummary::default();
+        // Left out unless they're to be walked
+        assert!(expand_globs(&patterns, true, false, &mut summary)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            expand_globs(&patterns, false, true, &mut summary).unwrap(),
+            ["tests/fixtures/tree"]
+        );
+        assert!(summary.files_failed.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand_globs_unreadable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("a.log"), "a\n").unwrap();
+        fs::write(dir.path().join("b.log"), "b\n").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let readable = fs::read_dir(&locked).is_ok();
+        let pattern = format!("{}/*/*.log", dir.path().display());
+        let mut summary = UploadSummary::default();
+        let expanded = expand_globs(&[pattern], false, false, &mut summary);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            // As root, say, which can read it all the same
+            return;
+        }
+
+        // Reported as failing to be read, not as matching nothing
+        assert!(expanded.unwrap().is_empty());
+        assert_eq!(summary.files_failed.len(), 1);
+        assert_eq!(summary.files_failed[0].0, locked.display().to_string());
+    }
+
+    #[test]
+    fn test_expand_directories() {
+        let paths = ["tests/fixtures/tree".to_string()];
+        let excludes = [glob::Pattern::new("*.gz").unwrap()];
+
+        // Without --recursive a directory is a failure
+        let mut summary = UploadSummary::default();
+        assert!(expand_directories(&paths, false, &excludes, &mut summary).is_empty());
+        assert_eq!(summary.files_failed.len(), 1);
+
+        // The symlink back up the tree is only visited once
+        let mut summary = UploadSummary::default();
+        assert_eq!(
+            expand_directories(&paths, true, &excludes, &mut summary),
+            [
+                "tests/fixtures/tree/nested/two.log",
+                "tests/fixtures/tree/one.log",
+            ]
+        );
+        assert_eq!(
+            summary.files_skipped,
+            [
+                (
+                    "tests/fixtures/tree/nested/loop".to_string(),
+                    "directory already visited".to_string()
+                ),
+                (
+                    "tests/fixtures/tree/one.log.1.gz".to_string(),
+                    "excluded".to_string()
+                ),
+            ]
+        );
+        assert!(summary.is_success());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn test_get_last_line_compressed() {
+        let events = vec![InputLogEvent::builder()
+            .timestamp(0)
+            .message("massa massa. Vitae proin sagittis nisl rhoncus mattis rhoncus urna.")
+            .build()];
+        let ret = get_events(
+            "tests/fixtures/lorem-ipsum-5.txt.gz".to_string(),
+            &options(0, 1),
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(events, reset_timestamp(ret));
+    }
+
+    #[tokio::test]
+    async fn test_get_latin1_lines() {
+        let path = "tests/fixtures/latin1.txt".to_string();
+
+        let mut summary = UploadSummary::default();
+        let ret = get_events(path.clone(), &options(0, 0), &mut summary)
+            .await
+            .unwrap();
+        let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(
+            messages,
+            ["caf\u{FFFD} au lait", "na\u{FFFD}ve", "plain ascii"]
+        );
+        assert_eq!(summary.lines_replaced, 2);
+
+        let skip = EventOptions {
+            encoding_errors: EncodingErrors::Skip,
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let ret = get_events(path, &skip, &mut summary).await.unwrap();
+        assert_eq!(ret.len(), 1);
+        assert_eq!(summary.lines_skipped_encoding, 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_encoded_lines() {
+        let latin1 = EventOptions {
+            encoding: Some(Encoding::Latin1),
+            ..options(0, 0)
+        };
+        let mut summary = UploadSummary::default();
+        let ret = get_events(
+            "tests/fixtures/latin1.txt".to_string(),
+            &latin1,
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(messages, ["café au lait", "naïve", "plain ascii"]);
+        assert_eq!(summary.lines_replaced, 0);
+
+        // Byte order marks give the encoding away, without --encoding
+        for path in ["utf16le.txt", "utf16be.txt", "utf8-bom.txt"] {
+            let path = format!("tests/fixtures/{}", path);
+            let mut summary = UploadSummary::default();
+            let ret = get_events(path.clone(), &options(0, 0), &mut summary)
+                .await
+                .unwrap();
+            let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+            assert_eq!(
+                messages,
+                ["café au lait", "naïve", "rocket \u{1F680}"],
+                "{}",
+                path
+            );
+            assert_eq!(summary.bytes_read, fs::metadata(&path).unwrap().len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_badly_encoded_lines() {
+        let path = "tests/fixtures/utf16le-invalid.txt".to_string();
+
+        let mut summary = UploadSummary::default();
+        let ret = get_events(path.clone(), &options(0, 0), &mut summary)
+            .await
+            .unwrap();
+        let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(messages, ["first", "\u{FFFD}x", "last"]);
+        assert_eq!(summary.lines_replaced, 1);
+
+        let fail = EventOptions {
+            encoding_errors: EncodingErrors::Fail,
+            ..options(0, 0)
+        };
+        let err = get_events(path, &fail, &mut summary).await.unwrap_err();
+        assert_eq!(err.to_string(), "line 2 isn't valid UTF-8");
+    }
+
+    #[tokio::test]
+    async fn test_get_binary_lines() {
+        let path = "tests/fixtures/binary-mixed.txt".to_string();
+        // The NUL byte makes this look binary, so force it through as text
+        let fail = EventOptions {
+            encoding_errors: EncodingErrors::Fail,
+            binary: BinaryPolicy::Force,
+            ..options(1, 1)
+        };
+
+        // Only the lines that would be uploaded are held to the policy
+        let mut summary = UploadSummary::default();
+        let ret = get_events(path.clone(), &fail, &mut summary).await.unwrap();
+        let messages: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(messages, ["start", "----- 2 lines omitted -----", "end"]);
+
+        let fail = EventOptions { tail: 3, ..fail };
+        let err = get_events(path, &fail, &mut summary).await.unwrap_err();
+        assert!(matches!(err, Error::Encoding { line: 2 }), "{:?}", err);
+        assert_eq!(err.to_string(), "line 2 isn't valid UTF-8");
+    }
+
+    #[tokio::test]
+    async fn test_get_binary_file() {
+        let path = "tests/fixtures/core.bin".to_string();
+
+        let mut summary = UploadSummary::default();
+        let err = get_events(path.clone(), &options(0, 0), &mut summary)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("--binary hexdump"));
+
+        let hexdump = EventOptions {
+            binary: BinaryPolicy::Hexdump,
+            ..options(0, 1)
+        };
+        let ret = get_events(path.clone(), &hexdump, &mut summary)
+            .await
+            .unwrap();
+        assert_eq!(
+            ret[0].message.as_deref(),
+            Some("00000020: 6c6f 0a77 6f72 6c64                      lo.world")
+        );
+
+        let force = EventOptions {
+            binary: BinaryPolicy::Force,
+            ..options(0, 1)
+        };
+        let ret = get_events(path.clone(), &force, &mut summary)
+            .await
+            .unwrap();
+        assert_eq!(ret[0].message.as_deref(), Some("world"));
+
+        assert_eq!(
+            summary.binary_files,
+            [
+                (path.clone(), BinaryPolicy::Hexdump, 40),
+                (path, BinaryPolicy::Force, 40)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_lines_from_fifo() {
+        let expected = [
+            (options(2, 0), vec!["one", "two"]),
+            (options(0, 2), vec!["four", "five"]),
+            (
+                options(2, 2),
+                vec!["one", "two", "----- 1 line omitted -----", "four", "five"],
+            ),
+        ];
+
+        for (options, messages) in expected {
+            let dir = tempfile::tempdir().unwrap();
+            let fifo = dir.path().join("fifo");
+            assert!(std::process::Command::new("mkfifo")
+                .arg(&fifo)
+                .status()
+                .unwrap()
+                .success());
+
+            // Opening a FIFO blocks until both ends are open, so write from elsewhere
+            let writer = {
+                let fifo = fifo.clone();
+                std::thread::spawn(move || fs::write(fifo, "one\ntwo\nthree\nfour\nfive\n"))
+            };
+
+            let path = fifo.display().to_string();
+            let mut summary = UploadSummary::default();
+            let ret = get_events(path, &options, &mut summary).await.unwrap();
+            writer.join().unwrap().unwrap();
+
+            let ret: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+            assert_eq!(ret, messages);
+        }
+    }
+
+    #[test]
+    fn test_large_file_is_read_once() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut writer = io::BufWriter::new(&mut file);
+            for n in 0..200_000 {
+                writeln!(writer, "line {} of a rather large generated log file", n).unwrap();
+            }
+        }
+        let size = file.as_file().metadata().unwrap().len();
+
+        let reader = BufReader::new(File::open(file.path()).unwrap());
+        let mut counter = input::CountingReader::new(reader);
+        let ret = read_events(&mut counter, &options(3, 3), &mut UploadSummary::default()).unwrap();
+
+        let ret: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(ret[0], "line 0 of a rather large generated log file");
+        assert_eq!(ret[3], "----- 199,994 lines omitted -----");
+        assert_eq!(ret[6], "line 199999 of a rather large generated log file");
+        // Every byte was read exactly once
+        assert_eq!(counter.count(), size);
+    }
+
+    #[tokio::test]
+    async fn test_mmap_matches_buffered_reading() {
+        let paths = [
+            "tests/fixtures/lorem-ipsum-5.txt",
+            "tests/fixtures/latin1.txt",
+            "tests/fixtures/lorem-ipsum-5.txt.gz",
+            "tests/fixtures/core.bin",
+        ];
+        let limits = [(0, 0), (1, 0), (0, 1), (5, 0), (0, 5), (5, 5), (3, 100)];
+
+        for path in paths {
+            for (head, tail) in limits {
+                let mut buffered = UploadSummary::default();
+                let expected = get_events(path.to_string(), &options(head, tail), &mut buffered)
+                    .await
+                    .map(reset_timestamp)
+                    .map_err(|e| e.to_string());
+
+                let mmap = EventOptions {
+                    mmap: true,
+                    ..options(head, tail)
+                };
+                let mut mapped = UploadSummary::default();
+                let ret = get_events(path.to_string(), &mmap, &mut mapped)
+                    .await
+                    .map(reset_timestamp)
+                    .map_err(|e| e.to_string());
+
+                assert_eq!(expected, ret);
+                assert_eq!(buffered.lines_replaced, mapped.lines_replaced);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reads_less() {
+        let mmap = EventOptions {
+            mmap: true,
+            ..options(0, 1)
+        };
+        let mut summary = UploadSummary::default();
+        get_events(
+            "tests/fixtures/lorem-ipsum-5.txt".to_string(),
+            &mmap,
+            &mut summary,
+        )
+        .await
+        .unwrap();
+
+        let last = "massa massa. Vitae proin sagittis nisl rhoncus mattis rhoncus urna.\n";
+        assert_eq!(summary.bytes_read, last.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_get_crlf_and_cr_lines() {
+        for path in ["tests/fixtures/crlf.txt", "tests/fixtures/cr.txt"] {
+            for mmap in [false, true] {
+                let get = |head, tail| {
+                    let options = EventOptions {
+                        mmap,
+                        ..options(head, tail)
+                    };
+                    async move {
+                        let mut summary = UploadSummary::default();
+                        let ret = get_events(path.to_string(), &options, &mut summary)
+                            .await
+                            .unwrap();
+                        ret.into_iter()
+                            .map(|e| e.message.unwrap())
+                            .collect::<Vec<_>>()
+                    }
+                };
+
+                assert_eq!(get(0, 0).await, ["one", "two", " ", "four", "five"]);
+                assert_eq!(get(3, 0).await, ["one", "two", " "]);
+                assert_eq!(get(0, 3).await, [" ", "four", "five"]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_oversized_line() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "before\n{}\nafter", "x".repeat(300 * 1024)).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+
+        let mut summary = UploadSummary::default();
+        let events = get_events(path.clone(), &options(0, 0), &mut summary)
+            .await
+            .unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0], "before");
+        assert!(messages[1].starts_with("[part 1/2] xxx"));
+        assert!(messages[2].starts_with("[part 2/2] xxx"));
+        assert_eq!(messages[3], "after");
+        assert_eq!(messages[1].len() + messages[2].len(), 300 * 1024 + 22);
+        assert_eq!((summary.lines_split, summary.lines_truncated), (1, 0));
+
+        let options = EventOptions {
+            oversize: Oversize::Truncate,
+            ..options(0, 0)
+        };
+        let mut summary = UploadSummary::default();
+        let events = get_events(path.clone(), &options, &mut summary)
+            .await
+            .unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[1].starts_with("xxx"));
+        assert!(messages[1].ends_with("x…[truncated 44 KB]"));
+        assert!(messages[1].len() <= message::MAX_MESSAGE_SIZE);
+        assert_eq!((summary.lines_split, summary.lines_truncated), (0, 1));
+
+        let options = EventOptions {
+            oversize: Oversize::Drop,
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let events = get_events(path, &options, &mut summary).await.unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(messages, ["before", "after"]);
+        assert_eq!(summary.lines_dropped_oversize, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_blank_lines() {
+        let get = |blank_lines, head, tail| {
+            let options = EventOptions {
+                blank_lines,
+                ..options(head, tail)
+            };
+            async move {
+                let events = get_events(
+                    String::from("tests/fixtures/blank-lines.txt"),
+                    &options,
+                    &mut UploadSummary::default(),
+                )
+                .await
+                .unwrap();
+                events
+                    .into_iter()
+                    .map(|e| e.message.unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            get(BlankLines::Space, 0, 0).await,
+            ["one", " ", "two", " ", " ", "three", " ", "four"]
+        );
+        assert_eq!(
+            get(BlankLines::Skip, 0, 0).await,
+            ["one", "two", "three", "four"]
+        );
+        assert_eq!(
+            get(BlankLines::Placeholder, 0, 0).await,
+            ["one", "(blank)", "two", "(blank)", "(blank)", "three", "(blank)", "four"]
+        );
+
+        // Skipped blank lines still count towards the head and tail
+        assert_eq!(get(BlankLines::Skip, 3, 0).await, ["one", "two"]);
+        assert_eq!(get(BlankLines::Skip, 0, 3).await, ["three", "four"]);
+        assert_eq!(
+            get(BlankLines::Placeholder, 0, 3).await,
+            ["three", "(blank)", "four"]
+        );
+    }
+
+    #[test]
+    fn test_parse_line_range() {
+        let range = |start, end| Ok(LineRange { start, end });
+        assert_eq!(LineRange::parse("120:180"), range(120, Some(180)));
+        assert_eq!(LineRange::parse("500:"), range(500, None));
+        assert_eq!(LineRange::parse(":200"), range(1, Some(200)));
+        assert_eq!(LineRange::parse("7:7"), range(7, Some(7)));
+
+        assert!(LineRange::parse("120").is_err());
+        assert!(LineRange::parse("0:5").is_err());
+        assert!(LineRange::parse("9:5").is_err());
+        assert!(LineRange::parse("a:b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_line_ranges() {
+        let get = |ranges: &[&str]| {
+            let options = EventOptions {
+                lines: ranges
+                    .iter()
+                    .map(|r| LineRange::parse(r).unwrap())
+                    .collect(),
+                ..options(0, 0)
+            };
+            async move {
+                let events = get_events(
+                    String::from("tests/fixtures/lorem-ipsum-5.txt"),
+                    &options,
+                    &mut UploadSummary::default(),
+                )
+                .await
+                .unwrap();
+                events.len()
+            }
+        };
+
+        assert_eq!(get(&["2:4"]).await, 3);
+        assert_eq!(get(&["50:"]).await, 6);
+        // Ranges past the end of the file are clamped
+        assert_eq!(get(&["54:500"]).await, 2);
+        assert_eq!(get(&["60:"]).await, 0);
+        // Overlapping ranges don't repeat lines
+        assert_eq!(get(&["1:2", "5:5", "2:3"]).await, 4);
+    }
+
+    #[test]
+    fn test_read_line_ranges() {
+        let input = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let options = EventOptions {
+            lines: vec![
+                LineRange::parse("6:7").unwrap(),
+                LineRange::parse(":2").unwrap(),
+            ],
+            ..options(0, 0)
+        };
+
+        let events =
+            read_events(input.as_bytes(), &options, &mut UploadSummary::default()).unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(messages, ["1", "2", "6", "7"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_from_start_offset() {
+        let path = String::from("tests/fixtures/lorem-ipsum-5.txt");
+        let size = fs::metadata(&path).unwrap().len();
+
+        // Read the head, then pick up from wherever that got to
+        let mut summary = UploadSummary::default();
+        let head = get_events(path.clone(), &options(20, 0), &mut summary)
+            .await
+            .unwrap();
+        let (_, offset) = summary.final_offsets[0];
+
+        let resumed = EventOptions {
+            start_offset: offset,
+            ..options(0, 0)
+        };
+        let mut summary = UploadSummary::default();
+        let rest = get_events(path.clone(), &resumed, &mut summary)
+            .await
+            .unwrap();
+        assert_eq!(head.len() + rest.len(), 55);
+        assert_eq!(summary.final_offsets, [(path.clone(), size)]);
+
+        // Landing part way through a line skips the rest of it
+        let resumed = EventOptions {
+            start_offset: offset - 5,
+            ..options(0, 0)
+        };
+        let events = get_events(path.clone(), &resumed, &mut UploadSummary::default())
+            .await
+            .unwrap();
+        assert_eq!(events.len(), rest.len());
+
+        // Offsets past the end give nothing, but aren't an error
+        let past = EventOptions {
+            start_offset: size + 100,
+            ..options(0, 0)
+        };
+        let mut summary = UploadSummary::default();
+        let events = get_events(path.clone(), &past, &mut summary).await.unwrap();
+        assert!(events.is_empty());
+        assert_eq!(summary.final_offsets, [(path, size + 100)]);
+    }
+
+    #[tokio::test]
+    async fn test_mmap_final_offset() {
+        for (head, tail) in [(0, 0), (3, 0), (0, 3), (3, 3), (100, 0)] {
+            let mut offsets = Vec::new();
+            for mmap in [false, true] {
+                let options = EventOptions {
+                    mmap,
+                    ..options(head, tail)
+                };
+                let mut summary = UploadSummary::default();
+                get_events(
+                    String::from("tests/fixtures/crlf.txt"),
+                    &options,
+                    &mut summary,
+                )
+                .await
+                .unwrap();
+                offsets.push(summary.final_offsets);
+            }
+            assert_eq!(offsets[0], offsets[1], "head {} tail {}", head, tail);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collect_resumes_from_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        let paths = [log.display().to_string()];
+        fs::write(&log, "one\ntwo\n").unwrap();
+
+        let mut state = state::StateFile::open(&dir.path().join("state")).unwrap();
+        let mut summary = UploadSummary::default();
+        let ret = collect_events(&paths, &options(0, 0), Some(&mut state), &mut summary).await;
+        assert_eq!(ret.len(), 2);
+        for (path, offset) in &summary.final_offsets {
+            state.advance(path, *offset);
+        }
+        state.save().unwrap();
+
+        let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+        writeln!(file, "three").unwrap();
+
+        let mut summary = UploadSummary::default();
+        let ret = collect_events(&paths, &options(0, 0), Some(&mut state), &mut summary).await;
+        let ret: Vec<_> = ret.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(ret, ["three"]);
+    }
+
+    #[tokio::test]
+    async fn test_tail_reads_backwards() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut writer = io::BufWriter::new(&mut file);
+            for n in 0..200_000 {
+                writeln!(writer, "line {} of a rather large generated log file", n).unwrap();
+            }
+        }
+        let size = file.as_file().metadata().unwrap().len();
+
+        let mut summary = UploadSummary::default();
+        let path = file.path().to_str().unwrap().to_string();
+        let ret = get_events(path, &options(0, 3), &mut summary)
+            .await
+            .unwrap();
+
+        let ret: Vec<_> = ret.iter().map(|e| e.message.as_deref().unwrap()).collect();
+        assert_eq!(
+            ret,
+            [
+                "line 199997 of a rather large generated log file",
+                "line 199998 of a rather large generated log file",
+                "line 199999 of a rather large generated log file",
+            ]
+        );
+        // Only the block at the end of the file was read
+        assert!(
+            summary.bytes_read < size / 100,
+            "{} of {}",
+            summary.bytes_read,
+            size
+        );
+    }
+
+    #[tokio::test]
+    async fn test_omission_marker() {
+        let get = |options: EventOptions| async move {
+            let events = get_events(
+                String::from("tests/fixtures/crlf.txt"),
+                &options,
+                &mut UploadSummary::default(),
+            )
+            .await
+            .unwrap();
+            events
+                .into_iter()
+                .map(|e| e.message.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        for mmap in [false, true] {
+            let with = |head, tail| EventOptions {
+                mmap,
+                ..options(head, tail)
+            };
+            assert_eq!(
+                get(with(1, 1)).await,
+                ["one", "----- 3 lines omitted -----", "five"]
+            );
+            // Nothing left out, so no marker
+            assert_eq!(get(with(2, 3)).await, ["one", "two", " ", "four", "five"]);
+            assert_eq!(get(with(4, 4)).await.len(), 5);
+
+            let without = EventOptions {
+                no_omission_marker: true,
+                ..with(1, 1)
+            };
+            assert_eq!(get(without).await, ["one", "five"]);
+        }
+    }
+
+    #[test]
+    fn test_sample_every_nth_line() {
+        let input = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let sample = |options: EventOptions| {
+            let mut summary = UploadSummary::default();
+            let events = read_events(input.as_bytes(), &options, &mut summary).unwrap();
+            let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+            (messages.join(" "), summary.lines_sampled_out)
+        };
+
+        let every = |n, head, tail| EventOptions {
+            sample: Some(n),
+            no_omission_marker: true,
+            ..options(head, tail)
+        };
+        assert_eq!(sample(every(3, 0, 0)), (String::from("1 4 7 10"), 6));
+        assert_eq!(sample(every(1, 0, 0)).1, 0);
+        // Sampling happens after the head and tail are picked out
+        assert_eq!(sample(every(2, 3, 3)), (String::from("1 3 9"), 3));
+    }
+
+    #[test]
+    fn test_sample_random_is_seedable() {
+        let input: String = (0..1000).map(|n| format!("{}\n", n)).collect();
+        let sample = |seed| {
+            let options = EventOptions {
+                sample_random: Some(0.1),
+                sample_seed: Some(seed),
+                ..options(0, 0)
+            };
+            let mut summary = UploadSummary::default();
+            let events = read_events(input.as_bytes(), &options, &mut summary).unwrap();
+            assert_eq!(events.len() + summary.lines_sampled_out, 1000);
+            events
+                .into_iter()
+                .map(|e| e.message.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let sampled = sample(42);
+        assert_eq!(sampled, sample(42));
+        assert_ne!(sampled, sample(43));
+        assert!((50..150).contains(&sampled.len()), "{}", sampled.len());
+    }
+
+    #[test]
+    fn test_sampled_lines_are_still_shaped() {
+        let input = format!("\nskipped\n{}\n", "x".repeat(300 * 1024));
+        let options = EventOptions {
+            sample: Some(2),
+            blank_lines: BlankLines::Placeholder,
+            ..options(0, 0)
+        };
+        let mut summary = UploadSummary::default();
+        let events = read_events(input.as_bytes(), &options, &mut summary).unwrap();
+
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], "(blank)");
+        assert!(messages[1].starts_with("[part 1/2] "));
+        assert_eq!(summary.lines_split, 1);
+    }
+
+    #[tokio::test]
+    async fn test_grep_filters_before_head_and_tail() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for n in 1..=20 {
+            let level = if n % 3 == 0 { "ERROR" } else { "INFO" };
+            writeln!(file, "{} line {}", level, n).unwrap();
+        }
+        let path = file.path().to_str().unwrap().to_string();
+
+        for mmap in [false, true] {
+            let get = |head, tail| {
+                let options = EventOptions {
+                    filter: filter::LineFilter::new(
+                        &[String::from("^ERROR")],
+                        &[],
+                        filter::PatternOptions::default(),
+                    )
+                    .unwrap(),
+                    no_omission_marker: true,
+                    mmap,
+                    ..options(head, tail)
+                };
+                let path = path.clone();
+                async move {
+                    let events = get_events(path, &options, &mut UploadSummary::default())
+                        .await
+                        .unwrap();
+                    events
+                        .into_iter()
+                        .map(|e| e.message.unwrap())
+                        .collect::<Vec<_>>()
+                }
+            };
+
+            assert_eq!(get(0, 0).await.len(), 6);
+            // The head and tail are of the matching lines, not of the whole file
+            assert_eq!(get(2, 0).await, ["ERROR line 3", "ERROR line 6"]);
+            assert_eq!(get(0, 2).await, ["ERROR line 15", "ERROR line 18"]);
+            assert_eq!(
+                get(1, 1).await,
+                ["ERROR line 3", "ERROR line 18"],
+                "mmap {}",
+                mmap
+            );
+        }
+    }
+
+    #[test]
+    fn test_grep_and_grep_v_with_head_and_tail() {
+        let input: String = (1..=30)
+            .map(|n| match n % 5 {
+                0 => format!("GET /healthz 200 ({})\n", n),
+                1 => format!("GET /orders/{} 200\n", n),
+                2 => format!("POST /orders/{} 201\n", n),
+                _ => format!("GET /assets/{}.css 304\n", n),
+            })
+            .collect();
+        let filter = filter::LineFilter::new(
+            &[String::from("^GET"), String::from("^POST")],
+            &[String::from("healthz"), String::from(r"\.css")],
+            filter::PatternOptions::default(),
+        )
+        .unwrap();
+
+        let read = |head, tail| {
+            let options = EventOptions {
+                filter: filter.clone(),
+                ..options(head, tail)
+            };
+            let mut summary = UploadSummary::default();
+            let events = read_events(input.as_bytes(), &options, &mut summary).unwrap();
+            let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+            (messages, summary.lines_excluded)
+        };
+
+        let (all, excluded) = read(0, 0);
+        assert_eq!(all.len(), 12);
+        assert_eq!(excluded, 18);
+
+        let (some, excluded) = read(2, 1);
+        assert_eq!(
+            some,
+            [
+                "GET /orders/1 200",
+                "POST /orders/2 201",
+                "----- 9 lines omitted -----",
+                "POST /orders/27 201"
+            ]
+        );
+        // Every line is still looked at, so every excluded line is counted
+        assert_eq!(excluded, 18);
+    }
+
+    #[tokio::test]
+    async fn test_multiline_records() {
+        let get = |path: &str, start: &str, head, tail| {
+            let options = EventOptions {
+                multiline_start: Some(regex::bytes::Regex::new(start).unwrap()),
+                no_omission_marker: true,
+                ..options(head, tail)
+            };
+            let path = path.to_string();
+            async move {
+                get_events(path, &options, &mut UploadSummary::default())
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|e| e.message.unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let java = "tests/fixtures/java-trace.log";
+        let records = get(java, r"^\d{4}-\d{2}-\d{2} ", 0, 0).await;
+        assert_eq!(records.len(), 4);
+        assert!(records[1].starts_with("2024-05-01 14:25:03,442 ERROR"));
+        assert!(records[1].contains("Request failed\njava.lang.IllegalStateException"));
+        assert!(records[1].ends_with("\n\t... 4 more"));
+        assert_eq!(records[1].lines().count(), 11);
+
+        let python = "tests/fixtures/python-traceback.log";
+        let records = get(python, r"^\[\d{4}-", 0, 0).await;
+        assert_eq!(records.len(), 5);
+        // Blank lines within a traceback are kept
+        assert!(records[3].contains("'abc'\n\nDuring handling"));
+        assert!(records[3].ends_with("KeyError: 'reporter'"));
+
+        // The head and tail are of records rather than lines
+        let records = get(python, r"^\[\d{4}-", 1, 2).await;
+        assert_eq!(records.len(), 3);
+        assert!(records[0].ends_with("started 4 processes"));
+        assert!(records[1].starts_with("[2024-05-01 14:25:03] ERROR"));
+        assert!(records[2].ends_with("shutting down"));
+        let records = get(java, r"^\d{4}-\d{2}-\d{2} ", 2, 0).await;
+        assert_eq!(records.len(), 2);
+        assert!(records[1].ends_with("... 4 more"));
+    }
+
+    #[test]
+    fn test_multiline_grep_and_oversize() {
+        // A match anywhere in a record keeps the whole record
+        let options = EventOptions {
+            multiline_start: Some(regex::bytes::Regex::new("^START").unwrap()),
+            filter: filter::LineFilter::new(
+                &[String::from("Timeout")],
+                &[],
+                filter::PatternOptions::default(),
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let input = "START one\n  at a\nSTART two\n  Timeout\n  at b\n";
+        let events = read_events(input.as_bytes(), &options, &mut UploadSummary::default());
+        let messages: Vec<_> = events
+            .unwrap()
+            .into_iter()
+            .map(|e| e.message.unwrap())
+            .collect();
+        assert_eq!(messages, ["START two\n  Timeout\n  at b"]);
+
+        // A record too big for one event is split like any other oversized line
+        let frame = format!("  at {}\n", "x".repeat(1000));
+        let input = format!("START big\n{}START small\n", frame.repeat(300));
+        let options = EventOptions {
+            multiline_start: Some(regex::bytes::Regex::new("^START").unwrap()),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let events = read_events(input.as_bytes(), &options, &mut summary).unwrap();
+        let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("[part 1/2] START big\n  at x"));
+        assert!(messages[1].starts_with("[part 2/2] "));
+        assert_eq!(messages[2], "START small");
+        assert_eq!(summary.lines_split, 1);
+    }
+
+    #[tokio::test]
+    async fn test_strip_ansi() {
+        let options = EventOptions {
+            strip_ansi: true,
+            ..Default::default()
+        };
+        let events = get_events(
+            String::from("tests/fixtures/ansi.log"),
+            &options,
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap();
+        let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+        assert_eq!(
+            messages,
+            [
+                "   Compiling rusty-axe v0.1.0 (/root/crate)",
+                "warning: unused variable: `état`",
+                "2024-05-01 14:25:03 ERROR payment café declined",
+                "✓ deployed in 4.2s",
+                "See the runbook for details",
+                "⠋ Waiting for health checks",
+            ]
+        );
+
+        // The escapes don't count towards the size of the message
+        let line = "\x1b[31mx\x1b[0m".repeat(message::MAX_MESSAGE_SIZE / 4);
+        let mut summary = UploadSummary::default();
+        let events = read_events(line.as_bytes(), &options, &mut summary).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(summary.lines_split, 0);
+    }
+
+    #[tokio::test]
+    async fn test_normalize_control_chars() {
+        let get = |control_chars, tab_width| async move {
+            // The NUL makes the file look binary, so force it through as text
+            let options = EventOptions {
+                control_chars,
+                tab_width,
+                binary: BinaryPolicy::Force,
+                ..Default::default()
+            };
+            let events = get_events(
+                String::from("tests/fixtures/control-chars.txt"),
+                &options,
+                &mut UploadSummary::default(),
+            )
+            .await
+            .unwrap();
+            events
+                .into_iter()
+                .map(|e| e.message.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let messages = get(Some(ControlChars::Escape), Some(8)).await;
+        assert_eq!(
+            messages,
+            [
+                "id      level   message",
+                "42      WARN    disk\\u{0000}full",
+                "43      ERROR   bell\\u{0007} and backspace\\u{0008}",
+                "\\u{007F}done",
+            ]
+        );
+        assert!(messages.iter().all(|m| !m.chars().any(|c| c.is_control())));
+
+        let messages = get(Some(ControlChars::Placeholder), None).await;
+        assert_eq!(messages[1], "42\tWARN\tdisk\u{FFFD}full");
+        let messages = get(None, Some(4)).await;
+        assert_eq!(messages[1], "42  WARN    disk\0full");
+
+        // Sizes are checked after escaping, which makes the line longer
+        let line = "\0".repeat(message::MAX_MESSAGE_SIZE / 2);
+        for (control_chars, parts) in [
+            (None, 1),
+            (Some(ControlChars::Placeholder), 2),
+            (Some(ControlChars::Escape), 5),
+        ] {
+            let options = EventOptions {
+                control_chars,
+                binary: BinaryPolicy::Force,
+                ..Default::default()
+            };
+            let events =
+                read_events(line.as_bytes(), &options, &mut UploadSummary::default()).unwrap();
+            assert_eq!(events.len(), parts, "{:?}", control_chars);
+            assert!(events
+                .iter()
+                .all(|e| e.message().unwrap().len() <= message::MAX_MESSAGE_SIZE));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_csv() {
+        let get = |head, tail, csv_no_header| async move {
+            let options = EventOptions {
+                format: Format::Csv,
+                csv_no_header,
+                no_omission_marker: true,
+                ..options(head, tail)
+            };
+            let mut summary = UploadSummary::default();
+            let events = get_events(
+                String::from("tests/fixtures/diagnostics.csv"),
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+            let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+            (messages, summary.lines_invalid)
+        };
+
+        let (rows, invalid) = get(0, 0, false).await;
+        assert_eq!(
+            rows,
+            [
+                r#"{"host":"web-1","check":"disk","status":"ok","detail":"/var 41% used"}"#,
+                r#"{"host":"web-1","check":"memory","status":"warn","detail":"swap in use, 1.2 GB"}"#,
+                r#"{"host":"web-2","check":"disk","status":"ok","detail":""}"#,
+                r#"{"host":"web-2","check":"cert","status":"fail","detail":"expires in 3 days\nrenew with \"certbot renew\""}"#,
+                // Too few fields, so it's sent as it was
+                "web-3,disk,ok",
+                r#"{"host":"web-3","check":"memory","status":"ok","detail":"fine"}"#,
+            ]
+        );
+        assert_eq!(invalid, 1);
+
+        // The head and tail are of rows, not counting the header
+        let (rows, _) = get(1, 1, false).await;
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with(r#"{"host":"web-1","check":"disk""#));
+        assert!(rows[1].starts_with(r#"{"host":"web-3","check":"memory""#));
+
+        let (rows, invalid) = get(2, 0, true).await;
+        assert_eq!(
+            rows,
+            [
+                r#"{"col1":"host","col2":"check","col3":"status","col4":"detail"}"#,
+                r#"{"col1":"web-1","col2":"disk","col3":"ok","col4":"/var 41% used"}"#,
+            ]
+        );
+        assert_eq!(invalid, 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_json_lines() {
+        let get = |on_invalid| async move {
+            let options = EventOptions {
+                format: Format::Jsonl,
+                timestamp_field: Some(String::from("ts")),
+                on_invalid,
+                ..Default::default()
+            };
+            let mut summary = UploadSummary::default();
+            let events = get_events(
+                String::from("tests/fixtures/app.jsonl"),
+                &options,
+                &mut summary,
+            )
+            .await;
+            (events, summary.lines_invalid)
+        };
+
+        let (events, invalid) = get(OnInvalid::Raw).await;
+        let events = events.unwrap();
+        assert_eq!(invalid, 1);
+        assert_eq!(events.len(), 5);
+        // Valid lines are sent exactly as they were
+        assert_eq!(
+            events[1].message.as_deref(),
+            Some(
+                r#"{"ts":1714573503,"level":"error","msg":"request failed","req":{"id":"r-1182","path":"/orders"}}"#
+            )
+        );
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| e.timestamp.unwrap())
+                .collect::<Vec<_>>()[..2],
+            [1714573500120, 1714573503000]
+        );
+        // Lines without a timestamp of their own get the one before
+        assert!(events[2].message.as_deref().unwrap().starts_with("panic:"));
+        assert_eq!(events[2].timestamp, Some(1714573503000));
+        assert_eq!(events[3].timestamp, Some(1714573504000));
+        assert_eq!(events[4].timestamp, Some(1714573504000));
+
+        let (events, invalid) = get(OnInvalid::Skip).await;
+        assert_eq!(events.unwrap().len(), 4);
+        assert_eq!(invalid, 1);
+
+        let err = get(OnInvalid::Fail).await.0.unwrap_err();
+        assert_eq!(err.to_string(), "line 3 isn't valid JSON");
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_format() {
+        let options = EventOptions {
+            timestamp_format: Some(timestamp::LineFormat::new("%b %e %H:%M:%S", None, None)),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let events = get_events(
+            "tests/fixtures/syslog.log".to_string(),
+            &options,
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let year = chrono::Datelike::year(&Utc::now());
+        let millis = |time: &str| {
+            let time = format!("{}-05-01T{}Z", year, time);
+            DateTime::parse_from_rfc3339(&time)
+                .unwrap()
+                .timestamp_millis()
+        };
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(
+            timestamps,
+            [
+                // Before the first timestamp, and in the middle of a traceback
+                millis("14:25:00"),
+                millis("14:25:00"),
+                millis("14:25:07"),
+                millis("14:25:07"),
+                millis("15:02:44"),
+            ]
+        );
+        assert_eq!(summary.lines_without_timestamp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_auto() {
+        let options = EventOptions {
+            timestamp: Some(timestamp::Source::Auto),
+            ..Default::default()
+        };
+        let get = |name| {
+            let options = &options;
+            async move {
+                let path = format!("tests/fixtures/timestamps/{}.log", name);
+                let mut summary = UploadSummary::default();
+                let events = get_events(path, options, &mut summary).await.unwrap();
+                let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+                (timestamps, summary.lines_without_timestamp)
+            }
+        };
+
+        for (name, last) in [
+            ("rfc3339", 1714573502500),
+            ("iso8601", 1714573502500),
+            ("apache", 1714573502000),
+            ("epoch-seconds", 1714573502500),
+            ("epoch-millis", 1714573502500),
+        ] {
+            assert_eq!(get(name).await, (vec![1714573500000, last], 0), "{}", name);
+        }
+
+        let (timestamps, _) = get("syslog").await;
+        let year = chrono::Datelike::year(&Utc::now());
+        let started = format!("{}-05-01T14:25:00Z", year);
+        let started = DateTime::parse_from_rfc3339(&started).unwrap();
+        assert_eq!(
+            timestamps,
+            [
+                started.timestamp_millis(),
+                started.timestamp_millis() + 2000
+            ]
+        );
+
+        // Without anything recognisable, lines get the time they were read
+        let before = now_millis();
+        let (timestamps, without) = get("none").await;
+        assert!(timestamps.iter().all(|&timestamp| timestamp >= before));
+        assert_eq!(without, 2);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_mtime() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::copy("tests/fixtures/timestamps/none.log", file.path()).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let options = EventOptions {
+            timestamp: Some(timestamp::Source::Mtime),
+            ..Default::default()
+        };
+
+        for (days, clamped) in [(7, 0), (20, 2)] {
+            let modified = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+            file.as_file().set_modified(modified).unwrap();
+            let modified = modified_millis(&path).unwrap();
+
+            let mut summary = UploadSummary::default();
+            let mut events = get_events(path.clone(), &options, &mut summary)
+                .await
+                .unwrap();
+            let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+            assert_eq!(timestamps, [modified, modified + 1]);
+
+            // Only a file from more than 14 days ago is too old for CloudWatch Logs
+            check_event_times(
+                &mut events,
+                OutOfRange::Clamp,
+                now_millis(),
+                None,
+                &mut summary,
+            )
+            .unwrap();
+            assert_eq!(summary.events_clamped.too_old, clamped, "{} days", days);
+        }
+
+        let err = get_events(
+            STDIN_PATH.to_string(),
+            &options,
+            &mut UploadSummary::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidTimestamp(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_keep_line_order() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt".to_string();
+        let before = now_millis();
+        let events = get_events(path, &options(2, 2), &mut UploadSummary::default())
+            .await
+            .unwrap();
+        // Including the omission marker
+        assert_eq!(events.len(), 5);
+        let first = events[0].timestamp.unwrap();
+        assert!(first >= before);
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, (first..first + 5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_explicit_timestamp() {
+        let at = now_millis() - 60 * 60 * 1000;
+        let args =
+            Args::try_parse_from(["rusty-axe", "-g", "group", "--timestamp", &at.to_string()])
+                .unwrap();
+        assert_eq!(args.timestamp, Some(timestamp::Source::At(at)));
+
+        let options = EventOptions {
+            base_timestamp: Some(at),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let mut events = read_events(&b"one\ntwo\nthree\n"[..], &options, &mut summary).unwrap();
+        space_out_timestamps(&mut events, now_millis(), &mut summary);
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [at, at + 1, at + 2]);
+
+        let err =
+            Args::try_parse_from(["rusty-axe", "-g", "group", "--timestamp", "0"]).unwrap_err();
+        assert!(err.to_string().contains("more than 14 days ago"));
+    }
+
+    #[test]
+    fn test_space_out_timestamps() {
+        let now = 1714573500000;
+        let mut events: Vec<_> = [now, now, now, now + 10, now + 5]
+            .into_iter()
+            .map(|timestamp| build_event(timestamp, String::from("x")))
+            .collect();
+        let mut summary = UploadSummary::default();
+        space_out_timestamps(&mut events, now, &mut summary);
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [now, now + 1, now + 2, now + 10, now + 11]);
+        assert_eq!(summary.events_sharing_timestamp, 0);
+
+        // Nothing is pushed too far into the future to be accepted
+        let latest = now + MAX_EVENT_LEAD - EVENT_AGE_MARGIN;
+        let mut events: Vec<_> = [latest - 1, latest - 1, latest - 1, latest - 1]
+            .into_iter()
+            .map(|timestamp| build_event(timestamp, String::from("x")))
+            .collect();
+        space_out_timestamps(&mut events, now, &mut summary);
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [latest - 1, latest, latest, latest]);
+        assert_eq!(summary.events_sharing_timestamp, 2);
+    }
+
+    #[test]
+    fn test_check_event_times() {
+        let now = 1714573500000;
+        let oldest = now - MAX_EVENT_AGE + EVENT_AGE_MARGIN;
+        let latest = now + MAX_EVENT_LEAD - EVENT_AGE_MARGIN;
+        let events = || {
+            [oldest - 2, oldest - 1, oldest, now, latest, latest + 1]
+                .into_iter()
+                .map(|timestamp| build_event(timestamp, String::from("x")))
+                .collect::<Vec<_>>()
+        };
+        let timestamps = |events: &[InputLogEvent]| -> Vec<i64> {
+            events.iter().map(|e| e.timestamp.unwrap()).collect()
+        };
+
+        let mut clamped = events();
+        let mut summary = UploadSummary::default();
+        check_event_times(&mut clamped, OutOfRange::Clamp, now, None, &mut summary).unwrap();
+        assert_eq!(
+            timestamps(&clamped),
+            [oldest, oldest, oldest, now, latest, latest]
+        );
+        let counts = RangeCounts {
+            too_old: 2,
+            past_retention: 0,
+            too_new: 1,
+        };
+        assert_eq!(summary.events_clamped, counts);
+
+        let mut skipped = events();
+        check_event_times(&mut skipped, OutOfRange::Skip, now, None, &mut summary).unwrap();
+        assert_eq!(timestamps(&skipped), [oldest, now, latest]);
+        assert_eq!(summary.events_skipped, counts);
+
+        let mut refused = events();
+        let err =
+            check_event_times(&mut refused, OutOfRange::Fail, now, None, &mut summary).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("2 older than 14 days, 1 more than 2 hours ahead"),
+            "{}",
+            err
+        );
+        assert_eq!(timestamps(&refused), timestamps(&events()));
+
+        let mut fine = vec![build_event(now, String::from("x"))];
+        check_event_times(&mut fine, OutOfRange::Fail, now, None, &mut summary).unwrap();
+    }
+
+    #[test]
+    fn test_check_event_times_retention() {
+        let now = 1714573500000;
+        let retained = now - 3 * DAY + EVENT_AGE_MARGIN;
+        let events = || {
+            [now - 20 * DAY, now - 5 * DAY, retained, now]
+                .into_iter()
+                .map(|timestamp| build_event(timestamp, String::from("x")))
+                .collect::<Vec<_>>()
+        };
+
+        let mut clamped = events();
+        let mut summary = UploadSummary::default();
+        check_event_times(&mut clamped, OutOfRange::Clamp, now, Some(3), &mut summary).unwrap();
+        let timestamps: Vec<_> = clamped.iter().map(|e| e.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [retained, retained, retained, now]);
+        assert_eq!(summary.events_clamped.too_old, 1);
+        assert_eq!(summary.events_clamped.past_retention, 1);
+
+        // A retention period longer than 14 days changes nothing
+        let mut skipped = events();
+        check_event_times(&mut skipped, OutOfRange::Skip, now, Some(30), &mut summary).unwrap();
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(summary.events_skipped.too_old, 1);
+        assert_eq!(summary.events_skipped.past_retention, 0);
+    }
+
+    #[tokio::test]
+    async fn test_epoch_timestamps() {
+        let timestamps = |unit| async move {
+            let path = "tests/fixtures/timestamps/epoch-mixed.log".to_string();
+            let options = EventOptions {
+                timestamp_format: Some(timestamp::LineFormat::epoch(unit, None)),
+                ..Default::default()
+            };
+            let events = get_events(path, &options, &mut UploadSummary::default())
+                .await
+                .unwrap();
+            events
+                .iter()
+                .map(|e| e.timestamp.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            timestamps(EpochUnit::Auto).await,
+            [1714573500000, 1714573500250, 1714573500500, 1714573501750]
+        );
+        // Told they're all seconds, the bigger numbers land far in the future
+        let seconds = timestamps(EpochUnit::S).await;
+        assert_eq!(seconds[..2], [1714573500000, 1714573501750]);
+    }
+
+    #[test]
+    fn test_arrange_events_per_batch() {
+        let options = EventOptions {
+            timestamp_format: Some(timestamp::LineFormat::new("%s", None, None)),
+            ..Default::default()
+        };
+        let batch = |timestamps: &[i64]| -> Vec<InputLogEvent> {
+            timestamps
+                .iter()
+                .map(|&timestamp| build_event(timestamp, timestamp.to_string()))
+                .collect()
+        };
+        let timestamps = |events: &[InputLogEvent]| -> Vec<i64> {
+            events.iter().map(|e| e.timestamp.unwrap()).collect()
+        };
+
+        let mut summary = UploadSummary::default();
+        let mut first = batch(&[30, 10, 20]);
+        arrange_events(&mut first, &options, 0, &mut summary).unwrap();
+        assert_eq!(timestamps(&first), [10, 20, 30]);
+
+        // A later batch is sorted on its own, even if it starts before the last ended
+        let mut second = batch(&[25, 5, 25]);
+        arrange_events(&mut second, &options, 0, &mut summary).unwrap();
+        assert_eq!(timestamps(&second), [5, 25, 25]);
+
+        let unsorted = EventOptions {
+            no_sort_events: true,
+            ..options
+        };
+        let mut third = batch(&[30, 10, 20]);
+        arrange_events(&mut third, &unsorted, 0, &mut summary).unwrap();
+        assert_eq!(timestamps(&third), [30, 10, 20]);
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_out_of_order() {
+        let path = "tests/fixtures/iso8601.log".to_string();
+        let options = EventOptions {
+            timestamp_format: Some(timestamp::LineFormat::new(
+                "%Y-%m-%dT%H:%M:%S%.f%#z",
+                None,
+                None,
+            )),
+            ..Default::default()
+        };
+        let events = get_events(path.clone(), &options, &mut UploadSummary::default())
+            .await
+            .unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(
+            messages,
+            [
+                "2024-05-01T14:25:00.000Z INFO writer-1 started",
+                "2024-05-01T14:25:01.500Z WARN writer-2 slow request",
+                "    took 1.2s",
+                "2024-05-01T14:25:03.250Z INFO writer-1 request served",
+                "2024-05-01T16:25:04.000+02:00 INFO writer-1 request served",
+            ]
+        );
+        assert!(events.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        let unsorted = EventOptions {
+            no_sort_events: true,
+            ..options.clone()
+        };
+        let events = get_events(path.clone(), &unsorted, &mut UploadSummary::default())
+            .await
+            .unwrap();
+        let messages: Vec<_> = events.iter().map(|e| e.message().unwrap()).collect();
+        assert_eq!(
+            messages[1],
+            "2024-05-01T14:25:03.250Z INFO writer-1 request served"
+        );
+
+        let fail = EventOptions {
+            out_of_order: OutOfOrder::Fail,
+            ..options
+        };
+        let err = get_events(path, &fail, &mut UploadSummary::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a line logged at 2024-05-01T14:25:01.500+00:00 comes after one logged at \
+             2024-05-01T14:25:03.250+00:00 (use --out-of-order sort to upload it anyway)"
+        );
+    }
+
+    #[test]
+    fn test_timestamp_regex() {
+        let args = Args::try_parse_from([
+            "rusty-axe",
+            "-f",
+            "access.log",
+            "-g",
+            "group",
+            "--timestamp-format",
+            "%d/%b/%Y:%H:%M:%S %z",
+            "--timestamp-regex",
+            r"\[([^]]+)\]",
+        ])
+        .unwrap();
+        let layout = timestamp::LineFormat::new(
+            args.timestamp_format.as_deref().unwrap(),
+            args.timestamp_regex,
+            args.timezone,
+        );
+        let options = EventOptions {
+            timestamp_format: Some(layout),
+            ..Default::default()
+        };
+        let line = b"10.0.0.1 - - [01/May/2024:16:25:00 +0200] \"GET / HTTP/1.1\" 200\n";
+        let events = read_events(&line[..], &options, &mut UploadSummary::default()).unwrap();
+        assert_eq!(events[0].timestamp, Some(1714573500000));
+
+        let bad = Args::try_parse_from(["rusty-axe", "-g", "group", "--timestamp-format", "%Q"]);
+        assert!(bad.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_since_and_until() {
+        let utc = |s| timestamp::parse_bound(s).unwrap();
+
+        for (mmap, tail) in [(false, 0), (true, 0), (true, 2), (false, 2)] {
+            let options = EventOptions {
+                since: Some(utc("2024-05-01T14:25:00Z")),
+                until: Some(utc("2024-05-01T14:40:00Z")),
+                mmap,
+                ..options(0, tail)
+            };
+            let mut summary = UploadSummary::default();
+            let events = get_events(
+                String::from("tests/fixtures/window.log"),
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+            let messages: Vec<_> = events.into_iter().map(|e| e.message.unwrap()).collect();
+
+            let expected = [
+                "2024-05-01T14:25:00Z ERROR request failed",
+                "java.lang.IllegalStateException: pool exhausted",
+                "    at com.example.Pool.take(Pool.java:42)",
+                "2024-05-01T14:32:10Z INFO recovered",
+                "2024-05-01T14:40:00Z WARN slow request",
+            ];
+            // Both edges are inside the window, as are the lines following on from them
+            let skipped = if tail == 0 { 0 } else { expected.len() - tail };
+            assert_eq!(messages, expected[skipped..], "mmap {}", mmap);
+            assert_eq!(summary.lines_outside_window, 4);
+        }
+    }
+
+    /// Options selecting the given number of head and tail lines
+    fn options(head: usize, tail: usize) -> EventOptions {
+        EventOptions {
+            head,
+            tail,
+            ..Default::default()
+        }
+    }
+
+    /// Check the events are in order, then zero their timestamps so they can be
+    /// compared with the expected events
+    fn reset_timestamp(mut events: Vec<InputLogEvent>) -> Vec<InputLogEvent> {
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        for event in events.iter_mut() {
+            event.timestamp = Some(0);
+        }
+
+        events
+    }
+}
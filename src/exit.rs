@@ -0,0 +1,117 @@
+//! The statuses the program exits with, so whatever runs it can tell one way of
+//! failing from another
+
+use crate::retry;
+
+use aws_sdk_cloudwatchlogs::Error;
+use serde::{Deserialize, Serialize};
+
+/// What `--help` says about the statuses
+pub const HELP: &str = "\
+EXIT STATUS:
+    0    Everything was read and sent
+    1    Something else went wrong
+    2    The command line was wrong
+    3    The credentials were turned down, or aren't allowed to send
+    4    A log group or stream wasn't there to send to
+    5    Only some of it was sent (some streams failed, or events were rejected)
+    6    An input file couldn't be read
+    7    A call ran out of time
+    8    An input file was over --max-file-size, and --force wasn't given";
+
+/// How a run failed, as the status the program exits with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// Anything not told apart below
+    Other = 1,
+    /// The command line was wrong, as clap exits with too
+    Usage = 2,
+    /// The credentials were turned down, or aren't allowed to make a call
+    Denied = 3,
+    /// A log group or stream wasn't there
+    NotFound = 4,
+    /// Some of what there was to send wasn't
+    Partial = 5,
+    /// An input file couldn't be read
+    Input = 6,
+    /// A call ran out of time
+    TimedOut = 7,
+    /// An input file was over --max-file-size, and sending it anyway wasn't meant
+    TooLarge = 8,
+}
+
+impl Failure {
+    /// Which failure a call to CloudWatch Logs that failed amounts to
+    pub fn of(e: &Error) -> Failure {
+        match e {
+            Error::ResourceNotFoundException(_) => Failure::NotFound,
+            e if retry::is_denied(e) => Failure::Denied,
+            e if retry::is_timeout(e) => Failure::TimedOut,
+            _ => Failure::Other,
+        }
+    }
+
+    /// The status the program exits with
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Exit the program with this failure's status
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_cloudwatchlogs::error::ResourceNotFoundException;
+
+    fn coded(code: &str) -> Error {
+        Error::Unhandled(Box::new(
+            aws_smithy_types::Error::builder()
+                .code(code)
+                .message("turned down")
+                .build(),
+        ))
+    }
+
+    #[test]
+    fn test_of() {
+        let missing = ResourceNotFoundException::builder()
+            .message("The specified log group does not exist.")
+            .build();
+        assert_eq!(
+            Failure::of(&Error::ResourceNotFoundException(missing)),
+            Failure::NotFound
+        );
+        assert_eq!(
+            Failure::of(&coded("AccessDeniedException")),
+            Failure::Denied
+        );
+        assert_eq!(
+            Failure::of(&coded("ExpiredTokenException")),
+            Failure::Denied
+        );
+        assert_eq!(Failure::of(&coded("ThrottlingException")), Failure::Other);
+    }
+
+    #[test]
+    fn test_codes() {
+        let failures = [
+            Failure::Other,
+            Failure::Usage,
+            Failure::Denied,
+            Failure::NotFound,
+            Failure::Partial,
+            Failure::Input,
+            Failure::TimedOut,
+            Failure::TooLarge,
+        ];
+        for failure in failures {
+            let line = format!("    {}    ", failure.code());
+            assert!(HELP.contains(&line), "{:?} isn't in the help", failure);
+        }
+    }
+}
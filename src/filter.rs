@@ -0,0 +1,214 @@
+//! Picking out lines by what they say
+
+use crate::uploader::UploadSummary;
+
+use regex::bytes::{RegexSet, RegexSetBuilder};
+
+/// Patterns deciding which lines are worth uploading
+///
+/// Lines are matched as raw bytes, so they can be filtered before (or without) being
+/// decoded as UTF-8.
+#[derive(Debug, Clone, Default)]
+pub struct LineFilter {
+    /// A line has to match at least one of these, if there are any
+    include: Option<RegexSet>,
+    /// A line matching any of these is left out, whatever else it matches
+    exclude: Option<RegexSet>,
+}
+
+/// How the patterns in a filter should be read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PatternOptions {
+    /// Match regardless of upper and lower case
+    pub ignore_case: bool,
+    /// Treat patterns as plain text rather than regular expressions
+    pub fixed_strings: bool,
+}
+
+impl LineFilter {
+    /// Compile the patterns given on the command line
+    ///
+    /// # Arguments
+    ///
+    /// * `include` - Patterns, any one of which a line has to match
+    /// * `exclude` - Patterns, none of which a line may match
+    /// * `options` - How to read both sets of patterns
+    ///
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        options: PatternOptions,
+    ) -> Result<LineFilter, String> {
+        Ok(LineFilter {
+            include: compile(include, options, "--grep")?,
+            exclude: compile(exclude, options, "--grep-v")?,
+        })
+    }
+
+    /// Does the filter let every line through?
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    /// Should this line be uploaded?
+    ///
+    /// Lines left out because they matched an exclude pattern are counted in the
+    /// summary, so a pattern that's too eager can be spotted.
+    pub fn keep(&self, line: &[u8], summary: &mut UploadSummary) -> bool {
+        if self.exclude.as_ref().is_some_and(|set| set.is_match(line)) {
+            summary.lines_excluded += 1;
+            return false;
+        }
+        self.include.as_ref().is_none_or(|set| set.is_match(line))
+    }
+}
+
+/// Compile a set of patterns, if there are any
+fn compile(
+    patterns: &[String],
+    options: PatternOptions,
+    flag: &str,
+) -> Result<Option<RegexSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let patterns = patterns.iter().map(|pattern| {
+        if options.fixed_strings {
+            regex::escape(pattern)
+        } else {
+            pattern.to_string()
+        }
+    });
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map(Some)
+        .map_err(|e| format!("Invalid {} pattern: {}", flag, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let filter = LineFilter::new(&[], &[], PatternOptions::default()).unwrap();
+        let mut summary = UploadSummary::default();
+        assert!(filter.is_empty());
+        assert!(filter.keep(b"anything", &mut summary));
+        assert!(filter.keep(b"caf\xe9", &mut summary));
+    }
+
+    #[test]
+    fn test_include_patterns_are_ored() {
+        let filter = LineFilter::new(
+            &patterns(&["ERROR", r"req-\d+"]),
+            &[],
+            PatternOptions::default(),
+        )
+        .unwrap();
+        let mut summary = UploadSummary::default();
+        assert!(!filter.is_empty());
+        assert!(filter.keep(b"2022-08-01 ERROR disk full", &mut summary));
+        assert!(filter.keep(b"handling req-1234", &mut summary));
+        assert!(!filter.keep(b"INFO all good", &mut summary));
+        assert!(!filter.keep(b"req-", &mut summary));
+        // Invalid UTF-8 doesn't stop a line matching
+        assert!(filter.keep(b"\xff ERROR \xfe", &mut summary));
+        // Lines that just didn't match aren't counted as excluded
+        assert_eq!(summary.lines_excluded, 0);
+    }
+
+    #[test]
+    fn test_exclude_patterns_win() {
+        let filter = LineFilter::new(
+            &patterns(&["GET"]),
+            &patterns(&["/healthz 200", "/metrics"]),
+            PatternOptions::default(),
+        )
+        .unwrap();
+        let mut summary = UploadSummary::default();
+        assert!(filter.keep(b"GET /orders 200", &mut summary));
+        assert!(!filter.keep(b"GET /healthz 200", &mut summary));
+        assert!(!filter.keep(b"GET /metrics 200", &mut summary));
+        assert!(!filter.keep(b"POST /orders 201", &mut summary));
+        assert_eq!(summary.lines_excluded, 2);
+
+        let filter =
+            LineFilter::new(&[], &patterns(&["DEBUG"]), PatternOptions::default()).unwrap();
+        assert!(!filter.is_empty());
+        assert!(filter.keep(b"INFO started", &mut summary));
+        assert!(!filter.keep(b"DEBUG detail", &mut summary));
+        assert_eq!(summary.lines_excluded, 3);
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let options = PatternOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+
+        let include = LineFilter::new(&patterns(&["error"]), &[], options).unwrap();
+        assert!(include.keep(b"ERROR: disk full", &mut summary));
+        assert!(include.keep(b"Error: disk full", &mut summary));
+        assert!(!include.keep(b"all good", &mut summary));
+
+        let exclude = LineFilter::new(&[], &patterns(&["debug"]), options).unwrap();
+        assert!(!exclude.keep(b"DEBUG: detail", &mut summary));
+        assert!(exclude.keep(b"INFO: started", &mut summary));
+
+        // Without the flag, case matters to both
+        let include = LineFilter::new(&patterns(&["error"]), &[], PatternOptions::default());
+        assert!(!include.unwrap().keep(b"ERROR: disk full", &mut summary));
+        let exclude = LineFilter::new(&[], &patterns(&["debug"]), PatternOptions::default());
+        assert!(exclude.unwrap().keep(b"DEBUG: detail", &mut summary));
+    }
+
+    #[test]
+    fn test_fixed_strings() {
+        let options = PatternOptions {
+            fixed_strings: true,
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+
+        let include = LineFilter::new(&patterns(&["[pool-1]"]), &[], options).unwrap();
+        assert!(include.keep(b"12:00:01 [pool-1] started", &mut summary));
+        assert!(!include.keep(b"12:00:01 [pool-2] started", &mut summary));
+        assert!(!include.keep(b"12:00:01 p started", &mut summary));
+
+        let exclude = LineFilter::new(&[], &patterns(&["a.b", "(unclosed"]), options).unwrap();
+        assert!(!exclude.keep(b"see a.b", &mut summary));
+        assert!(!exclude.keep(b"oops (unclosed", &mut summary));
+        assert!(exclude.keep(b"see axb", &mut summary));
+
+        // Both flags together
+        let options = PatternOptions {
+            ignore_case: true,
+            fixed_strings: true,
+        };
+        let exclude = LineFilter::new(&[], &patterns(&["[Pool-1]"]), options).unwrap();
+        assert!(!exclude.keep(b"[POOL-1] started", &mut summary));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = LineFilter::new(
+            &patterns(&["ok", "(unclosed"]),
+            &[],
+            PatternOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Invalid --grep pattern"), "{}", err);
+        let err =
+            LineFilter::new(&[], &patterns(&["[z-a]"]), PatternOptions::default()).unwrap_err();
+        assert!(err.starts_with("Invalid --grep-v pattern"), "{}", err);
+    }
+}
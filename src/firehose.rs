@@ -0,0 +1,126 @@
+//! Sending events to a Kinesis Data Firehose delivery stream instead of CloudWatch
+//! Logs, each one's message as a line of its own, for delivery to S3 and the like
+
+use crate::records::{CallFailed, Limits, PutRecords};
+use crate::retry;
+use crate::uploader::{service_config, UploadOptions};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use aws_sdk_firehose::error::{PutRecordBatchError, PutRecordBatchErrorKind};
+use aws_sdk_firehose::model::Record;
+use aws_sdk_firehose::types::{Blob, SdkError};
+use aws_sdk_firehose::Client as Firehose_Client;
+
+/// A Firehose delivery stream
+pub struct DeliveryStream {
+    client: Firehose_Client,
+    name: String,
+}
+
+impl DeliveryStream {
+    pub async fn new(options: &UploadOptions, name: &str) -> DeliveryStream {
+        let config = service_config(&options.client, "firehose", "Firehose").await;
+        DeliveryStream {
+            client: Firehose_Client::new(&config),
+            name: name.to_string(),
+        }
+    }
+}
+
+impl PutRecords for DeliveryStream {
+    const LIMITS: Limits = Limits {
+        records: 500,
+        bytes: 4 * 1024 * 1024,
+    };
+
+    fn describe(&self) -> String {
+        format!("Firehose delivery stream {}", self.name)
+    }
+
+    /// The message as a line, as Firehose runs records together in what it delivers
+    fn record(&self, event: InputLogEvent) -> Vec<u8> {
+        line(event.message.unwrap_or_default())
+    }
+
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed> {
+        let records = records
+            .into_iter()
+            .map(|data| Record::builder().data(Blob::new(data)).build())
+            .collect();
+        let output = self
+            .client
+            .put_record_batch()
+            .delivery_stream_name(&self.name)
+            .set_records(Some(records))
+            .send()
+            .await
+            .map_err(|e| CallFailed {
+                transient: is_transient(&e),
+                message: match &e {
+                    SdkError::ServiceError { err, .. } => err.to_string(),
+                    e => e.to_string(),
+                },
+            })?;
+        Ok(output
+            .request_responses()
+            .unwrap_or_default()
+            .iter()
+            .map(|response| response.error_code().map(String::from))
+            .collect())
+    }
+}
+
+/// A message as a line, ending in a newline (unless it does already)
+fn line(message: String) -> Vec<u8> {
+    let mut line = message.into_bytes();
+    if line.last() != Some(&b'\n') {
+        line.push(b'\n');
+    }
+    line
+}
+
+/// Is the error one that trying again later might get past?
+fn is_transient(e: &SdkError<PutRecordBatchError>) -> bool {
+    if let SdkError::ServiceError { err, .. } = e {
+        if let PutRecordBatchErrorKind::ServiceUnavailableException(_) = err.kind {
+            return true;
+        }
+    }
+    retry::is_transient_sdk_error(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use crate::records;
+
+    #[test]
+    fn test_batches() {
+        let config = aws_sdk_firehose::Config::builder().build();
+        let stream = DeliveryStream {
+            client: Firehose_Client::from_conf(config),
+            name: String::from("logs"),
+        };
+        let sizes = |records: Vec<Vec<u8>>| -> Vec<usize> {
+            records::batches(&stream, records)
+                .iter()
+                .map(Vec::len)
+                .collect()
+        };
+        assert_eq!(
+            sizes(vec![stream.record(build_event(0, String::from("x"))); 501]),
+            [500, 1]
+        );
+        // Four megabytes a batch, so only four records just short of a megabyte
+        let big = stream.record(build_event(0, "x".repeat(1000 * 1024)));
+        assert_eq!(sizes(vec![big; 9]), [4, 4, 1]);
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line(String::from("started")), b"started\n");
+        assert_eq!(line(String::from("started\n")), b"started\n");
+        assert_eq!(line(String::new()), b"\n");
+    }
+}
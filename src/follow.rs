@@ -0,0 +1,297 @@
+//! Keep shipping lines as they're appended to a file, like `tail -f`
+
+use crate::error::Error;
+use crate::events::{arrange_events, now_millis, read_events, EventOptions, LineProcessor};
+use crate::uploader::{
+    create_log_stream, lookup_host, new_client, stream_name, with_tags, UploadOptions,
+    UploadSummary,
+};
+use crate::{multiline, timestamp};
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+/// Upload a file, then keep uploading whatever gets appended to it
+///
+/// The head/tail of the existing contents are sent first, then the file is polled
+/// every `interval` and any new lines are sent as a batch.  Runs until SIGTERM or
+/// Ctrl-C, at which point anything still buffered is sent before returning.
+///
+/// # Arguments
+///
+/// * `path` - The file to follow
+/// * `options` - How to pick lines out of the existing contents
+/// * `upload` - Where and how to send the lines
+/// * `interval` - How long to wait between checks for new lines
+///
+pub async fn follow_file(
+    path: &str,
+    options: &EventOptions,
+    upload: &UploadOptions,
+    interval: Duration,
+) -> Result<(), Error> {
+    info!("Following {:?}...", path);
+
+    let mut summary = UploadSummary::default();
+    let mut follower = Follower::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Couldn't read {:?}: {}", path, e)))?;
+    let mut events = follower.read_existing(options, &mut summary)?;
+    arrange_events(&mut events, options, now_millis(), &mut summary)?;
+
+    let host = lookup_host(upload).await;
+    let name = stream_name(upload, &host, Some(path), false);
+    let upload = &with_tags(upload, &host, Some(path));
+    let mut writer = create_log_stream(upload, new_client(&upload.client).await, &name).await?;
+    if !events.is_empty() {
+        writer.write(events, &mut summary).await?;
+    }
+
+    let mut window = timestamp::TimeWindow::new(options.since, options.until);
+    let mut records = multiline::Records::new(options.multiline_start.clone());
+    let mut processor = LineProcessor::new(options);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut ticker = tokio::time::interval(interval);
+    let mut stopping = false;
+
+    while !stopping {
+        tokio::select! {
+            _ = ticker.tick() => (),
+            _ = sigterm.recv() => stopping = true,
+            _ = tokio::signal::ctrl_c() => stopping = true,
+        }
+
+        let mut lines = follower.poll()?;
+        if stopping {
+            lines.extend(follower.flush());
+        }
+
+        // A record can't be known to be finished until the next one starts, so one
+        // left waiting is only sent once a whole interval passes with nothing new
+        let quiet = lines.is_empty();
+        let mut grouped: Vec<_> = lines
+            .into_iter()
+            .filter_map(|line| records.push(0, line.into_bytes()))
+            .collect();
+        if quiet || stopping {
+            grouped.extend(records.flush());
+        }
+
+        let timestamp = now_millis();
+        for (_, record) in grouped {
+            if window.keep(&record, &mut summary) && options.filter.keep(&record, &mut summary) {
+                processor.push(timestamp, 0, &record, &mut summary)?;
+            }
+        }
+        let mut events = processor.take();
+        if events.is_empty() {
+            continue;
+        }
+        // Only within each batch; one that's already been sent can't be changed
+        arrange_events(&mut events, options, timestamp, &mut summary)?;
+        writer.write(events, &mut summary).await?;
+    }
+
+    info!("Stopped following {:?}", path);
+    summary.report();
+
+    Ok(())
+}
+
+/// A file being followed, which may be truncated or rotated underneath us
+pub struct Follower {
+    /// Where the file lives, so rotation can be spotted
+    path: PathBuf,
+    /// The currently open file, positioned after the last complete line read
+    pub reader: BufReader<File>,
+    /// The start of a line whose newline hasn't been written yet
+    partial: Vec<u8>,
+}
+
+impl Follower {
+    /// Open a file to follow from the beginning
+    pub fn open(path: &str) -> io::Result<Follower> {
+        Ok(Follower {
+            path: PathBuf::from(path),
+            reader: BufReader::new(File::open(path)?),
+            partial: Vec::new(),
+        })
+    }
+
+    /// Turn what's already in the file into events, as `options` pick them out
+    ///
+    /// Reading stops once the --head or every --lines range is done with, so the rest
+    /// of the file is skipped then: it's existing contents, not lines to follow.
+    pub fn read_existing(
+        &mut self,
+        options: &EventOptions,
+        summary: &mut UploadSummary,
+    ) -> Result<Vec<InputLogEvent>, Error> {
+        let events = read_events(&mut self.reader, options, summary)?;
+        if options.stops_early() {
+            self.reader.seek(SeekFrom::End(0))?;
+            self.partial.clear();
+        }
+        Ok(events)
+    }
+
+    /// Read any complete lines appended since the last call
+    ///
+    /// If the file has been rotated (a different file now lives at the path) the rest
+    /// of the old file is read before switching to the new one.  If it has been
+    /// truncated, reading starts again from the beginning.
+    pub fn poll(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = self.read_lines()?;
+
+        let current = match fs::metadata(&self.path) {
+            Ok(current) => current,
+            // Mid-rotation there may briefly be no file at all, so try again next time
+            Err(_) => return Ok(lines),
+        };
+
+        if current.ino() != self.reader.get_ref().metadata()?.ino() {
+            info!("{:?} was rotated, reopening", self.path);
+            lines.extend(self.flush());
+            self.reader = BufReader::new(File::open(&self.path)?);
+            lines.extend(self.read_lines()?);
+        } else if current.len() < self.reader.stream_position()? {
+            info!("{:?} was truncated, starting from the beginning", self.path);
+            self.partial.clear();
+            self.reader.rewind()?;
+            lines.extend(self.read_lines()?);
+        }
+
+        Ok(lines)
+    }
+
+    /// Hand back the unfinished line, if there is one
+    pub fn flush(&mut self) -> Option<String> {
+        if self.partial.is_empty() {
+            None
+        } else {
+            let partial = std::mem::take(&mut self.partial);
+            Some(String::from_utf8_lossy(&partial).into_owned())
+        }
+    }
+
+    /// Read lines up to the end of the file, holding on to any unfinished one
+    ///
+    /// Invalid UTF-8 is always replaced here, since there's no sensible way to stop
+    /// part way through following a file.
+    fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+
+        while self.reader.read_until(b'\n', &mut self.partial)? != 0 {
+            if self.partial.ends_with(b"\n") {
+                let mut line = std::mem::take(&mut self.partial);
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+                lines.push(String::from_utf8_lossy(&line).into_owned());
+            }
+        }
+
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_poll_appended_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "existing").unwrap();
+
+        let mut follower = Follower::open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(
+            read_events(
+                &mut follower.reader,
+                &EventOptions::default(),
+                &mut UploadSummary::default()
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+        assert!(follower.poll().unwrap().is_empty());
+
+        // Unfinished lines wait for their newline
+        write!(file, "first\nsec").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["first"]);
+        write!(file, "ond\nthi").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["second"]);
+        assert_eq!(follower.flush(), Some(String::from("thi")));
+        assert_eq!(follower.flush(), None);
+    }
+
+    #[test]
+    fn test_follow_after_head() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "one\ntwo\nthree\nfour").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let head = EventOptions {
+            head: 2,
+            ..Default::default()
+        };
+        let lines = EventOptions {
+            lines: vec![crate::events::LineRange {
+                start: 2,
+                end: Some(2),
+            }],
+            ..Default::default()
+        };
+
+        for (options, existing) in [(head, 2), (lines, 1)] {
+            let mut follower = Follower::open(&path).unwrap();
+            let events = follower
+                .read_existing(&options, &mut UploadSummary::default())
+                .unwrap();
+            assert_eq!(events.len(), existing);
+            // The rest of what was there isn't shipped as if it had just been appended
+            assert!(follower.poll().unwrap().is_empty());
+            writeln!(file, "appended").unwrap();
+            assert_eq!(follower.poll().unwrap(), ["appended"]);
+        }
+    }
+
+    #[test]
+    fn test_poll_truncated_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "one\ntwo\nthree").unwrap();
+
+        let mut follower = Follower::open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(follower.poll().unwrap(), ["one", "two", "three"]);
+
+        file.as_file().set_len(0).unwrap();
+        file.rewind().unwrap();
+        writeln!(file, "four").unwrap();
+        assert_eq!(follower.poll().unwrap(), ["four"]);
+    }
+
+    #[test]
+    fn test_poll_rotated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "one\n").unwrap();
+
+        let mut follower = Follower::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(follower.poll().unwrap(), ["one"]);
+
+        // Lines written to the old file just before rotation aren't lost
+        let mut old = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(old, "two\nunfinished").unwrap();
+        fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        fs::write(&path, "three\n").unwrap();
+
+        assert_eq!(follower.poll().unwrap(), ["two", "unfinished", "three"]);
+    }
+}
@@ -0,0 +1,188 @@
+//! Making sense of lines that hold structured data, like JSON Lines or CSV
+
+use crate::events::EpochUnit;
+use crate::timestamp;
+
+use serde_json::Value;
+
+/// Check that a line is a JSON value, and find its timestamp if asked to
+///
+/// Returns `None` if the line isn't valid JSON, otherwise the timestamp (in
+/// milliseconds since the epoch) from `timestamp_field`, if there's one that can be
+/// made sense of.
+///
+/// # Arguments
+///
+/// * `line` - The line to check
+/// * `timestamp_field` - The field holding the timestamp, with dots separating the
+///   names of nested fields (like `meta.time`)
+///
+pub fn parse_json_line(line: &str, timestamp_field: Option<&str>) -> Option<Option<i64>> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    let timestamp = timestamp_field.and_then(|field| {
+        let found = field
+            .split('.')
+            .try_fold(&value, |value, name| value.get(name))?;
+        json_timestamp(found)
+    });
+    Some(timestamp)
+}
+
+/// Milliseconds since the epoch from a JSON timestamp
+///
+/// Strings can be in any of the layouts `--since` understands.  Numbers are seconds
+/// since the epoch, unless they're too big to be, in which case they're milliseconds
+/// (or microseconds).
+fn json_timestamp(value: &Value) -> Option<i64> {
+    let epoch_millis = |number| timestamp::epoch_millis(number, EpochUnit::Auto);
+    match value {
+        Value::String(s) => match s.parse::<f64>() {
+            Ok(number) => epoch_millis(number),
+            Err(_) => timestamp::find(s.as_bytes()).map(|time| time.timestamp_millis()),
+        },
+        Value::Number(number) => epoch_millis(number.as_f64()?),
+        _ => None,
+    }
+}
+
+/// Split a CSV row into its fields
+///
+/// Fields can be quoted to hold commas, line breaks or (doubled) quotes.  Returns
+/// `None` if a quoted field is never closed or is followed by anything but a comma.
+pub fn parse_csv_row(row: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = row.chars().peekable();
+
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => break,
+                    c => field.push(c),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return None;
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Some(fields);
+        }
+    }
+}
+
+/// Turn a CSV row into a JSON object, its fields named after the columns
+///
+/// The fields keep the order of the columns, and without a header the columns are
+/// named `col1`, `col2` and so on.  Returns `None` if the row doesn't have a field
+/// for every column.
+pub fn csv_to_json(columns: Option<&[String]>, fields: &[String]) -> Option<String> {
+    if columns.is_some_and(|columns| columns.len() != fields.len()) {
+        return None;
+    }
+
+    let pairs: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let name = match columns {
+                Some(columns) => Value::from(columns[i].as_str()),
+                None => Value::from(format!("col{}", i + 1)),
+            };
+            format!("{}:{}", name, Value::from(field.as_str()))
+        })
+        .collect();
+    Some(format!("{{{}}}", pairs.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_line() {
+        assert_eq!(parse_json_line(r#"{"msg":"hi"}"#, None), Some(None));
+        assert_eq!(parse_json_line("[1, 2]", None), Some(None));
+        assert_eq!(parse_json_line(r#"{"msg":"hi""#, None), None);
+        assert_eq!(parse_json_line("plain text", None), None);
+
+        // No such field, or not a timestamp
+        let line = r#"{"ts":"soon","msg":"hi"}"#;
+        assert_eq!(parse_json_line(line, Some("time")), Some(None));
+        assert_eq!(parse_json_line(line, Some("ts")), Some(None));
+    }
+
+    #[test]
+    fn test_json_timestamps() {
+        let millis = Some(Some(1714573500000));
+        for line in [
+            r#"{"ts":"2024-05-01T14:25:00Z"}"#,
+            r#"{"ts":"2024-05-01T16:25:00+02:00"}"#,
+            r#"{"ts":1714573500}"#,
+            r#"{"ts":1714573500000}"#,
+            r#"{"ts":"1714573500"}"#,
+        ] {
+            assert_eq!(parse_json_line(line, Some("ts")), millis, "{}", line);
+        }
+        assert_eq!(
+            parse_json_line(r#"{"ts":1714573500.25}"#, Some("ts")),
+            Some(Some(1714573500250))
+        );
+        assert_eq!(
+            parse_json_line(r#"{"meta":{"time":1714573500}}"#, Some("meta.time")),
+            millis
+        );
+        assert_eq!(parse_json_line(r#"{"ts":-1}"#, Some("ts")), Some(None));
+    }
+
+    fn fields(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_csv_row() {
+        assert_eq!(parse_csv_row("a,b,c"), Some(fields(&["a", "b", "c"])));
+        assert_eq!(parse_csv_row(""), Some(fields(&[""])));
+        assert_eq!(parse_csv_row("a,,"), Some(fields(&["a", "", ""])));
+        assert_eq!(
+            parse_csv_row(r#"1,"Smith, Jane","said ""hi""",€"#),
+            Some(fields(&["1", "Smith, Jane", r#"said "hi""#, "€"]))
+        );
+        assert_eq!(
+            parse_csv_row("\"two\nlines\",x"),
+            Some(fields(&["two\nlines", "x"]))
+        );
+        assert_eq!(parse_csv_row(r#""""#), Some(fields(&[""])));
+
+        assert_eq!(parse_csv_row(r#"1,"never closed"#), None);
+        assert_eq!(parse_csv_row(r#""closed"early,2"#), None);
+    }
+
+    #[test]
+    fn test_csv_to_json() {
+        let columns = fields(&["host", "load \"1m\""]);
+        assert_eq!(
+            csv_to_json(Some(&columns), &fields(&["web-1", "0.42"])),
+            Some(String::from(r#"{"host":"web-1","load \"1m\"":"0.42"}"#))
+        );
+        assert_eq!(csv_to_json(Some(&columns), &fields(&["web-1"])), None);
+        assert_eq!(
+            csv_to_json(None, &fields(&["web-1", "0.42", "ok"])),
+            Some(String::from(
+                r#"{"col1":"web-1","col2":"0.42","col3":"ok"}"#
+            ))
+        );
+    }
+}
@@ -0,0 +1,651 @@
+//! Creating the log group (and streams in it) when it isn't there, set up the way
+//! it was asked for
+
+use crate::retry::RetryPolicy;
+use crate::template::Template;
+
+use aws_sdk_cloudwatchlogs::{Client as CWL_Client, Error};
+use std::collections::BTreeMap;
+use std::future::Future;
+use tracing::{info, warn};
+
+/// The retention periods CloudWatch Logs accepts, in days
+pub const RETENTION_DAYS: &[i32] = &[
+    1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365, 400, 545, 731, 1096, 1827, 2192, 2557, 2922,
+    3288, 3653,
+];
+
+/// The variables a tag's value can use
+pub const TAG_VARIABLES: &[&str] = &["instance_id", "filename", "version"];
+
+/// The longest tag key, and the longest tag value, AWS accepts
+const MAX_TAG_KEY: usize = 128;
+const MAX_TAG_VALUE: usize = 256;
+
+/// A tag for the log group, given as KEY=VALUE, whose value can use variables like
+/// `{instance_id}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub key: String,
+    pub value: Template,
+}
+
+impl Tag {
+    pub fn parse(s: &str) -> Result<Tag, String> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("{:?} isn't a tag like team=infra", s))?;
+        if key.is_empty() || key.chars().count() > MAX_TAG_KEY {
+            return Err(format!(
+                "{:?} isn't a tag key (they're 1 to {} characters)",
+                key, MAX_TAG_KEY
+            ));
+        }
+        if key.starts_with("aws:") {
+            return Err(format!("{:?} is a tag key only AWS can use", key));
+        }
+        Ok(Tag {
+            key: key.to_string(),
+            value: Template::parse(value, TAG_VARIABLES)?,
+        })
+    }
+}
+
+/// The tags with their values filled in (and cut short if too long)
+///
+/// A key given more than once takes its last value.
+pub fn render_tags(tags: &[Tag], values: &[(&str, &str)]) -> BTreeMap<String, String> {
+    tags.iter()
+        .map(|tag| {
+            let value = tag
+                .value
+                .render(values)
+                .chars()
+                .take(MAX_TAG_VALUE)
+                .collect();
+            (tag.key.clone(), value)
+        })
+        .collect()
+}
+
+/// Something that can create log groups and streams, and set groups up
+pub trait CreateLogs {
+    /// Create a log group, encrypted with `kms_key` if it's given
+    fn create_group(
+        &self,
+        group: &str,
+        kms_key: Option<&str>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Create a log stream in a log group that already exists
+    fn create_stream(
+        &self,
+        group: &str,
+        stream: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Have a log group keep events for `days`
+    fn set_retention(
+        &self,
+        group: &str,
+        days: i32,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Add tags to a log group
+    fn tag_group(
+        &self,
+        group: &str,
+        tags: &BTreeMap<String, String>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// The KMS key a log group is encrypted with, if it is (or exists at all)
+    fn group_kms_key(
+        &self,
+        group: &str,
+    ) -> impl Future<Output = Result<Option<String>, Error>> + Send;
+
+    /// Encrypt a log group's new events with `kms_key` from now on
+    fn associate_kms_key(
+        &self,
+        group: &str,
+        kms_key: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+impl CreateLogs for CWL_Client {
+    async fn create_group(&self, group: &str, kms_key: Option<&str>) -> Result<(), Error> {
+        self.create_log_group()
+            .log_group_name(group)
+            .set_kms_key_id(kms_key.map(String::from))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn create_stream(&self, group: &str, stream: &str) -> Result<(), Error> {
+        self.create_log_stream()
+            .log_group_name(group)
+            .log_stream_name(stream)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn set_retention(&self, group: &str, days: i32) -> Result<(), Error> {
+        self.put_retention_policy()
+            .log_group_name(group)
+            .retention_in_days(days)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn tag_group(&self, group: &str, tags: &BTreeMap<String, String>) -> Result<(), Error> {
+        self.tag_log_group()
+            .log_group_name(group)
+            .set_tags(Some(tags.clone().into_iter().collect()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn group_kms_key(&self, group: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .describe_log_groups()
+            .log_group_name_prefix(group)
+            .send()
+            .await?;
+        Ok(output
+            .log_groups()
+            .unwrap_or_default()
+            .iter()
+            .find(|found| found.log_group_name() == Some(group))
+            .and_then(|found| found.kms_key_id())
+            .map(String::from))
+    }
+
+    async fn associate_kms_key(&self, group: &str, kms_key: &str) -> Result<(), Error> {
+        self.associate_kms_key()
+            .log_group_name(group)
+            .kms_key_id(kms_key)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Whether to create the log group, and how to set it up
+#[derive(Debug, Clone, Default)]
+pub struct GroupSetup {
+    /// Create the log group if it doesn't exist
+    pub create: bool,
+    /// How many days the log group keeps events for, when it's created
+    pub retention_days: Option<i32>,
+    /// Set the retention on a log group that already exists, too
+    pub force_retention: bool,
+    /// Tags for the log group, when it's created
+    pub tags: BTreeMap<String, String>,
+    /// Add the tags to a log group that already exists, too
+    pub force_tags: bool,
+    /// The ARN of the KMS key to encrypt the log group with, when it's created
+    pub kms_key: Option<String>,
+    /// Associate the KMS key with a log group that already exists but isn't encrypted
+    /// with it, rather than only warning about it
+    pub force_kms: bool,
+}
+
+/// Create a log stream, first creating its log group if that doesn't exist and
+/// `setup` says to
+///
+/// A group that was already there is only changed if `setup` forces it.  A stream
+/// that already exists is left to the caller, as the error it is.
+pub async fn open_stream<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    stream: &str,
+    retry: &RetryPolicy,
+    setup: &GroupSetup,
+) -> Result<(), Error> {
+    let create = || retry.run("CreateLogStream", || client.create_stream(group, stream));
+    let created = match create().await {
+        Err(Error::ResourceNotFoundException(_)) if setup.create => {
+            ensure_group(client, group, retry, setup).await?;
+            return create().await;
+        }
+        created => created,
+    };
+    if matches!(
+        created,
+        Ok(()) | Err(Error::ResourceAlreadyExistsException(_))
+    ) {
+        force_setup(client, group, retry, setup).await?;
+    }
+    created
+}
+
+/// Create a log group and a stream in it again, after the group's gone missing
+pub async fn recreate<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    stream: &str,
+    retry: &RetryPolicy,
+    setup: &GroupSetup,
+) -> Result<(), Error> {
+    ensure_group(client, group, retry, setup).await?;
+    match retry
+        .run("CreateLogStream", || client.create_stream(group, stream))
+        .await
+    {
+        Ok(()) | Err(Error::ResourceAlreadyExistsException(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Create a log group that wasn't there a moment ago, and set it up
+///
+/// Something else (another instance, say) might have created it in the meantime,
+/// which is just as good, though then it's only set up if `setup` forces it.
+async fn ensure_group<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    retry: &RetryPolicy,
+    setup: &GroupSetup,
+) -> Result<(), Error> {
+    let kms_key = setup.kms_key.as_deref();
+    match retry
+        .run("CreateLogGroup", || client.create_group(group, kms_key))
+        .await
+    {
+        Ok(()) => {
+            match kms_key {
+                Some(key) => info!("Created log group: {} (encrypted with {})", group, key),
+                None => info!("Created log group: {}", group),
+            }
+            if let Some(days) = setup.retention_days {
+                set_retention(client, group, retry, days).await?;
+            }
+            if !setup.tags.is_empty() {
+                tag_group(client, group, retry, &setup.tags).await?;
+            }
+        }
+        Err(Error::ResourceAlreadyExistsException(_)) => {
+            force_setup(client, group, retry, setup).await?
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Set up a log group that was already there, as far as `setup` forces it to be
+async fn force_setup<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    retry: &RetryPolicy,
+    setup: &GroupSetup,
+) -> Result<(), Error> {
+    if let Some(days) = setup.retention_days.filter(|_| setup.force_retention) {
+        set_retention(client, group, retry, days).await?;
+    }
+    if setup.force_tags && !setup.tags.is_empty() {
+        tag_group(client, group, retry, &setup.tags).await?;
+    }
+    if let Some(key) = &setup.kms_key {
+        check_kms_key(client, group, retry, key, setup.force_kms).await?;
+    }
+    Ok(())
+}
+
+/// Make sure a log group that was already there is encrypted with `kms_key`,
+/// associating the key with it if `force` says to and only warning otherwise
+async fn check_kms_key<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    retry: &RetryPolicy,
+    kms_key: &str,
+    force: bool,
+) -> Result<(), Error> {
+    let current = retry
+        .run("DescribeLogGroups", || client.group_kms_key(group))
+        .await?;
+    if current.as_deref() == Some(kms_key) {
+        return Ok(());
+    }
+    if force {
+        retry
+            .run("AssociateKmsKey", || {
+                client.associate_kms_key(group, kms_key)
+            })
+            .await?;
+        info!("Encrypted the log group with {}", kms_key);
+    } else {
+        warn!(
+            "log group {} isn't encrypted with {} (pass --force-kms to associate it)",
+            group, kms_key
+        );
+    }
+    Ok(())
+}
+
+async fn set_retention<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    retry: &RetryPolicy,
+    days: i32,
+) -> Result<(), Error> {
+    retry
+        .run("PutRetentionPolicy", || client.set_retention(group, days))
+        .await?;
+    info!("Set the log group to keep events for {} days", days);
+    Ok(())
+}
+
+async fn tag_group<C: CreateLogs>(
+    client: &C,
+    group: &str,
+    retry: &RetryPolicy,
+    tags: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    retry
+        .run("TagLogGroup", || client.tag_group(group, tags))
+        .await?;
+    info!("Tagged the log group with {} tags", tags.len());
+    Ok(())
+}
+
+/// Parse a --kms-key-arn, which has to be a KMS key's full ARN (CloudWatch Logs
+/// doesn't take aliases or bare key ids)
+pub fn parse_kms_key_arn(s: &str) -> Result<String, String> {
+    let fields: Vec<_> = s.splitn(6, ':').collect();
+    match fields[..] {
+        ["arn", _, "kms", _, _, resource] if resource.starts_with("key/") && resource.len() > 4 => {
+            Ok(s.to_string())
+        }
+        _ => Err(format!(
+            "{:?} isn't a KMS key ARN like arn:aws:kms:us-east-1:123456789012:key/1234abcd-...",
+            s
+        )),
+    }
+}
+
+/// Parse a --retention-days, which has to be one of the periods CloudWatch Logs
+/// accepts
+pub fn parse_retention_days(s: &str) -> Result<i32, String> {
+    match s.parse::<i32>() {
+        Ok(days) if RETENTION_DAYS.contains(&days) => Ok(days),
+        _ => {
+            let allowed: Vec<_> = RETENTION_DAYS.iter().map(i32::to_string).collect();
+            Err(format!(
+                "{:?} isn't a retention period CloudWatch Logs accepts (one of {})",
+                s,
+                allowed.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{already_exists, not_found, MockClient};
+
+    fn setup(retention_days: Option<i32>, force_retention: bool) -> GroupSetup {
+        GroupSetup {
+            create: true,
+            retention_days,
+            force_retention,
+            ..Default::default()
+        }
+    }
+
+    async fn open(client: &MockClient, setup: &GroupSetup) -> Result<(), Error> {
+        open_stream(client, "group", "stream", &RetryPolicy::default(), setup).await
+    }
+
+    #[tokio::test]
+    async fn test_open_stream_creates_group() {
+        let client = MockClient::failing_to_create([not_found()]);
+        open(&client, &setup(None, false)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "group group", "stream group/stream"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_stream_group_created_meanwhile() {
+        // Something else creates the group between our finding it missing and
+        // creating it ourselves
+        let client = MockClient::failing_to_create([not_found(), already_exists()]);
+        open(&client, &setup(Some(7), false)).await.unwrap();
+        // Which leaves it as they set it up
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "group group", "stream group/stream"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_stream_without_create_group() {
+        let client = MockClient::failing_to_create([not_found()]);
+        let setup = GroupSetup::default();
+        let err = open(&client, &setup).await.unwrap_err();
+        assert!(matches!(err, Error::ResourceNotFoundException(_)));
+        assert!(crate::retry::describe(&err).contains("--create-group"));
+        assert_eq!(*client.created.lock().unwrap(), ["stream group/stream"]);
+    }
+
+    #[tokio::test]
+    async fn test_retention_on_create() {
+        let client = MockClient::failing_to_create([not_found()]);
+        open(&client, &setup(Some(14), false)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            [
+                "stream group/stream",
+                "group group",
+                "retention group 14",
+                "stream group/stream"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retention_on_existing_group() {
+        // Left alone unless forced
+        let client = MockClient::default();
+        open(&client, &setup(Some(14), false)).await.unwrap();
+        assert_eq!(*client.created.lock().unwrap(), ["stream group/stream"]);
+
+        let client = MockClient::default();
+        open(&client, &setup(Some(14), true)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "retention group 14"]
+        );
+
+        // Even when the stream's there already
+        let client = MockClient::failing_to_create([already_exists()]);
+        let err = open(&client, &setup(Some(30), true)).await.unwrap_err();
+        assert!(matches!(err, Error::ResourceAlreadyExistsException(_)));
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "retention group 30"]
+        );
+
+        // And when something else has just created the group
+        let client = MockClient::failing_to_create([not_found(), already_exists()]);
+        open(&client, &setup(Some(1), true)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            [
+                "stream group/stream",
+                "group group",
+                "retention group 1",
+                "stream group/stream"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_retention_days() {
+        assert_eq!(parse_retention_days("7"), Ok(7));
+        assert_eq!(parse_retention_days("3653"), Ok(3653));
+        let err = parse_retention_days("10").unwrap_err();
+        assert!(err.contains("1, 3, 5, 7, 14, 30"), "{}", err);
+        assert!(parse_retention_days("forever").is_err());
+    }
+
+    fn tagged(force_tags: bool) -> GroupSetup {
+        let tags = [
+            Tag::parse("team=infra").unwrap(),
+            Tag::parse("source={instance_id}:{filename}").unwrap(),
+            Tag::parse("tool=rusty_axe {version}").unwrap(),
+        ];
+        let values = [
+            ("instance_id", "i-0123"),
+            ("filename", "syslog"),
+            ("version", "0.1.0"),
+        ];
+        GroupSetup {
+            create: true,
+            tags: render_tags(&tags, &values),
+            force_tags,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tags_on_create() {
+        let client = MockClient::failing_to_create([not_found()]);
+        open(&client, &tagged(false)).await.unwrap();
+        assert_eq!(
+            client.created.lock().unwrap()[2],
+            "tags group source=i-0123:syslog,team=infra,tool=rusty_axe 0.1.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tags_on_existing_group() {
+        let client = MockClient::default();
+        open(&client, &tagged(false)).await.unwrap();
+        assert_eq!(*client.created.lock().unwrap(), ["stream group/stream"]);
+
+        let client = MockClient::default();
+        open(&client, &tagged(true)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            [
+                "stream group/stream",
+                "tags group source=i-0123:syslog,team=infra,tool=rusty_axe 0.1.0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        let tag = Tag::parse("env=prod=blue").unwrap();
+        assert_eq!(tag.key, "env");
+        assert_eq!(tag.value.render(&[]), "prod=blue");
+        assert_eq!(Tag::parse("empty=").unwrap().value.render(&[]), "");
+
+        assert!(Tag::parse("team").is_err());
+        assert!(Tag::parse("=infra").is_err());
+        assert!(Tag::parse(&format!("{}=x", "k".repeat(129))).is_err());
+        assert!(Tag::parse("aws:team=infra").is_err());
+        assert!(Tag::parse("host={hostname}").is_err());
+    }
+
+    #[test]
+    fn test_render_tags() {
+        let tags = [
+            Tag::parse("a=1").unwrap(),
+            Tag::parse("a=2").unwrap(),
+            Tag::parse("long={filename}").unwrap(),
+        ];
+        let long = "x".repeat(300);
+        let rendered = render_tags(&tags, &[("filename", &long)]);
+        assert_eq!(rendered["a"], "2");
+        assert_eq!(rendered["long"].len(), 256);
+    }
+
+    const KEY: &str = "arn:aws:kms:us-east-1:123456789012:key/1234abcd-12ab-34cd-56ef-1234567890ab";
+
+    fn encrypted(force_kms: bool) -> GroupSetup {
+        GroupSetup {
+            create: true,
+            kms_key: Some(KEY.to_string()),
+            force_kms,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kms_key_on_create() {
+        let client = MockClient::failing_to_create([not_found()]);
+        open(&client, &encrypted(false)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            [
+                "stream group/stream".to_string(),
+                format!("group group {}", KEY),
+                "stream group/stream".to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kms_key_on_existing_group() {
+        // Already encrypted with the key, so left alone
+        let client = MockClient {
+            kms_key: Some(KEY.to_string()),
+            ..Default::default()
+        };
+        open(&client, &encrypted(true)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "describe group"]
+        );
+
+        // Not encrypted, which is only warned about unless forced
+        let client = MockClient::default();
+        open(&client, &encrypted(false)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/stream", "describe group"]
+        );
+
+        let client = MockClient::default();
+        open(&client, &encrypted(true)).await.unwrap();
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            [
+                "stream group/stream".to_string(),
+                "describe group".to_string(),
+                format!("associate group {}", KEY)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kms_key_not_allowed() {
+        use aws_sdk_cloudwatchlogs::error::InvalidParameterException;
+        let denied = Error::InvalidParameterException(
+            InvalidParameterException::builder()
+                .message("Unable to access KMS key")
+                .build(),
+        );
+        let client = MockClient::failing_to_create([not_found(), denied]);
+        let err = open(&client, &encrypted(false)).await.unwrap_err();
+        let message = crate::retry::describe(&err);
+        assert!(message.contains("key's policy"), "{}", message);
+    }
+
+    #[test]
+    fn test_parse_kms_key_arn() {
+        assert_eq!(parse_kms_key_arn(KEY).as_deref(), Ok(KEY));
+        assert!(parse_kms_key_arn("alias/logs").is_err());
+        assert!(parse_kms_key_arn("1234abcd-12ab-34cd-56ef-1234567890ab").is_err());
+        assert!(parse_kms_key_arn("arn:aws:kms:us-east-1:123456789012:alias/logs").is_err());
+        assert!(parse_kms_key_arn("arn:aws:kms:us-east-1:123456789012:key/").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Making sure a very large input file is meant to be sent, before CloudWatch Logs
+//! charges for ingesting it
+//!
+//! A file over --max-file-size (or, when --head, --tail, --lines or --max-bytes bound
+//! what's sent of it, with about that much to send) is only sent if --force says to, or
+//! whoever's at the terminal says yes when asked.  Anything else stops the run.
+
+use crate::batch::EVENT_OVERHEAD;
+use crate::error::Error;
+use crate::events::EventOptions;
+use crate::message::format_size;
+use crate::rate;
+
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use tracing::warn;
+
+/// What --max-file-size is unless it's given
+pub const DEFAULT_MAX_FILE_SIZE: &str = "1GB";
+
+const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// What CloudWatch Logs charges to ingest a GB of events in US dollars (the standard
+/// log class, in most regions)
+const COST_PER_GB: f64 = 0.50;
+
+/// How much of the start of a file is read to find how long its lines are
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Asks whoever's at the terminal a question, and says whether they answered yes
+pub type Ask<'a> = &'a mut dyn FnMut(&str) -> io::Result<bool>;
+
+/// Parse a --max-file-size, like `1GB` or `500MB` (KB, MB and GB are 1024, 1024² and
+/// 1024³ bytes)
+pub fn parse_max_file_size(s: &str) -> Result<u64, String> {
+    let upper = s.to_ascii_uppercase();
+    let size = match upper.strip_suffix("GB").or_else(|| upper.strip_suffix('G')) {
+        Some(number) => rate::parse_size(number).map(|number| number * GB),
+        None => rate::parse_size(s),
+    };
+    size.map(|bytes| bytes as u64)
+        .ok_or_else(|| format!("{:?} isn't a size like 1GB or 500MB", s))
+}
+
+/// A number of bytes, in GB once there are that many
+fn format_large_size(bytes: u64) -> String {
+    if bytes as f64 >= GB {
+        format!("{:.1} GB", bytes as f64 / GB)
+    } else {
+        format_size(bytes as usize)
+    }
+}
+
+/// Roughly what sending a file comes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub events: u64,
+    /// The bytes PutLogEvents counts: the messages, and 26 more for each event
+    pub bytes: u64,
+}
+
+impl Estimate {
+    /// Estimate what's sent of a file of `size` bytes whose lines are `line_length`
+    /// bytes long on average (newline and all), given what `options` picks out
+    ///
+    /// Lines a filter (like --grep or --since) leaves out can't be known without
+    /// reading them all, so those count as sent.
+    fn of(size: u64, line_length: f64, options: &EventOptions) -> Estimate {
+        let size = size.saturating_sub(options.start_offset);
+        if size == 0 || line_length <= 0.0 {
+            return Estimate {
+                events: 0,
+                bytes: 0,
+            };
+        }
+        let lines = (size as f64 / line_length).ceil() as u64;
+        let mut events = if !options.lines.is_empty() {
+            options
+                .lines
+                .iter()
+                .map(|range| {
+                    let end = range.end.map_or(lines, |end| (end as u64).min(lines));
+                    end.saturating_sub(range.start as u64 - 1)
+                })
+                .sum()
+        } else if options.head > 0 || options.tail > 0 {
+            lines.min((options.head + options.tail) as u64)
+        } else {
+            lines
+        };
+        let message_length = (line_length - 1.0).max(0.0);
+        let mut messages = events as f64 * message_length;
+        if let Some(max_bytes) = options.max_bytes.map(|max| max as f64) {
+            if messages > max_bytes {
+                events = (max_bytes / message_length.max(1.0)).ceil() as u64;
+                messages = max_bytes;
+            }
+        }
+        Estimate {
+            events,
+            bytes: messages as u64 + events * EVENT_OVERHEAD as u64,
+        }
+    }
+
+    /// What ingesting it costs, in US dollars
+    pub fn cost(&self) -> f64 {
+        self.bytes as f64 / GB * COST_PER_GB
+    }
+}
+
+/// How long the lines at the start of the file at `path` are on average, newline and
+/// all (a compressed file's are its compressed bytes)
+fn line_length(path: &str) -> io::Result<f64> {
+    let mut sample = Vec::new();
+    File::open(path)?
+        .take(SAMPLE_SIZE)
+        .read_to_end(&mut sample)?;
+    let newlines = sample.iter().filter(|&&byte| byte == b'\n').count();
+    let lines = newlines + usize::from(sample.last().is_some_and(|&byte| byte != b'\n'));
+    Ok(match lines {
+        0 => 0.0,
+        lines => sample.len() as f64 / lines as f64,
+    })
+}
+
+/// Whether `options` bound how much of a file is sent, whatever its size
+fn bounded(options: &EventOptions) -> bool {
+    options.head > 0 || options.tail > 0 || !options.lines.is_empty() || options.max_bytes.is_some()
+}
+
+/// Say what sending `path`, which is `size` bytes, would come to, if that's over
+/// `max_file_size`
+///
+/// When --head, --tail, --lines or --max-bytes bound what's sent, it's the estimate
+/// of that which has to be over it, rather than the file itself.
+fn describe(path: &str, size: u64, max_file_size: u64, options: &EventOptions) -> Option<String> {
+    let estimate = line_length(path)
+        .ok()
+        .map(|line_length| Estimate::of(size, line_length, options));
+    let picked = estimate.filter(|_| bounded(options));
+    if picked.map_or(size, |estimate| estimate.bytes) <= max_file_size {
+        return None;
+    }
+    let mut description = format!(
+        "{} is {}, {}over --max-file-size ({})",
+        path,
+        format_large_size(size),
+        if picked.is_some() {
+            "and what's picked out of it still "
+        } else {
+            ""
+        },
+        format_large_size(max_file_size)
+    );
+    if let Some(estimate) = estimate {
+        description.push_str(&format!(
+            ": about {} events, {} to send, costing ${:.2} to ingest",
+            estimate.events,
+            format_large_size(estimate.bytes),
+            estimate.cost()
+        ));
+    }
+    Some(description)
+}
+
+/// Check none of `files` (paths, and their sizes if they're known) is over
+/// `max_file_size`, or that sending it anyway is meant
+///
+/// A file over it is sent if `force` is set, with a warning, or if `ask` (which is
+/// only given when there's someone at the terminal) gets a yes.  Otherwise the error
+/// says which files are too large.
+pub fn check(
+    files: &[(String, Option<u64>)],
+    max_file_size: u64,
+    force: bool,
+    options: &EventOptions,
+    ask: Option<Ask>,
+) -> Result<(), Error> {
+    let descriptions: Vec<_> = files
+        .iter()
+        .filter_map(|(path, size)| size.map(|size| (path, size)))
+        .filter(|&(_, size)| size > max_file_size)
+        .filter_map(|(path, size)| describe(path, size, max_file_size, options))
+        .collect();
+    if descriptions.is_empty() {
+        return Ok(());
+    }
+    let description = descriptions.join("\n");
+    if force {
+        warn!("{} (sending it anyway, as --force says)", description);
+        return Ok(());
+    }
+    match ask {
+        Some(ask) => match ask(&format!("{}\nSend it anyway? [y/N] ", description)) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::PayloadTooLarge(String::from(
+                "Not sending it, as asked",
+            ))),
+            Err(e) => Err(Error::PayloadTooLarge(format!(
+                "Couldn't ask whether to send it anyway: {}",
+                e
+            ))),
+        },
+        None => Err(Error::PayloadTooLarge(format!(
+            "{}\nGive --force to send it anyway, or a larger --max-file-size",
+            description
+        ))),
+    }
+}
+
+/// Ask `question` on `output`, and read whether the answer on `input` is yes
+pub fn ask(question: &str, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<bool> {
+    write!(output, "{}", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Ask `question` on the terminal
+pub fn ask_terminal(question: &str) -> io::Result<bool> {
+    ask(question, &mut io::stdin().lock(), &mut io::stderr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::LineRange;
+    use std::io::Cursor;
+
+    /// A file of `lines` lines of 99 bytes and a newline
+    fn file(lines: usize) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for _ in 0..lines {
+            writeln!(file, "{}", "x".repeat(99)).unwrap();
+        }
+        file
+    }
+
+    fn sized(file: &tempfile::NamedTempFile) -> Vec<(String, Option<u64>)> {
+        let path = file.path().to_str().unwrap().to_string();
+        let size = crate::events::input_size(&path);
+        vec![(path, size)]
+    }
+
+    #[test]
+    fn test_parse_max_file_size() {
+        assert_eq!(parse_max_file_size("1GB"), Ok(1 << 30));
+        assert_eq!(parse_max_file_size("1.5g"), Ok(3 << 29));
+        assert_eq!(parse_max_file_size("500MB"), Ok(500 << 20));
+        assert_eq!(parse_max_file_size("1000"), Ok(1000));
+        assert_eq!(
+            parse_max_file_size(DEFAULT_MAX_FILE_SIZE),
+            Ok(1024 * 1024 * 1024)
+        );
+        assert!(parse_max_file_size("GB").is_err());
+        assert!(parse_max_file_size("-1GB").is_err());
+        assert!(parse_max_file_size("1TB").is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let all = EventOptions::default();
+        let estimate = Estimate::of(100_000, 100.0, &all);
+        assert_eq!(estimate.events, 1000);
+        assert_eq!(estimate.bytes, 1000 * (99 + 26));
+        assert_eq!(
+            Estimate::of(1 << 30, 1024.0, &all).cost(),
+            0.50 * 1049.0 / 1024.0
+        );
+
+        let head_and_tail = EventOptions {
+            head: 10,
+            tail: 5,
+            ..Default::default()
+        };
+        assert_eq!(Estimate::of(100_000, 100.0, &head_and_tail).events, 15);
+        let lines = EventOptions {
+            lines: vec![
+                LineRange {
+                    start: 11,
+                    end: Some(20),
+                },
+                LineRange {
+                    start: 991,
+                    end: None,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(Estimate::of(100_000, 100.0, &lines).events, 20);
+        let capped = EventOptions {
+            max_bytes: Some(9900),
+            ..Default::default()
+        };
+        let estimate = Estimate::of(100_000, 100.0, &capped);
+        assert_eq!(estimate.events, 100);
+        assert_eq!(estimate.bytes, 9900 + 100 * 26);
+        let offset = EventOptions {
+            start_offset: 50_000,
+            ..Default::default()
+        };
+        assert_eq!(Estimate::of(100_000, 100.0, &offset).events, 500);
+        assert_eq!(Estimate::of(0, 0.0, &all).events, 0);
+    }
+
+    #[test]
+    fn test_line_length() {
+        let file = file(20);
+        assert_eq!(line_length(file.path().to_str().unwrap()).unwrap(), 100.0);
+        let mut unfinished = tempfile::NamedTempFile::new().unwrap();
+        write!(unfinished, "abc\nde").unwrap();
+        assert_eq!(
+            line_length(unfinished.path().to_str().unwrap()).unwrap(),
+            3.0
+        );
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(line_length(empty.path().to_str().unwrap()).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_check() {
+        let options = EventOptions::default();
+        let file = file(20);
+        let files = sized(&file);
+        // Under the limit, and standard input (whose size isn't known), go ahead
+        assert!(check(&files, 2000, false, &options, None).is_ok());
+        let stdin = [(String::from(crate::events::STDIN_PATH), None)];
+        assert!(check(&stdin, 1, false, &options, None).is_ok());
+
+        // Over it, with no one to ask
+        let err = check(&files, 1000, false, &options, None).unwrap_err();
+        assert!(matches!(err, Error::PayloadTooLarge(_)), "{:?}", err);
+        let err = err.to_string();
+        assert!(
+            err.contains("is 2 KB, over --max-file-size (1000 bytes)"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("about 20 events, 2 KB to send, costing $0.00"),
+            "{}",
+            err
+        );
+        assert!(err.contains("--force"), "{}", err);
+
+        // Unless it's forced, when there's no asking
+        let mut asked = false;
+        let mut ask = |_: &str| {
+            asked = true;
+            Ok(false)
+        };
+        assert!(check(&files, 1000, true, &options, Some(&mut ask)).is_ok());
+        assert!(!asked);
+    }
+
+    #[test]
+    fn test_check_bounded() {
+        let file = file(20);
+        let files = sized(&file);
+        // Five lines of the file are about 625 bytes to send, under the limit
+        for options in [
+            EventOptions {
+                tail: 5,
+                ..Default::default()
+            },
+            EventOptions {
+                head: 5,
+                ..Default::default()
+            },
+            EventOptions {
+                lines: vec![LineRange {
+                    start: 3,
+                    end: Some(7),
+                }],
+                ..Default::default()
+            },
+            EventOptions {
+                max_bytes: Some(500),
+                ..Default::default()
+            },
+        ] {
+            assert!(check(&files, 1000, false, &options, None).is_ok());
+        }
+
+        // Fifteen are still too many
+        let options = EventOptions {
+            tail: 15,
+            ..Default::default()
+        };
+        let err = check(&files, 1000, false, &options, None)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("is 2 KB, and what's picked out of it still over --max-file-size"),
+            "{}",
+            err
+        );
+        assert!(err.contains("about 15 events"), "{}", err);
+        // A range to the end is only as long as what's left of the file
+        let options = EventOptions {
+            lines: vec![LineRange {
+                start: 19,
+                end: None,
+            }],
+            ..Default::default()
+        };
+        assert!(check(&files, 1000, false, &options, None).is_ok());
+    }
+
+    #[test]
+    fn test_check_asks() {
+        let options = EventOptions::default();
+        let file = file(20);
+        let files = sized(&file);
+        for (answer, sent) in [("y\n", true), ("Yes\n", true), ("n\n", false), ("", false)] {
+            let mut output = Vec::new();
+            let mut ask = |question: &str| ask(question, &mut Cursor::new(answer), &mut output);
+            let result = check(&files, 1000, false, &options, Some(&mut ask));
+            assert_eq!(result.is_ok(), sent, "{:?}", answer);
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("about 20 events"), "{}", output);
+            assert!(output.ends_with("Send it anyway? [y/N] "), "{}", output);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Calling the destinations that are sent to over HTTP or HTTPS, rather than with an
+//! AWS SDK
+
+use crate::records::CallFailed;
+use crate::retry::Timeouts;
+use crate::syslog;
+
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use std::time::Duration;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::warn;
+
+/// Statuses a server answers with when it can't take a request for now, which is
+/// worth making again later
+pub const RETRYABLE: &[StatusCode] = &[
+    StatusCode::TOO_MANY_REQUESTS,
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+pub type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+/// A client for HTTP and HTTPS, trusting the certificates the system does
+pub fn client(timeouts: Timeouts) -> HttpsClient {
+    let tls = syslog::tls_config().unwrap_or_else(|e| {
+        warn!(
+            "couldn't load the certificates the system trusts, so HTTPS will \
+             fail: {}",
+            e
+        );
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth()
+    });
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(timeouts.connect));
+    let https = HttpsConnectorBuilder::new()
+        .with_tls_config(tls)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http);
+    Client::builder().build(https)
+}
+
+/// An http:// or https:// URL with no query, like `example`, without any `/` it ends
+/// with
+pub fn parse_base_url(s: &str, example: &str) -> Result<String, String> {
+    let invalid = || format!("{:?} isn't a URL like {}", s, example);
+    let uri: Uri = s.parse().map_err(|_| invalid())?;
+    match (uri.scheme_str(), uri.authority(), uri.query()) {
+        (Some("http" | "https"), Some(_), None) => Ok(s.trim_end_matches('/').to_string()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Make the request, reading the whole response, within `timeout`
+///
+/// Failing to get a response at all is taken to be transient; what the status says
+/// is for the caller to make out.
+pub async fn send(
+    client: &HttpsClient,
+    request: Request<Body>,
+    timeout: Duration,
+) -> Result<(StatusCode, Bytes), CallFailed> {
+    let failed = |message: String| CallFailed {
+        message,
+        transient: true,
+    };
+    let exchange = async {
+        let response = client.request(request).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok::<_, hyper::Error>((status, body))
+    };
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(Ok(answer)) => Ok(answer),
+        Ok(Err(e)) => Err(failed(e.to_string())),
+        Err(_) => Err(failed(String::from("timed out"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_base_url() {
+        let parse = |s| parse_base_url(s, "http://collector:4318");
+        assert_eq!(
+            parse("http://collector:4318").unwrap(),
+            "http://collector:4318"
+        );
+        assert_eq!(
+            parse("https://otel.example.com/otlp/").unwrap(),
+            "https://otel.example.com/otlp"
+        );
+        let err = parse("collector:4318").unwrap_err();
+        assert!(
+            err.ends_with("isn't a URL like http://collector:4318"),
+            "{}",
+            err
+        );
+        assert!(parse("grpc://collector:4317").is_err());
+        assert!(parse("http://collector:4318/?x=1").is_err());
+    }
+}
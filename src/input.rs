@@ -0,0 +1,1017 @@
+//! Opening input files, decompressing (and decoding) them on the fly when needed
+
+use crate::events::{Encoding, STDIN_PATH};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+/// How much of the start of a file is checked when deciding whether it's binary
+pub const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// How much of a file is read at a time while looking backwards for its tail
+const TAIL_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// The number of bytes shown on each line of a hex dump
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Stands in for a sequence that can't be decoded, so it's caught by the same
+/// --encoding-errors policy as invalid UTF-8 (it never appears in valid UTF-8)
+const INVALID_SEQUENCE: u8 = 0xff;
+
+/// Compression formats that can be recognised from the first few bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Work out how (or whether) some data is compressed from its magic bytes
+    pub fn sniff(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// The cargo feature that enables support for this format
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// Open an input file (or stdin) for reading
+///
+/// # Arguments
+///
+/// * `path` - The file to open ("-" reads from standard input)
+/// * `decompress` - Whether to sniff for and decode compressed data
+///
+pub fn open_input(path: &str, decompress: bool) -> io::Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = if path == STDIN_PATH {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    if decompress {
+        decompressed(reader)
+    } else {
+        Ok(reader)
+    }
+}
+
+/// Open an input file (or stdin) for reading from the first line at or after `offset`
+///
+/// An offset part way through a line moves on to the start of the next one, so a
+/// partial line is never read.  Plain files are seeked, but stdin and compressed
+/// files have to be read through to get there (and for compressed files the offset
+/// counts decompressed bytes).
+///
+/// Returns the reader along with the offset it was left at, or None for the offset
+/// if the input ends before `offset`.
+///
+/// # Arguments
+///
+/// * `path` - The file to open ("-" reads from standard input)
+/// * `decompress` - Whether to sniff for and decode compressed data
+/// * `offset` - How many bytes into the input to start
+///
+pub fn open_input_at(
+    path: &str,
+    decompress: bool,
+    offset: u64,
+) -> io::Result<(Box<dyn BufRead>, Option<u64>)> {
+    if offset == 0 {
+        return Ok((open_input(path, decompress)?, Some(0)));
+    }
+
+    // Stop one byte short, since that byte says whether `offset` starts a line
+    let mut reader: Box<dyn BufRead> =
+        if path != STDIN_PATH && !(decompress && is_compressed(path)?) {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset - 1))?;
+            Box::new(BufReader::new(file))
+        } else {
+            let mut reader = open_input(path, decompress)?;
+            io::copy(&mut reader.by_ref().take(offset - 1), &mut io::sink())?;
+            reader
+        };
+
+    let mut previous = [0];
+    if reader.read(&mut previous)? == 0 {
+        return Ok((reader, None));
+    }
+
+    // Move on past the rest of the line `offset` landed in, unless it starts one
+    let mut position = offset;
+    let mut last = previous[0];
+    while last != b'\n' && last != b'\r' {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let len = memchr::memchr2(b'\n', b'\r', buf).map_or(buf.len(), |i| i + 1);
+        last = buf[len - 1];
+        reader.consume(len);
+        position += len as u64;
+    }
+    // A "\n" straight after a "\r" belongs to the same line ending
+    if last == b'\r' && reader.fill_buf()?.first() == Some(&b'\n') {
+        reader.consume(1);
+        position += 1;
+    }
+
+    Ok((reader, Some(position)))
+}
+
+/// Does the file start with the magic bytes of a compression format?
+fn is_compressed(path: &str) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(6);
+    File::open(path)?.take(6).read_to_end(&mut magic)?;
+
+    Ok(Compression::sniff(&magic).is_some())
+}
+
+/// Wrap a reader in whichever decoder its first few bytes call for
+///
+/// Nothing is consumed while sniffing, so uncompressed data is handed back untouched.
+/// Compressed data in a format this build doesn't support is an error rather than a
+/// stream of garbage lines.
+pub fn decompressed(mut reader: Box<dyn BufRead>) -> io::Result<Box<dyn BufRead>> {
+    let compression = match Compression::sniff(reader.fill_buf()?) {
+        Some(compression) => compression,
+        None => return Ok(reader),
+    };
+
+    #[allow(unreachable_patterns)]
+    match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?))),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Ok(Box::new(BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(reader),
+        ))),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Ok(Box::new(BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(reader),
+        ))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "input is {:?} compressed, but this build lacks the \"{}\" feature (or use --no-decompress)",
+                compression,
+                compression.feature()
+            ),
+        )),
+    }
+}
+
+/// Does this look like the start of a binary file (rather than text)?
+///
+/// The same cheap check `grep` and friends use: text files don't contain NUL bytes.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+/// The last lines of a file, found by reading backwards from its end
+#[derive(Debug, PartialEq, Eq)]
+pub struct Tail {
+    pub lines: Vec<Vec<u8>>,
+    /// How many bytes of the file had to be read to find them
+    pub bytes_read: u64,
+    /// How long the file is
+    pub len: u64,
+}
+
+/// Read the last `tail` lines of a file without reading the rest of it
+///
+/// Blocks are read backwards from the end of the file until enough whole lines have
+/// turned up.  Returns None for anything that has to be read front to back instead,
+/// like pipes, files that look binary and (when decompressing) compressed files.
+pub fn read_tail(path: &str, tail: usize, decompress: bool) -> io::Result<Option<Tail>> {
+    if path == STDIN_PATH {
+        return Ok(None);
+    }
+
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+
+    let mut start = Vec::with_capacity(BINARY_SNIFF_LEN);
+    (&mut file)
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut start)?;
+    if looks_binary(&start) || (decompress && Compression::sniff(&start).is_some()) {
+        return Ok(None);
+    }
+
+    read_tail_blocks(&mut file, metadata.len(), tail, TAIL_BLOCK_SIZE).map(Some)
+}
+
+/// Read backwards through `file` a block at a time until the last `tail` lines are found
+fn read_tail_blocks(file: &mut File, len: u64, tail: usize, block_size: u64) -> io::Result<Tail> {
+    let mut position = len;
+    let mut data = Vec::new();
+
+    loop {
+        let block_start = position.saturating_sub(block_size);
+        let mut block = vec![0; (position - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&data);
+        data = block;
+        position = block_start;
+
+        // The first line found is only known to be whole once there's a line ending
+        // in front of it (or the start of the file)
+        let lines = mapped_lines(&data, 0, tail);
+        let whole = lines.first().is_some_and(|&(offset, _)| offset > 0);
+        if position == 0 || (lines.len() == tail && whole) {
+            return Ok(Tail {
+                lines: lines.into_iter().map(|(_, line)| line.to_vec()).collect(),
+                bytes_read: len - position,
+                len,
+            });
+        }
+    }
+}
+
+/// Map a regular file into memory, for reading very large files quickly
+///
+/// Returns None for anything that can't sensibly be mapped, like pipes, devices and
+/// empty or `/proc`-style files that claim a length of zero.
+pub fn map_file(path: &str) -> io::Result<Option<memmap2::Mmap>> {
+    if path == STDIN_PATH {
+        return Ok(None);
+    }
+
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: the map is only ever read, but if another process truncates the file
+    // while we're reading it we may see a SIGBUS.  That's the trade-off for asking
+    // for --mmap, just as it is for any other tool that maps log files.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+
+    Ok(Some(map))
+}
+
+/// Pick the head and tail lines out of a mapped file without reading the rest of it
+///
+/// The head is found by scanning forwards from the start and the tail by scanning
+/// backwards from the end, so a small tail of a huge file only touches the last few
+/// pages.  Lines are split exactly as `LineReader` splits them.  Each line comes with
+/// the offset where it starts.
+///
+/// # Arguments
+///
+/// * `data` - The mapped file
+/// * `head` - The number of lines to take from the beginning of the file
+/// * `tail` - The number of lines to take from the end of the file
+///
+pub fn mapped_lines(data: &[u8], head: usize, tail: usize) -> Vec<(usize, &[u8])> {
+    let everything = head == 0 && tail == 0;
+    let mut lines = Vec::new();
+
+    // Walk forwards through the head (or the whole file)
+    let mut start = 0;
+    while start < data.len() && (everything || lines.len() < head) {
+        let end = memchr::memchr2(b'\n', b'\r', &data[start..]).map_or(data.len(), |i| start + i);
+        lines.push((start, &data[start..end]));
+        start = end
+            + if data[end..].starts_with(b"\r\n") {
+                2
+            } else {
+                1
+            };
+    }
+    if everything || tail == 0 || start >= data.len() {
+        return lines;
+    }
+
+    // Walk backwards through the tail, stopping where the head left off
+    let head_end = start;
+    let mut tail_lines = Vec::new();
+    let mut end = data.len() - line_ending_len(&data[head_end..]);
+    while tail_lines.len() < tail {
+        let start = memchr::memrchr2(b'\n', b'\r', &data[head_end..end])
+            .map_or(head_end, |i| head_end + i + 1);
+        tail_lines.push((start, &data[start..end]));
+        if start == head_end {
+            break;
+        }
+        end = start - line_ending_len(&data[head_end..start]);
+    }
+
+    lines.extend(tail_lines.into_iter().rev());
+    lines
+}
+
+/// The offset just past the line ending of a line found by `mapped_lines`
+pub fn line_end(data: &[u8], offset: usize, line: &[u8]) -> usize {
+    let end = offset + line.len();
+    if data[end..].starts_with(b"\r\n") {
+        end + 2
+    } else {
+        (end + 1).min(data.len())
+    }
+}
+
+/// Count the lines ending in `data`, splitting them the way `LineReader` does
+pub fn count_lines(data: &[u8]) -> usize {
+    memchr::memchr2_iter(b'\n', b'\r', data)
+        .filter(|&i| !(data[i] == b'\n' && i > 0 && data[i - 1] == b'\r'))
+        .count()
+}
+
+/// The length of the line ending at the very end of `data`, if there is one
+fn line_ending_len(data: &[u8]) -> usize {
+    if data.ends_with(b"\r\n") {
+        2
+    } else if data.ends_with(b"\n") || data.ends_with(b"\r") {
+        1
+    } else {
+        0
+    }
+}
+
+/// Splits input into lines ending in "\n", "\r\n", or a lone "\r"
+///
+/// Files from Windows hosts end their lines with "\r\n" and classic Mac files with
+/// just "\r".  Either way the line ending is never part of the line.
+pub struct LineReader<R> {
+    inner: R,
+    /// The last line ended in "\r", so a "\n" straight after it belongs to it too
+    after_cr: bool,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(inner: R) -> LineReader<R> {
+        LineReader {
+            inner,
+            after_cr: false,
+        }
+    }
+
+    /// Consume the "\n" of a "\r\n" line ending whose "\r" ended the last line
+    ///
+    /// Only needed when reading stops early, so the input is left at the start of a line.
+    pub fn finish_line(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.after_cr) && self.inner.fill_buf()?.first() == Some(&b'\n') {
+            self.inner.consume(1);
+        }
+        Ok(())
+    }
+
+    /// Read the next line's raw bytes into `line`, without the line ending
+    ///
+    /// Returns false once the input is exhausted.
+    pub fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<bool> {
+        line.clear();
+        let mut started = false;
+
+        loop {
+            let buf = match self.inner.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if buf.is_empty() {
+                return Ok(started);
+            }
+            if std::mem::take(&mut self.after_cr) && buf[0] == b'\n' {
+                self.inner.consume(1);
+                continue;
+            }
+            started = true;
+
+            match memchr::memchr2(b'\n', b'\r', buf) {
+                Some(i) => {
+                    line.extend_from_slice(&buf[..i]);
+                    self.after_cr = buf[i] == b'\r';
+                    self.inner.consume(i + 1);
+                    return Ok(true);
+                }
+                None => {
+                    line.extend_from_slice(buf);
+                    let len = buf.len();
+                    self.inner.consume(len);
+                }
+            }
+        }
+    }
+}
+
+/// Wraps a reader and keeps count of how many bytes have been read through it
+pub struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> CountingReader<R> {
+        CountingReader { inner, count: 0 }
+    }
+
+    /// The number of bytes read so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+/// Work out which encoding the input is in, and move past its byte order mark
+///
+/// A byte order mark matching `chosen` (or any, if no encoding was chosen) is
+/// consumed.  Without either, the input is taken to be UTF-8.
+pub fn sniff_encoding<R: BufRead>(
+    reader: &mut R,
+    chosen: Option<Encoding>,
+) -> io::Result<Encoding> {
+    const MARKS: [(&[u8], Encoding); 3] = [
+        (&[0xef, 0xbb, 0xbf], Encoding::Utf8),
+        (&[0xff, 0xfe], Encoding::Utf16le),
+        (&[0xfe, 0xff], Encoding::Utf16be),
+    ];
+
+    let buf = reader.fill_buf()?;
+    let marked = MARKS
+        .iter()
+        .find(|(mark, encoding)| buf.starts_with(mark) && chosen.is_none_or(|c| c == *encoding));
+    match marked {
+        Some((mark, encoding)) => {
+            reader.consume(mark.len());
+            Ok(*encoding)
+        }
+        None => Ok(chosen.unwrap_or(Encoding::Utf8)),
+    }
+}
+
+/// Turns text in another encoding into UTF-8
+///
+/// Anything that can't be decoded (like half of a UTF-16 surrogate pair) comes out
+/// as a byte that's invalid in UTF-8, to be dealt with along with the rest of the line.
+pub struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    /// Bytes of a character split across reads, waiting for the rest of it
+    carry: Vec<u8>,
+    /// Decoded text that hasn't been read yet
+    pending: Vec<u8>,
+    pending_start: usize,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Decoder<R> {
+        Decoder {
+            inner,
+            encoding,
+            carry: Vec::new(),
+            pending: Vec::new(),
+            pending_start: 0,
+        }
+    }
+
+    /// Decode the next chunk of input, returning false at the end
+    fn decode_more(&mut self) -> io::Result<bool> {
+        self.pending.clear();
+        self.pending_start = 0;
+
+        while self.pending.is_empty() {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() {
+                if self.carry.is_empty() {
+                    return Ok(false);
+                }
+                // The input ended part way through a character
+                self.carry.clear();
+                self.pending.push(INVALID_SEQUENCE);
+                break;
+            }
+
+            let mut raw = std::mem::take(&mut self.carry);
+            raw.extend_from_slice(buf);
+            let len = buf.len();
+            self.inner.consume(len);
+
+            let used = match self.encoding {
+                Encoding::Utf8 => {
+                    self.pending.extend_from_slice(&raw);
+                    raw.len()
+                }
+                Encoding::Latin1 => {
+                    // Every byte is a character, and they're numbered the same as Unicode
+                    self.pending
+                        .extend(raw.iter().map(|&b| b as char).collect::<String>().bytes());
+                    raw.len()
+                }
+                Encoding::Utf16le => decode_utf16(&raw, u16::from_le_bytes, &mut self.pending),
+                Encoding::Utf16be => decode_utf16(&raw, u16::from_be_bytes, &mut self.pending),
+            };
+            self.carry = raw[used..].to_vec();
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_start == self.pending.len() && !self.decode_more()? {
+            return Ok(0);
+        }
+
+        let pending = &self.pending[self.pending_start..];
+        let len = pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&pending[..len]);
+        self.pending_start += len;
+
+        Ok(len)
+    }
+}
+
+/// Decode as much UTF-16 as there's enough of, returning how many bytes were used
+fn decode_utf16(raw: &[u8], unit: fn([u8; 2]) -> u16, out: &mut Vec<u8>) -> usize {
+    let units: Vec<u16> = raw.chunks_exact(2).map(|b| unit([b[0], b[1]])).collect();
+
+    let mut i = 0;
+    while i < units.len() {
+        let is_high = (0xd800..0xdc00).contains(&units[i]);
+        // Wait for the other half of a surrogate pair
+        if is_high && i + 1 == units.len() {
+            break;
+        }
+        let len = if is_high { 2 } else { 1 };
+        match char::decode_utf16(units[i..i + len].iter().copied()).next() {
+            Some(Ok(c)) => {
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                i += len;
+            }
+            // A lone surrogate; whatever follows it is decoded on its own
+            _ => {
+                out.push(INVALID_SEQUENCE);
+                i += 1;
+            }
+        }
+    }
+
+    i * 2
+}
+
+/// Turns binary data into `xxd`-style lines of text
+///
+/// ```text
+/// 00000000: 7f45 4c46 0201 0100 0000 0000 0000 0000  .ELF............
+/// ```
+pub struct HexDump<R> {
+    inner: R,
+    /// Offset into the input of the next line to be produced
+    offset: u64,
+    /// Text of the current line that hasn't been read yet
+    pending: Vec<u8>,
+    pending_start: usize,
+}
+
+impl<R: Read> HexDump<R> {
+    pub fn new(inner: R) -> HexDump<R> {
+        HexDump {
+            inner,
+            offset: 0,
+            pending: Vec::new(),
+            pending_start: 0,
+        }
+    }
+
+    /// Format the next chunk of input as a line of hex, returning false at the end
+    fn next_line(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; HEXDUMP_WIDTH];
+        let mut len = 0;
+        while len < HEXDUMP_WIDTH {
+            match self.inner.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if len == 0 {
+            return Ok(false);
+        }
+        let chunk = &chunk[..len];
+
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let text: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        self.pending = format!("{:08x}: {:<39}  {}\n", self.offset, hex, text).into_bytes();
+        self.pending_start = 0;
+        self.offset += len as u64;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for HexDump<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_start == self.pending.len() && !self.next_line()? {
+            return Ok(0);
+        }
+
+        let pending = &self.pending[self.pending_start..];
+        let len = pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&pending[..len]);
+        self.pending_start += len;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const FIRST_LINE: &str =
+        "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor";
+
+    /// Read the first line of a fixture, decompressing it if possible
+    fn first_line(path: &str, decompress: bool) -> io::Result<String> {
+        let mut line = String::new();
+        open_input(path, decompress)?.read_line(&mut line)?;
+        Ok(line.trim_end().to_string())
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(
+            Compression::sniff(b"\x1f\x8b\x08\x00"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::sniff(b"\x28\xb5\x2f\xfd"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::sniff(b"\xfd7zXZ\x00"), Some(Compression::Xz));
+        assert_eq!(Compression::sniff(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::sniff(b"BZ"), None);
+        assert_eq!(Compression::sniff(FIRST_LINE.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_plain_file_is_untouched() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt";
+        assert_eq!(first_line(path, true).unwrap(), FIRST_LINE);
+        assert_eq!(first_line(path, false).unwrap(), FIRST_LINE);
+    }
+
+    #[test]
+    fn test_no_decompress_reads_raw_bytes() {
+        let mut raw = Vec::new();
+        open_input("tests/fixtures/lorem-ipsum-5.txt.gz", false)
+            .unwrap()
+            .read_to_end(&mut raw)
+            .unwrap();
+        assert_eq!(Compression::sniff(&raw), Some(Compression::Gzip));
+    }
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(looks_binary(b"text\0more"));
+        assert!(!looks_binary(FIRST_LINE.as_bytes()));
+
+        // Only the start of the file is checked
+        let mut late = vec![b'a'; BINARY_SNIFF_LEN];
+        late.push(0);
+        assert!(!looks_binary(&late));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let file = File::open("tests/fixtures/core.bin").unwrap();
+        let mut hex = HexDump::new(file);
+        let mut dump = String::new();
+        hex.read_to_string(&mut dump).unwrap();
+
+        assert_eq!(
+            dump,
+            "00000000: 7f45 4c46 0201 0100 0000 0000 0000 0000  .ELF............\n\
+             00000010: 636f 7265 2064 756d 7000 0102 2068 656c  core dump... hel\n\
+             00000020: 6c6f 0a77 6f72 6c64                      lo.world\n"
+        );
+    }
+
+    #[test]
+    fn test_mapped_lines() {
+        let data = b"one\r\n\ntwo\rthree\n\r\nfour";
+        let lines = |head, tail| -> Vec<&[u8]> {
+            mapped_lines(data, head, tail)
+                .into_iter()
+                .map(|(_, line)| line)
+                .collect()
+        };
+
+        let all: [&[u8]; 6] = [b"one", b"", b"two", b"three", b"", b"four"];
+        assert_eq!(lines(0, 0), all);
+        assert_eq!(lines(2, 0), all[..2]);
+        assert_eq!(lines(0, 2), all[4..]);
+        assert_eq!(lines(1, 1), [all[0], all[5]]);
+        // Head and tail meet in the middle without repeating anything
+        assert_eq!(lines(2, 100), all);
+        assert_eq!(lines(100, 2), all);
+
+        // Offsets point at the start of each line
+        assert_eq!(mapped_lines(data, 0, 1), [(18, &b"four"[..])]);
+        assert_eq!(
+            mapped_lines(b"a\n\n", 1, 5),
+            [(0, &b"a"[..]), (2, &b""[..])]
+        );
+        assert_eq!(
+            mapped_lines(b"a\r\r\n", 0, 5),
+            [(0, &b"a"[..]), (2, &b""[..])]
+        );
+    }
+
+    #[test]
+    fn test_read_tail_blocks() {
+        let inputs: [&[u8]; 6] = [
+            b"one\r\n\ntwo\rthree\n\r\nfour",
+            b"one\r\ntwo\r\nthree\r\n",
+            b"a\r\r\n",
+            b"\n\n\n",
+            b"no line ending at all",
+            b"x\n",
+        ];
+
+        // Tiny blocks put block boundaries everywhere, including inside "\r\n"
+        for data in inputs {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(data).unwrap();
+            for tail in 1..8 {
+                let expected: Vec<_> = mapped_lines(data, 0, tail)
+                    .into_iter()
+                    .map(|(_, line)| line.to_vec())
+                    .collect();
+                for block_size in [1, 2, 3, 5, 64] {
+                    let found =
+                        read_tail_blocks(&mut file, data.len() as u64, tail, block_size).unwrap();
+                    assert_eq!(
+                        found.lines, expected,
+                        "{:?} tail {} block {}",
+                        data, tail, block_size
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_tail() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt";
+        let tail = read_tail(path, 2, true).unwrap().unwrap();
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(tail.len, data.len() as u64);
+        assert_eq!(tail.bytes_read, tail.len);
+        assert_eq!(tail.lines.len(), 2);
+        assert!(data.ends_with(&tail.lines[1]));
+
+        // Binary and compressed files are left to be read the usual way
+        assert_eq!(read_tail("tests/fixtures/core.bin", 2, true).unwrap(), None);
+        assert_eq!(
+            read_tail("tests/fixtures/lorem-ipsum-5.txt.gz", 2, true).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"one\r\n\ntwo\rthree\n\r\nfour"), 5);
+        assert_eq!(count_lines(b"a\r\r\n"), 2);
+    }
+
+    #[test]
+    fn test_open_input_at() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"one\ntwo\r\nthree\rfour\n").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let open = |offset| {
+            let (mut reader, position) = open_input_at(path, false, offset).unwrap();
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).unwrap();
+            (position, rest)
+        };
+
+        assert_eq!(
+            open(0),
+            (Some(0), String::from("one\ntwo\r\nthree\rfour\n"))
+        );
+        // Offsets at the start of a line stay put
+        assert_eq!(open(4), (Some(4), String::from("two\r\nthree\rfour\n")));
+        assert_eq!(open(9), (Some(9), String::from("three\rfour\n")));
+        assert_eq!(open(15), (Some(15), String::from("four\n")));
+        // Offsets part way through a line (or its ending) move on to the next one
+        assert_eq!(open(1), (Some(4), String::from("two\r\nthree\rfour\n")));
+        assert_eq!(open(8), (Some(9), String::from("three\rfour\n")));
+        assert_eq!(open(10), (Some(15), String::from("four\n")));
+        // At the very end there's nothing left, and past it there's nothing at all
+        assert_eq!(open(20), (Some(20), String::new()));
+        assert_eq!(open(19), (Some(20), String::new()));
+        assert_eq!(open(21), (None, String::new()));
+        assert_eq!(open(500), (None, String::new()));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_open_compressed_input_at() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.gz";
+        let (mut reader, position) = open_input_at(path, true, 1).unwrap();
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(position, Some(FIRST_LINE.len() as u64 + 1));
+        assert!(line.starts_with("incididunt ut labore"), "{:?}", line);
+    }
+
+    #[test]
+    fn test_line_reader() {
+        let inputs: [&[u8]; 4] = [
+            b"one\n\ntwo\nthree\n",
+            b"one\r\n\r\ntwo\r\nthree",
+            b"one\r\rtwo\rthree\r",
+            b"one\r\n\rtwo\nthree\r\n",
+        ];
+
+        for input in inputs {
+            // A tiny buffer makes sure "\r\n" split across reads is still one ending
+            let mut reader = LineReader::new(BufReader::with_capacity(1, input));
+            let mut lines = Vec::new();
+            let mut line = Vec::new();
+            while reader.read_line(&mut line).unwrap() {
+                lines.push(String::from_utf8(line.clone()).unwrap());
+            }
+            assert_eq!(lines, ["one", "", "two", "three"], "{:?}", input);
+
+            let data: Vec<_> = mapped_lines(input, 0, 0).into_iter().map(|l| l.1).collect();
+            assert_eq!(data, lines.iter().map(|l| l.as_bytes()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_map_file() {
+        let map = map_file("tests/fixtures/lorem-ipsum-5.txt")
+            .unwrap()
+            .unwrap();
+        assert!(map.starts_with(FIRST_LINE.as_bytes()));
+
+        assert!(map_file("/proc/self/status").unwrap().is_none());
+        assert!(map_file("tests/fixtures").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_counting_reader() {
+        let mut reader = CountingReader::new(io::Cursor::new("one\ntwo\n"));
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(reader.count(), 4);
+        reader.read_to_string(&mut line).unwrap();
+        assert_eq!(reader.count(), 8);
+    }
+
+    /// Decode a few bytes at a time, so characters get split across reads
+    fn decode(raw: &[u8], encoding: Encoding) -> Vec<u8> {
+        let chunked = BufReader::with_capacity(3, raw);
+        let mut decoded = Vec::new();
+        Decoder::new(chunked, encoding)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn test_decoder() {
+        assert_eq!(decode(b"caf\xe9", Encoding::Latin1), "café".as_bytes());
+
+        let text = "naïve \u{1F680}\n";
+        let le: Vec<_> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<_> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(decode(&le, Encoding::Utf16le), text.as_bytes());
+        assert_eq!(decode(&be, Encoding::Utf16be), text.as_bytes());
+
+        // A lone surrogate, and a character cut off at the end
+        assert_eq!(decode(b"\x3d\xd8a\x00b", Encoding::Utf16le), b"\xffa\xff");
+    }
+
+    #[test]
+    fn test_sniff_encoding() {
+        let sniff = |raw: &[u8], chosen| {
+            let mut reader = raw;
+            let encoding = sniff_encoding(&mut reader, chosen).unwrap();
+            (encoding, reader.len())
+        };
+        assert_eq!(sniff(b"plain", None), (Encoding::Utf8, 5));
+        assert_eq!(sniff(b"\xef\xbb\xbfa", None), (Encoding::Utf8, 1));
+        assert_eq!(sniff(b"\xff\xfea\x00", None), (Encoding::Utf16le, 2));
+        assert_eq!(sniff(b"\xfe\xff\x00a", None), (Encoding::Utf16be, 2));
+
+        // A chosen encoding wins, and only its own byte order mark is skipped
+        let latin1 = Some(Encoding::Latin1);
+        assert_eq!(sniff(b"\xff\xfea\x00", latin1), (Encoding::Latin1, 4));
+        let utf16le = Some(Encoding::Utf16le);
+        assert_eq!(sniff(b"\xff\xfea\x00", utf16le), (Encoding::Utf16le, 2));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.gz";
+        assert_eq!(first_line(path, true).unwrap(), FIRST_LINE);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzip_without_feature() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.gz";
+        let err = first_line(path, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.zst";
+        assert_eq!(first_line(path, true).unwrap(), FIRST_LINE);
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn test_xz() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.xz";
+        assert_eq!(first_line(path, true).unwrap(), FIRST_LINE);
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_bzip2() {
+        let path = "tests/fixtures/lorem-ipsum-5.txt.bz2";
+        assert_eq!(first_line(path, true).unwrap(), FIRST_LINE);
+    }
+}
@@ -0,0 +1,235 @@
+//! Reading entries from the systemd journal by way of `journalctl`
+
+use crate::error::Error;
+use crate::events::{now_millis, EventOptions, LineProcessor, Selector};
+use crate::uploader::UploadSummary;
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use std::io::{self, BufRead, BufReader};
+use std::process::{Command, Stdio};
+use tracing::info;
+
+/// Which part of the journal to read
+#[derive(Debug, Clone, Default)]
+pub struct JournalOptions {
+    /// Only read entries for these systemd units
+    pub units: Vec<String>,
+    /// Only read entries from this boot ("0" is the current boot, "-1" the one before)
+    pub boot: Option<String>,
+}
+
+/// A single journal entry, as far as we care about it
+#[derive(Debug, PartialEq)]
+struct JournalEntry {
+    /// When the entry was logged, in milliseconds since the epoch
+    timestamp: i64,
+    /// The raw message, which journald allows to be any bytes at all
+    message: Vec<u8>,
+}
+
+/// Create a vector of InputLogEvents from entries in the systemd journal
+///
+/// Each event carries the time its entry was logged rather than the current time.
+///
+/// # Arguments
+///
+/// * `journal` - Which part of the journal to read
+/// * `options` - How to pick entries out of the journal (head/tail count entries)
+/// * `summary` - Where to count anything noteworthy about the entries read
+///
+pub async fn get_events(
+    journal: &JournalOptions,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<Vec<InputLogEvent>, Error> {
+    info!("Reading the journal...");
+
+    let mut child = Command::new("journalctl")
+        .args(journalctl_args(journal, options))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("journalctl stdout is piped");
+    let events = read_entries(BufReader::new(stdout), options, summary);
+
+    // With only a head to read we may have stopped early, and journalctl can go now
+    let _ = child.kill();
+    let status = child.wait()?;
+    if status.code().is_some_and(|code| code != 0) {
+        return Err(io::Error::other(format!("journalctl failed ({})", status)).into());
+    }
+
+    events
+}
+
+/// The command line arguments for `journalctl`
+fn journalctl_args(journal: &JournalOptions, options: &EventOptions) -> Vec<String> {
+    let mut args = vec![String::from("--output=json"), String::from("--no-pager")];
+
+    for unit in &journal.units {
+        args.push(format!("--unit={}", unit));
+    }
+    if let Some(boot) = &journal.boot {
+        args.push(format!("--boot={}", boot));
+    }
+    // The journal knows when every entry was logged, so it can pick out the window
+    if let Some(since) = options.since {
+        args.push(format!("--since=@{}", since.timestamp()));
+    }
+    if let Some(until) = options.until {
+        args.push(format!("--until=@{}", until.timestamp()));
+    }
+    // There's no need to read the whole journal just to throw most of it away, unless
+    // the tail is of the entries that match a filter
+    if options.head == 0 && options.tail != 0 && options.filter.is_empty() {
+        args.push(format!("--lines={}", options.tail));
+    }
+
+    args
+}
+
+/// Create a vector of InputLogEvents from `journalctl --output=json` output
+fn read_entries<R: BufRead>(
+    reader: R,
+    options: &EventOptions,
+    summary: &mut UploadSummary,
+) -> Result<Vec<InputLogEvent>, Error> {
+    let mut processor = LineProcessor::new(options);
+    let mut selector = Selector::new(options.head, options.tail, &options.lines);
+
+    for (index, line) in reader.lines().enumerate() {
+        let entry = parse_entry(&line?)?;
+        if !options.filter.keep(&entry.message, summary) {
+            continue;
+        }
+        if let Some((index, entry)) = selector.offer((index, entry)) {
+            processor.push(entry.timestamp, index, &entry.message, summary)?;
+        }
+        if selector.is_done() {
+            break;
+        }
+    }
+
+    processor.mark_omitted(selector.omitted(), now_millis());
+    for (index, entry) in selector.finish() {
+        processor.push(entry.timestamp, index, &entry.message, summary)?;
+    }
+
+    Ok(processor.finish(summary))
+}
+
+/// Pull the timestamp and message out of one line of `journalctl` JSON output
+///
+/// Messages that aren't valid UTF-8 are written as an array of byte values, and
+/// entries without a message at all are treated as blank.
+fn parse_entry(line: &str) -> io::Result<JournalEntry> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    let entry: serde_json::Value =
+        serde_json::from_str(line).map_err(|e| invalid(&e.to_string()))?;
+
+    let timestamp = entry["__REALTIME_TIMESTAMP"]
+        .as_str()
+        .and_then(|micros| micros.parse::<i64>().ok())
+        .ok_or_else(|| invalid("journal entry has no __REALTIME_TIMESTAMP"))?;
+
+    let message = match &entry["MESSAGE"] {
+        serde_json::Value::String(message) => message.as_bytes().to_vec(),
+        serde_json::Value::Array(bytes) => bytes
+            .iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("journal entry has a malformed MESSAGE"))?,
+        _ => Vec::new(),
+    };
+
+    Ok(JournalEntry {
+        timestamp: timestamp / 1000,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_parse_entry() {
+        let line = r#"{"__REALTIME_TIMESTAMP":"1651415527123456","MESSAGE":"Started Daily apt."}"#;
+        assert_eq!(
+            parse_entry(line).unwrap(),
+            JournalEntry {
+                timestamp: 1651415527123,
+                message: b"Started Daily apt.".to_vec()
+            }
+        );
+
+        let line = r#"{"__REALTIME_TIMESTAMP":"1651415527123456","MESSAGE":[104,105,255]}"#;
+        assert_eq!(parse_entry(line).unwrap().message, b"hi\xff");
+
+        let line = r#"{"MESSAGE":"no timestamp"}"#;
+        assert!(parse_entry(line).is_err());
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let file = File::open("tests/fixtures/journal.json").unwrap();
+        let options = EventOptions {
+            head: 1,
+            tail: 1,
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+
+        let events = read_entries(BufReader::new(file), &options, &mut summary).unwrap();
+        let events: Vec<_> = events
+            .iter()
+            .map(|e| (e.timestamp.unwrap(), e.message.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (1651415520000, "Linux version 5.10.109"),
+                (1651415520000, "----- 2 lines omitted -----"),
+                (1651415529500, "caf\u{FFFD}")
+            ]
+        );
+        assert_eq!(summary.lines_replaced, 1);
+    }
+
+    #[test]
+    fn test_journalctl_args() {
+        let journal = JournalOptions {
+            units: vec![String::from("my-service")],
+            boot: Some(String::from("-1")),
+        };
+        let options = EventOptions {
+            tail: 50,
+            ..Default::default()
+        };
+        assert_eq!(
+            journalctl_args(&journal, &options),
+            [
+                "--output=json",
+                "--no-pager",
+                "--unit=my-service",
+                "--boot=-1",
+                "--lines=50"
+            ]
+        );
+
+        let options = EventOptions {
+            since: Some(crate::timestamp::parse_bound("2024-05-01T14:25:00Z").unwrap()),
+            until: Some(crate::timestamp::parse_bound("2024-05-01T14:40:00Z").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            journalctl_args(&JournalOptions::default(), &options),
+            [
+                "--output=json",
+                "--no-pager",
+                "--since=@1714573500",
+                "--until=@1714574400"
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Sending events to a Kinesis data stream instead of CloudWatch Logs, each one's
+//! message as a record of its own
+
+use crate::naming;
+use crate::records::{CallFailed, Limits, PutRecords};
+use crate::retry;
+use crate::uploader::{host_details, service_config, UploadOptions};
+
+use aws_sdk_kinesis::error::PutRecordsError;
+use aws_sdk_kinesis::model::PutRecordsRequestEntry;
+use aws_sdk_kinesis::types::{Blob, SdkError};
+use aws_sdk_kinesis::Client as Kinesis_Client;
+
+/// The longest partition key Kinesis allows
+pub const MAX_PARTITION_KEY: usize = 256;
+
+/// Error codes Kinesis uses when a stream's shards are taking all they can
+const THROTTLING_CODES: &[&str] = &[
+    "ProvisionedThroughputExceededException",
+    "KMSThrottlingException",
+];
+
+/// Where in Kinesis events go
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KinesisOptions {
+    /// The data stream to put records into
+    pub stream: String,
+    /// The partition key for every record, if not the instance id
+    pub partition_key: Option<String>,
+}
+
+/// A Kinesis data stream, and the partition key records are put into it with
+pub struct KinesisStream {
+    client: Kinesis_Client,
+    stream: String,
+    partition_key: String,
+}
+
+impl KinesisStream {
+    /// The data stream in `kinesis`, with its partition key (or else the instance id,
+    /// looked up now)
+    pub async fn new(options: &UploadOptions, kinesis: &KinesisOptions) -> KinesisStream {
+        let config = service_config(&options.client, "kinesis", "Kinesis").await;
+        let partition_key = match &kinesis.partition_key {
+            Some(key) => key.clone(),
+            None => {
+                let template = naming::parse_template("{instance_id}").expect("valid template");
+                let host = host_details(options, &[&template]).await;
+                host.instance_id.chars().take(MAX_PARTITION_KEY).collect()
+            }
+        };
+        KinesisStream {
+            client: Kinesis_Client::new(&config),
+            stream: kinesis.stream.clone(),
+            partition_key,
+        }
+    }
+}
+
+impl PutRecords for KinesisStream {
+    const LIMITS: Limits = Limits {
+        records: 500,
+        bytes: 5 * 1024 * 1024,
+    };
+
+    fn describe(&self) -> String {
+        format!("Kinesis stream {}", self.stream)
+    }
+
+    /// Partition keys count towards the limits as much as the data
+    fn size(&self, record: &[u8]) -> usize {
+        record.len() + self.partition_key.len()
+    }
+
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed> {
+        let entries = records
+            .into_iter()
+            .map(|data| {
+                PutRecordsRequestEntry::builder()
+                    .data(Blob::new(data))
+                    .partition_key(&self.partition_key)
+                    .build()
+            })
+            .collect();
+        let output = self
+            .client
+            .put_records()
+            .stream_name(&self.stream)
+            .set_records(Some(entries))
+            .send()
+            .await
+            .map_err(|e| CallFailed {
+                transient: is_transient(&e),
+                message: match &e {
+                    SdkError::ServiceError { err, .. } => err.to_string(),
+                    e => e.to_string(),
+                },
+            })?;
+        Ok(output
+            .records()
+            .unwrap_or_default()
+            .iter()
+            .map(|record| record.error_code().map(String::from))
+            .collect())
+    }
+}
+
+/// Is the error one that trying again later might get past?
+fn is_transient(e: &SdkError<PutRecordsError>) -> bool {
+    if let SdkError::ServiceError { err, .. } = e {
+        if err
+            .code()
+            .is_some_and(|code| THROTTLING_CODES.contains(&code))
+        {
+            return true;
+        }
+    }
+    retry::is_transient_sdk_error(e)
+}
+
+/// Parse a --partition-key, which Kinesis needs to be 1 to 256 characters
+pub fn parse_partition_key(s: &str) -> Result<String, String> {
+    match s.chars().count() {
+        1..=MAX_PARTITION_KEY => Ok(s.to_string()),
+        _ => Err(format!(
+            "A partition key has to be 1 to {} characters",
+            MAX_PARTITION_KEY
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partition_key() {
+        assert_eq!(parse_partition_key("web-1").unwrap(), "web-1");
+        assert!(parse_partition_key("").is_err());
+        assert!(parse_partition_key(&"k".repeat(257)).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+#![warn(missing_docs)]
+//! Quickly shove files into CloudWatch Logs (or Kinesis, Firehose, a file, syslog,
+//! Splunk or an OpenTelemetry collector)
+//!
+//! The `rusty-axe` program is a thin layer over this.  [`events`] picks out the files
+//! to read and reads them into events, [`uploader`] sends those events and sums up
+//! how it went, [`metadata`] asks the instance metadata where this is running, and
+//! [`cli`] has the command line's options, and runs the commands they give.  Anything
+//! that stops them is an [`error::Error`], which says what status to exit with.
+//!
+//! ```no_run
+//! use rusty_axe::error::Error;
+//! use rusty_axe::events::{self, EventOptions};
+//! use rusty_axe::uploader::{self, StreamEvents, UploadOptions, UploadSummary};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Error> {
+//!     // The last 100 lines of each file
+//!     let options = EventOptions {
+//!         tail: 100,
+//!         ..Default::default()
+//!     };
+//!     let mut summary = UploadSummary::default();
+//!     let patterns = [String::from("/var/log/app/*.log")];
+//!     let files = events::expand_globs(&patterns, false, false, &mut summary)
+//!         .map_err(Error::Other)?;
+//!     let events = events::collect_events(&files, &options, None, &mut summary).await;
+//!
+//!     let options = UploadOptions {
+//!         group: String::from("crash-logs"),
+//!         ..Default::default()
+//!     };
+//!     let streams = vec![StreamEvents::merged(events)];
+//!     let unsent = uploader::send_logs(&options, streams, &mut summary).await?;
+//!     assert!(unsent.is_empty() && summary.is_success());
+//!     Ok(())
+//! }
+//! ```
+
+mod batch;
+pub mod cli;
+mod completions;
+mod config;
+mod credentials;
+mod dump;
+mod endpoints;
+mod environment;
+pub mod error;
+pub mod events;
+pub mod exit;
+mod filter;
+mod firehose;
+mod follow;
+mod format;
+mod group;
+mod guard;
+mod http;
+mod input;
+#[cfg(feature = "journald")]
+mod journal;
+mod kinesis;
+mod list;
+mod logging;
+mod man;
+mod message;
+pub mod metadata;
+mod metric;
+#[cfg(test)]
+mod mock;
+mod multiline;
+mod naming;
+mod notify;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod plan;
+mod profile;
+mod progress;
+mod rate;
+mod records;
+mod redact;
+mod region;
+mod retry;
+mod s3;
+mod splunk;
+mod state;
+mod stream;
+#[cfg(test)]
+#[path = "../tests/support/stub.rs"]
+mod stub;
+mod syslog;
+mod template;
+mod timestamp;
+pub mod uploader;
+mod verify;
+mod version;
+mod zone;
@@ -0,0 +1,518 @@
+//! Listing what's in CloudWatch Logs, for the `groups` and `streams` commands
+
+use crate::cli::Global;
+use crate::message::format_size;
+use crate::retry::RetryPolicy;
+use crate::uploader::{ClientOptions, Output};
+
+use aws_sdk_cloudwatchlogs::model::{LogGroup, LogStream, OrderBy};
+use aws_sdk_cloudwatchlogs::{Client as CWL_Client, Error};
+use chrono::{TimeZone, Utc};
+use clap::ValueHint;
+use serde_json::json;
+use std::cmp::Reverse;
+use tracing::error;
+
+/// List log groups, to find the name of the one you're after
+#[derive(clap::Args, Debug)]
+pub struct GroupsArgs {
+    /// Only list log groups whose names start with this
+    #[clap(long, env = "RUSTY_AXE_PREFIX", value_hint = ValueHint::Other)]
+    prefix: Option<String>,
+}
+
+/// List the log streams in a log group, most recently written to first
+#[derive(clap::Args, Debug)]
+pub struct StreamsArgs {
+    /// The log group to list the streams of
+    #[clap(short, long, env = "RUSTY_AXE_GROUP", value_hint = ValueHint::Other)]
+    group: String,
+
+    /// Only list log streams whose names start with this
+    #[clap(long, env = "RUSTY_AXE_PREFIX", value_hint = ValueHint::Other)]
+    prefix: Option<String>,
+
+    /// List no more than this many log streams
+    #[clap(long, env = "RUSTY_AXE_LIMIT")]
+    limit: Option<usize>,
+}
+
+/// A page of log groups, and the token for the next page (None for the last page)
+pub type GroupsPage = (Vec<LogGroup>, Option<String>);
+
+/// Something that can list log groups a page at a time
+pub trait DescribeGroups {
+    /// The page of log groups named with `prefix` that `next_token` points to (the
+    /// first, for None)
+    async fn describe_groups(
+        &self,
+        prefix: Option<&str>,
+        next_token: Option<String>,
+    ) -> Result<GroupsPage, Error>;
+}
+
+impl DescribeGroups for CWL_Client {
+    async fn describe_groups(
+        &self,
+        prefix: Option<&str>,
+        next_token: Option<String>,
+    ) -> Result<GroupsPage, Error> {
+        let resp = self
+            .describe_log_groups()
+            .set_log_group_name_prefix(prefix.map(String::from))
+            .set_next_token(next_token)
+            .send()
+            .await?;
+        Ok((resp.log_groups.unwrap_or_default(), resp.next_token))
+    }
+}
+
+/// A page of log streams, and the token for the next page (None for the last page)
+pub type StreamsPage = (Vec<LogStream>, Option<String>);
+
+/// Something that can list the log streams in a log group a page at a time
+pub trait DescribeStreams {
+    /// The page of `group`'s log streams named with `prefix` that `next_token` points
+    /// to (the first, for None)
+    ///
+    /// Without a prefix, they come most recently written to first.  With one, they
+    /// come in order of name, since CloudWatch Logs won't order them by time then.
+    async fn describe_streams(
+        &self,
+        group: &str,
+        prefix: Option<&str>,
+        next_token: Option<String>,
+    ) -> Result<StreamsPage, Error>;
+}
+
+impl DescribeStreams for CWL_Client {
+    async fn describe_streams(
+        &self,
+        group: &str,
+        prefix: Option<&str>,
+        next_token: Option<String>,
+    ) -> Result<StreamsPage, Error> {
+        let request = self
+            .describe_log_streams()
+            .log_group_name(group)
+            .set_next_token(next_token);
+        let request = match prefix {
+            Some(prefix) => request.log_stream_name_prefix(prefix),
+            None => request.order_by(OrderBy::LastEventTime).descending(true),
+        };
+        let resp = request.send().await?;
+        Ok((resp.log_streams.unwrap_or_default(), resp.next_token))
+    }
+}
+
+/// Every log group named with `prefix`, however many pages they take
+pub async fn list_groups<C: DescribeGroups>(
+    client: &C,
+    prefix: Option<&str>,
+    retry: &RetryPolicy,
+) -> Result<Vec<LogGroup>, Error> {
+    let mut groups = Vec::new();
+    let mut next_token = None;
+    loop {
+        let (page, next) = retry
+            .run("DescribeLogGroups", || {
+                client.describe_groups(prefix, next_token.clone())
+            })
+            .await?;
+        groups.extend(page);
+        match next {
+            // A token that doesn't move on would go round forever
+            Some(next) if next_token.as_ref() != Some(&next) => next_token = Some(next),
+            _ => return Ok(groups),
+        }
+    }
+}
+
+/// The log streams in `group` named with `prefix`, most recently written to first
+/// and no more than `limit` of them
+///
+/// Without a prefix, the streams already come in that order, so only as many pages
+/// are fetched as the limit takes.  With one, every page has to be fetched to sort
+/// them.
+pub async fn list_streams<C: DescribeStreams>(
+    client: &C,
+    group: &str,
+    prefix: Option<&str>,
+    limit: Option<usize>,
+    retry: &RetryPolicy,
+) -> Result<Vec<LogStream>, Error> {
+    let mut streams = Vec::new();
+    let mut next_token = None;
+    loop {
+        let (page, next) = retry
+            .run("DescribeLogStreams", || {
+                client.describe_streams(group, prefix, next_token.clone())
+            })
+            .await?;
+        streams.extend(page);
+        if prefix.is_none() && limit.is_some_and(|limit| streams.len() >= limit) {
+            break;
+        }
+        match next {
+            Some(next) if next_token.as_ref() != Some(&next) => next_token = Some(next),
+            _ => break,
+        }
+    }
+    streams.sort_by_key(|stream| Reverse(stream.last_event_timestamp()));
+    if let Some(limit) = limit {
+        streams.truncate(limit);
+    }
+    Ok(streams)
+}
+
+/// Run the `groups` command
+pub async fn groups(args: GroupsArgs, global: &Global) -> Result<(), crate::error::Error> {
+    let client = crate::uploader::new_client(&client_options(global)).await;
+    let groups = match list_groups(&client, args.prefix.as_deref(), &RetryPolicy::default()).await {
+        Ok(groups) => groups,
+        Err(e) => {
+            error!("Couldn't list log groups");
+            return Err(e.into());
+        }
+    };
+    match global.output {
+        Output::Text => print!("{}", groups_table(&groups)),
+        Output::Json => println!("{}", groups_json(&groups)),
+    }
+    Ok(())
+}
+
+/// Calling CloudWatch Logs in the region, and with the profile, the global options
+/// give
+fn client_options(global: &Global) -> ClientOptions {
+    ClientOptions {
+        region: global.region.clone(),
+        profile: crate::profile::selected(global.profile.clone()),
+        ..Default::default()
+    }
+}
+
+/// Run the `streams` command
+pub async fn streams(args: StreamsArgs, global: &Global) -> Result<(), crate::error::Error> {
+    let client = crate::uploader::new_client(&client_options(global)).await;
+    let listed = list_streams(
+        &client,
+        &args.group,
+        args.prefix.as_deref(),
+        args.limit,
+        &RetryPolicy::default(),
+    )
+    .await;
+    let streams = match listed {
+        Ok(streams) => streams,
+        Err(e) => {
+            error!("Couldn't list log streams");
+            return Err(e.into());
+        }
+    };
+    match global.output {
+        Output::Text => print!("{}", streams_table(&streams)),
+        Output::Json => println!("{}", streams_json(&streams)),
+    }
+    Ok(())
+}
+
+/// The time CloudWatch Logs gives in milliseconds since the epoch, as RFC 3339
+fn format_time(millis: Option<i64>) -> Option<String> {
+    millis
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .map(|time| time.to_rfc3339())
+}
+
+/// The log groups as a table, with a header row and a row each
+fn groups_table(groups: &[LogGroup]) -> String {
+    let rows: Vec<[String; 4]> = groups
+        .iter()
+        .map(|group| {
+            [
+                group.log_group_name().unwrap_or_default().to_string(),
+                group
+                    .retention_in_days()
+                    .map_or_else(|| String::from("forever"), |days| format!("{} days", days)),
+                format_size(group.stored_bytes().unwrap_or(0).max(0) as usize),
+                format_time(group.creation_time()).unwrap_or_default(),
+            ]
+        })
+        .collect();
+    table(["NAME", "RETENTION", "STORED", "CREATED"], &rows)
+}
+
+/// The log groups as a JSON array
+fn groups_json(groups: &[LogGroup]) -> serde_json::Value {
+    groups
+        .iter()
+        .map(|group| {
+            json!({
+                "name": group.log_group_name(),
+                "retention_days": group.retention_in_days(),
+                "stored_bytes": group.stored_bytes(),
+                "creation_time": format_time(group.creation_time()),
+            })
+        })
+        .collect()
+}
+
+/// The log streams as a table, with a header row and a row each
+fn streams_table(streams: &[LogStream]) -> String {
+    let rows: Vec<[String; 3]> = streams
+        .iter()
+        .map(|stream| {
+            [
+                stream.log_stream_name().unwrap_or_default().to_string(),
+                format_time(stream.last_event_timestamp()).unwrap_or_else(|| String::from("never")),
+                format_size(stream.stored_bytes().unwrap_or(0).max(0) as usize),
+            ]
+        })
+        .collect();
+    table(["NAME", "LAST EVENT", "STORED"], &rows)
+}
+
+/// The log streams as a JSON array
+fn streams_json(streams: &[LogStream]) -> serde_json::Value {
+    streams
+        .iter()
+        .map(|stream| {
+            json!({
+                "name": stream.log_stream_name(),
+                "last_event_time": format_time(stream.last_event_timestamp()),
+                "stored_bytes": stream.stored_bytes(),
+            })
+        })
+        .collect()
+}
+
+/// Lay rows out in columns under a header, each as wide as its widest value
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(|title| title.chars().count());
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let line = |values: Vec<&str>| {
+        let cells: Vec<_> = values
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+    let mut table = line(header.to_vec());
+    for row in rows {
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Hands out the pages it's given in turn, remembering the token each was asked
+    /// for with
+    #[derive(Default)]
+    struct MockClient {
+        pages: Mutex<VecDeque<GroupsPage>>,
+        tokens: Mutex<Vec<Option<String>>>,
+    }
+
+    impl DescribeGroups for MockClient {
+        async fn describe_groups(
+            &self,
+            prefix: Option<&str>,
+            next_token: Option<String>,
+        ) -> Result<GroupsPage, Error> {
+            assert_eq!(prefix, Some("/ec2"));
+            self.tokens.lock().unwrap().push(next_token);
+            Ok(self.pages.lock().unwrap().pop_front().unwrap_or_default())
+        }
+    }
+
+    fn group(name: &str) -> LogGroup {
+        LogGroup::builder()
+            .log_group_name(name)
+            .stored_bytes(2048)
+            .creation_time(1_660_469_405_000)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_list_groups_pages() {
+        // 120 groups, 50 to a page
+        let names: Vec<_> = (0..120).map(|i| format!("/ec2/group-{}", i)).collect();
+        let pages = names.chunks(50).enumerate().map(|(i, chunk)| {
+            let next = (i < 2).then(|| format!("page-{}", i + 1));
+            (chunk.iter().map(|name| group(name)).collect(), next)
+        });
+        let client = MockClient {
+            pages: Mutex::new(pages.collect()),
+            ..Default::default()
+        };
+        let groups = list_groups(&client, Some("/ec2"), &RetryPolicy::default())
+            .await
+            .unwrap();
+        let listed: Vec<_> = groups
+            .iter()
+            .map(|group| group.log_group_name().unwrap())
+            .collect();
+        assert_eq!(listed, names);
+        assert_eq!(
+            *client.tokens.lock().unwrap(),
+            [
+                None,
+                Some(String::from("page-1")),
+                Some(String::from("page-2"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_groups_output() {
+        let groups = [
+            LogGroup::builder()
+                .log_group_name("/ec2/crash-logs")
+                .retention_in_days(14)
+                .stored_bytes(3 * 1024 * 1024)
+                .creation_time(1_660_469_405_000)
+                .build(),
+            group("ec2-crash-logs"),
+        ];
+        assert_eq!(
+            groups_table(&groups),
+            "NAME             RETENTION  STORED  CREATED\n\
+             /ec2/crash-logs  14 days    3.0 MB  2022-08-14T09:30:05+00:00\n\
+             ec2-crash-logs   forever    2 KB    2022-08-14T09:30:05+00:00\n"
+        );
+        assert_eq!(
+            groups_json(&groups[..1]),
+            json!([{
+                "name": "/ec2/crash-logs",
+                "retention_days": 14,
+                "stored_bytes": 3 * 1024 * 1024,
+                "creation_time": "2022-08-14T09:30:05+00:00",
+            }])
+        );
+    }
+
+    /// Hands out the pages of streams it's given in turn, remembering the token each
+    /// was asked for with
+    #[derive(Default)]
+    struct MockStreams {
+        pages: Mutex<VecDeque<StreamsPage>>,
+        tokens: Mutex<Vec<Option<String>>>,
+    }
+
+    impl DescribeStreams for MockStreams {
+        async fn describe_streams(
+            &self,
+            group: &str,
+            _prefix: Option<&str>,
+            next_token: Option<String>,
+        ) -> Result<StreamsPage, Error> {
+            assert_eq!(group, "crash-logs");
+            self.tokens.lock().unwrap().push(next_token);
+            Ok(self.pages.lock().unwrap().pop_front().unwrap_or_default())
+        }
+    }
+
+    fn stream(name: &str, last_event: Option<i64>) -> LogStream {
+        LogStream::builder()
+            .log_stream_name(name)
+            .set_last_event_timestamp(last_event)
+            .build()
+    }
+
+    /// Pages of three streams each, named for when they were last written to
+    fn stream_pages(last_events: &[Option<i64>]) -> MockStreams {
+        let count = last_events.chunks(3).count();
+        let pages = last_events.chunks(3).enumerate().map(|(i, chunk)| {
+            let streams = chunk
+                .iter()
+                .map(|&time| stream(&format!("{:?}", time), time))
+                .collect();
+            (streams, (i + 1 < count).then(|| format!("page-{}", i + 1)))
+        });
+        MockStreams {
+            pages: Mutex::new(pages.collect()),
+            ..Default::default()
+        }
+    }
+
+    fn names(streams: &[LogStream]) -> Vec<&str> {
+        streams
+            .iter()
+            .map(|stream| stream.log_stream_name().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_list_streams_pages() {
+        let policy = RetryPolicy::default();
+        // Without a prefix they come in order, so paging stops at the limit
+        let client = stream_pages(&[
+            Some(9),
+            Some(8),
+            Some(7),
+            Some(6),
+            Some(5),
+            Some(4),
+            Some(3),
+        ]);
+        let streams = list_streams(&client, "crash-logs", None, Some(4), &policy)
+            .await
+            .unwrap();
+        assert_eq!(
+            names(&streams),
+            ["Some(9)", "Some(8)", "Some(7)", "Some(6)"]
+        );
+        assert_eq!(client.tokens.lock().unwrap().len(), 2);
+
+        // With one they come by name, so every page is fetched and sorted
+        let client = stream_pages(&[Some(1), None, Some(7), Some(3), Some(9)]);
+        let streams = list_streams(&client, "crash-logs", Some("i-0abc"), Some(3), &policy)
+            .await
+            .unwrap();
+        assert_eq!(names(&streams), ["Some(9)", "Some(7)", "Some(3)"]);
+        assert_eq!(
+            *client.tokens.lock().unwrap(),
+            [None, Some(String::from("page-1"))]
+        );
+
+        // And without a limit, everything
+        let client = stream_pages(&[Some(5), Some(4), Some(3), Some(2), Some(1), None, None]);
+        let streams = list_streams(&client, "crash-logs", None, None, &policy)
+            .await
+            .unwrap();
+        assert_eq!(streams.len(), 7);
+        assert_eq!(client.tokens.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_streams_output() {
+        let streams = [
+            LogStream::builder()
+                .log_stream_name("i-0abc-2022-08-14")
+                .last_event_timestamp(1_660_469_405_000)
+                .stored_bytes(0)
+                .build(),
+            stream("i-0abd", None),
+        ];
+        assert_eq!(
+            streams_table(&streams),
+            "NAME               LAST EVENT                 STORED\n\
+             i-0abc-2022-08-14  2022-08-14T09:30:05+00:00  0 bytes\n\
+             i-0abd             never                      0 bytes\n"
+        );
+        assert_eq!(
+            streams_json(&streams[1..]),
+            json!([{"name": "i-0abd", "last_event_time": null, "stored_bytes": null}])
+        );
+    }
+}
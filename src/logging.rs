@@ -0,0 +1,392 @@
+//! Saying what's going on, on stderr, as much as -v, -vv and --quiet (or RUST_LOG, for
+//! finer control) ask for, so stdout only has what a command puts out, like the
+//! events of a dry run
+//!
+//! How far a run has got is shown here too, as a bar if stderr is a terminal.
+
+use crate::progress::{Progress, Update};
+
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// The progress bar, once there is one, which has to be cleared away while anything
+/// else is said
+static BAR: OnceLock<ProgressBar> = OnceLock::new();
+
+/// How much to say, which every command takes (before its name or after it)
+#[derive(clap::Args, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Verbosity {
+    /// Say more about what's going on, like calls that are retried (-vv says
+    /// everything, the AWS SDK's workings included)
+    #[clap(
+        short,
+        long,
+        env = "RUSTY_AXE_VERBOSE",
+        global = true,
+        action = clap::ArgAction::Count
+    )]
+    pub verbose: u8,
+
+    /// Only say what went wrong, and anything that might have
+    #[clap(
+        short,
+        long,
+        env = "RUSTY_AXE_QUIET",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet: bool,
+}
+
+impl Verbosity {
+    /// What to say, as RUST_LOG would put it
+    ///
+    /// Other than with -vv, only this program's own messages go below warnings; the
+    /// AWS SDK has a lot to say at those levels.
+    pub fn directives(self) -> &'static str {
+        match (self.quiet, self.verbose) {
+            (true, _) => "warn",
+            (false, 0) => "warn,rusty_axe=info",
+            (false, 1) => "warn,rusty_axe=debug",
+            (false, _) => "debug,rusty_axe=trace",
+        }
+    }
+
+    /// What to say, as RUST_LOG has it if it's set, or else as these flags do
+    fn filter(self) -> EnvFilter {
+        match std::env::var(EnvFilter::DEFAULT_ENV) {
+            Ok(directives) if !directives.trim().is_empty() => EnvFilter::new(directives),
+            _ => EnvFilter::new(self.directives()),
+        }
+    }
+}
+
+/// Say what's going on on stderr from now on
+pub fn init(verbosity: Verbosity) {
+    let ansi = io::stderr().is_terminal();
+    tracing_subscriber::fmt()
+        .with_env_filter(verbosity.filter())
+        .with_writer(|| Stderr)
+        .with_ansi(ansi)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+/// Stderr, keeping out of the progress bar's way
+struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match BAR.get() {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Say how far a run has got on stderr, unless `verbosity` (or --no-progress, when
+/// `enabled` is false) says not to
+///
+/// That's a bar when stderr is a terminal, and otherwise a line at most every
+/// `interval`, for logs that are only read later.
+pub fn progress(verbosity: Verbosity, enabled: bool, interval: Duration) -> Progress {
+    if !enabled || verbosity.quiet {
+        Progress::default()
+    } else if io::stderr().is_terminal() {
+        let bar =
+            BAR.get_or_init(|| ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr()));
+        let bar = Bar {
+            bar: bar.clone(),
+            phase: Mutex::new(Phase::Starting),
+        };
+        Progress::new(move |update| bar.show(update))
+    } else {
+        let lines = Lines {
+            interval,
+            last: Mutex::new(Instant::now()),
+            sending: OnceLock::new(),
+        };
+        Progress::new(move |update| lines.show(update))
+    }
+}
+
+/// A rate in bytes a second, from `bytes` sent since `started`
+fn rate(bytes: u64, started: Instant) -> HumanBytes {
+    let seconds = started.elapsed().as_secs_f64().max(0.001);
+    HumanBytes((bytes as f64 / seconds) as u64)
+}
+
+/// What a progress bar is showing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Starting,
+    /// Reading input of this size, if it's known
+    Reading(Option<u64>),
+    /// Sending, which started then
+    Sending(Instant),
+}
+
+/// Updates shown on a progress bar
+struct Bar {
+    bar: ProgressBar,
+    phase: Mutex<Phase>,
+}
+
+impl Bar {
+    fn show(&self, update: Update) {
+        let mut phase = self.phase.lock().unwrap();
+        match update {
+            Update::Read { bytes, total } => {
+                if *phase != Phase::Reading(total) {
+                    *phase = Phase::Reading(total);
+                    self.restyle(match total {
+                        Some(_) => "Reading [{bar:30}] {bytes}/{total_bytes}",
+                        None => "Reading {bytes}",
+                    });
+                }
+                self.bar.set_length(total.unwrap_or(0));
+                self.bar.set_position(bytes);
+            }
+            Update::Sent {
+                batches,
+                total,
+                bytes,
+            } => {
+                let started = match *phase {
+                    Phase::Sending(started) => started,
+                    _ => {
+                        self.restyle("Sending [{bar:30}] {pos}/{len} batches, {msg}/s");
+                        let started = Instant::now();
+                        *phase = Phase::Sending(started);
+                        started
+                    }
+                };
+                self.bar.set_message(rate(bytes, started).to_string());
+                self.bar.set_length(total);
+                self.bar.set_position(batches);
+                if batches == total {
+                    self.bar.finish_and_clear();
+                }
+            }
+        }
+    }
+
+    fn restyle(&self, template: &str) {
+        let style = ProgressStyle::with_template(template)
+            .expect("a valid template")
+            .progress_chars("=> ");
+        self.bar.reset();
+        self.bar.set_style(style);
+    }
+}
+
+/// Updates said as a line every so often
+struct Lines {
+    interval: Duration,
+    /// When the last line was said
+    last: Mutex<Instant>,
+    /// When sending started, once it has
+    sending: OnceLock<Instant>,
+}
+
+impl Lines {
+    fn show(&self, update: Update) {
+        if let Update::Sent { .. } = update {
+            self.sending.get_or_init(Instant::now);
+        }
+        let mut last = self.last.lock().unwrap();
+        if last.elapsed() < self.interval {
+            return;
+        }
+        *last = Instant::now();
+        let started = self.sending.get().copied().unwrap_or(*last);
+        info!("{}", describe(update, started));
+    }
+}
+
+/// A line saying how far a run has got, sending since `started`
+fn describe(update: Update, started: Instant) -> String {
+    match update {
+        Update::Read {
+            bytes,
+            total: Some(total),
+        } => format!("Read {} of {}", HumanBytes(bytes), HumanBytes(total)),
+        Update::Read { bytes, total: None } => format!("Read {}", HumanBytes(bytes)),
+        Update::Sent {
+            batches,
+            total,
+            bytes,
+        } => format!(
+            "Sent {} of {} batches ({}, {}/s)",
+            batches,
+            total,
+            HumanBytes(bytes),
+            rate(bytes, started)
+        ),
+    }
+}
+
+/// Somewhere that keeps what's said, for the tests to look at
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    /// Keep what's said, at the levels `verbosity` asks for, until the guard is
+    /// dropped (on this thread only, so with tokio's current thread runtime)
+    pub fn start(verbosity: Verbosity) -> (Captured, tracing::subscriber::DefaultGuard) {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new(verbosity.directives()))
+            .with_writer(captured.clone())
+            .with_ansi(false)
+            .with_target(false)
+            .without_time()
+            .finish();
+        let guard = tracing::subscriber::set_default(subscriber);
+        (captured, guard)
+    }
+
+    /// Each line said so far
+    pub fn lines(&self) -> Vec<String> {
+        let said = self.0.lock().unwrap();
+        String::from_utf8_lossy(&said)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'a self) -> Captured {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{debug, info, trace, warn};
+
+    fn say_everything() {
+        trace!("tracing");
+        debug!("debugging");
+        info!("informing");
+        warn!("warning");
+    }
+
+    #[test]
+    fn test_levels() {
+        let said = |verbosity| {
+            let (captured, _guard) = Captured::start(verbosity);
+            say_everything();
+            captured.lines()
+        };
+        assert_eq!(
+            said(Verbosity::default()),
+            ["INFO informing", "WARN warning"]
+        );
+        let verbose = Verbosity {
+            verbose: 1,
+            quiet: false,
+        };
+        assert_eq!(said(verbose)[0], "DEBUG debugging");
+        let verbose = Verbosity {
+            verbose: 2,
+            quiet: false,
+        };
+        assert_eq!(said(verbose)[0], "TRACE tracing");
+        let quiet = Verbosity {
+            verbose: 0,
+            quiet: true,
+        };
+        assert_eq!(said(quiet), ["WARN warning"]);
+    }
+
+    #[test]
+    fn test_describe() {
+        let started = Instant::now();
+        let read = Update::Read {
+            bytes: 1536,
+            total: Some(3 * 1024 * 1024),
+        };
+        assert_eq!(describe(read, started), "Read 1.50 KiB of 3.00 MiB");
+        let read = Update::Read {
+            bytes: 12,
+            total: None,
+        };
+        assert_eq!(describe(read, started), "Read 12 B");
+        let sent = Update::Sent {
+            batches: 2,
+            total: 5,
+            bytes: 2048,
+        };
+        let line = describe(sent, started);
+        assert!(
+            line.starts_with("Sent 2 of 5 batches (2.00 KiB, "),
+            "{}",
+            line
+        );
+        assert!(line.ends_with("/s)"), "{}", line);
+    }
+
+    #[test]
+    fn test_lines() {
+        let (captured, _guard) = Captured::start(Verbosity::default());
+        let lines = Lines {
+            interval: Duration::from_secs(3600),
+            last: Mutex::new(Instant::now() - Duration::from_secs(3600)),
+            sending: OnceLock::new(),
+        };
+        let read = |bytes| Update::Read {
+            bytes,
+            total: Some(100),
+        };
+        lines.show(read(10));
+        // Too soon after the last line to say another
+        lines.show(read(20));
+        assert_eq!(captured.lines(), ["INFO Read 10 B of 100 B"]);
+    }
+
+    #[test]
+    fn test_args() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(flatten)]
+            verbosity: Verbosity,
+        }
+        let parse = |args: &[&str]| {
+            <Args as clap::Parser>::try_parse_from([&["rusty-axe"], args].concat())
+                .map(|args| args.verbosity)
+        };
+        assert_eq!(parse(&[]).unwrap(), Verbosity::default());
+        assert_eq!(parse(&["-vv"]).unwrap().verbose, 2);
+        assert!(parse(&["--quiet"]).unwrap().quiet);
+        assert!(parse(&["-q", "-v"]).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Manual pages, for the (hidden) `man` command to write out when packaging
+
+use crate::cli::Cli;
+use crate::error::Error;
+use crate::exit;
+
+use clap::{CommandFactory, ErrorKind, ValueHint};
+use clap_mangen::Man;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Print rusty-axe's manual page, or a command's, as roff
+#[derive(clap::Args, Debug)]
+pub struct ManArgs {
+    /// The command whose page to print, like push, rather than rusty-axe's own
+    command: Option<String>,
+
+    /// Write every page into this directory instead, as rusty-axe.1, rusty-axe-push.1
+    /// and so on
+    #[clap(long, env = "RUSTY_AXE_OUT_DIR", value_hint = ValueHint::DirPath, conflicts_with = "command")]
+    out_dir: Option<PathBuf>,
+}
+
+pub fn print(args: ManArgs) -> Result<(), Error> {
+    let pages = pages();
+    if let Some(dir) = args.out_dir {
+        for page in pages {
+            let path = dir.join(format!("{}.1", page.get_name()));
+            File::create(&path)
+                .and_then(|mut file| render(page, &mut file))
+                .map_err(|e| Error::Other(format!("Couldn't write {}: {}", path.display(), e)))?;
+        }
+        return Ok(());
+    }
+
+    let name = match args.command {
+        Some(command) => format!("rusty-axe-{}", command),
+        None => String::from("rusty-axe"),
+    };
+    let page = match pages.into_iter().find(|page| page.get_name() == name) {
+        Some(page) => page,
+        None => {
+            let message = format!("There's no manual page for {}", name);
+            return Err(Cli::command()
+                .error(ErrorKind::InvalidSubcommand, message)
+                .into());
+        }
+    };
+    let mut stdout = io::stdout().lock();
+    render(page, &mut stdout)
+        .and_then(|()| stdout.flush())
+        .map_err(|e| Error::Other(format!("Couldn't print the manual page: {}", e)))
+}
+
+/// rusty-axe's page, then one for each command it has (but this one, and help), named
+/// as rusty-axe-push is
+fn pages() -> Vec<clap::Command<'static>> {
+    let mut command = Cli::command();
+    // Which puts the global options into each command, for its page to list
+    command.build();
+    let commands: Vec<_> = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+        .map(|subcommand| {
+            let name = format!("{}-{}", command.get_name(), subcommand.get_name());
+            subcommand.clone().name(name)
+        })
+        .collect();
+    std::iter::once(command).chain(commands).collect()
+}
+
+/// Write `command`'s page to `out`
+///
+/// It's what clap_mangen writes, but for the exit statuses being laid out as a list
+/// of their own, rather than run together as after_help's text would be.
+fn render(command: clap::Command<'static>, out: &mut dyn Write) -> io::Result<()> {
+    let has_options = command.get_arguments().any(|arg| !arg.is_hide_set());
+    let has_commands = command.has_subcommands();
+    let has_version = command.get_version().is_some();
+    let has_author = command.get_author().is_some();
+    let man = Man::new(command);
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    if has_options {
+        man.render_options_section(out)?;
+    }
+    if has_commands {
+        man.render_subcommands_section(out)?;
+    }
+    render_exit_status(out)?;
+    if has_version {
+        man.render_version_section(out)?;
+    }
+    if has_author {
+        man.render_authors_section(out)?;
+    }
+    Ok(())
+}
+
+/// Write the EXIT STATUS section, from what --help says about them
+fn render_exit_status(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, ".SH \"EXIT STATUS\"")?;
+    for line in exit::HELP.lines().skip(1) {
+        if let Some((code, meaning)) = line.trim().split_once(char::is_whitespace) {
+            writeln!(out, ".TP\n{}\n{}", code, escape(meaning.trim()))?;
+        }
+    }
+    Ok(())
+}
+
+/// `text` as roff, with its hyphens kept from being taken for dashes
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every page, by name
+    fn rendered() -> Vec<(String, String)> {
+        pages()
+            .into_iter()
+            .map(|page| {
+                let name = page.get_name().to_string();
+                let mut out = Vec::new();
+                render(page, &mut out).unwrap();
+                (name, String::from_utf8(out).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pages() {
+        let pages = rendered();
+        let names: Vec<_> = pages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "rusty-axe",
+                "rusty-axe-push",
+                "rusty-axe-groups",
+                "rusty-axe-streams",
+                "rusty-axe-completions",
+                "rusty-axe-version"
+            ]
+        );
+        for (name, page) in &pages {
+            assert!(page.starts_with(".ie \\n(.g .ds Aq"), "{}", name);
+            assert!(page.contains(".SH \"EXIT STATUS\"\n"), "{}", name);
+            assert!(
+                page.contains(".TP\n8\nAn input file was over \\-\\-max\\-file\\-size"),
+                "{}",
+                name
+            );
+        }
+        let (_, top) = &pages[0];
+        assert!(top.contains("rusty\\-axe\\-push(1)"), "{}", top);
+        assert!(!top.contains("rusty\\-axe\\-man(1)"), "{}", top);
+    }
+
+    #[test]
+    fn test_every_option_is_documented() {
+        let mut command = Cli::command();
+        command.build();
+        for (name, page) in rendered() {
+            let documented = match name.strip_prefix("rusty-axe-") {
+                Some(subcommand) => command.find_subcommand(subcommand).unwrap(),
+                None => &command,
+            };
+            for arg in documented.get_arguments().filter(|arg| !arg.is_hide_set()) {
+                if let Some(long) = arg.get_long() {
+                    let flag = format!("\\fB\\-\\-{}\\fR", escape(long));
+                    assert!(page.contains(&flag), "--{} isn't on {}'s page", long, name);
+                }
+                if let Some(short) = arg.get_short() {
+                    let flag = format!("\\fB\\-{}\\fR", short);
+                    assert!(page.contains(&flag), "-{} isn't on {}'s page", short, name);
+                }
+                if let Some(env) = arg.get_env().and_then(|env| env.to_str()) {
+                    let variable = format!("\\fB{}\\fR", env);
+                    assert!(page.contains(&variable), "{} isn't on {}'s page", env, name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_out_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = ManArgs {
+            command: None,
+            out_dir: Some(dir.path().to_path_buf()),
+        };
+        print(args).unwrap();
+        let page = std::fs::read_to_string(dir.path().join("rusty-axe-push.1")).unwrap();
+        assert!(page.contains("\\-\\-max\\-file\\-size"));
+        assert!(dir.path().join("rusty-axe.1").exists());
+        assert!(!dir.path().join("rusty-axe-man.1").exists());
+    }
+}
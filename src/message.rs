@@ -0,0 +1,337 @@
+//! Shaping individual messages so CloudWatch Logs will accept them
+
+/// The largest message PutLogEvents accepts: 256 KB, less 26 bytes of per-event overhead
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 - 26;
+
+/// Room kept free at the start of each part of a split message for its marker
+const PART_MARKER_SIZE: usize = 32;
+
+/// Room kept free at the end of a truncated message to say how much was cut
+const TRUNCATED_NOTE_SIZE: usize = 32;
+
+/// Cut `message` down to at most `max` bytes, ending it with a note of how much was
+/// cut (like `…[truncated 412 KB]`)
+///
+/// The cut never splits a UTF-8 character, and the note fits within `max`.
+pub fn truncate(message: &mut String, max: usize) {
+    if message.len() <= max {
+        return;
+    }
+
+    let end = floor_char_boundary(message, max.saturating_sub(TRUNCATED_NOTE_SIZE));
+    let note = format!("…[truncated {}]", format_size(message.len() - end));
+    message.truncate(end);
+    message.push_str(&note);
+}
+
+/// A number of bytes in the units a person would use
+pub fn format_size(bytes: usize) -> String {
+    const KB: usize = 1024;
+    const MB: usize = 1024 * KB;
+
+    if bytes < KB {
+        format!("{} bytes", bytes)
+    } else if bytes < MB {
+        format!("{} KB", (bytes + KB / 2) / KB)
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    }
+}
+
+/// Split a message that's too big into parts that each fit in an event
+///
+/// Every part is prefixed with a marker like `[part 2/3] ` so the pieces can be put
+/// back together, and splits only ever land between UTF-8 characters.  Messages that
+/// already fit are handed back untouched.
+pub fn split(message: String) -> Vec<String> {
+    if message.len() <= MAX_MESSAGE_SIZE {
+        return vec![message];
+    }
+
+    let mut chunks = Vec::new();
+    let mut rest = message.as_str();
+    while !rest.is_empty() {
+        let end = floor_char_boundary(rest, MAX_MESSAGE_SIZE - PART_MARKER_SIZE);
+        let (chunk, remainder) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(n, chunk)| format!("[part {}/{}] {}", n + 1, count, chunk))
+        .collect()
+}
+
+/// The event that stands in for lines left out between the head and the tail
+pub fn omission_marker(omitted: usize) -> String {
+    let digits = omitted.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    format!(
+        "----- {} line{} omitted -----",
+        grouped,
+        if omitted == 1 { "" } else { "s" }
+    )
+}
+
+/// Remove terminal escape sequences, like colours, from a message
+///
+/// Handles CSI sequences (`ESC [ ... m` and friends), OSC sequences (`ESC ] ...`,
+/// ended by BEL or `ESC \`) and the shorter two and three byte escapes.  The escape
+/// byte never turns up inside a multi-byte UTF-8 character, so characters either
+/// side of a sequence are left whole.
+pub fn strip_ansi(message: &mut String) {
+    const ESC: u8 = 0x1b;
+    const BEL: u8 = 0x07;
+
+    if !message.as_bytes().contains(&ESC) {
+        return;
+    }
+
+    let bytes = message.as_bytes();
+    let mut stripped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != ESC {
+            stripped.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 1;
+        match bytes.get(i) {
+            // CSI: parameters and intermediates, then a final byte
+            Some(b'[') => {
+                i += 1;
+                while i < bytes.len() && (0x20..=0x3f).contains(&bytes[i]) {
+                    i += 1;
+                }
+                if i < bytes.len() && (0x40..=0x7e).contains(&bytes[i]) {
+                    i += 1;
+                }
+            }
+            // OSC: anything, up to a BEL or string terminator
+            Some(b']') => {
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == BEL {
+                        i += 1;
+                        break;
+                    }
+                    if bytes[i] == ESC && bytes.get(i + 1) == Some(&b'\\') {
+                        i += 2;
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            // Anything else: intermediates, then a final byte
+            Some(_) => {
+                while i < bytes.len() && (0x20..=0x2f).contains(&bytes[i]) {
+                    i += 1;
+                }
+                if i < bytes.len() && (0x30..=0x7e).contains(&bytes[i]) {
+                    i += 1;
+                }
+            }
+            None => (),
+        }
+    }
+
+    // Only whole ASCII sequences were taken out, so what's left is still UTF-8
+    *message = String::from_utf8(stripped).expect("stripping escapes kept UTF-8 intact");
+}
+
+/// Replace tabs with spaces, up to the next tab stop every `width` columns
+///
+/// Columns are counted in characters from the start of the message, or from the
+/// last line break in a multiline record.
+pub fn expand_tabs(message: &mut String, width: usize) {
+    if !message.contains('\t') {
+        return;
+    }
+
+    let mut expanded = String::with_capacity(message.len());
+    let mut column = 0;
+    for c in message.chars() {
+        match c {
+            '\t' => {
+                let spaces = width - column % width;
+                expanded.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            }
+            '\n' => {
+                expanded.push(c);
+                column = 0;
+            }
+            _ => {
+                expanded.push(c);
+                column += 1;
+            }
+        }
+    }
+    *message = expanded;
+}
+
+/// Replace control characters, like NUL or BEL, with something printable
+///
+/// Each one becomes an escape like `\u{0007}`, or `placeholder` if there is one.
+/// Tabs and the line breaks inside multiline records are left alone.
+pub fn escape_control_chars(message: &mut String, placeholder: Option<char>) {
+    let is_escaped = |c: char| c.is_control() && c != '\t' && c != '\n';
+    if !message.chars().any(is_escaped) {
+        return;
+    }
+
+    let mut escaped = String::with_capacity(message.len());
+    for c in message.chars() {
+        match placeholder {
+            _ if !is_escaped(c) => escaped.push(c),
+            Some(placeholder) => escaped.push(placeholder),
+            None => escaped.push_str(&format!("\\u{{{:04X}}}", c as u32)),
+        }
+    }
+    *message = escaped;
+}
+
+/// The largest index no greater than `index` that falls on a character boundary
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    if index >= s.len() {
+        return s.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_small_message() {
+        assert_eq!(split(String::from("short")), ["short"]);
+
+        let exact = "x".repeat(MAX_MESSAGE_SIZE);
+        assert_eq!(split(exact.clone()), [exact]);
+    }
+
+    #[test]
+    fn test_split_300kb_line() {
+        // Three-byte characters make sure splits land between characters
+        let line = "€".repeat(100 * 1024);
+        let parts = split(line.clone());
+
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("[part 1/2] €"));
+        assert!(parts[1].starts_with("[part 2/2] €"));
+        for part in &parts {
+            assert!(part.len() <= MAX_MESSAGE_SIZE);
+        }
+
+        let rejoined: String = parts.iter().map(|p| &p[11..]).collect();
+        assert_eq!(rejoined, line);
+    }
+
+    #[test]
+    fn test_omission_marker() {
+        assert_eq!(omission_marker(1), "----- 1 line omitted -----");
+        assert_eq!(omission_marker(999), "----- 999 lines omitted -----");
+        assert_eq!(omission_marker(99_900), "----- 99,900 lines omitted -----");
+        assert_eq!(
+            omission_marker(1_234_567),
+            "----- 1,234,567 lines omitted -----"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut message = "€".repeat(100 * 1024);
+        truncate(&mut message, MAX_MESSAGE_SIZE);
+        assert!(message.len() <= MAX_MESSAGE_SIZE);
+        assert!(
+            message.ends_with("€€…[truncated 44 KB]"),
+            "{}",
+            &message[262000..]
+        );
+
+        let mut message = "x".repeat(20 * 1024 * 1024);
+        truncate(&mut message, MAX_MESSAGE_SIZE);
+        assert!(message.len() <= MAX_MESSAGE_SIZE);
+        assert!(message.ends_with("x…[truncated 19.8 MB]"));
+
+        let mut message = "x".repeat(100);
+        truncate(&mut message, 64);
+        assert_eq!(message, format!("{}…[truncated 68 bytes]", "x".repeat(32)));
+
+        let mut message = String::from("short");
+        truncate(&mut message, MAX_MESSAGE_SIZE);
+        assert_eq!(message, "short");
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        let mut message = String::from("a\tbc\tdefg\t|\n\tnext");
+        expand_tabs(&mut message, 4);
+        assert_eq!(message, "a   bc  defg    |\n    next");
+
+        let mut message = String::from("é\t|");
+        expand_tabs(&mut message, 8);
+        assert_eq!(message, "é       |");
+    }
+
+    #[test]
+    fn test_escape_control_chars() {
+        let mut message = String::from("nul\0 bel\x07 del\x7f c1\u{85}\nnext\tcafé");
+        escape_control_chars(&mut message, None);
+        assert_eq!(
+            message,
+            "nul\\u{0000} bel\\u{0007} del\\u{007F} c1\\u{0085}\nnext\tcafé"
+        );
+
+        let mut message = String::from("a\0\0b\r");
+        escape_control_chars(&mut message, Some('\u{FFFD}'));
+        assert_eq!(message, "a\u{FFFD}\u{FFFD}b\u{FFFD}");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        let strip = |s: &str| {
+            let mut message = s.to_string();
+            strip_ansi(&mut message);
+            message
+        };
+
+        assert_eq!(strip("plain, café"), "plain, café");
+        assert_eq!(
+            strip("\x1b[1;31mERROR\x1b[0m: disk full"),
+            "ERROR: disk full"
+        );
+        assert_eq!(strip("\x1b[38;5;208m€\x1b[m€"), "€€");
+        // Window titles and hyperlinks
+        assert_eq!(strip("\x1b]0;build\x07done"), "done");
+        assert_eq!(
+            strip("see \x1b]8;;https://example.com\x1b\\the docs\x1b]8;;\x1b\\."),
+            "see the docs."
+        );
+        // Character set switches and cursor saves
+        assert_eq!(strip("\x1b(Bok\x1b7"), "ok");
+        // Cut off part way through
+        assert_eq!(strip("ok\x1b["), "ok");
+        assert_eq!(strip("ok\x1b]0;title"), "ok");
+        assert_eq!(strip("ok\x1b"), "ok");
+        // Malformed, but the character after it survives
+        assert_eq!(strip("\x1b[1€"), "€");
+        assert_eq!(strip("\x1b€"), "€");
+    }
+}
@@ -0,0 +1,376 @@
+//! The EC2 instance metadata (IMDS), or else an ECS task's, for what stream names
+//! say about where this is running: asked only when it's wanted, for only so long,
+//! and not again once it's failed to answer
+
+use crate::error::Error;
+
+use aws_config::imds::client::{Client as IMDS_Client, ImdsError};
+use aws_config::provider_config::ProviderConfig;
+use aws_types::os_shim_internal::Env;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+/// Set to `true` to keep anything from asking the instance metadata, as the AWS SDKs
+/// and CLI have it
+pub const DISABLED_VAR: &str = "AWS_EC2_METADATA_DISABLED";
+
+/// Set by ECS (Fargate included) to the endpoint of the task metadata, version 4
+pub const ECS_METADATA_VAR: &str = "ECS_CONTAINER_METADATA_URI_V4";
+
+/// Something that can look things up in the instance metadata
+///
+/// Its futures needn't be `Send`, as they're only awaited where they're made.
+#[allow(async_fn_in_trait)]
+pub trait InstanceMetadata {
+    /// Look up `path` under `/latest/meta-data`, like `instance-id` (None if it can't
+    /// be, or isn't there)
+    async fn get(&self, path: &str) -> Option<String>;
+
+    /// The ECS task this is running in, if it is one
+    async fn ecs_task(&self) -> Option<EcsTask>;
+
+    /// Whether it's to be asked at all, so what's looked for in it along with other
+    /// places (like the region) isn't looked for in it either when it isn't
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+/// What the SDK finds regions and credentials with, which only asks the instance
+/// metadata for them if `enabled`
+///
+/// The SDK only goes by `AWS_EC2_METADATA_DISABLED`, so that's set in the environment
+/// its providers see (a copy of this one's), not in this one.
+pub fn provider_config(enabled: bool) -> ProviderConfig {
+    let config = ProviderConfig::default();
+    if enabled {
+        return config;
+    }
+    let mut vars: HashMap<String, String> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    vars.insert(String::from(DISABLED_VAR), String::from("true"));
+    config.with_env(Env::from(vars))
+}
+
+/// What an ECS task's metadata says about it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EcsTask {
+    /// The task's id, the last part of its ARN
+    pub task_id: String,
+    /// The name of the cluster it's running in
+    pub cluster: String,
+}
+
+/// How the instance (or task) metadata is asked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImdsOptions {
+    /// Whether to ask it at all
+    pub enabled: bool,
+    /// How long to wait for an answer, token and retries included
+    pub timeout: Duration,
+}
+
+impl Default for ImdsOptions {
+    fn default() -> Self {
+        ImdsOptions {
+            enabled: true,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The instance metadata, asked with a session token (IMDSv2), so instances that
+/// require one are asked the same as any other
+///
+/// The token's answer only gets as far as the instance's hop limit, which has to be
+/// 2 for it to reach a container.  An ECS task's metadata is asked at the endpoint
+/// ECS gives it, by the same options.
+pub struct Imds {
+    options: ImdsOptions,
+    /// Where to ask, if not where the environment says (or else the usual address)
+    endpoint: Option<hyper::Uri>,
+    client: OnceCell<Option<IMDS_Client>>,
+    failed: AtomicBool,
+}
+
+impl Imds {
+    /// The instance metadata, to be asked as `options` say (not until it's needed)
+    pub fn new(options: ImdsOptions) -> Imds {
+        Imds {
+            options,
+            endpoint: None,
+            client: OnceCell::new(),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Ask at `endpoint` instead, like a stand-in for it
+    #[cfg(test)]
+    fn at(mut self, endpoint: &str) -> Imds {
+        self.endpoint = Some(endpoint.parse().expect("valid endpoint"));
+        self
+    }
+
+    /// The client to ask with, made the first time it's needed
+    async fn client(&self) -> Option<&IMDS_Client> {
+        let timeout = self.options.timeout;
+        self.client
+            .get_or_init(|| async move {
+                let mut builder = IMDS_Client::builder()
+                    .connect_timeout(timeout)
+                    .read_timeout(timeout);
+                if let Some(endpoint) = &self.endpoint {
+                    builder = builder.endpoint(endpoint.clone());
+                }
+                match builder.build().await {
+                    Ok(client) => Some(client),
+                    Err(e) => {
+                        warn!("Couldn't ask the instance metadata: {}", e);
+                        None
+                    }
+                }
+            })
+            .await
+            .as_ref()
+    }
+}
+
+impl InstanceMetadata for Imds {
+    /// Not when told not to (by `AWS_EC2_METADATA_DISABLED` too), and not once it's
+    /// failed to answer
+    fn enabled(&self) -> bool {
+        let disabled = std::env::var(DISABLED_VAR).is_ok_and(|v| v.eq_ignore_ascii_case("true"));
+        self.options.enabled && !disabled && !self.failed.load(Ordering::Relaxed)
+    }
+
+    async fn get(&self, path: &str) -> Option<String> {
+        if !self.enabled() {
+            return None;
+        }
+        let client = self.client().await?;
+        let url = format!("/latest/meta-data/{}", path);
+        let e = match tokio::time::timeout(self.options.timeout, client.get(&url)).await {
+            Ok(Ok(value)) => return Some(value),
+            // Not there (like a tag the instance doesn't have), which isn't failing
+            Ok(Err(ImdsError::ErrorResponse { response, .. })) if response.status() == 404 => {
+                return None
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!(
+                "no answer in {:?} (not on EC2? In a container, the instance's metadata hop \
+                 limit has to be 2: aws ec2 modify-instance-metadata-options \
+                 --http-put-response-hop-limit 2)",
+                self.options.timeout
+            ),
+        };
+        warn!("Couldn't retrieve {}: {}", path, e);
+        self.failed.store(true, Ordering::Relaxed);
+        None
+    }
+
+    async fn ecs_task(&self) -> Option<EcsTask> {
+        let endpoint = std::env::var(ECS_METADATA_VAR).ok()?;
+        if !self.options.enabled {
+            return None;
+        }
+        match ecs_task(&endpoint, self.options.timeout).await {
+            Ok(task) => Some(task),
+            Err(e) => {
+                warn!("Couldn't retrieve the ECS task metadata: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Look up the task in the ECS task metadata at `endpoint`
+async fn ecs_task(endpoint: &str, timeout: Duration) -> Result<EcsTask, Error> {
+    let url = format!("{}/task", endpoint.trim_end_matches('/'));
+    let uri: hyper::Uri = url
+        .parse()
+        .map_err(|e| Error::Imds(format!("{:?} isn't a URL: {}", url, e)))?;
+    let body = tokio::time::timeout(timeout, async {
+        let response = hyper::Client::new().get(uri).await?;
+        if !response.status().is_success() {
+            return Ok(Err(Error::Imds(format!(
+                "{} from {}",
+                response.status(),
+                url
+            ))));
+        }
+        hyper::body::to_bytes(response.into_body()).await.map(Ok)
+    })
+    .await
+    .map_err(|_| Error::Imds(format!("no answer from {} in {:?}", url, timeout)))?
+    .map_err(|e| Error::Imds(e.to_string()))??;
+    let task: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| Error::Imds(format!("{} isn't JSON: {}", url, e)))?;
+    // Both the task and its cluster can be ARNs, ending in their names
+    let last = |field| {
+        task[field]
+            .as_str()
+            .and_then(|value| value.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .ok_or_else(|| Error::Imds(format!("{} has no {}", url, field)))
+    };
+    Ok(EcsTask {
+        task_id: last("TaskARN")?,
+        cluster: last("Cluster")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{Response, Stub};
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    /// Stand in for the instance metadata, with a session token and what's at `paths`
+    /// under /latest/meta-data
+    fn serve_imds(paths: &'static [(&'static str, &'static str)]) -> Stub {
+        Stub::serve(|_, request| {
+            let found = match request.line() {
+                ("PUT", "/latest/api/token") => Some("token"),
+                ("GET", path) if request.header("x-aws-ec2-metadata-token") == Some("token") => {
+                    path.strip_prefix("/latest/meta-data/")
+                        .and_then(|path| paths.iter().find(|(name, _)| *name == path))
+                        .map(|(_, value)| *value)
+                }
+                _ => None,
+            };
+            match found {
+                Some(body) => {
+                    Response::new(200, body).header("x-aws-ec2-metadata-token-ttl-seconds", "21600")
+                }
+                None => Response::new(404, ""),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_imds() {
+        let stub = serve_imds(&[("instance-id", "i-0123"), ("tags/instance/Name", "web")]);
+        let imds = Imds::new(ImdsOptions {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        })
+        .at(stub.url());
+        assert_eq!(imds.get("tags/instance/Name").await.as_deref(), Some("web"));
+        // A tag that isn't there doesn't stop anything else being looked up
+        assert_eq!(imds.get("tags/instance/Service").await, None);
+        assert_eq!(imds.get("instance-id").await.as_deref(), Some("i-0123"));
+    }
+
+    #[tokio::test]
+    async fn test_imds_unavailable() {
+        // Nothing's listening where it's asked
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let imds = Imds::new(ImdsOptions {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        })
+        .at(&endpoint);
+        assert_eq!(imds.get("instance-id").await, None);
+        // And isn't asked again
+        assert!(!imds.enabled());
+        let host =
+            crate::naming::Host::lookup(&[&crate::naming::template(None, false)], None, &imds)
+                .await;
+        assert_eq!(host.instance_id_source, crate::naming::IdSource::Hostname);
+    }
+
+    /// Serve `body` to the requests for a task's metadata, returning the endpoint to ask
+    fn serve(body: &'static str) -> String {
+        let stub = Stub::serve(|_, request| match request.line() {
+            ("GET", "/v4/abc/task") => Response::new(200, body),
+            _ => Response::new(404, ""),
+        });
+        format!("{}/v4/abc", stub.url())
+    }
+
+    #[tokio::test]
+    async fn test_ecs_task() {
+        let endpoint = serve(
+            r#"{
+                "Cluster": "arn:aws:ecs:us-west-2:111122223333:cluster/log-dumpers",
+                "TaskARN": "arn:aws:ecs:us-west-2:111122223333:task/log-dumpers/158d1c8083dd49d6b527399fd6414f5c",
+                "Family": "dump"
+            }"#,
+        );
+        let task = ecs_task(&endpoint, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(
+            task,
+            EcsTask {
+                task_id: String::from("158d1c8083dd49d6b527399fd6414f5c"),
+                cluster: String::from("log-dumpers"),
+            }
+        );
+
+        let endpoint = serve(r#"{"Cluster": "default"}"#);
+        let err = ecs_task(&endpoint, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Imds(e) if e.contains("TaskARN")),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ecs_task_timeout() {
+        // Listening, but never answering
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let err = ecs_task(&endpoint, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Imds(e) if e.contains("no answer")),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let imds = Imds::new(ImdsOptions {
+            enabled: false,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        assert_eq!(imds.get("instance-id").await, None);
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_provider_config() {
+        use aws_config::imds::region::ImdsRegionProvider;
+
+        let stub = serve_imds(&[("placement/region", "eu-west-1")]);
+        let endpoint: hyper::Uri = stub.url().parse().unwrap();
+        let region = |enabled| {
+            let endpoint = endpoint.clone();
+            async move {
+                let client = IMDS_Client::builder().endpoint(endpoint).build().await;
+                let provider = ImdsRegionProvider::builder()
+                    .configure(&provider_config(enabled))
+                    .imds_client(client.unwrap())
+                    .build();
+                provider.region().await.map(|region| region.to_string())
+            }
+        };
+        assert_eq!(region(true).await.as_deref(), Some("eu-west-1"));
+        // The SDK's told not to ask, without this process's environment being changed
+        assert_eq!(region(false).await, None);
+        assert_eq!(stub.received().len(), 2);
+    }
+}
@@ -0,0 +1,296 @@
+//! Publishing how much was sent as a CloudWatch metric once an upload succeeds, so
+//! dashboards and alarms can see runs without reading the log groups
+
+use crate::naming;
+use crate::template::Template;
+use crate::uploader::{host_details, service_config, StreamEvents, UploadOptions};
+
+use aws_sdk_cloudwatch::model::{Dimension as CW_Dimension, MetricDatum, StandardUnit};
+use aws_sdk_cloudwatch::types::SdkError;
+use aws_sdk_cloudwatch::Client as CW_Client;
+use tracing::warn;
+
+/// The namespace metrics are published in, unless --metric-namespace says otherwise
+pub const DEFAULT_NAMESPACE: &str = "RustyAxe";
+
+/// The dimensions metrics have, unless --metric-dimension says otherwise
+const DEFAULT_DIMENSIONS: &[&str] = &["LogGroup={group}", "InstanceId={instance_id}"];
+
+/// The variables dimension values can use
+const DIMENSION_VARIABLES: &[&str] = &["group", "instance_id"];
+
+/// A dimension for the metrics, given as NAME=VALUE, whose value can use `{group}` and
+/// `{instance_id}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dimension {
+    pub name: String,
+    pub value: Template,
+}
+
+impl Dimension {
+    pub fn parse(s: &str) -> Result<Dimension, String> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("{:?} isn't a dimension like Environment=prod", s))?;
+        if name.is_empty() || value.is_empty() {
+            return Err(format!("{:?} needs both a name and a value", s));
+        }
+        Ok(Dimension {
+            name: name.to_string(),
+            value: Template::parse(value, DIMENSION_VARIABLES)?,
+        })
+    }
+}
+
+/// Where metrics are published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricOptions {
+    pub namespace: String,
+    pub dimensions: Vec<Dimension>,
+}
+
+impl MetricOptions {
+    /// Metrics in `namespace` with `dimensions`, or else LogGroup and InstanceId
+    pub fn new(namespace: String, dimensions: Vec<Dimension>) -> MetricOptions {
+        let dimensions = match dimensions.is_empty() {
+            true => DEFAULT_DIMENSIONS
+                .iter()
+                .map(|dimension| Dimension::parse(dimension).expect("valid dimension"))
+                .collect(),
+            false => dimensions,
+        };
+        MetricOptions {
+            namespace,
+            dimensions,
+        }
+    }
+}
+
+/// How much was sent to each log group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Shipped {
+    pub events: usize,
+    /// The bytes of the events' messages
+    pub bytes: usize,
+}
+
+impl Shipped {
+    /// How much is in the streams, all of which go to every log group
+    pub fn of(streams: &[StreamEvents]) -> Shipped {
+        Shipped {
+            events: streams.iter().map(|stream| stream.events.len()).sum(),
+            bytes: streams
+                .iter()
+                .map(|stream| crate::events::message_bytes(&stream.events))
+                .sum(),
+        }
+    }
+}
+
+/// Something that can publish metrics
+pub trait PutMetrics {
+    async fn put_metric_data(&self, namespace: &str, data: Vec<MetricDatum>) -> Result<(), String>;
+}
+
+impl PutMetrics for CW_Client {
+    async fn put_metric_data(&self, namespace: &str, data: Vec<MetricDatum>) -> Result<(), String> {
+        self.put_metric_data()
+            .namespace(namespace)
+            .set_metric_data(Some(data))
+            .send()
+            .await
+            .map_err(|e| match e {
+                SdkError::ServiceError { err, .. } => err.to_string(),
+                e => e.to_string(),
+            })?;
+        Ok(())
+    }
+}
+
+/// Publish what was shipped to the log groups, warning (and carrying on) if it can't be
+pub async fn publish(options: &UploadOptions, metric: &MetricOptions, shipped: Shipped) {
+    let template = naming::parse_template("{instance_id}").expect("valid template");
+    let host = host_details(options, &[&template]).await;
+    let config = service_config(&options.client, "monitoring", "CloudWatch").await;
+    let groups: Vec<_> = std::iter::once(&options.group)
+        .chain(&options.more_groups)
+        .map(String::as_str)
+        .collect();
+    let client = CW_Client::new(&config);
+    if let Err(e) = emit(&client, metric, &groups, &host.instance_id, shipped).await {
+        warn!("couldn't publish metrics to {}: {}", metric.namespace, e);
+    }
+}
+
+/// Publish LinesShipped and BytesShipped for each of the log groups
+///
+/// Groups that come out with the same dimensions (as when they don't use `{group}`)
+/// are only counted once.
+pub async fn emit(
+    to: &impl PutMetrics,
+    metric: &MetricOptions,
+    groups: &[&str],
+    instance_id: &str,
+    shipped: Shipped,
+) -> Result<(), String> {
+    let mut dimension_sets = Vec::new();
+    for group in groups {
+        let values = [("group", *group), ("instance_id", instance_id)];
+        let dimensions: Vec<_> = metric
+            .dimensions
+            .iter()
+            .map(|dimension| {
+                CW_Dimension::builder()
+                    .name(&dimension.name)
+                    .value(dimension.value.render(&values))
+                    .build()
+            })
+            .collect();
+        if !dimension_sets.contains(&dimensions) {
+            dimension_sets.push(dimensions);
+        }
+    }
+    let data = dimension_sets
+        .into_iter()
+        .flat_map(|dimensions| {
+            [
+                ("LinesShipped", shipped.events, StandardUnit::Count),
+                ("BytesShipped", shipped.bytes, StandardUnit::Bytes),
+            ]
+            .map(|(name, value, unit)| {
+                MetricDatum::builder()
+                    .metric_name(name)
+                    .set_dimensions(Some(dimensions.clone()))
+                    .value(value as f64)
+                    .unit(unit)
+                    .build()
+            })
+        })
+        .collect();
+    to.put_metric_data(&metric.namespace, data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use std::sync::Mutex;
+
+    /// Somewhere that keeps the metrics published to it, or fails if told to
+    #[derive(Default)]
+    struct MockMetrics {
+        fail: bool,
+        published: Mutex<Vec<(String, Vec<MetricDatum>)>>,
+    }
+
+    impl PutMetrics for MockMetrics {
+        async fn put_metric_data(
+            &self,
+            namespace: &str,
+            data: Vec<MetricDatum>,
+        ) -> Result<(), String> {
+            if self.fail {
+                return Err(String::from("AccessDenied"));
+            }
+            let published = (namespace.to_string(), data);
+            self.published.lock().unwrap().push(published);
+            Ok(())
+        }
+    }
+
+    fn dimensions(datum: &MetricDatum) -> Vec<(&str, &str)> {
+        datum
+            .dimensions()
+            .unwrap_or_default()
+            .iter()
+            .map(|d| (d.name().unwrap_or_default(), d.value().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_dimension() {
+        let dimension = Dimension::parse("Environment=prod-{instance_id}").unwrap();
+        assert_eq!(dimension.name, "Environment");
+        assert_eq!(
+            dimension.value.render(&[("instance_id", "i-1")]),
+            "prod-i-1"
+        );
+        assert!(Dimension::parse("Environment").is_err());
+        assert!(Dimension::parse("=prod").is_err());
+        assert!(Dimension::parse("Host={hostname}").is_err());
+    }
+
+    #[test]
+    fn test_shipped() {
+        let streams = vec![
+            StreamEvents::merged(vec![build_event(0, String::from("started"))]),
+            StreamEvents::merged(vec![
+                build_event(1, String::from("crashed")),
+                build_event(2, String::from("\u{1F980}")),
+            ]),
+        ];
+        let shipped = Shipped::of(&streams);
+        assert_eq!(
+            shipped,
+            Shipped {
+                events: 3,
+                bytes: 18
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_emit() {
+        let to = MockMetrics::default();
+        let metric = MetricOptions::new(String::from(DEFAULT_NAMESPACE), Vec::new());
+        let shipped = Shipped {
+            events: 120,
+            bytes: 4096,
+        };
+        emit(&to, &metric, &["app", "audit"], "i-0abc", shipped)
+            .await
+            .unwrap();
+        let (namespace, data) = to.published.lock().unwrap().remove(0);
+        assert_eq!(namespace, "RustyAxe");
+        // The two metrics for each group
+        assert_eq!(data.len(), 4);
+        assert_eq!(data[0].metric_name(), Some("LinesShipped"));
+        assert_eq!(data[0].value(), Some(120.0));
+        assert_eq!(data[0].unit(), Some(&StandardUnit::Count));
+        assert_eq!(data[1].metric_name(), Some("BytesShipped"));
+        assert_eq!(data[1].value(), Some(4096.0));
+        assert_eq!(data[1].unit(), Some(&StandardUnit::Bytes));
+        assert_eq!(
+            dimensions(&data[2]),
+            [("LogGroup", "audit"), ("InstanceId", "i-0abc")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_emit_dimensions() {
+        let to = MockMetrics::default();
+        let given = vec![Dimension::parse("Host={instance_id}").unwrap()];
+        let metric = MetricOptions::new(String::from("Crashes"), given);
+        emit(
+            &to,
+            &metric,
+            &["app", "audit"],
+            "i-0abc",
+            Shipped::default(),
+        )
+        .await
+        .unwrap();
+        let (namespace, data) = to.published.lock().unwrap().remove(0);
+        assert_eq!(namespace, "Crashes");
+        // Without {group}, both groups' data are the same, so sent once
+        assert_eq!(data.len(), 2);
+        assert_eq!(dimensions(&data[0]), [("Host", "i-0abc")]);
+
+        let to = MockMetrics {
+            fail: true,
+            ..Default::default()
+        };
+        let err = emit(&to, &metric, &["app"], "i-0abc", Shipped::default()).await;
+        assert_eq!(err, Err(String::from("AccessDenied")));
+    }
+}
@@ -0,0 +1,271 @@
+//! A stand-in for CloudWatch Logs, for the tests of everything that calls it
+//!
+//! It creates groups and streams, takes batches and reads them back as it's told to,
+//! and keeps every call made to it to be looked at afterwards.
+
+use crate::group::CreateLogs;
+use crate::stream::{rejections, Accepted, PutEvents};
+use crate::verify::{EventsPage, GetEvents};
+
+use aws_sdk_cloudwatchlogs::error::{
+    InvalidSequenceTokenException, ResourceAlreadyExistsException, ResourceNotFoundException,
+};
+use aws_sdk_cloudwatchlogs::model::{InputLogEvent, OutputLogEvent, RejectedLogEventsInfo};
+use aws_sdk_cloudwatchlogs::Error;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// What the clones of a mock all share
+type Shared<T> = Arc<Mutex<T>>;
+
+/// How the mock answers a call to put events
+pub(crate) enum Put {
+    /// Takes the batch, handing out the next token
+    Accepted,
+    /// Takes the batch, but for the events it turns down for their time
+    Rejected(RejectedLogEventsInfo),
+    /// Fails, as when throttled or given a stale token
+    Failed(Error),
+}
+
+/// Stands in for CloudWatch Logs, handing out a new token for every batch and
+/// remembering which token each came with
+///
+/// Each batch is answered with the next of `script` in turn, and accepted once
+/// that's run out.  Creating or setting up a group or stream fails with the next of
+/// `create_errors` until they've run out.  A stream read back has the events it took,
+/// in pages of `page_size` (all in one, for 0), of which only the first `visible`
+/// can be read (all of them, for None) until a read reaches the end of them, when
+/// `step` more can.  Clones share it all, as the streams of an upload share a client.
+#[derive(Clone, Default)]
+pub(crate) struct MockClient {
+    /// The token of the stream, for a stream that's already been written to
+    pub existing_token: Option<String>,
+    /// Whether the stream isn't there until it's created
+    pub missing: Shared<bool>,
+    pub calls: Shared<Vec<(Option<String>, usize)>>,
+    /// The log group and stream each batch was sent to, as group/stream
+    pub streams: Shared<Vec<String>>,
+    pub batches: Shared<Vec<Vec<InputLogEvent>>>,
+    pub script: Shared<VecDeque<Put>>,
+    /// Each call made to create or set up a group or stream, like `stream g/s`
+    pub created: Shared<Vec<String>>,
+    pub create_errors: Shared<VecDeque<Error>>,
+    /// The KMS key the group's encrypted with
+    pub kms_key: Option<String>,
+    pub page_size: usize,
+    pub visible: Shared<Option<usize>>,
+    pub step: usize,
+    /// The token asked with for each page read
+    pub reads: Shared<Vec<Option<String>>>,
+    /// The events taken, and the group/stream each went to
+    pub taken: Shared<Vec<(String, InputLogEvent)>>,
+}
+
+impl MockClient {
+    /// One that answers the first batches as `script` says
+    pub(crate) fn scripted(script: impl IntoIterator<Item = Put>) -> MockClient {
+        MockClient {
+            script: Arc::new(Mutex::new(script.into_iter().collect())),
+            ..Default::default()
+        }
+    }
+
+    /// One that fails the first batches with `errors`, one each
+    pub(crate) fn failing(errors: impl IntoIterator<Item = Error>) -> MockClient {
+        MockClient::scripted(errors.into_iter().map(Put::Failed))
+    }
+
+    /// One that fails the first calls to create or set up a group or stream with
+    /// `errors`, one each
+    pub(crate) fn failing_to_create(errors: impl IntoIterator<Item = Error>) -> MockClient {
+        MockClient {
+            create_errors: Arc::new(Mutex::new(errors.into_iter().collect())),
+            ..Default::default()
+        }
+    }
+
+    /// One whose stream `to` (as group/stream) already has `events` in it
+    pub(crate) fn holding(to: &str, events: Vec<InputLogEvent>) -> MockClient {
+        let taken = events.into_iter().map(|event| (to.to_string(), event));
+        MockClient {
+            taken: Arc::new(Mutex::new(taken.collect())),
+            ..Default::default()
+        }
+    }
+
+    /// Keep `call`, failing with the next of the errors to create with if there are
+    /// any left
+    fn create(&self, call: String) -> Result<(), Error> {
+        self.created.lock().unwrap().push(call);
+        match self.create_errors.lock().unwrap().pop_front() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CreateLogs for MockClient {
+    async fn create_group(&self, group: &str, kms_key: Option<&str>) -> Result<(), Error> {
+        match kms_key {
+            Some(key) => self.create(format!("group {} {}", group, key)),
+            None => self.create(format!("group {}", group)),
+        }
+    }
+
+    async fn create_stream(&self, group: &str, stream: &str) -> Result<(), Error> {
+        self.create(format!("stream {}/{}", group, stream))?;
+        *self.missing.lock().unwrap() = false;
+        Ok(())
+    }
+
+    async fn set_retention(&self, group: &str, days: i32) -> Result<(), Error> {
+        self.create(format!("retention {} {}", group, days))
+    }
+
+    async fn tag_group(&self, group: &str, tags: &BTreeMap<String, String>) -> Result<(), Error> {
+        let tags: Vec<_> = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        self.create(format!("tags {} {}", group, tags.join(",")))
+    }
+
+    async fn group_kms_key(&self, group: &str) -> Result<Option<String>, Error> {
+        self.create(format!("describe {}", group))?;
+        Ok(self.kms_key.clone())
+    }
+
+    async fn associate_kms_key(&self, group: &str, kms_key: &str) -> Result<(), Error> {
+        self.create(format!("associate {} {}", group, kms_key))
+    }
+}
+
+impl PutEvents for MockClient {
+    async fn put_events(
+        &self,
+        group: &str,
+        stream: &str,
+        events: Vec<InputLogEvent>,
+        sequence_token: Option<String>,
+    ) -> Result<Accepted, Error> {
+        let to = format!("{}/{}", group, stream);
+        self.streams.lock().unwrap().push(to.clone());
+        let mut calls = self.calls.lock().unwrap();
+        calls.push((sequence_token, events.len()));
+        self.batches.lock().unwrap().push(events.clone());
+        let rejected = match self.script.lock().unwrap().pop_front() {
+            Some(Put::Failed(e)) => return Err(e),
+            Some(Put::Rejected(info)) => Some(info),
+            Some(Put::Accepted) | None => None,
+        };
+        let reasons = match &rejected {
+            Some(info) => rejections(info, events.len()),
+            None => vec![None; events.len()],
+        };
+        let taken = events
+            .into_iter()
+            .zip(reasons)
+            .filter(|(_, rejection)| rejection.is_none())
+            .map(|(event, _)| (to.clone(), event));
+        self.taken.lock().unwrap().extend(taken);
+        Ok(Accepted {
+            next_token: Some(format!("token-{}", calls.len())),
+            rejected,
+        })
+    }
+
+    async fn sequence_token(&self, _group: &str, _stream: &str) -> Result<Option<String>, Error> {
+        if *self.missing.lock().unwrap() {
+            return Err(not_found());
+        }
+        Ok(self.existing_token.clone())
+    }
+}
+
+impl GetEvents for MockClient {
+    /// The next token is how many of the stream's events have been read so far
+    async fn get_events(
+        &self,
+        group: &str,
+        stream: &str,
+        (start, end): (i64, i64),
+        next_token: Option<String>,
+    ) -> Result<EventsPage, Error> {
+        self.reads.lock().unwrap().push(next_token.clone());
+        let from = format!("{}/{}", group, stream);
+        let events: Vec<_> = self
+            .taken
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(to, _)| *to == from)
+            .map(|(_, event)| event.clone())
+            .collect();
+        let mut visible = self.visible.lock().unwrap();
+        let shown = visible.unwrap_or(events.len()).min(events.len());
+        let first = next_token.map_or(0, |token| token.parse().unwrap());
+        let last = match self.page_size {
+            0 => shown,
+            size => (first + size).min(shown),
+        };
+        let page: Vec<_> = events[first.min(last)..last]
+            .iter()
+            .filter(|event| (start..end).contains(&event.timestamp.unwrap()))
+            .map(|event| {
+                OutputLogEvent::builder()
+                    .set_timestamp(event.timestamp)
+                    .set_message(event.message.clone())
+                    .build()
+            })
+            .collect();
+        if page.is_empty() {
+            // Read to the end, so it's time for more to show up
+            *visible = visible.map(|visible| (visible + self.step).min(events.len()));
+        }
+        Ok((page, Some(last.to_string())))
+    }
+}
+
+pub(crate) fn stale_token(expected: &str) -> Error {
+    Error::InvalidSequenceTokenException(
+        InvalidSequenceTokenException::builder()
+            .expected_sequence_token(expected)
+            .message("The given sequenceToken is invalid")
+            .build(),
+    )
+}
+
+pub(crate) fn throttled() -> Error {
+    Error::Unhandled(Box::new(
+        aws_smithy_types::Error::builder()
+            .code("ThrottlingException")
+            .message("Rate exceeded")
+            .build(),
+    ))
+}
+
+pub(crate) fn not_found() -> Error {
+    Error::ResourceNotFoundException(
+        ResourceNotFoundException::builder()
+            .message("The specified log group does not exist.")
+            .build(),
+    )
+}
+
+pub(crate) fn already_exists() -> Error {
+    Error::ResourceAlreadyExistsException(
+        ResourceAlreadyExistsException::builder()
+            .message("The specified log group already exists")
+            .build(),
+    )
+}
+
+pub(crate) fn rejected(
+    too_old_end: Option<i32>,
+    expired_end: Option<i32>,
+    too_new_start: Option<i32>,
+) -> RejectedLogEventsInfo {
+    RejectedLogEventsInfo::builder()
+        .set_too_old_log_event_end_index(too_old_end)
+        .set_expired_log_event_end_index(expired_end)
+        .set_too_new_log_event_start_index(too_new_start)
+        .build()
+}
@@ -0,0 +1,167 @@
+//! Grouping the lines of a multiline record, like a stack trace, into one message
+
+use regex::bytes::Regex;
+
+/// Gathers lines into records, each starting with a line that matches a pattern
+///
+/// Lines that don't match are continuations, added to the record before them with a
+/// `\n` in between.  Without a pattern every line is a record of its own.  Lines
+/// before the first match make up a record of their own, so nothing is lost.  CSV
+/// rows are gathered the same way, a row carrying on while a quoted field is open.
+#[derive(Debug, Default)]
+pub struct Records {
+    boundary: Boundary,
+    /// The record still being gathered, and the index of its first line
+    pending: Option<(usize, Vec<u8>)>,
+}
+
+/// Where one record ends and the next begins
+#[derive(Debug, Default)]
+enum Boundary {
+    /// Every line is a record
+    #[default]
+    Line,
+    /// Each record starts with a line matching the pattern
+    Start(Regex),
+    /// Each record is a CSV row, which carries on over line breaks inside quotes
+    CsvRow,
+}
+
+impl Records {
+    pub fn new(start: Option<Regex>) -> Records {
+        Records {
+            boundary: start.map_or(Boundary::Line, Boundary::Start),
+            pending: None,
+        }
+    }
+
+    /// Gather lines into CSV rows, so quoted fields can hold line breaks
+    pub fn csv_rows() -> Records {
+        Records {
+            boundary: Boundary::CsvRow,
+            pending: None,
+        }
+    }
+
+    /// Add the next line, handing back any record it finishes
+    ///
+    /// With a start pattern, that's the record before this line, if this line starts
+    /// a new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Where the line sits in the input (0-based)
+    /// * `line` - The line itself, without its line ending
+    ///
+    pub fn push(&mut self, index: usize, line: Vec<u8>) -> Option<(usize, Vec<u8>)> {
+        match &self.boundary {
+            Boundary::Line => Some((index, line)),
+            Boundary::Start(start) => match &mut self.pending {
+                Some((_, record)) if !start.is_match(&line) => {
+                    append(record, &line);
+                    None
+                }
+                _ => self.pending.replace((index, line)),
+            },
+            Boundary::CsvRow => {
+                let (index, row) = match self.pending.take() {
+                    Some((index, mut row)) => {
+                        append(&mut row, &line);
+                        (index, row)
+                    }
+                    None => (index, line),
+                };
+                // Doubled quotes inside a quoted field cancel out, so an odd number
+                // means a quoted field is still open
+                if memchr::memchr_iter(b'"', &row).count().is_multiple_of(2) {
+                    Some((index, row))
+                } else {
+                    self.pending = Some((index, row));
+                    None
+                }
+            }
+        }
+    }
+
+    /// Hand back the record still being gathered, once there are no more lines
+    pub fn flush(&mut self) -> Option<(usize, Vec<u8>)> {
+        self.pending.take()
+    }
+}
+
+/// Carry a record on to the next line
+fn append(record: &mut Vec<u8>, line: &[u8]) {
+    record.push(b'\n');
+    record.extend_from_slice(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(start: Option<&str>, input: &str) -> Vec<(usize, String)> {
+        let mut records = Records::new(start.map(|s| Regex::new(s).unwrap()));
+        let mut grouped: Vec<_> = input
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| records.push(index, line.as_bytes().to_vec()))
+            .collect();
+        grouped.extend(records.flush());
+        grouped
+            .into_iter()
+            .map(|(index, record)| (index, String::from_utf8(record).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_without_pattern() {
+        assert_eq!(
+            group(None, "one\n  two\nthree"),
+            [
+                (0, String::from("one")),
+                (1, String::from("  two")),
+                (2, String::from("three"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_continuation_lines() {
+        let input = "  orphan\nINFO one\nERROR two\n  at a\n  at b\n\nINFO three";
+        assert_eq!(
+            group(Some("^[A-Z]+ "), input),
+            [
+                (0, String::from("  orphan")),
+                (1, String::from("INFO one")),
+                (2, String::from("ERROR two\n  at a\n  at b\n")),
+                (6, String::from("INFO three"))
+            ]
+        );
+        assert!(group(Some("^[A-Z]+ "), "").is_empty());
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let mut records = Records::csv_rows();
+        let mut rows: Vec<_> = "id,note\n1,\"two\nlines\"\n2,\"say \"\"hi\"\"\"\n3,\"open"
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| records.push(index, line.as_bytes().to_vec()))
+            .collect();
+        rows.extend(records.flush());
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|(index, row)| (index, String::from_utf8(row).unwrap()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (0, String::from("id,note")),
+                (1, String::from("1,\"two\nlines\"")),
+                (3, String::from("2,\"say \"\"hi\"\"\"")),
+                // Never closed, so it's handed back as is at the end
+                (4, String::from("3,\"open"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Naming log streams from a template like `{hostname}/{filename}/{date}`, filled in
+//! with what's known about where this is running
+
+use crate::metadata::InstanceMetadata;
+use crate::template::Template;
+
+use aws_sdk_cloudwatchlogs::Region;
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use tracing::warn;
+
+/// The variables a --stream-template can use
+pub const VARIABLES: &[&str] = &[
+    "instance_id",
+    "hostname",
+    "az",
+    "region",
+    "cluster",
+    "tag:",
+    "filename",
+    "date",
+    "epoch",
+    "timestamp",
+];
+
+/// The template stream names have always followed, for a stream of every file and
+/// for a file of its own
+const DEFAULT_TEMPLATE: &str = "{instance_id}-{timestamp}";
+const DEFAULT_FILE_TEMPLATE: &str = "{instance_id}-{timestamp}-{filename}";
+
+/// The template for a stream that's added to run after run, with --append, for a
+/// stream of every file and for a file of its own
+const APPEND_TEMPLATE: &str = "{instance_id}";
+const APPEND_FILE_TEMPLATE: &str = "{instance_id}-{filename}";
+
+/// The hostname when the system doesn't say
+const NO_HOSTNAME: &str = "localhost";
+
+/// The longest name CloudWatch Logs allows a log stream
+const MAX_STREAM_NAME: usize = 512;
+
+/// Parse a --stream-template, whose variables all have to be among `VARIABLES`
+pub fn parse_template(s: &str) -> Result<Template, String> {
+    Template::parse(s, VARIABLES)
+}
+
+/// The template to name a stream by: the one given, or else the default (which
+/// puts the file's name on the end for a stream of a file of its own)
+pub fn template(given: Option<&Template>, own_file: bool) -> Template {
+    given.cloned().unwrap_or_else(|| {
+        let default = if own_file {
+            DEFAULT_FILE_TEMPLATE
+        } else {
+            DEFAULT_TEMPLATE
+        };
+        parse_template(default).expect("valid default template")
+    })
+}
+
+/// The template to name streams by with --append, when no other's given
+pub fn append_template(own_file: bool) -> Template {
+    let template = if own_file {
+        APPEND_FILE_TEMPLATE
+    } else {
+        APPEND_TEMPLATE
+    };
+    parse_template(template).expect("valid append template")
+}
+
+/// Where this is running, as far as naming streams goes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Host {
+    pub instance_id: String,
+    pub hostname: String,
+    pub az: String,
+    pub region: String,
+    pub cluster: String,
+    /// The instance's tags, by their variables (like `tag:Name`)
+    pub tags: Vec<(String, String)>,
+    /// Where the instance id came from
+    pub instance_id_source: IdSource,
+}
+
+/// Where a host's instance id came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdSource {
+    /// The instance metadata, so it's the EC2 instance's
+    #[default]
+    Imds,
+    /// The ECS task metadata, so it's the task's id
+    Ecs,
+    /// The hostname, off EC2 and ECS
+    Hostname,
+    /// Nowhere, so it's a placeholder
+    Placeholder,
+}
+
+impl IdSource {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            IdSource::Imds => "the instance metadata",
+            IdSource::Ecs => "the ECS task metadata",
+            IdSource::Hostname => "the hostname",
+            IdSource::Placeholder => "a placeholder, as there's no hostname",
+        }
+    }
+}
+
+impl Host {
+    /// Look up whichever of the host's details `templates` use (the rest are left
+    /// empty), the region being the one events are sent to (or else found the same
+    /// way it is)
+    ///
+    /// Each falls back to something when it can't be looked up in the `metadata`, as
+    /// when this isn't running on EC2: the instance id to the ECS task's id, if this
+    /// is running in one, or else the hostname (or else a placeholder), and the
+    /// availability zone to `unknown`.  The cluster is the ECS task's, and empty
+    /// outside one.  Tags are `unknown` too when they can't be looked up, which is
+    /// warned about, as they're only in the instance metadata when the instance
+    /// allows it.
+    pub async fn lookup(
+        templates: &[&Template],
+        region: Option<&Region>,
+        metadata: &impl InstanceMetadata,
+    ) -> Host {
+        let uses = |name| templates.iter().any(|template| template.uses(name));
+        let mut host = Host::default();
+        let mut task = None;
+        if uses("instance_id") {
+            (host.instance_id, host.instance_id_source) = match metadata.get("instance-id").await {
+                Some(instance_id) => (instance_id, IdSource::Imds),
+                None => {
+                    task = metadata.ecs_task().await;
+                    match (&task, hostname()) {
+                        (Some(task), _) => (task.task_id.clone(), IdSource::Ecs),
+                        (None, Some(hostname)) => (hostname, IdSource::Hostname),
+                        (None, None) => (String::from(NO_HOSTNAME), IdSource::Placeholder),
+                    }
+                }
+            };
+        }
+        if uses("hostname") {
+            host.hostname = hostname().unwrap_or_else(|| String::from(NO_HOSTNAME));
+        }
+        if uses("az") {
+            host.az = metadata
+                .get("placement/availability-zone")
+                .await
+                .unwrap_or_else(|| String::from("unknown"));
+        }
+        if uses("cluster") {
+            if task.is_none() {
+                task = metadata.ecs_task().await;
+            }
+            host.cluster = task.map(|task| task.cluster).unwrap_or_default();
+        }
+        let mut missing = Vec::new();
+        for variable in templates.iter().flat_map(|template| template.variables()) {
+            let Some(key) = variable.strip_prefix("tag:") else {
+                continue;
+            };
+            if host.tags.iter().any(|(name, _)| name == variable) {
+                continue;
+            }
+            let value = match metadata.get(&format!("tags/instance/{}", key)).await {
+                Some(value) => value,
+                None => {
+                    missing.push(key);
+                    String::from("unknown")
+                }
+            };
+            host.tags.push((variable.to_string(), value));
+        }
+        if !missing.is_empty() {
+            warn!(
+                "Couldn't find the instance's {} tag in the instance metadata, so it's \"unknown\" \
+                 in stream names (an instance's tags are only there if it allows it: aws ec2 \
+                 modify-instance-metadata-options --instance-metadata-tags enabled)",
+                missing.join(", ")
+            );
+        }
+        if uses("region") {
+            host.region = match region {
+                Some(region) => region.to_string(),
+                None => crate::region::resolve(None, None, metadata.enabled())
+                    .await
+                    .to_string(),
+            };
+        }
+        host
+    }
+}
+
+/// The name of this host, as the system has it (or else `$HOSTNAME`), made one a
+/// stream's name can have
+pub fn hostname() -> Option<String> {
+    system_hostname()
+        .filter(|name| !name.trim().is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .map(|name| sanitize(&name))
+}
+
+/// The name of this host, as `gethostname` has it
+#[cfg(unix)]
+fn system_hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    // SAFETY: gethostname writes no more than the length it's given into the buffer
+    let result = unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) };
+    if result != 0 {
+        return None;
+    }
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8(name[..end].to_vec()).ok()
+}
+
+/// The name of this host, as Windows has it
+#[cfg(not(unix))]
+fn system_hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// The last part of a file's path, which is what `{filename}` stands for
+pub fn basename(file: &str) -> &str {
+    Path::new(file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(file)
+}
+
+/// A stream's name from its template, for events from `file` (None for every file)
+/// sent at `now`
+pub fn stream_name(
+    template: &Template,
+    host: &Host,
+    file: Option<&str>,
+    now: DateTime<Utc>,
+) -> String {
+    sanitize(&render(template, host, file, now))
+}
+
+/// The template filled in with the host's details, `file` (None for every file) and
+/// `now`, as it is, for names with rules other than a stream's
+pub fn render(template: &Template, host: &Host, file: Option<&str>, now: DateTime<Utc>) -> String {
+    let date = now.format("%F").to_string();
+    let epoch = now.timestamp().to_string();
+    let timestamp = now.format("%F_%H-%M-%S-%f").to_string();
+    let mut values = vec![
+        ("instance_id", host.instance_id.as_str()),
+        ("hostname", &host.hostname),
+        ("az", &host.az),
+        ("region", &host.region),
+        ("cluster", &host.cluster),
+        ("filename", file.map_or("", basename)),
+        ("date", &date),
+        ("epoch", &epoch),
+        ("timestamp", &timestamp),
+    ];
+    values.extend(
+        host.tags
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    template.render(&values)
+}
+
+/// Make a name one CloudWatch Logs allows a stream: characters it doesn't allow are
+/// swapped for `_`, it's cut to 512 characters, and one left empty becomes `_`
+pub fn sanitize(name: &str) -> String {
+    let name: String = name
+        .replace([':', '*'], "_")
+        .chars()
+        .take(MAX_STREAM_NAME)
+        .collect();
+    if name.is_empty() {
+        String::from("_")
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::EcsTask;
+    use chrono::TimeZone;
+
+    fn host() -> Host {
+        Host {
+            instance_id: String::from("i-0123"),
+            hostname: String::from("web-1"),
+            az: String::from("us-east-1b"),
+            region: String::from("us-east-1"),
+            cluster: String::from("dumpers"),
+            tags: vec![(String::from("tag:Name"), String::from("web"))],
+            instance_id_source: IdSource::Imds,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let now = Utc.ymd(2022, 8, 14).and_hms_micro(9, 30, 5, 123456);
+        let name = |template: &str, file| {
+            let template = parse_template(template).unwrap();
+            stream_name(&template, &host(), file, now)
+        };
+        assert_eq!(
+            name("{hostname}/{filename}/{date}", Some("/var/log/syslog")),
+            "web-1/syslog/2022-08-14"
+        );
+        assert_eq!(
+            name("{region}/{az}/{epoch}", None),
+            "us-east-1/us-east-1b/1660469405"
+        );
+        assert_eq!(name("{tag:Name}/{hostname}", None), "web/web-1");
+
+        // The defaults are the names streams have always had
+        let default = |own_file, file| stream_name(&template(None, own_file), &host(), file, now);
+        assert_eq!(default(false, None), "i-0123-2022-08-14_09-30-05-123456000");
+        assert_eq!(
+            default(true, Some("/var/log/syslog")),
+            "i-0123-2022-08-14_09-30-05-123456000-syslog"
+        );
+        // Following a file names its stream without it
+        assert_eq!(
+            default(false, Some("/var/log/syslog")),
+            "i-0123-2022-08-14_09-30-05-123456000"
+        );
+
+        // Streams appended to run after run are named the same every time
+        let append = |own_file, file| stream_name(&append_template(own_file), &host(), file, now);
+        assert_eq!(append(false, None), "i-0123");
+        assert_eq!(append(true, Some("/var/log/syslog")), "i-0123-syslog");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let now = Utc::now();
+        let name = stream_name(
+            &template(None, true),
+            &host(),
+            Some("/var/log/app:1*.log"),
+            now,
+        );
+        assert!(name.starts_with("i-0123-"));
+        assert!(name.ends_with("-app_1_.log"), "{}", name);
+
+        assert_eq!(sanitize("a:b*c"), "a_b_c");
+        assert_eq!(sanitize(""), "_");
+        assert_eq!(sanitize(&"x".repeat(600)).len(), 512);
+        // Standing for nothing, {filename} of every file leaves the name empty
+        let template = parse_template("{filename}").unwrap();
+        assert_eq!(stream_name(&template, &host(), None, now), "_");
+    }
+
+    /// Instance metadata that has only what it's given, and the ECS task if there's
+    /// one
+    struct FakeMetadata(Vec<(&'static str, &'static str)>, Option<EcsTask>);
+
+    impl InstanceMetadata for FakeMetadata {
+        async fn get(&self, path: &str) -> Option<String> {
+            let (_, value) = self.0.iter().find(|(name, _)| *name == path)?;
+            Some(value.to_string())
+        }
+
+        async fn ecs_task(&self) -> Option<EcsTask> {
+            self.1.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let template = parse_template("{instance_id}/{az}/{region}").unwrap();
+        let region = Region::new("eu-west-1");
+        let on_ec2 = FakeMetadata(
+            vec![
+                ("instance-id", "i-0123"),
+                ("placement/availability-zone", "eu-west-1a"),
+            ],
+            None,
+        );
+        let host = Host::lookup(&[&template], Some(&region), &on_ec2).await;
+        assert_eq!(host.instance_id, "i-0123");
+        assert_eq!(host.instance_id_source, IdSource::Imds);
+        assert_eq!(host.az, "eu-west-1a");
+        assert_eq!(host.region, "eu-west-1");
+        // Only what the templates use is looked up
+        assert_eq!(host.hostname, "");
+
+        // Off EC2, the instance is named by its hostname
+        let nowhere = FakeMetadata(vec![], None);
+        let host = Host::lookup(&[&template], Some(&region), &nowhere).await;
+        assert_eq!(Some(host.instance_id), hostname());
+        assert_eq!(host.instance_id_source, IdSource::Hostname);
+        assert_eq!(host.az, "unknown");
+
+        // And in an ECS task, by the task's id
+        let task = EcsTask {
+            task_id: String::from("158d1c8083dd49d6b527399fd6414f5c"),
+            cluster: String::from("dumpers"),
+        };
+        let on_ecs = FakeMetadata(vec![], Some(task));
+        let template = parse_template("{cluster}/{instance_id}").unwrap();
+        let host = Host::lookup(&[&template], Some(&region), &on_ecs).await;
+        assert_eq!(host.instance_id_source, IdSource::Ecs);
+        let name = stream_name(&template, &host, None, Utc::now());
+        assert_eq!(name, "dumpers/158d1c8083dd49d6b527399fd6414f5c");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_tags() {
+        let tagged = FakeMetadata(
+            vec![
+                ("tags/instance/Name", "web"),
+                ("tags/instance/Service", "shop"),
+            ],
+            None,
+        );
+        let template = parse_template("{tag:Service}/{tag:Name}/{tag:Team}").unwrap();
+        let host = Host::lookup(&[&template, &template], None, &tagged).await;
+        // Each looked up once, however many times it's used
+        assert_eq!(host.tags.len(), 3);
+        let name = stream_name(&template, &host, None, Utc::now());
+        assert_eq!(name, "shop/web/unknown");
+    }
+
+    #[test]
+    fn test_hostname() {
+        let hostname = hostname().unwrap();
+        assert!(!hostname.is_empty());
+        assert_eq!(sanitize(&hostname), hostname);
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = parse_template("{host}/{filename}").unwrap_err();
+        assert!(err.contains("{host}"), "{}", err);
+        assert!(err.contains("{hostname}"), "{}", err);
+        assert!(err.contains("{epoch}"), "{}", err);
+    }
+}
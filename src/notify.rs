@@ -0,0 +1,431 @@
+//! Telling an SNS topic how a run went, whether it succeeded or not, so whoever's on
+//! call hears about it
+
+use crate::naming;
+use crate::uploader::{host_details, service_config, Output, UploadOptions, UploadSummary};
+
+use aws_sdk_sns::types::SdkError;
+use aws_sdk_sns::Client as SNS_Client;
+use serde_json::json;
+use tracing::warn;
+
+/// The longest subject SNS allows
+const MAX_SUBJECT: usize = 100;
+
+/// Where to send the notification, and how it's laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyOptions {
+    pub topic_arn: String,
+    pub format: Output,
+}
+
+/// How a run went, on the whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Succeeded,
+    /// Some events were sent, but not all of them (or some were rejected)
+    PartlyFailed,
+    /// Nothing was sent at all
+    Failed,
+}
+
+impl Status {
+    fn describe(&self) -> &'static str {
+        match self {
+            Status::Succeeded => "succeeded",
+            Status::PartlyFailed => "partly failed",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// What the notification says
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub status: Status,
+    pub instance_id: String,
+    /// The region the log groups are in, for links to them
+    pub region: Option<String>,
+    pub groups: Vec<String>,
+    /// The streams sent to, and how many events each was sent
+    pub streams: Vec<(String, usize)>,
+    pub events: usize,
+    pub bytes: usize,
+    /// Events CloudWatch Logs rejected that weren't sent again
+    pub events_rejected: usize,
+    /// What failed (streams and files) and why
+    pub failures: Vec<(String, String)>,
+}
+
+impl Notification {
+    /// How the run summed up in `summary` went, or failed with `error` before it was
+    /// done
+    pub fn new(
+        options: &UploadOptions,
+        summary: &UploadSummary,
+        error: Option<String>,
+        instance_id: String,
+    ) -> Notification {
+        let events_rejected = summary
+            .events_rejected
+            .total()
+            .saturating_sub(summary.events_resent);
+        let mut failures: Vec<_> = summary
+            .files_failed
+            .iter()
+            .chain(&summary.streams_failed)
+            .cloned()
+            .collect();
+        failures.extend(error.map(|error| (String::from("upload"), error)));
+        let status = match (summary.streams_sent.is_empty(), failures.is_empty()) {
+            (true, false) => Status::Failed,
+            (false, false) => Status::PartlyFailed,
+            (_, true) if events_rejected > 0 => Status::PartlyFailed,
+            (_, true) => Status::Succeeded,
+        };
+        Notification {
+            status,
+            instance_id,
+            region: options.client.region.as_ref().map(ToString::to_string),
+            groups: std::iter::once(&options.group)
+                .chain(&options.more_groups)
+                .filter(|group| !group.is_empty())
+                .cloned()
+                .collect(),
+            streams: summary.streams_sent.clone(),
+            events: summary.streams_sent.iter().map(|(_, count)| count).sum(),
+            bytes: summary.bytes_sent,
+            events_rejected,
+            failures,
+        }
+    }
+
+    /// The subject line, like `rusty-axe upload failed on i-0abc`
+    pub fn subject(&self) -> String {
+        let subject = format!(
+            "rusty-axe upload {} on {}",
+            self.status.describe(),
+            self.instance_id
+        );
+        // SNS only takes printable ASCII
+        subject
+            .chars()
+            .map(|c| if c.is_ascii_graphic() { c } else { ' ' })
+            .take(MAX_SUBJECT)
+            .collect()
+    }
+
+    /// The message, for reading
+    pub fn text(&self) -> String {
+        let mut lines = vec![format!(
+            "Upload from {} {}",
+            self.instance_id,
+            self.status.describe()
+        )];
+        for group in &self.groups {
+            match &self.region {
+                Some(region) => lines.push(format!(
+                    "Log group: {} ({})",
+                    group,
+                    console_url(region, group)
+                )),
+                None => lines.push(format!("Log group: {}", group)),
+            }
+        }
+        for (stream, events) in &self.streams {
+            lines.push(format!("Stream: {} ({} events)", stream, events));
+        }
+        lines.push(format!(
+            "Events sent: {} ({} bytes)",
+            self.events, self.bytes
+        ));
+        if self.events_rejected > 0 {
+            lines.push(format!("Events rejected: {}", self.events_rejected));
+        }
+        for (what, reason) in &self.failures {
+            lines.push(format!("Failed: {}: {}", what, reason));
+        }
+        lines.join("\n")
+    }
+
+    /// The message as a JSON object, for scripts
+    pub fn json(&self) -> serde_json::Value {
+        let groups: Vec<_> = self
+            .groups
+            .iter()
+            .map(|group| {
+                json!({
+                    "name": group,
+                    "url": self.region.as_ref().map(|region| console_url(region, group)),
+                })
+            })
+            .collect();
+        let streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|(name, events)| json!({"name": name, "events": events}))
+            .collect();
+        let failures: Vec<_> = self
+            .failures
+            .iter()
+            .map(|(name, reason)| json!({"name": name, "reason": reason}))
+            .collect();
+        json!({
+            "status": self.status.describe().replace(' ', "_"),
+            "instance_id": self.instance_id,
+            "region": self.region,
+            "groups": groups,
+            "streams": streams,
+            "events": self.events,
+            "bytes": self.bytes,
+            "events_rejected": self.events_rejected,
+            "failures": failures,
+        })
+    }
+
+    /// The message, laid out as `format` says
+    pub fn message(&self, format: Output) -> String {
+        match format {
+            Output::Text => self.text(),
+            Output::Json => self.json().to_string(),
+        }
+    }
+}
+
+/// A link to the log group in the CloudWatch console
+///
+/// The console wants the group's name escaped twice over, with `$` for `%` the
+/// second time.
+fn console_url(region: &str, group: &str) -> String {
+    let domain = match region {
+        region if region.starts_with("cn-") => "console.amazonaws.cn",
+        region if region.starts_with("us-gov-") => "console.amazonaws-us-gov.com",
+        _ => "console.aws.amazon.com",
+    };
+    let escaped: String = group
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+            b => format!("$25{:02X}", b),
+        })
+        .collect();
+    format!(
+        "https://{}.{}/cloudwatch/home?region={}#logsV2:log-groups/log-group/{}",
+        region, domain, region, escaped
+    )
+}
+
+/// Somewhere that can publish a message to an SNS topic
+pub trait Publish {
+    async fn publish(&self, topic_arn: &str, subject: &str, message: String) -> Result<(), String>;
+}
+
+impl Publish for SNS_Client {
+    async fn publish(&self, topic_arn: &str, subject: &str, message: String) -> Result<(), String> {
+        self.publish()
+            .topic_arn(topic_arn)
+            .subject(subject)
+            .message(message)
+            .send()
+            .await
+            .map_err(|e| match e {
+                SdkError::ServiceError { err, .. } => err.to_string(),
+                e => e.to_string(),
+            })?;
+        Ok(())
+    }
+}
+
+/// Tell the topic how the run went (or that it failed with `error`), warning (and
+/// carrying on) if it can't be told
+pub async fn publish(
+    options: &UploadOptions,
+    notify: &NotifyOptions,
+    summary: &UploadSummary,
+    error: Option<String>,
+) {
+    let template = naming::parse_template("{instance_id}").expect("valid template");
+    let host = host_details(options, &[&template]).await;
+    let notification = Notification::new(options, summary, error, host.instance_id);
+    let config = service_config(&options.client, "sns", "SNS").await;
+    if let Err(e) = send(&SNS_Client::new(&config), notify, &notification).await {
+        warn!("couldn't notify {}: {}", notify.topic_arn, e);
+    }
+}
+
+/// Publish the notification to the topic
+pub async fn send(
+    to: &impl Publish,
+    notify: &NotifyOptions,
+    notification: &Notification,
+) -> Result<(), String> {
+    let message = notification.message(notify.format);
+    to.publish(&notify.topic_arn, &notification.subject(), message)
+        .await
+}
+
+/// Parse a --notify-sns, the ARN of an SNS topic
+pub fn parse_topic_arn(s: &str) -> Result<String, String> {
+    let parts: Vec<_> = s.split(':').collect();
+    let valid = match parts[..] {
+        ["arn", partition, "sns", region, account, topic] => {
+            partition.starts_with("aws")
+                && !region.is_empty()
+                && account.len() == 12
+                && account.bytes().all(|b| b.is_ascii_digit())
+                && !topic.is_empty()
+        }
+        _ => false,
+    };
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "{:?} isn't the ARN of an SNS topic, like \
+             arn:aws:sns:us-east-1:123456789012:on-call",
+            s
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uploader::RangeCounts;
+    use aws_sdk_cloudwatchlogs::Region;
+    use std::sync::Mutex;
+
+    const TOPIC: &str = "arn:aws:sns:us-east-1:123456789012:on-call";
+
+    /// A topic that keeps what's published to it
+    #[derive(Default)]
+    struct MockTopic {
+        published: Mutex<Vec<(String, String, String)>>,
+    }
+
+    impl Publish for MockTopic {
+        async fn publish(
+            &self,
+            topic_arn: &str,
+            subject: &str,
+            message: String,
+        ) -> Result<(), String> {
+            let published = (topic_arn.to_string(), subject.to_string(), message);
+            self.published.lock().unwrap().push(published);
+            Ok(())
+        }
+    }
+
+    fn options() -> UploadOptions {
+        let mut options = UploadOptions {
+            group: String::from("/app/crash"),
+            ..Default::default()
+        };
+        options.client.region = Some(Region::new("us-east-1"));
+        options
+    }
+
+    async fn published(notification: &Notification, format: Output) -> (String, String) {
+        let to = MockTopic::default();
+        let notify = NotifyOptions {
+            topic_arn: String::from(TOPIC),
+            format,
+        };
+        send(&to, &notify, notification).await.unwrap();
+        let (topic, subject, message) = to.published.lock().unwrap().remove(0);
+        assert_eq!(topic, TOPIC);
+        (subject, message)
+    }
+
+    #[tokio::test]
+    async fn test_success() {
+        let summary = UploadSummary {
+            streams_sent: vec![(String::from("i-0abc-crash"), 120)],
+            bytes_sent: 4096,
+            ..Default::default()
+        };
+        let notification = Notification::new(&options(), &summary, None, String::from("i-0abc"));
+        let (subject, message) = published(&notification, Output::Text).await;
+        assert_eq!(subject, "rusty-axe upload succeeded on i-0abc");
+        assert_eq!(
+            message,
+            "Upload from i-0abc succeeded\n\
+             Log group: /app/crash (https://us-east-1.console.aws.amazon.com/cloudwatch/home\
+             ?region=us-east-1#logsV2:log-groups/log-group/$252Fapp$252Fcrash)\n\
+             Stream: i-0abc-crash (120 events)\n\
+             Events sent: 120 (4096 bytes)"
+        );
+
+        let (_, message) = published(&notification, Output::Json).await;
+        let json: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(json["status"], "succeeded");
+        assert_eq!(json["groups"][0]["name"], "/app/crash");
+        assert_eq!(json["streams"][0]["events"], 120);
+        assert_eq!(json["bytes"], 4096);
+        assert_eq!(json["failures"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_failure() {
+        let summary = UploadSummary {
+            streams_failed: vec![(String::from("i-0abc-crash"), String::from("AccessDenied"))],
+            ..Default::default()
+        };
+        let notification = Notification::new(&options(), &summary, None, String::from("i-0abc"));
+        assert_eq!(notification.status, Status::Failed);
+        let (subject, message) = published(&notification, Output::Json).await;
+        assert_eq!(subject, "rusty-axe upload failed on i-0abc");
+        let json: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["events"], 0);
+        assert_eq!(
+            json["failures"],
+            json!([{"name": "i-0abc-crash", "reason": "AccessDenied"}])
+        );
+
+        // Failing before anything was sent
+        let error = Some(String::from("Events older than 14 days"));
+        let notification = Notification::new(
+            &options(),
+            &UploadSummary::default(),
+            error,
+            String::from("i-0abc"),
+        );
+        let (_, message) = published(&notification, Output::Text).await;
+        assert!(message.ends_with("Failed: upload: Events older than 14 days"));
+    }
+
+    #[test]
+    fn test_partly_failed() {
+        let mut summary = UploadSummary {
+            streams_sent: vec![(String::from("a"), 10)],
+            events_rejected: RangeCounts {
+                too_old: 3,
+                ..Default::default()
+            },
+            events_resent: 1,
+            ..Default::default()
+        };
+        let notification = Notification::new(&options(), &summary, None, String::new());
+        assert_eq!(notification.status, Status::PartlyFailed);
+        assert_eq!(notification.events_rejected, 2);
+
+        summary.events_resent = 3;
+        let notification = Notification::new(&options(), &summary, None, String::new());
+        assert_eq!(notification.status, Status::Succeeded);
+
+        summary
+            .files_failed
+            .push((String::from("b.log"), String::from("missing")));
+        let notification = Notification::new(&options(), &summary, None, String::new());
+        assert_eq!(notification.status, Status::PartlyFailed);
+    }
+
+    #[test]
+    fn test_parse_topic_arn() {
+        assert_eq!(parse_topic_arn(TOPIC).unwrap(), TOPIC);
+        assert!(parse_topic_arn("arn:aws:sns:us-east-1:123456789012").is_err());
+        assert!(parse_topic_arn("arn:aws:sqs:us-east-1:123456789012:queue").is_err());
+        assert!(parse_topic_arn("on-call").is_err());
+    }
+}
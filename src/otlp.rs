@@ -0,0 +1,464 @@
+//! Exporting events to an OpenTelemetry collector instead of CloudWatch Logs, each as
+//! an OTLP log record, sent over OTLP/HTTP as protobuf
+//!
+//! Records say which host (and file) they're from in their resource, and have no
+//! severity, as nothing works out the level of a line.
+
+use crate::http::{self, HttpsClient};
+use crate::naming;
+use crate::records::{self, CallFailed, Limits, PutRecords};
+use crate::uploader::{host_details, StreamEvents, UploadOptions, UploadSummary};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Request};
+use prost::encoding::{self, WireType};
+use prost::Message;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where logs go, under the endpoint
+const LOGS_PATH: &str = "/v1/logs";
+
+/// What the instrumentation scope of every record says made it
+const SCOPE_NAME: &str = "rusty_axe";
+
+/// The parts of the OTLP logs protocol that are sent, with the field numbers of
+/// opentelemetry/proto/collector/logs/v1/logs_service.proto and what it uses
+///
+/// A collector writes the messages around the records out itself, so
+/// only the tests, which read requests back, need those.
+pub mod proto {
+    #[cfg(test)]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportLogsServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_logs: Vec<ResourceLogs>,
+    }
+
+    #[cfg(test)]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceLogs {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_logs: Vec<ScopeLogs>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[cfg(test)]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScopeLogs {
+        #[prost(message, optional, tag = "1")]
+        pub scope: Option<InstrumentationScope>,
+        #[prost(message, repeated, tag = "2")]
+        pub log_records: Vec<LogRecord>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InstrumentationScope {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LogRecord {
+        #[prost(fixed64, tag = "1")]
+        pub time_unix_nano: u64,
+        #[prost(message, optional, tag = "5")]
+        pub body: Option<AnyValue>,
+        #[prost(fixed64, tag = "11")]
+        pub observed_time_unix_nano: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "Value", tags = "1")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+    }
+
+    impl AnyValue {
+        pub fn string(s: impl Into<String>) -> AnyValue {
+            AnyValue {
+                value: Some(Value::StringValue(s.into())),
+            }
+        }
+    }
+}
+
+/// A collector's endpoint, an http:// or https:// URL that logs are sent under (as
+/// `/v1/logs`)
+pub fn parse_endpoint(s: &str) -> Result<String, String> {
+    http::parse_base_url(s, "http://collector:4318")
+}
+
+/// A collector, and the resource every record sent to it is from
+pub struct Collector {
+    client: HttpsClient,
+    endpoint: String,
+    timeout: Duration,
+    resource: proto::Resource,
+}
+
+impl Collector {
+    /// The collector at `endpoint`, with the resource's attributes
+    pub fn new(
+        client: HttpsClient,
+        endpoint: &str,
+        timeout: Duration,
+        attributes: Vec<(&str, String)>,
+    ) -> Collector {
+        let attributes = attributes
+            .into_iter()
+            .map(|(key, value)| proto::KeyValue {
+                key: key.to_string(),
+                value: Some(proto::AnyValue::string(value)),
+            })
+            .collect();
+        Collector {
+            client,
+            endpoint: endpoint.to_string(),
+            timeout,
+            resource: proto::Resource { attributes },
+        }
+    }
+
+    /// A request for all the records, encoded, with the records put in as they were
+    /// encoded by [`PutRecords::record`]
+    ///
+    /// A repeated message field is just each message under the field's tag, one after
+    /// another, so nothing has to be decoded to make it.  The tags are those of
+    /// [`proto::ScopeLogs`], [`proto::ResourceLogs`] and
+    /// [`proto::ExportLogsServiceRequest`].
+    fn request(&self, records: &[Vec<u8>]) -> Vec<u8> {
+        let scope = proto::InstrumentationScope {
+            name: String::from(SCOPE_NAME),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+        };
+        let mut scope_logs = Vec::new();
+        encoding::message::encode(1, &scope, &mut scope_logs);
+        for record in records {
+            length_delimited(2, record, &mut scope_logs);
+        }
+        let mut resource_logs = Vec::new();
+        encoding::message::encode(1, &self.resource, &mut resource_logs);
+        length_delimited(2, &scope_logs, &mut resource_logs);
+        let mut request = Vec::new();
+        length_delimited(1, &resource_logs, &mut request);
+        request
+    }
+}
+
+/// Put `bytes`, an encoded message, in `buf` as field `tag`
+fn length_delimited(tag: u32, bytes: &[u8], buf: &mut Vec<u8>) {
+    encoding::encode_key(tag, WireType::LengthDelimited, buf);
+    encoding::encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+impl PutRecords for Collector {
+    // Well under the 4 MiB collectors take in a request, as they're set up by default
+    const LIMITS: Limits = Limits {
+        records: 1000,
+        bytes: 3 * 1024 * 1024,
+    };
+
+    fn describe(&self) -> String {
+        format!("OTLP collector {}", self.endpoint)
+    }
+
+    /// The event as an encoded log record
+    fn record(&self, event: InputLogEvent) -> Vec<u8> {
+        log_record(event, SystemTime::now()).encode_to_vec()
+    }
+
+    /// Export the records in one request
+    ///
+    /// A collector takes all of them or none, though it may drop some it took (which
+    /// it only says in the response, not read here).
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed> {
+        let count = records.len();
+        let body = self.request(&records);
+        let request = Request::post(format!("{}{}", self.endpoint, LOGS_PATH))
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(body))
+            .map_err(|e| CallFailed {
+                message: e.to_string(),
+                transient: false,
+            })?;
+        let (status, _) = http::send(&self.client, request, self.timeout).await?;
+        if !status.is_success() {
+            return Err(CallFailed {
+                message: format!("the collector answered {}", status),
+                transient: http::RETRYABLE.contains(&status),
+            });
+        }
+        Ok(vec![None; count])
+    }
+}
+
+/// An event as a log record, its message the body, observed at `now`
+fn log_record(event: InputLogEvent, now: SystemTime) -> proto::LogRecord {
+    let nanos = |millis: i64| u64::try_from(millis).unwrap_or_default() * 1_000_000;
+    let observed = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    proto::LogRecord {
+        time_unix_nano: event.timestamp.map(nanos).unwrap_or_default(),
+        body: Some(proto::AnyValue::string(event.message.unwrap_or_default())),
+        observed_time_unix_nano: observed.as_nanos() as u64,
+    }
+}
+
+/// Export each set of events to the collector at `endpoint`, one after another, the
+/// file each is from (if it's only from one) in its resource
+///
+/// The summary and what's returned are as for [`send_logs`](crate::uploader::send_logs).
+pub async fn send_streams(
+    options: &UploadOptions,
+    endpoint: &str,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Vec<Option<String>> {
+    let templates = ["{hostname}", "{instance_id}"]
+        .map(|template| naming::parse_template(template).expect("valid template"));
+    let host = host_details(options, &[&templates[0], &templates[1]]).await;
+    let client = http::client(options.client.timeouts);
+    let mut unsent = Vec::new();
+    for stream in streams {
+        let mut attributes = vec![
+            ("host.name", host.hostname.clone()),
+            ("host.id", host.instance_id.clone()),
+        ];
+        if let Some(file) = &stream.file {
+            attributes.push(("log.file.path", file.clone()));
+        }
+        let timeout = options.client.timeouts.operation;
+        let to = Collector::new(client.clone(), endpoint, timeout, attributes);
+        unsent.extend(records::send_streams(options, &to, vec![stream], summary).await);
+    }
+    unsent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use crate::retry::RetryPolicy;
+    use crate::stub::{Response, Stub};
+
+    /// Stand in for a collector, answering each request with the next of `statuses`
+    /// (then 200), and keeping what it was sent
+    fn serve(statuses: &'static [u16]) -> Stub {
+        Stub::serve(|n, _| Response::new(statuses.get(n).copied().unwrap_or(200), ""))
+    }
+
+    fn collector(endpoint: &str) -> Collector {
+        let attributes = vec![
+            ("host.name", String::from("web-1")),
+            ("host.id", String::from("i-0abc")),
+        ];
+        let client = http::client(Default::default());
+        Collector::new(client, endpoint, Duration::from_secs(5), attributes)
+    }
+
+    fn attributes(resource: &proto::Resource) -> Vec<(&str, &str)> {
+        resource
+            .attributes
+            .iter()
+            .map(|attribute| {
+                let value = match &attribute.value {
+                    Some(proto::AnyValue {
+                        value: Some(proto::Value::StringValue(value)),
+                    }) => value.as_str(),
+                    _ => "",
+                };
+                (attribute.key.as_str(), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_log_record() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_001);
+        let record = log_record(build_event(1_700_000_000_123, String::from("started")), now);
+        assert_eq!(record.time_unix_nano, 1_700_000_000_123_000_000);
+        assert_eq!(record.observed_time_unix_nano, 1_700_000_001_000_000_000);
+        assert_eq!(record.body, Some(proto::AnyValue::string("started")));
+    }
+
+    #[test]
+    fn test_request() {
+        let to = collector("http://collector:4318");
+        let records = records::for_messages(&to, &["a", "b"]);
+        let log_records = records
+            .iter()
+            .map(|record| proto::LogRecord::decode(&record[..]).unwrap())
+            .collect();
+        let request = proto::ExportLogsServiceRequest {
+            resource_logs: vec![proto::ResourceLogs {
+                resource: Some(to.resource.clone()),
+                scope_logs: vec![proto::ScopeLogs {
+                    scope: Some(proto::InstrumentationScope {
+                        name: String::from("rusty_axe"),
+                        version: String::from(env!("CARGO_PKG_VERSION")),
+                    }),
+                    log_records,
+                }],
+            }],
+        };
+        assert_eq!(to.request(&records), request.encode_to_vec());
+        assert_eq!(to.request(&[]).len(), {
+            let mut empty = request;
+            empty.resource_logs[0].scope_logs[0].log_records.clear();
+            empty.encoded_len()
+        });
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let stub = serve(&[]);
+        let to = collector(stub.url());
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a", "b"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+
+        let received = stub.received().remove(0);
+        assert!(
+            received.head.starts_with("POST /v1/logs HTTP/1.1\r\n"),
+            "{}",
+            received.head
+        );
+        assert!(received
+            .head
+            .contains("content-type: application/x-protobuf\r\n"));
+        let request = proto::ExportLogsServiceRequest::decode(&received.body[..]).unwrap();
+        let resource_logs = &request.resource_logs[0];
+        assert_eq!(
+            attributes(resource_logs.resource.as_ref().unwrap()),
+            [("host.name", "web-1"), ("host.id", "i-0abc")]
+        );
+        let scope_logs = &resource_logs.scope_logs[0];
+        assert_eq!(scope_logs.scope.as_ref().unwrap().name, "rusty_axe");
+        let bodies: Vec<_> = scope_logs
+            .log_records
+            .iter()
+            .map(|record| record.body.clone().unwrap())
+            .collect();
+        assert_eq!(
+            bodies,
+            [proto::AnyValue::string("a"), proto::AnyValue::string("b")]
+        );
+        assert_eq!(
+            scope_logs.log_records[0].time_unix_nano,
+            1_700_000_000_000_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        // Told to wait, it's sent again
+        let stub = serve(&[503]);
+        let to = collector(stub.url());
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stub.received().len(), 2);
+        assert_eq!(summary.records_retried, 1);
+
+        // But not if it's turned away
+        let stub = serve(&[400]);
+        let to = collector(stub.url());
+        let err = records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.contains("400"), "{}", err);
+        assert_eq!(stub.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_streams() {
+        let stub = serve(&[]);
+        let options = UploadOptions {
+            imds: crate::metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let streams = vec![
+            StreamEvents {
+                file: Some(String::from("/var/log/app.log")),
+                events: vec![build_event(0, String::from("a"))],
+            },
+            StreamEvents {
+                file: Some(String::from("/var/log/audit.log")),
+                events: vec![build_event(0, String::from("b"))],
+            },
+        ];
+        let mut summary = UploadSummary::default();
+        assert!(send_streams(&options, stub.url(), streams, &mut summary)
+            .await
+            .is_empty());
+        assert_eq!(summary.streams_sent.len(), 2);
+        assert_eq!(
+            summary.streams_sent[1].0,
+            format!("/var/log/audit.log in OTLP collector {}", stub.url())
+        );
+        let received = stub.received();
+        let files: Vec<_> = received
+            .iter()
+            .map(|received| {
+                let request = proto::ExportLogsServiceRequest::decode(&received.body[..]).unwrap();
+                let resource = request.resource_logs[0].resource.clone().unwrap();
+                let attributes = attributes(&resource);
+                attributes
+                    .iter()
+                    .find(|(key, _)| *key == "log.file.path")
+                    .map(|(_, value)| value.to_string())
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                Some(String::from("/var/log/app.log")),
+                Some(String::from("/var/log/audit.log"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Saying what a run would send to CloudWatch Logs, for --dry-run, without calling
+//! AWS for anything
+
+use crate::batch;
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+use std::fmt::Write;
+
+/// What would be sent to one log stream
+#[derive(Debug)]
+pub struct StreamPlan {
+    /// The name of the stream, as it would be created
+    pub name: String,
+    /// The file its events are from, if each file has its own stream
+    pub file: Option<String>,
+    pub events: Vec<InputLogEvent>,
+}
+
+impl StreamPlan {
+    /// The bytes of events PutLogEvents would count
+    fn bytes(&self) -> usize {
+        batch::batch_size(&self.events)
+    }
+
+    /// How many PutLogEvents calls the events would take
+    fn batches(&self) -> usize {
+        batch::make_batches(self.events.clone()).len()
+    }
+}
+
+/// What a run would send, to which log groups
+#[derive(Debug)]
+pub struct Plan {
+    pub groups: Vec<String>,
+    pub streams: Vec<StreamPlan>,
+}
+
+impl Plan {
+    /// Each event, a line of its own with its timestamp, then how much would be sent
+    /// where
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for stream in &self.streams {
+            for event in &stream.events {
+                let time = format_time(event.timestamp()).unwrap_or_default();
+                let _ = writeln!(text, "{} {}", time, event.message().unwrap_or_default());
+            }
+        }
+        let _ = writeln!(text, "Would send to: {}", self.groups.join(", "));
+        for stream in &self.streams {
+            let _ = writeln!(
+                text,
+                "  {}: {} events, {} bytes in {} batches",
+                stream.name,
+                stream.events.len(),
+                stream.bytes(),
+                stream.batches()
+            );
+        }
+        text
+    }
+
+    /// The plan as a JSON object, every event included
+    pub fn json(&self) -> serde_json::Value {
+        let streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|stream| {
+                let events: Vec<_> = stream
+                    .events
+                    .iter()
+                    .map(|event| {
+                        json!({
+                            "timestamp": event.timestamp(),
+                            "message": event.message(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "name": stream.name,
+                    "file": stream.file,
+                    "event_count": stream.events.len(),
+                    "bytes": stream.bytes(),
+                    "batches": stream.batches(),
+                    "events": events,
+                })
+            })
+            .collect();
+        json!({
+            "groups": self.groups,
+            "event_count": self.streams.iter().map(|s| s.events.len()).sum::<usize>(),
+            "bytes": self.streams.iter().map(StreamPlan::bytes).sum::<usize>(),
+            "batches": self.streams.iter().map(StreamPlan::batches).sum::<usize>(),
+            "streams": streams,
+        })
+    }
+}
+
+/// A timestamp in milliseconds as RFC 3339, to the millisecond
+fn format_time(millis: Option<i64>) -> Option<String> {
+    millis
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> Plan {
+        let events = ["started", "stopped"]
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                InputLogEvent::builder()
+                    .timestamp(1_700_000_000_000 + i as i64)
+                    .message(*message)
+                    .build()
+            })
+            .collect();
+        Plan {
+            groups: vec![String::from("app"), String::from("audit")],
+            streams: vec![StreamPlan {
+                name: String::from("i-0abc-20231114"),
+                file: None,
+                events,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            plan().text(),
+            "2023-11-14T22:13:20.000Z started\n\
+             2023-11-14T22:13:20.001Z stopped\n\
+             Would send to: app, audit\n  \
+             i-0abc-20231114: 2 events, 66 bytes in 1 batches\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json = plan().json();
+        assert_eq!(json["groups"], json!(["app", "audit"]));
+        assert_eq!(json["event_count"], 2);
+        assert_eq!(json["bytes"], 66);
+        assert_eq!(json["batches"], 1);
+        assert_eq!(json["streams"][0]["name"], "i-0abc-20231114");
+        assert_eq!(json["streams"][0]["file"], serde_json::Value::Null);
+        assert_eq!(
+            json["streams"][0]["events"][1],
+            json!({"timestamp": 1_700_000_000_001_i64, "message": "stopped"})
+        );
+    }
+}
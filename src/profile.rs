@@ -0,0 +1,150 @@
+//! Sending with a named profile from the AWS config files, as when backfilling logs
+//! from a laptop rather than an instance
+
+use aws_config::environment::region::EnvironmentVariableRegionProvider;
+use aws_config::meta::credentials::LazyCachingCredentialsProvider;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::{ProfileFileCredentialsProvider, ProfileFileRegionProvider};
+use aws_config::provider_config::ProviderConfig;
+use aws_sdk_cloudwatchlogs::Region;
+use aws_types::credentials::SharedCredentialsProvider;
+use aws_types::os_shim_internal::{Env, Fs};
+
+/// The profile to send with: the one given, or else `AWS_PROFILE`'s
+pub fn selected(given: Option<String>) -> Option<String> {
+    given.or_else(|| {
+        std::env::var("AWS_PROFILE")
+            .ok()
+            .filter(|name| !name.is_empty())
+    })
+}
+
+/// Check the profile is in the AWS config files, saying where it was looked for if
+/// it isn't
+pub async fn check(name: &str) -> Result<(), String> {
+    check_with(name, &Env::real(), &Fs::real()).await
+}
+
+async fn check_with(name: &str, env: &Env, fs: &Fs) -> Result<(), String> {
+    let profiles = aws_config::profile::load(fs, env)
+        .await
+        .map_err(|e| format!("Couldn't read the AWS config files: {}", e))?;
+    if profiles.get_profile(name).is_some() {
+        return Ok(());
+    }
+    let mut names: Vec<_> = profiles.profiles().collect();
+    names.sort_unstable();
+    let found = match names.is_empty() {
+        true => String::from("there aren't any"),
+        false => format!("the ones there are: {}", names.join(", ")),
+    };
+    Err(format!(
+        "There's no profile {:?} in {} or {} ({})",
+        name,
+        path(env, "AWS_CONFIG_FILE", "~/.aws/config"),
+        path(env, "AWS_SHARED_CREDENTIALS_FILE", "~/.aws/credentials"),
+        found
+    ))
+}
+
+/// Where an AWS config file is looked for: where `var` says, or else `default`
+fn path(env: &Env, var: &str, default: &str) -> String {
+    let path = env.get(var).unwrap_or_else(|_| default.to_string());
+    match (path.strip_prefix("~/"), env.get("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => path,
+    }
+}
+
+/// A provider of the profile's credentials, which keeps them until shortly before
+/// they expire (for a profile with a role to assume, say)
+pub fn credentials(name: &str) -> SharedCredentialsProvider {
+    credentials_with(name, Env::real())
+}
+
+fn credentials_with(name: &str, env: Env) -> SharedCredentialsProvider {
+    let provider = ProfileFileCredentialsProvider::builder()
+        .configure(&ProviderConfig::default().with_env(env))
+        .profile_name(name)
+        .build();
+    let cache = LazyCachingCredentialsProvider::builder()
+        .load(provider)
+        .build();
+    SharedCredentialsProvider::new(cache)
+}
+
+/// The region the environment says, or else the profile (without asking the
+/// instance metadata, which isn't there off EC2)
+pub async fn region(name: &str) -> Option<Region> {
+    region_with(name, Env::real()).await
+}
+
+async fn region_with(name: &str, env: Env) -> Option<Region> {
+    let profile = ProfileFileRegionProvider::builder()
+        .configure(&ProviderConfig::default().with_env(env.clone()))
+        .profile_name(name)
+        .build();
+    RegionProviderChain::first_try(EnvironmentVariableRegionProvider::new_with_env(env))
+        .or_else(profile)
+        .region()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_types::credentials::ProvideCredentials;
+
+    /// An environment whose AWS config file is the fixture, and has no credentials
+    /// file
+    fn env() -> Env {
+        Env::from_slice(&[
+            ("AWS_CONFIG_FILE", "tests/fixtures/aws-config"),
+            ("AWS_SHARED_CREDENTIALS_FILE", "tests/fixtures/missing"),
+            ("HOME", "/home/user"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        check_with("prod-logging", &env(), &Fs::real())
+            .await
+            .unwrap();
+
+        let err = check_with("prod", &env(), &Fs::real()).await.unwrap_err();
+        assert!(err.contains("no profile \"prod\""), "{}", err);
+        assert!(err.contains("tests/fixtures/aws-config"), "{}", err);
+        assert!(
+            err.contains("the ones there are: default, prod-logging, staging"),
+            "{}",
+            err
+        );
+
+        // With nothing there, it says where it looked
+        let env = Env::from_slice(&[("HOME", "/home/user")]);
+        let err = check_with("prod", &env, &Fs::from_slice(&[]))
+            .await
+            .unwrap_err();
+        assert!(err.contains("/home/user/.aws/config"), "{}", err);
+        assert!(err.contains("/home/user/.aws/credentials"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_profile() {
+        let provider = credentials_with("staging", env());
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "AKIDSTAGING");
+        assert_eq!(
+            region_with("prod-logging", env()).await,
+            Some(Region::new("eu-west-1"))
+        );
+    }
+
+    #[test]
+    fn test_selected() {
+        assert_eq!(
+            selected(Some(String::from("prod-logging"))).as_deref(),
+            Some("prod-logging")
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Keeping track of how far a run has got, reading the input and then sending it
+//!
+//! Reading and sending only tell a [`Progress`] what they've done; whatever it was
+//! made with (a bar on a terminal, or a line said every so often) is left to make
+//! something of the totals it's given, so none of that is tied to a terminal.
+
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, Mutex};
+
+/// How far a run has got, as totals that only ever go up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    /// The bytes of input read so far, out of all there are to read, if that's known
+    /// (it isn't with standard input)
+    Read { bytes: u64, total: Option<u64> },
+    /// The batches sent so far, out of all there are to send, and the bytes of their
+    /// messages
+    Sent {
+        batches: u64,
+        total: u64,
+        bytes: u64,
+    },
+}
+
+/// Somewhere to say how far a run has got, which does nothing unless it was made
+/// with [`Progress::new`]
+///
+/// Clones share their totals, so every stream being sent at once adds to the same
+/// ones.
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<Tracker>>);
+
+struct Tracker {
+    report: Box<dyn Fn(Update) + Send + Sync>,
+    totals: Mutex<Totals>,
+}
+
+#[derive(Default)]
+struct Totals {
+    read_total: Option<u64>,
+    /// The bytes of the files already read
+    read_before: u64,
+    /// The size of the file being read, if it's known
+    file_size: Option<u64>,
+    /// The bytes read of that file
+    file_read: u64,
+    batches_total: u64,
+    batches_sent: u64,
+    bytes_sent: u64,
+}
+
+impl Totals {
+    fn read(&self) -> Update {
+        let file_read = match self.file_size {
+            Some(size) => self.file_read.min(size),
+            None => self.file_read,
+        };
+        Update::Read {
+            bytes: self.read_before + file_read,
+            total: self.read_total,
+        }
+    }
+
+    fn sent(&self) -> Update {
+        Update::Sent {
+            batches: self.batches_sent,
+            total: self.batches_total,
+            bytes: self.bytes_sent,
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Progress").field(&self.0.is_some()).finish()
+    }
+}
+
+impl Progress {
+    /// Pass every update to `report`, one at a time and in order
+    pub fn new(report: impl Fn(Update) + Send + Sync + 'static) -> Progress {
+        Progress(Some(Arc::new(Tracker {
+            report: Box::new(report),
+            totals: Mutex::new(Totals::default()),
+        })))
+    }
+
+    /// Change the totals, then report the update `f` returns
+    ///
+    /// The report is made with the totals still locked, so no update can overtake
+    /// an earlier one.
+    fn update(&self, f: impl FnOnce(&mut Totals) -> Update) {
+        if let Some(tracker) = &self.0 {
+            let mut totals = tracker.totals.lock().unwrap();
+            let update = f(&mut totals);
+            (tracker.report)(update);
+        }
+    }
+
+    /// Start reading input files of these sizes (`None` for any whose size can't be
+    /// known)
+    pub fn reading(&self, sizes: &[Option<u64>]) {
+        let total = sizes.iter().copied().sum::<Option<u64>>();
+        self.update(|totals| {
+            totals.read_total = total;
+            totals.read()
+        });
+    }
+
+    /// Start reading the next file, which is `size` bytes if that's known
+    pub fn start_file(&self, size: Option<u64>) {
+        self.update(|totals| {
+            totals.file_size = size;
+            totals.file_read = 0;
+            totals.read()
+        });
+    }
+
+    /// Another `bytes` of the file have been read
+    ///
+    /// Decompressing can make more of a file than it takes up, so the count never
+    /// goes past its size.
+    pub fn read(&self, bytes: u64) {
+        self.update(|totals| {
+            totals.file_read += bytes;
+            totals.read()
+        });
+    }
+
+    /// The file is done with, whether or not all of it had to be read
+    pub fn finish_file(&self) {
+        self.update(|totals| {
+            totals.read_before += totals.file_size.unwrap_or(totals.file_read);
+            totals.file_size = None;
+            totals.file_read = 0;
+            totals.read()
+        });
+    }
+
+    /// There are another `batches` to send
+    pub fn sending(&self, batches: usize) {
+        self.update(|totals| {
+            totals.batches_total += batches as u64;
+            totals.sent()
+        });
+    }
+
+    /// A batch with `bytes` of messages has been sent
+    pub fn sent(&self, bytes: usize) {
+        self.update(|totals| {
+            totals.batches_sent += 1;
+            totals.bytes_sent += bytes as u64;
+            totals.sent()
+        });
+    }
+}
+
+/// Input that tells a [`Progress`] how much of the file it's read, every so often
+pub struct Reading<R> {
+    inner: R,
+    progress: Progress,
+    /// Bytes read since the last report
+    unreported: u64,
+}
+
+impl<R> Reading<R> {
+    /// Taking a lock for every line would slow reading down, so it's only reported
+    /// after this much
+    const REPORT_EVERY: u64 = 64 * 1024;
+
+    pub fn new(inner: R, progress: &Progress) -> Reading<R> {
+        Reading {
+            inner,
+            progress: progress.clone(),
+            unreported: 0,
+        }
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.unreported += bytes as u64;
+        if self.unreported >= Self::REPORT_EVERY {
+            self.progress.read(std::mem::take(&mut self.unreported));
+        }
+    }
+}
+
+impl<R> Drop for Reading<R> {
+    fn drop(&mut self) {
+        if self.unreported != 0 {
+            self.progress.read(self.unreported);
+        }
+    }
+}
+
+impl<R: Read> Read for Reading<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.add(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Reading<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.add(amt);
+        self.inner.consume(amt)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A progress that keeps every update it's given
+    pub fn recorded() -> (Progress, Arc<Mutex<Vec<Update>>>) {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let kept = updates.clone();
+        let progress = Progress::new(move |update| kept.lock().unwrap().push(update));
+        (progress, updates)
+    }
+
+    /// The updates of reading, as (bytes, total)
+    pub fn reads(updates: &[Update]) -> Vec<(u64, Option<u64>)> {
+        updates
+            .iter()
+            .filter_map(|update| match *update {
+                Update::Read { bytes, total } => Some((bytes, total)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The updates of sending, as (batches, total, bytes)
+    pub fn sends(updates: &[Update]) -> Vec<(u64, u64, u64)> {
+        updates
+            .iter()
+            .filter_map(|update| match *update {
+                Update::Sent {
+                    batches,
+                    total,
+                    bytes,
+                } => Some((batches, total, bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether each of the numbers is at least the one before
+    pub fn increasing(numbers: impl IntoIterator<Item = u64>) -> bool {
+        let numbers: Vec<_> = numbers.into_iter().collect();
+        numbers.windows(2).all(|pair| pair[0] <= pair[1])
+    }
+
+    #[test]
+    fn test_nothing_without_a_report() {
+        let progress = Progress::default();
+        progress.reading(&[Some(10)]);
+        progress.read(5);
+        progress.sent(5);
+        assert_eq!(format!("{:?}", progress), "Progress(false)");
+    }
+
+    #[test]
+    fn test_reading() {
+        let (progress, updates) = recorded();
+        progress.reading(&[Some(10), Some(20)]);
+        progress.start_file(Some(10));
+        progress.read(4);
+        // More than the file's size, as a decompressed file might be
+        progress.read(40);
+        progress.finish_file();
+        progress.start_file(Some(20));
+        progress.read(5);
+        // Only part of the file had to be read
+        progress.finish_file();
+        let reads = reads(&updates.lock().unwrap());
+        assert_eq!(
+            reads,
+            [
+                (0, Some(30)),
+                (0, Some(30)),
+                (4, Some(30)),
+                (10, Some(30)),
+                (10, Some(30)),
+                (10, Some(30)),
+                (15, Some(30)),
+                (30, Some(30)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reading_unknown_sizes() {
+        let (progress, updates) = recorded();
+        progress.reading(&[Some(10), None]);
+        progress.start_file(None);
+        progress.read(7);
+        progress.finish_file();
+        progress.start_file(Some(10));
+        progress.read(3);
+        let reads = reads(&updates.lock().unwrap());
+        assert_eq!(reads.last(), Some(&(10, None)));
+        assert!(increasing(reads.iter().map(|read| read.0)));
+    }
+
+    #[test]
+    fn test_reader() {
+        let (progress, updates) = recorded();
+        let size = 200 * 1024;
+        let input = "x".repeat(99) + "\n";
+        let input = input.repeat(size / 100);
+        progress.start_file(Some(size as u64));
+        let mut reader = Reading::new(io::Cursor::new(input), &progress);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() != 0 {}
+        drop(reader);
+        let reads = reads(&updates.lock().unwrap());
+        // A report every 64 KiB, and the rest once it's done with
+        assert_eq!(reads.len(), 1 + 3 + 1);
+        assert_eq!(reads.last(), Some(&(size as u64, None)));
+        assert!(increasing(reads.iter().map(|read| read.0)));
+    }
+
+    #[test]
+    fn test_sending_from_many_threads() {
+        let (progress, updates) = recorded();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let progress = progress.clone();
+                std::thread::spawn(move || {
+                    progress.sending(25);
+                    for _ in 0..25 {
+                        progress.sent(10);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let sends = sends(&updates.lock().unwrap());
+        assert_eq!(sends.last(), Some(&(100, 100, 1000)));
+        assert!(increasing(sends.iter().map(|sent| sent.0)));
+        assert!(increasing(sends.iter().map(|sent| sent.1)));
+        assert!(increasing(sends.iter().map(|sent| sent.2)));
+        assert!(sends.iter().all(|&(batches, total, _)| batches <= total));
+    }
+}
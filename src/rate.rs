@@ -0,0 +1,193 @@
+//! Keeping calls to CloudWatch Logs under a rate, so many instances uploading at
+//! once don't all get throttled
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits on how fast calls are made and bytes sent, shared by everything uploading
+///
+/// Each limit is a token bucket holding up to a second's allowance, so a burst of
+/// calls within that goes straight through and only calls beyond it wait.  A call
+/// bigger than the whole allowance goes through once the bucket is full, leaving it
+/// in debt for the calls after.
+#[derive(Debug, Default)]
+pub struct RateLimit {
+    requests: Option<Mutex<Bucket>>,
+    bytes: Option<Mutex<Bucket>>,
+}
+
+impl RateLimit {
+    /// # Arguments
+    ///
+    /// * `requests` - The most calls to make per second, if there's a limit
+    /// * `bytes` - The most bytes to send per second, if there's a limit
+    ///
+    pub fn new(requests: Option<f64>, bytes: Option<f64>) -> RateLimit {
+        let now = Instant::now();
+        RateLimit {
+            requests: requests.map(|rate| Mutex::new(Bucket::new(rate, now))),
+            bytes: bytes.map(|rate| Mutex::new(Bucket::new(rate, now))),
+        }
+    }
+
+    /// Wait until a call sending `bytes` is allowed, and count it against the limits
+    pub async fn wait(&self, bytes: usize) {
+        let delay = self.reserve(bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Count a call sending `bytes` at `now` against the limits, returning how long
+    /// it has to wait before it's allowed
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let take = |bucket: &Option<Mutex<Bucket>>, cost: f64| {
+            bucket.as_ref().map_or(Duration::ZERO, |bucket| {
+                bucket.lock().unwrap().take(cost, now)
+            })
+        };
+        take(&self.requests, 1.0).max(take(&self.bytes, bytes as f64))
+    }
+}
+
+/// A token bucket, refilled at a steady rate up to a second's worth
+#[derive(Debug)]
+struct Bucket {
+    /// Tokens added per second, which is also the most it holds
+    rate: f64,
+    /// Tokens in the bucket as of `updated`, below zero if calls are waiting on it
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, now: Instant) -> Bucket {
+        Bucket {
+            rate,
+            tokens: rate,
+            updated: now,
+        }
+    }
+
+    /// Take `cost` tokens at `now`, returning how long until the bucket's no longer
+    /// in debt for them
+    fn take(&mut self, cost: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - cost;
+        self.updated = self.updated.max(now);
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Parse a --rate-limit, which is a number of calls per second
+pub fn parse_requests(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!(
+            "{:?} isn't a positive number of calls per second",
+            s
+        )),
+    }
+}
+
+/// Parse a --rate-limit-bytes, like `500KB/s` or `1MB` (the `/s` is optional)
+pub fn parse_bytes(s: &str) -> Result<f64, String> {
+    parse_size(s.strip_suffix("/s").unwrap_or(s))
+        .ok_or_else(|| format!("{:?} isn't a rate like 500KB/s or 1MB/s", s))
+}
+
+/// Parse a positive number of bytes, like `500KB` or `1.5MB`
+///
+/// KB and MB are 1024 and 1024² bytes, and a number alone is bytes.
+pub fn parse_size(s: &str) -> Option<f64> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1024.0,
+        "M" | "MB" => 1024.0 * 1024.0,
+        _ => return None,
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Some(number * multiplier),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limit = RateLimit::default();
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(limit.reserve(1_000_000, now), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_requests() {
+        let start = Instant::now();
+        let limit = RateLimit::new(Some(2.0), None);
+        // A second's allowance goes straight through
+        assert_eq!(limit.reserve(0, start), Duration::ZERO);
+        assert_eq!(limit.reserve(0, start), Duration::ZERO);
+        // Then calls are spaced out at the rate
+        assert_eq!(limit.reserve(0, start), millis(500));
+        assert_eq!(limit.reserve(0, start), millis(1000));
+
+        // Waiting lets the bucket fill up again, but no more than a second's worth
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limit.reserve(0, later), Duration::ZERO);
+        assert_eq!(limit.reserve(0, later), Duration::ZERO);
+        assert_eq!(limit.reserve(0, later), millis(500));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let start = Instant::now();
+        let limit = RateLimit::new(None, Some(1000.0));
+        assert_eq!(limit.reserve(600, start), Duration::ZERO);
+        assert_eq!(limit.reserve(600, start), millis(200));
+        // A call bigger than the allowance isn't held up forever
+        let later = start + Duration::from_secs(5);
+        assert_eq!(limit.reserve(3000, later), millis(2000));
+    }
+
+    #[test]
+    fn test_both() {
+        let start = Instant::now();
+        let limit = RateLimit::new(Some(10.0), Some(1000.0));
+        assert_eq!(limit.reserve(1000, start), Duration::ZERO);
+        // Plenty of calls left, but not bytes
+        assert_eq!(limit.reserve(500, start), millis(500));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_requests("2"), Ok(2.0));
+        assert_eq!(parse_requests("0.5"), Ok(0.5));
+        assert!(parse_requests("0").is_err());
+        assert!(parse_requests("fast").is_err());
+
+        assert_eq!(parse_bytes("500KB/s"), Ok(500.0 * 1024.0));
+        assert_eq!(parse_bytes("1MB"), Ok(1024.0 * 1024.0));
+        assert_eq!(parse_bytes("1.5mb/s"), Ok(1.5 * 1024.0 * 1024.0));
+        assert_eq!(parse_bytes("4096"), Ok(4096.0));
+        assert!(parse_bytes("0KB/s").is_err());
+        assert!(parse_bytes("500GB/s").is_err());
+        assert!(parse_bytes("KB/s").is_err());
+    }
+}
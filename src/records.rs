@@ -0,0 +1,310 @@
+//! Sending events as records, each one's message a record of its own, to a service
+//! that takes them in batches and can fail some of a batch while taking the rest (as
+//! Kinesis and Firehose do)
+
+use crate::retry::RetryPolicy;
+use crate::uploader::{StreamEvents, UploadOptions, UploadSummary};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use tracing::{debug, error};
+
+/// How much a service takes in one batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The most records
+    pub records: usize,
+    /// The most bytes, as the service counts them
+    pub bytes: usize,
+}
+
+/// A call to put records that failed as a whole
+#[derive(Debug)]
+pub struct CallFailed {
+    pub message: String,
+    /// Whether trying again later might get past it
+    pub transient: bool,
+}
+
+/// Somewhere that can put a batch of records
+///
+/// Either a Kinesis data stream or a Firehose delivery stream.
+pub trait PutRecords {
+    /// How much it takes in one batch
+    const LIMITS: Limits;
+
+    /// What it is, as the summary says, like `Kinesis stream logs`
+    fn describe(&self) -> String;
+
+    /// The record to put for an event, its message unless said otherwise
+    fn record(&self, event: InputLogEvent) -> Vec<u8> {
+        event.message.unwrap_or_default().into_bytes()
+    }
+
+    /// How much of a batch's allowance a record takes
+    fn size(&self, record: &[u8]) -> usize {
+        record.len()
+    }
+
+    /// Put the records, returning the error code of each one that failed (None for
+    /// each that went in), in the order they were given
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed>;
+}
+
+/// The records `to` puts for events with each of `messages`, for tests of the sinks
+#[cfg(test)]
+pub(crate) fn for_messages(to: &impl PutRecords, messages: &[&str]) -> Vec<Vec<u8>> {
+    use crate::events::build_event;
+    messages
+        .iter()
+        .map(|message| to.record(build_event(1_700_000_000_000, message.to_string())))
+        .collect()
+}
+
+/// Send each set of events as records, one after another
+///
+/// The summary and what's returned are as for [`send_logs`](crate::uploader::send_logs).
+pub async fn send_streams(
+    options: &UploadOptions,
+    to: &impl PutRecords,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Vec<Option<String>> {
+    let mut unsent = Vec::new();
+    for stream in streams {
+        let label = match &stream.file {
+            Some(file) => format!("{} in {}", file, to.describe()),
+            None => to.describe(),
+        };
+        let (count, bytes) = (
+            stream.events.len(),
+            crate::events::message_bytes(&stream.events),
+        );
+        let records = stream
+            .events
+            .into_iter()
+            .map(|event| to.record(event))
+            .collect();
+        match send(to, records, &options.retry, summary).await {
+            Ok(()) => {
+                summary.streams_sent.push((label, count));
+                summary.bytes_sent += bytes;
+            }
+            Err(reason) => {
+                error!("Couldn't send events to {}: {}", label, reason);
+                summary.streams_failed.push((label, reason));
+                unsent.push(stream.file);
+            }
+        }
+    }
+    unsent
+}
+
+/// Split records into batches `to` will take, in order
+pub fn batches(to: &impl PutRecords, records: Vec<Vec<u8>>) -> Vec<Vec<Vec<u8>>> {
+    let limits = limits(to);
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut bytes = 0;
+    for record in records {
+        let size = to.size(&record);
+        if batch.len() == limits.records || (!batch.is_empty() && bytes + size > limits.bytes) {
+            batches.push(std::mem::take(&mut batch));
+            bytes = 0;
+        }
+        bytes += size;
+        batch.push(record);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// The limits of whatever `to` is
+fn limits<P: PutRecords>(_to: &P) -> Limits {
+    P::LIMITS
+}
+
+/// Put the records a batch at a time, retrying after a delay whichever records of a
+/// batch failed (or the whole batch, if the call failed for a passing reason), up to
+/// the most retries the policy allows
+pub async fn send(
+    to: &impl PutRecords,
+    records: Vec<Vec<u8>>,
+    policy: &RetryPolicy,
+    summary: &mut UploadSummary,
+) -> Result<(), String> {
+    let rng = fastrand::Rng::new();
+    for mut batch in batches(to, records) {
+        let mut retry = 0;
+        loop {
+            let failed: Vec<(Vec<u8>, String)> = match to.put_records(batch.clone()).await {
+                Ok(codes) => batch
+                    .into_iter()
+                    .zip(codes)
+                    .filter_map(|(record, code)| Some((record, code?)))
+                    .collect(),
+                Err(e) if e.transient => {
+                    let reason = e.message;
+                    batch
+                        .into_iter()
+                        .map(|record| (record, reason.clone()))
+                        .collect()
+                }
+                Err(e) => return Err(e.message),
+            };
+            if failed.is_empty() {
+                break;
+            }
+            let (code, count) = (failed[0].1.clone(), failed.len());
+            if retry == policy.max_retries {
+                return Err(format!("{} records failed ({})", count, code));
+            }
+            retry += 1;
+            let delay = policy.delay(retry, &rng);
+            debug!(
+                "{} records failed ({}), retrying in {:?} ({} of {})",
+                count, code, delay, retry, policy.max_retries
+            );
+            summary.records_retried += count;
+            tokio::time::sleep(delay).await;
+            batch = failed.into_iter().map(|(record, _)| record).collect();
+        }
+        summary.batches_sent += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Somewhere that fails the records it's told to, the first time each is put, or
+    /// every call as a whole while there are `outages` left
+    #[derive(Default)]
+    struct MockRecords {
+        fail: Mutex<Vec<&'static str>>,
+        outages: Mutex<usize>,
+        put: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl PutRecords for MockRecords {
+        const LIMITS: Limits = Limits {
+            records: 3,
+            bytes: 10,
+        };
+
+        fn describe(&self) -> String {
+            String::from("mock")
+        }
+
+        async fn put_records(
+            &self,
+            records: Vec<Vec<u8>>,
+        ) -> Result<Vec<Option<String>>, CallFailed> {
+            let records: Vec<_> = records
+                .into_iter()
+                .map(|record| String::from_utf8(record).unwrap())
+                .collect();
+            self.put.lock().unwrap().push(records.clone());
+            let mut outages = self.outages.lock().unwrap();
+            if *outages > 0 {
+                *outages -= 1;
+                return Err(CallFailed {
+                    message: String::from("ServiceUnavailable"),
+                    transient: true,
+                });
+            }
+            let mut fail = self.fail.lock().unwrap();
+            Ok(records
+                .iter()
+                .map(|record| {
+                    let index = fail.iter().position(|failing| failing == record)?;
+                    fail.remove(index);
+                    Some(String::from("ProvisionedThroughputExceededException"))
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let to = MockRecords::default();
+        let sizes = |given: &[&str]| -> Vec<usize> {
+            batches(&to, for_messages(&to, given))
+                .iter()
+                .map(Vec::len)
+                .collect()
+        };
+        assert_eq!(sizes(&["a"; 7]), [3, 3, 1]);
+        // Ten bytes a batch, so a big one starts the next
+        assert_eq!(sizes(&["aaaa", "bbbb", "cccc", "d"]), [2, 2]);
+        // One too big for a batch is sent alone, for the service to turn down
+        let big = "b".repeat(20);
+        assert_eq!(sizes(&["a", &big, "c"]), [1, 1, 1]);
+        assert!(sizes(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_failed_records() {
+        let to = MockRecords {
+            fail: Mutex::new(vec!["b", "d"]),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        send(
+            &to,
+            for_messages(&to, &["a", "b", "c", "d"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        // Only the ones that failed are put again
+        assert_eq!(
+            *to.put.lock().unwrap(),
+            [vec!["a", "b", "c"], vec!["b"], vec!["d"], vec!["d"]]
+        );
+        assert_eq!(summary.records_retried, 2);
+        assert_eq!(summary.batches_sent, 2);
+
+        // And the whole batch, when the call fails
+        let to = MockRecords {
+            outages: Mutex::new(2),
+            ..Default::default()
+        };
+        send(
+            &to,
+            for_messages(&to, &["a", "b"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(to.put.lock().unwrap().len(), 3);
+        assert_eq!(summary.records_retried, 6);
+    }
+
+    #[tokio::test]
+    async fn test_send_gives_up() {
+        let to = MockRecords {
+            fail: Mutex::new(vec!["b"; 10]),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let err = send(
+            &to,
+            for_messages(&to, &["a", "b"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.contains("ProvisionedThroughputExceeded"), "{}", err);
+        assert_eq!(
+            to.put.lock().unwrap().len(),
+            RetryPolicy::immediate().max_retries as usize + 1
+        );
+    }
+}
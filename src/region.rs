@@ -0,0 +1,97 @@
+//! Which AWS region to send to: the one given, or else the one found the usual way
+//! (the environment, then the AWS config files, then the instance metadata, unless
+//! there's a profile to send with), or else a fallback that's loudly warned about
+
+use aws_config::default_provider::region::DefaultRegionChain;
+use aws_sdk_cloudwatchlogs::Region;
+use tracing::warn;
+
+/// The region sent to when none is given or can be found
+pub const FALLBACK: &str = "us-east-1";
+
+/// The region to send to, warning when it's the fallback, since events sent to the
+/// wrong region are easily taken for lost
+///
+/// With a `profile`, the region is looked for in it, rather than the instance
+/// metadata (which isn't there off EC2, and takes a while to find out it isn't).
+/// Nor is it asked unless `imds`.
+pub async fn resolve(given: Option<&Region>, profile: Option<&str>, imds: bool) -> Region {
+    let found = match (given, profile) {
+        (Some(_), _) => None,
+        (None, Some(profile)) => crate::profile::region(profile).await,
+        (None, None) => {
+            let config = crate::metadata::provider_config(imds);
+            let chain = DefaultRegionChain::builder().configure(&config).build();
+            chain.region().await
+        }
+    };
+    let (region, fell_back) = choose(given.cloned(), found);
+    if fell_back {
+        warn!(
+            "no region was given with --region, or set by AWS_REGION, the AWS config \
+             files or the instance metadata, so sending to {}",
+            region
+        );
+    }
+    region
+}
+
+/// The region given, or else the one found, or else the fallback (saying whether it
+/// was)
+fn choose(given: Option<Region>, found: Option<Region>) -> (Region, bool) {
+    match given.or(found) {
+        Some(region) => (region, false),
+        None => (Region::new(FALLBACK), true),
+    }
+}
+
+/// Parse a --region, like us-west-2
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    if s.contains('-') && s.chars().all(valid) {
+        Ok(Region::new(s.to_string()))
+    } else {
+        Err(format!("{:?} isn't a region, like us-west-2", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        let region = |name: &'static str| Some(Region::new(name));
+        // Given beats found, found beats the fallback
+        assert_eq!(
+            choose(region("eu-west-1"), region("us-west-2")),
+            (Region::new("eu-west-1"), false)
+        );
+        assert_eq!(
+            choose(None, region("us-west-2")),
+            (Region::new("us-west-2"), false)
+        );
+        assert_eq!(choose(None, None), (Region::new(FALLBACK), true));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_given() {
+        // One that's given isn't looked for anywhere else
+        let given = Region::new("ap-southeast-2");
+        assert_eq!(
+            resolve(Some(&given), Some("prod-logging"), true).await,
+            given
+        );
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            parse_region("us-gov-west-1").unwrap(),
+            Region::new("us-gov-west-1")
+        );
+        assert!(parse_region("US-EAST-1").is_err());
+        assert!(parse_region("useast1").is_err());
+        assert!(parse_region("us east 1").is_err());
+    }
+}
@@ -0,0 +1,551 @@
+//! Trying calls to CloudWatch Logs again when they fail for reasons that pass
+
+use aws_sdk_cloudwatchlogs::error::{
+    AssociateKmsKeyError, CreateLogGroupError, CreateLogStreamError, DescribeLogGroupsError,
+    DescribeLogStreamsError, GetLogEventsError, PutLogEventsError, PutRetentionPolicyError,
+    TagLogGroupError,
+};
+use aws_sdk_cloudwatchlogs::types::SdkError;
+use aws_sdk_cloudwatchlogs::Error;
+use aws_smithy_types::retry::ProvideErrorKind;
+use aws_smithy_types::timeout;
+
+use crate::credentials::Refreshing;
+
+use std::future::Future;
+use std::time::Duration;
+use tracing::debug;
+
+/// The longest to wait before any one retry, however many there have been
+const MAX_DELAY: Duration = Duration::from_secs(20);
+
+/// Error codes CloudWatch Logs uses when it's asking to be called less often
+const THROTTLING_CODES: &[&str] = &[
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "RequestLimitExceeded",
+];
+
+/// Error codes for failures on CloudWatch Logs' side that are likely to pass
+const SERVER_CODES: &[&str] = &[
+    "InternalFailure",
+    "InternalServerError",
+    "ServiceUnavailable",
+];
+
+/// Error codes for credentials turned down as expired, or no longer valid
+const EXPIRED_CODES: &[&str] = &[
+    "ExpiredToken",
+    "ExpiredTokenException",
+    "InvalidClientTokenId",
+];
+
+/// Error codes for calls the credentials they were made with aren't allowed to make
+const DENIED_CODES: &[&str] = &[
+    "AccessDenied",
+    "AccessDeniedException",
+    "UnauthorizedOperation",
+    "IncompleteSignature",
+    "InvalidSignatureException",
+    "MissingAuthenticationToken",
+    "SignatureDoesNotMatch",
+];
+
+/// How long calls to CloudWatch Logs can take before they're given up on (and
+/// retried, like any other passing failure)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long to wait for a connection to CloudWatch Logs
+    pub connect: Duration,
+    /// How long any one call can take, connecting included
+    pub operation: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(3),
+            operation: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Timeouts {
+    /// The SDK's timeout configuration for these timeouts
+    pub fn config(&self) -> timeout::Config {
+        timeout::Config::new()
+            .with_http_timeouts(
+                timeout::Http::new().with_connect_timeout(Some(self.connect).into()),
+            )
+            .with_api_timeouts(timeout::Api::new().with_call_timeout(Some(self.operation).into()))
+    }
+}
+
+/// How failed calls are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// How many times to retry a call before giving up (0 never retries)
+    pub max_retries: u32,
+    /// How long to wait before the first retry, doubling for each one after
+    pub base_delay: Duration,
+    /// The credentials calls are made with, to refresh if they're turned down as
+    /// expired (which a call is tried again once for)
+    pub credentials: Option<Refreshing>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(200),
+            credentials: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl RetryPolicy {
+    /// A policy that retries straight away, for tests of what's retried
+    pub(crate) fn immediate() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `retry` (counting from 1)
+    ///
+    /// The delay doubles with each retry, and half of it is random so that many
+    /// clients throttled at once don't all come back at once.
+    pub fn delay(&self, retry: u32, rng: &fastrand::Rng) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_DELAY);
+        backoff / 2 + backoff.mul_f64(rng.f64() / 2.0)
+    }
+
+    /// Make a call, retrying it after a delay for as long as it fails for reasons that
+    /// are likely to pass (like throttling), up to the most retries allowed
+    ///
+    /// A call turned down for expired credentials is tried again once more, straight
+    /// away, with the credentials refreshed.
+    ///
+    /// # Arguments
+    ///
+    /// * `what` - What the call does, for saying that it's being retried
+    /// * `call` - Makes the call, afresh each time it's tried
+    ///
+    pub async fn run<T, F, Fut>(&self, what: &str, mut call: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let rng = fastrand::Rng::new();
+        let mut retry = 0;
+        let mut refreshed = false;
+        loop {
+            let generation = self.credentials.as_ref().map(Refreshing::generation);
+            match call().await {
+                Err(e) if !refreshed && is_expired(&e) && self.credentials.is_some() => {
+                    refreshed = true;
+                    debug!(
+                        "{} failed ({}), refreshing the credentials and trying again",
+                        what,
+                        describe(&e)
+                    );
+                    let credentials = self.credentials.as_ref().expect("checked above");
+                    credentials.refresh(generation.unwrap_or_default()).await;
+                }
+                Err(e) if retry < self.max_retries && is_transient(&e) => {
+                    retry += 1;
+                    let delay = self.delay(retry, &rng);
+                    debug!(
+                        "{} failed ({}), retrying in {:?} ({} of {})",
+                        what,
+                        describe(&e),
+                        delay,
+                        retry,
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Is the error one that trying again later might get past?
+///
+/// That's throttling, timeouts, dropped connections and failures on CloudWatch
+/// Logs' side.  Anything wrong with the request itself, or the credentials it was
+/// made with, will fail the same way every time.
+pub fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::ServiceUnavailableException(_) => true,
+        Error::Unhandled(inner) => {
+            if let Some(e) = inner.downcast_ref::<aws_smithy_types::Error>() {
+                return e.code().is_some_and(|code| {
+                    THROTTLING_CODES.contains(&code) || SERVER_CODES.contains(&code)
+                });
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<PutLogEventsError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<CreateLogStreamError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<CreateLogGroupError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<PutRetentionPolicyError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<TagLogGroupError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<DescribeLogGroupsError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<AssociateKmsKeyError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<DescribeLogStreamsError>>() {
+                return is_transient_sdk_error(e);
+            }
+            if let Some(e) = inner.downcast_ref::<SdkError<GetLogEventsError>>() {
+                return is_transient_sdk_error(e);
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Were the credentials a call was made with turned down as expired (or not valid,
+/// which is how some services say it)?
+pub fn is_expired(e: &Error) -> bool {
+    match e {
+        Error::UnrecognizedClientException(_) => true,
+        Error::Unhandled(inner) => inner
+            .downcast_ref::<aws_smithy_types::Error>()
+            .and_then(|e| e.code())
+            .is_some_and(|code| EXPIRED_CODES.contains(&code)),
+        _ => false,
+    }
+}
+
+/// Was the call turned down for the credentials it was made with, whether they've
+/// expired or aren't allowed to make it?
+pub fn is_denied(e: &Error) -> bool {
+    is_expired(e)
+        || matches!(e, Error::Unhandled(inner) if inner
+            .downcast_ref::<aws_smithy_types::Error>()
+            .and_then(|e| e.code())
+            .is_some_and(|code| DENIED_CODES.contains(&code)))
+}
+
+/// Did the call fail for running out of one of its timeouts?
+pub fn is_timeout(e: &Error) -> bool {
+    timeout_ran_out(e).is_some()
+}
+
+/// Which of the timeouts a call ran out of, if it did, along with the flag that sets it
+fn timeout_ran_out(e: &Error) -> Option<(&'static str, &'static str)> {
+    let Error::Unhandled(inner) = e else {
+        return None;
+    };
+    if let Some(e) = inner.downcast_ref::<SdkError<PutLogEventsError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<CreateLogStreamError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<CreateLogGroupError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<PutRetentionPolicyError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<TagLogGroupError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<AssociateKmsKeyError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<DescribeLogStreamsError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<GetLogEventsError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    if let Some(e) = inner.downcast_ref::<SdkError<DescribeLogGroupsError>>() {
+        return sdk_timeout_ran_out(e);
+    }
+    None
+}
+
+fn sdk_timeout_ran_out<E>(e: &SdkError<E>) -> Option<(&'static str, &'static str)> {
+    match e {
+        SdkError::TimeoutError(_) => Some(("operation", "--operation-timeout")),
+        SdkError::DispatchFailure(e) if e.is_timeout() => Some(("connect", "--connect-timeout")),
+        _ => None,
+    }
+}
+
+/// Describe why a call failed, with a hint at what to do about it for the usual
+/// suspects: a timeout running out, a log group that doesn't exist, or a KMS key
+/// CloudWatch Logs isn't allowed to use
+pub fn describe(e: &Error) -> String {
+    if let Error::ResourceNotFoundException(_) = e {
+        return format!(
+            "{} (create the log group first, or pass --create-group to have it created)",
+            e
+        );
+    }
+    let message = e.to_string();
+    if message.contains("KMS") {
+        return format!(
+            "{} (the usual cause is the key's policy not letting the \
+             logs.<region>.amazonaws.com service use it)",
+            message
+        );
+    }
+    match timeout_ran_out(e) {
+        Some((timeout, flag)) => format!(
+            "{} (the {} timeout ran out; raise {} to allow longer)",
+            e, timeout, flag
+        ),
+        None => e.to_string(),
+    }
+}
+
+/// Is an error that never got as far as being a service error one that might pass?
+pub fn is_transient_sdk_error<E: ProvideErrorKind>(e: &SdkError<E>) -> bool {
+    match e {
+        SdkError::TimeoutError(_)
+        | SdkError::DispatchFailure(_)
+        | SdkError::ResponseError { .. } => true,
+        SdkError::ServiceError { err, raw } => {
+            err.retryable_error_kind().is_some() || raw.http().status().is_server_error()
+        }
+        SdkError::ConstructionFailure(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::Source;
+    use crate::logging::{Captured, Verbosity};
+    use aws_sdk_cloudwatchlogs::error::InvalidParameterException;
+    use std::cell::Cell;
+
+    fn expired() -> Error {
+        Error::Unhandled(Box::new(
+            aws_smithy_types::Error::builder()
+                .code("ExpiredTokenException")
+                .message("The security token included in the request is expired")
+                .build(),
+        ))
+    }
+
+    fn throttled() -> Error {
+        Error::Unhandled(Box::new(
+            aws_smithy_types::Error::builder()
+                .code("ThrottlingException")
+                .message("Rate exceeded")
+                .build(),
+        ))
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&throttled()));
+        let server = aws_smithy_types::Error::builder()
+            .code("InternalFailure")
+            .build();
+        assert!(is_transient(&Error::Unhandled(Box::new(server))));
+        let timeout: SdkError<PutLogEventsError> = SdkError::TimeoutError("timed out".into());
+        assert!(is_transient(&Error::Unhandled(Box::new(timeout))));
+
+        let denied = aws_smithy_types::Error::builder()
+            .code("AccessDeniedException")
+            .build();
+        assert!(!is_transient(&Error::Unhandled(Box::new(denied))));
+        let invalid = InvalidParameterException::builder().build();
+        assert!(!is_transient(&Error::InvalidParameterException(invalid)));
+    }
+
+    #[test]
+    fn test_timeouts_config() {
+        use aws_smithy_types::tristate::TriState;
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(500),
+            operation: Duration::from_secs(4),
+        };
+        let config = timeouts.config();
+        assert_eq!(
+            config.http_timeouts().connect_timeout(),
+            TriState::Set(Duration::from_millis(500))
+        );
+        assert_eq!(
+            config.api_timeouts().call_timeout(),
+            TriState::Set(Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn test_describe_timeouts() {
+        let timeout: SdkError<PutLogEventsError> = SdkError::TimeoutError("timed out".into());
+        let message = describe(&Error::Unhandled(Box::new(timeout)));
+        assert!(message.contains("raise --operation-timeout"), "{}", message);
+
+        let connect: SdkError<CreateLogStreamError> = SdkError::DispatchFailure(
+            aws_smithy_http::result::ConnectorError::timeout("connect timed out".into()),
+        );
+        let message = describe(&Error::Unhandled(Box::new(connect)));
+        assert!(message.contains("raise --connect-timeout"), "{}", message);
+
+        assert!(!describe(&throttled()).contains("timeout"));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let rng = fastrand::Rng::with_seed(7);
+        for (retry, backoff) in [(1, 100), (2, 200), (3, 400), (10, 20_000), (100, 20_000)] {
+            let backoff = Duration::from_millis(backoff);
+            let delay = policy.delay(retry, &rng);
+            assert!(delay >= backoff / 2 && delay <= backoff, "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let policy = RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        };
+
+        // Throttled twice, then through
+        let calls = Cell::new(0);
+        let result = policy
+            .run("Test", || async {
+                calls.set(calls.get() + 1);
+                match calls.get() {
+                    1 | 2 => Err(throttled()),
+                    _ => Ok(calls.get()),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // Anything else fails straight away
+        calls.set(0);
+        let result: Result<(), _> = policy
+            .run("Test", || async {
+                calls.set(calls.get() + 1);
+                Err(Error::InvalidParameterException(
+                    InvalidParameterException::builder().build(),
+                ))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        // And throttling only so many times
+        calls.set(0);
+        let result: Result<(), _> = policy
+            .run("Test", || async {
+                calls.set(calls.get() + 1);
+                Err(throttled())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), policy.max_retries + 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_says_when_retrying() {
+        let policy = RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let policy = &policy;
+        let retried = |verbosity| async move {
+            let (captured, _guard) = Captured::start(verbosity);
+            let _ = policy
+                .run("Test", || async { Err::<(), _>(throttled()) })
+                .await;
+            captured.lines()
+        };
+        // Only with -v
+        assert!(retried(Verbosity::default()).await.is_empty());
+        let verbose = Verbosity {
+            verbose: 1,
+            quiet: false,
+        };
+        let said = retried(verbose).await;
+        assert_eq!(said.len(), 1);
+        assert!(said[0].starts_with("DEBUG Test failed ("), "{}", said[0]);
+        assert!(
+            said[0].ends_with(", retrying in 0ns (1 of 1)"),
+            "{}",
+            said[0]
+        );
+    }
+
+    #[test]
+    fn test_is_expired() {
+        assert!(is_expired(&expired()));
+        assert!(!is_expired(&throttled()));
+        assert!(!is_transient(&expired()));
+    }
+
+    #[tokio::test]
+    async fn test_run_refreshes_credentials() {
+        let credentials = Refreshing::new(Source::Default { imds: true }).await;
+        let policy = RetryPolicy {
+            base_delay: Duration::ZERO,
+            credentials: Some(credentials.clone()),
+            ..Default::default()
+        };
+
+        // Turned down as expired, then through with the credentials refreshed
+        let calls = Cell::new(0);
+        let result = policy
+            .run("Test", || async {
+                calls.set(calls.get() + 1);
+                match calls.get() {
+                    1 => Err(expired()),
+                    _ => Ok(calls.get()),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(credentials.generation(), 1);
+
+        // But only the once
+        calls.set(0);
+        let result: Result<(), _> = policy
+            .run("Test", || async {
+                calls.set(calls.get() + 1);
+                Err(expired())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 2);
+        assert_eq!(credentials.generation(), 2);
+    }
+}
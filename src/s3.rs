@@ -0,0 +1,745 @@
+//! Putting files in an S3 bucket instead of sending their events anywhere, each file
+//! as an object of its own, for keeping them whole (like in an incident bucket)
+
+use crate::events::STDIN_PATH;
+use crate::naming;
+use crate::records::CallFailed;
+use crate::retry::{self, RetryPolicy};
+use crate::template::Template;
+use crate::uploader::{host_details, service_config, StreamEvents, UploadOptions, UploadSummary};
+
+use aws_sdk_s3::model::{CompletedMultipartUpload, CompletedPart, ServerSideEncryption};
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::Client as S3_Client;
+use aws_smithy_types::retry::ProvideErrorKind;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Read};
+use tracing::{debug, error, info, warn};
+
+/// Objects bigger than this are uploaded in parts this big (but for the last)
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// How objects are named after the --key-prefix, when no --key-template is given
+const DEFAULT_KEY_TEMPLATE: &str = "{instance_id}/{date}/{filename}";
+
+/// How S3 encrypts the objects put in it, if not as the bucket does by default
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sse {
+    /// With keys S3 manages itself (SSE-S3)
+    #[clap(name = "AES256")]
+    Aes256,
+    /// With a KMS key: --kms-key-arn, or else the account's default for S3 (SSE-KMS)
+    #[clap(name = "aws:kms")]
+    AwsKms,
+}
+
+/// Where in S3 files go, and how they're put there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Options {
+    /// The bucket to put them in
+    pub bucket: String,
+    /// What every key starts with, like `incidents/`
+    pub key_prefix: String,
+    /// How to name each object after the prefix, if not the default way
+    pub key_template: Option<Template>,
+    /// How to encrypt them, if not as the bucket does by default
+    pub sse: Option<Sse>,
+    /// The KMS key to encrypt them with, with SSE-KMS
+    pub kms_key: Option<String>,
+    /// Put each file as it is, rather than the lines taken from it
+    pub raw: bool,
+}
+
+/// Somewhere that can put objects, whole or a part at a time
+pub trait PutObjects {
+    /// Where the object at `key` is, as an s3:// URL
+    fn url(&self, key: &str) -> String;
+
+    /// Put an object in one call
+    async fn put_object(&self, key: &str, body: Bytes) -> Result<(), CallFailed>;
+
+    /// Start putting an object in parts, returning the id of the upload
+    async fn create_multipart_upload(&self, key: &str) -> Result<String, CallFailed>;
+
+    /// Put a part of an object (numbered from 1), returning its ETag
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, CallFailed>;
+
+    /// Put the parts together as the object, given each part's number and ETag
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<(i32, String)>,
+    ) -> Result<(), CallFailed>;
+
+    /// Give up on an upload, so its parts aren't kept (and charged for)
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), CallFailed>;
+}
+
+/// An S3 bucket, and how what's put in it is encrypted
+pub struct Bucket {
+    client: S3_Client,
+    name: String,
+    sse: Option<Sse>,
+    kms_key: Option<String>,
+}
+
+impl Bucket {
+    pub async fn new(options: &UploadOptions, s3: &S3Options) -> Bucket {
+        let config = service_config(&options.client, "s3", "S3").await;
+        Bucket {
+            client: S3_Client::new(&config),
+            name: s3.bucket.clone(),
+            sse: s3.sse,
+            kms_key: s3.kms_key.clone(),
+        }
+    }
+
+    fn encryption(&self) -> Option<ServerSideEncryption> {
+        self.sse.map(|sse| match sse {
+            Sse::Aes256 => ServerSideEncryption::Aes256,
+            Sse::AwsKms => ServerSideEncryption::AwsKms,
+        })
+    }
+}
+
+impl PutObjects for Bucket {
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.name, key)
+    }
+
+    async fn put_object(&self, key: &str, body: Bytes) -> Result<(), CallFailed> {
+        self.client
+            .put_object()
+            .bucket(&self.name)
+            .key(key)
+            .body(ByteStream::from(body))
+            .set_server_side_encryption(self.encryption())
+            .set_ssekms_key_id(self.kms_key.clone())
+            .send()
+            .await
+            .map_err(failed)?;
+        Ok(())
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String, CallFailed> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .set_server_side_encryption(self.encryption())
+            .set_ssekms_key_id(self.kms_key.clone())
+            .send()
+            .await
+            .map_err(failed)?;
+        output
+            .upload_id()
+            .map(String::from)
+            .ok_or_else(|| CallFailed {
+                message: String::from("S3 gave no upload id"),
+                transient: false,
+            })
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, CallFailed> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(failed)?;
+        Ok(output.e_tag().unwrap_or_default().to_string())
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<(i32, String)>,
+    ) -> Result<(), CallFailed> {
+        let parts = parts
+            .into_iter()
+            .map(|(number, e_tag)| {
+                CompletedPart::builder()
+                    .part_number(number)
+                    .e_tag(e_tag)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(failed)?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), CallFailed> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.name)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(failed)?;
+        Ok(())
+    }
+}
+
+/// A call to S3 that failed, as the summary says it
+fn failed<E: ProvideErrorKind + std::error::Error + 'static>(e: SdkError<E>) -> CallFailed {
+    CallFailed {
+        transient: retry::is_transient_sdk_error(&e),
+        message: match &e {
+            SdkError::ServiceError { err, .. } => err.to_string(),
+            e => e.to_string(),
+        },
+    }
+}
+
+/// The key of the object for `file`: the prefix, then the template filled in (with
+/// none of a stream name's rules, as a key can have any character)
+fn key(s3: &S3Options, host: &naming::Host, file: &str, now: DateTime<Utc>) -> String {
+    let template = key_template(s3);
+    let name = naming::render(&template, host, Some(file), now);
+    format!("{}{}", s3.key_prefix, name)
+}
+
+fn key_template(s3: &S3Options) -> Template {
+    s3.key_template.clone().unwrap_or_else(|| {
+        naming::parse_template(DEFAULT_KEY_TEMPLATE).expect("valid default template")
+    })
+}
+
+/// What's put for a file, taken a part at a time
+enum Body {
+    /// The lines taken from it, each ending in a newline
+    Lines(Bytes),
+    /// The file itself, open to be read as it's put, and how big it was when opened
+    File(File, usize),
+}
+
+impl Body {
+    fn len(&self) -> usize {
+        match self {
+            Body::Lines(lines) => lines.len(),
+            Body::File(_, size) => *size,
+        }
+    }
+
+    /// The next `size` bytes (or as many as are left) after those already taken
+    fn next_part(&mut self, size: usize) -> io::Result<Bytes> {
+        match self {
+            Body::Lines(lines) => Ok(lines.split_to(size.min(lines.len()))),
+            Body::File(file, _) => {
+                let mut part = Vec::with_capacity(size);
+                file.take(size as u64).read_to_end(&mut part)?;
+                Ok(Bytes::from(part))
+            }
+        }
+    }
+}
+
+/// What to put for a file: the file itself with --raw-object, or else the lines taken
+/// from it
+fn body(s3: &S3Options, stream: StreamEvents) -> Result<Body, String> {
+    match (&stream.file, s3.raw) {
+        (Some(file), true) if file != STDIN_PATH => {
+            open(file).map_err(|e| format!("Couldn't read {} to put as it is: {}", file, e))
+        }
+        (_, true) => Err(String::from("stdin can't be put as it is, only its lines")),
+        (_, false) => {
+            let mut body = Vec::new();
+            for event in stream.events {
+                body.extend(event.message.unwrap_or_default().into_bytes());
+                if body.last() != Some(&b'\n') {
+                    body.push(b'\n');
+                }
+            }
+            Ok(Body::Lines(Bytes::from(body)))
+        }
+    }
+}
+
+fn open(file: &str) -> io::Result<Body> {
+    let file = File::open(file)?;
+    let size = file.metadata()?.len() as usize;
+    Ok(Body::File(file, size))
+}
+
+/// Put each file in the bucket as an object of its own, one after another, saying
+/// where each went
+///
+/// The summary and what's returned are as for [`send_logs`](crate::uploader::send_logs), each
+/// object counted as a stream by its s3:// URL.
+pub async fn send_streams(
+    options: &UploadOptions,
+    s3: &S3Options,
+    to: &impl PutObjects,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Vec<Option<String>> {
+    let host = host_details(options, &[&key_template(s3)]).await;
+    let now = Utc::now();
+    let mut unsent = Vec::new();
+    for stream in streams {
+        let file = stream
+            .file
+            .clone()
+            .unwrap_or_else(|| String::from(STDIN_PATH));
+        let key = key(s3, &host, &file, now);
+        let url = to.url(&key);
+        let count = stream.events.len();
+        let put = match body(s3, stream) {
+            Ok(body) => {
+                let bytes = body.len();
+                put(to, &key, body, &options.retry, summary)
+                    .await
+                    .map(|()| bytes)
+            }
+            Err(reason) => Err(reason),
+        };
+        match put {
+            Ok(bytes) => {
+                info!("Put {} in {}", file, url);
+                summary.streams_sent.push((url, count));
+                summary.bytes_sent += bytes;
+            }
+            Err(reason) => {
+                error!("Couldn't put {} in {}: {}", file, url, reason);
+                summary.streams_failed.push((url, reason));
+                unsent.push(Some(file));
+            }
+        }
+    }
+    unsent
+}
+
+/// Put an object, in one call or (if it's over `PART_SIZE`) in parts, giving up on
+/// the upload if a part can't be put or the parts can't be put together
+async fn put(
+    to: &impl PutObjects,
+    key: &str,
+    mut body: Body,
+    policy: &RetryPolicy,
+    summary: &mut UploadSummary,
+) -> Result<(), String> {
+    if body.len() <= PART_SIZE {
+        let whole = body.next_part(PART_SIZE).map_err(unreadable)?;
+        attempt("PutObject", policy, || to.put_object(key, whole.clone())).await?;
+        summary.batches_sent += 1;
+        return Ok(());
+    }
+
+    let upload_id = attempt("CreateMultipartUpload", policy, || {
+        to.create_multipart_upload(key)
+    })
+    .await?;
+    let mut parts = Vec::new();
+    for number in 1..=body.len().div_ceil(PART_SIZE) as i32 {
+        let uploaded = match body.next_part(PART_SIZE) {
+            Ok(part) => {
+                attempt("UploadPart", policy, || {
+                    to.upload_part(key, &upload_id, number, part.clone())
+                })
+                .await
+            }
+            Err(e) => Err(unreadable(e)),
+        };
+        match uploaded {
+            Ok(e_tag) => parts.push((number, e_tag)),
+            Err(e) => {
+                abort(to, key, &upload_id).await;
+                return Err(e);
+            }
+        }
+        summary.batches_sent += 1;
+    }
+    let completed = attempt("CompleteMultipartUpload", policy, || {
+        to.complete_multipart_upload(key, &upload_id, parts.clone())
+    })
+    .await;
+    if completed.is_err() {
+        abort(to, key, &upload_id).await;
+    }
+    completed
+}
+
+fn unreadable(e: io::Error) -> String {
+    format!("Reading the file failed: {}", e)
+}
+
+/// Give up on an upload, warning that its parts are kept if even that fails
+async fn abort(to: &impl PutObjects, key: &str, upload_id: &str) {
+    if let Err(e) = to.abort_multipart_upload(key, upload_id).await {
+        warn!(
+            "Couldn't give up on the upload of {}, whose parts are kept until it's \
+             aborted: {}",
+            key, e.message
+        );
+    }
+}
+
+/// Make a call, retrying it after a delay while it fails for reasons likely to pass,
+/// up to the most retries the policy allows
+async fn attempt<T, F, Fut>(what: &str, policy: &RetryPolicy, mut call: F) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, CallFailed>>,
+{
+    let rng = fastrand::Rng::new();
+    let mut retry = 0;
+    loop {
+        match call().await {
+            Ok(done) => return Ok(done),
+            Err(e) if e.transient && retry < policy.max_retries => {
+                retry += 1;
+                let delay = policy.delay(retry, &rng);
+                debug!(
+                    "{} failed ({}), retrying in {:?} ({} of {})",
+                    what, e.message, delay, retry, policy.max_retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(format!("{} failed: {}", what, e.message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// A bucket that keeps what's put in it, failing the part numbered `fail_part`
+    /// (and putting the parts together, with `fail_complete`) for good, and every call
+    /// as a whole while there are `outages` left
+    #[derive(Default)]
+    struct MockBucket {
+        fail_part: Option<i32>,
+        fail_complete: bool,
+        outages: Mutex<usize>,
+        calls: Mutex<Vec<String>>,
+        objects: Mutex<Vec<(String, Vec<u8>)>>,
+        parts: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl MockBucket {
+        fn call(&self, call: String) -> Result<(), CallFailed> {
+            self.calls.lock().unwrap().push(call);
+            let mut outages = self.outages.lock().unwrap();
+            if *outages > 0 {
+                *outages -= 1;
+                return Err(CallFailed {
+                    message: String::from("SlowDown"),
+                    transient: true,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    impl PutObjects for MockBucket {
+        fn url(&self, key: &str) -> String {
+            format!("s3://incidents/{}", key)
+        }
+
+        async fn put_object(&self, key: &str, body: Bytes) -> Result<(), CallFailed> {
+            self.call(format!("put {} {}", key, body.len()))?;
+            self.objects
+                .lock()
+                .unwrap()
+                .push((key.to_string(), body.to_vec()));
+            Ok(())
+        }
+
+        async fn create_multipart_upload(&self, key: &str) -> Result<String, CallFailed> {
+            self.call(format!("create {}", key))?;
+            Ok(String::from("upload-1"))
+        }
+
+        async fn upload_part(
+            &self,
+            _key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Bytes,
+        ) -> Result<String, CallFailed> {
+            self.call(format!("part {} {} {}", upload_id, part_number, body.len()))?;
+            if self.fail_part == Some(part_number) {
+                return Err(CallFailed {
+                    message: String::from("AccessDenied"),
+                    transient: false,
+                });
+            }
+            self.parts.lock().unwrap().push(body.to_vec());
+            Ok(format!("etag-{}", part_number))
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<(), CallFailed> {
+            let e_tags: Vec<_> = parts.iter().map(|(_, e_tag)| e_tag.as_str()).collect();
+            self.call(format!("complete {} {}", upload_id, e_tags.join(",")))?;
+            if self.fail_complete {
+                return Err(CallFailed {
+                    message: String::from("InvalidPart"),
+                    transient: false,
+                });
+            }
+            let body = self.parts.lock().unwrap().concat();
+            self.objects.lock().unwrap().push((key.to_string(), body));
+            Ok(())
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            _key: &str,
+            upload_id: &str,
+        ) -> Result<(), CallFailed> {
+            self.call(format!("abort {}", upload_id))
+        }
+    }
+
+    fn s3() -> S3Options {
+        S3Options {
+            bucket: String::from("incidents"),
+            key_prefix: String::from("crash/"),
+            key_template: Some(naming::parse_template("{filename}").unwrap()),
+            sse: None,
+            kms_key: None,
+            raw: false,
+        }
+    }
+
+    fn options() -> UploadOptions {
+        UploadOptions {
+            imds: crate::metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            retry: RetryPolicy::immediate(),
+            ..Default::default()
+        }
+    }
+
+    fn stream(file: &str, messages: &[&str]) -> StreamEvents {
+        StreamEvents {
+            file: Some(file.to_string()),
+            events: messages
+                .iter()
+                .map(|message| build_event(0, message.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let host = naming::Host {
+            instance_id: String::from("i-0123"),
+            ..Default::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let s3 = S3Options {
+            key_template: None,
+            ..s3()
+        };
+        assert_eq!(
+            key(&s3, &host, "/var/log/app.log", now),
+            "crash/i-0123/2024-05-01/app.log"
+        );
+        // Kept as they are, unlike in a stream name
+        let long = format!("{}:*.log", "x".repeat(600));
+        assert_eq!(
+            key(&s3, &host, &long, now),
+            format!("crash/i-0123/2024-05-01/{}", long)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_put() {
+        let bucket = MockBucket {
+            outages: Mutex::new(1),
+            ..Default::default()
+        };
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream("/var/log/app.log", &["started", "crashed\n"])];
+        let unsent = send_streams(&options(), &s3(), &bucket, streams, &mut summary).await;
+
+        assert!(unsent.is_empty());
+        // Put again after the outage
+        assert_eq!(
+            *bucket.calls.lock().unwrap(),
+            ["put crash/app.log 16", "put crash/app.log 16"]
+        );
+        assert_eq!(
+            *bucket.objects.lock().unwrap(),
+            [(
+                String::from("crash/app.log"),
+                b"started\ncrashed\n".to_vec()
+            )]
+        );
+        assert_eq!(
+            summary.streams_sent,
+            [(String::from("s3://incidents/crash/app.log"), 2)]
+        );
+        assert_eq!((summary.bytes_sent, summary.batches_sent), (16, 1));
+    }
+
+    #[tokio::test]
+    async fn test_multipart() {
+        let bucket = MockBucket::default();
+        let line = "x".repeat(1023);
+        let lines = vec![line.as_str(); 2 * PART_SIZE / 1024 + 1];
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream("big.log", &lines)];
+        let unsent = send_streams(&options(), &s3(), &bucket, streams, &mut summary).await;
+
+        assert!(unsent.is_empty());
+        assert_eq!(
+            *bucket.calls.lock().unwrap(),
+            [
+                String::from("create crash/big.log"),
+                format!("part upload-1 1 {}", PART_SIZE),
+                format!("part upload-1 2 {}", PART_SIZE),
+                String::from("part upload-1 3 1024"),
+                String::from("complete upload-1 etag-1,etag-2,etag-3"),
+            ]
+        );
+        let objects = bucket.objects.lock().unwrap();
+        assert_eq!(objects[0].1.len(), 2 * PART_SIZE + 1024);
+        assert_eq!(summary.batches_sent, 3);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_aborted() {
+        let bucket = MockBucket {
+            fail_part: Some(2),
+            ..Default::default()
+        };
+        let line = "x".repeat(1023);
+        let lines = vec![line.as_str(); 2 * PART_SIZE / 1024];
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream("big.log", &lines), stream("small.log", &["ok"])];
+        let unsent = send_streams(&options(), &s3(), &bucket, streams, &mut summary).await;
+
+        // The other file's still put
+        assert_eq!(unsent, [Some(String::from("big.log"))]);
+        let calls = bucket.calls.lock().unwrap();
+        assert_eq!(calls[3..], ["abort upload-1", "put crash/small.log 3"]);
+        assert_eq!(
+            summary.streams_failed,
+            [(
+                String::from("s3://incidents/crash/big.log"),
+                String::from("UploadPart failed: AccessDenied")
+            )]
+        );
+        assert_eq!(summary.streams_sent.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_not_completed() {
+        let bucket = MockBucket {
+            fail_complete: true,
+            ..Default::default()
+        };
+        let line = "x".repeat(1023);
+        let lines = vec![line.as_str(); 2 * PART_SIZE / 1024];
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream("big.log", &lines)];
+        let unsent = send_streams(&options(), &s3(), &bucket, streams, &mut summary).await;
+
+        assert_eq!(unsent, [Some(String::from("big.log"))]);
+        // Its parts aren't left behind
+        let calls = bucket.calls.lock().unwrap();
+        assert_eq!(
+            calls[3..],
+            ["complete upload-1 etag-1,etag-2", "abort upload-1"]
+        );
+        assert_eq!(
+            summary.streams_failed[0].1,
+            "CompleteMultipartUpload failed: InvalidPart"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_raw() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"\x1f\x8b raw bytes, not lines").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let s3 = S3Options { raw: true, ..s3() };
+        let bucket = MockBucket::default();
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream(&path, &["lines", "taken"]), stream("-", &["piped"])];
+        let unsent = send_streams(&options(), &s3, &bucket, streams, &mut summary).await;
+
+        assert_eq!(
+            bucket.objects.lock().unwrap()[0].1,
+            b"\x1f\x8b raw bytes, not lines"
+        );
+        // Stdin's gone once it's read, so there's nothing to put as it was
+        assert_eq!(unsent, [Some(String::from("-"))]);
+        assert!(summary.streams_failed[0].1.contains("stdin"));
+    }
+
+    #[tokio::test]
+    async fn test_raw_multipart() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&vec![b'x'; PART_SIZE + 10]).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let s3 = S3Options { raw: true, ..s3() };
+        let bucket = MockBucket::default();
+        let mut summary = UploadSummary::default();
+        let streams = vec![stream(&path, &["lines"])];
+        let unsent = send_streams(&options(), &s3, &bucket, streams, &mut summary).await;
+
+        assert!(unsent.is_empty());
+        // Read a part at a time, as it's put
+        let calls = bucket.calls.lock().unwrap();
+        assert_eq!(
+            calls[1..3],
+            [
+                format!("part upload-1 1 {}", PART_SIZE),
+                String::from("part upload-1 2 10")
+            ]
+        );
+        assert_eq!(summary.bytes_sent, PART_SIZE + 10);
+    }
+}
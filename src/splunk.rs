@@ -0,0 +1,528 @@
+//! Sending events to a Splunk HTTP Event Collector (HEC) instead of CloudWatch Logs,
+//! in batches of JSON events, each with the host, source and sourcetype it's from
+//!
+//! The token is only ever read from an environment variable or a file, so it isn't
+//! seen in the process list, and is never shown.
+
+use crate::http::{self, HttpsClient};
+use crate::naming;
+use crate::records::{self, CallFailed, Limits, PutRecords};
+use crate::uploader::{host_details, StreamEvents, UploadOptions, UploadSummary};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Request};
+use serde_json::json;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use tracing::debug;
+
+/// Where events go, under the --hec-url
+const EVENT_PATH: &str = "/services/collector/event";
+
+/// Where to ask whether events were indexed, when the token needs acknowledgments
+const ACK_PATH: &str = "/services/collector/ack";
+
+/// The sourcetype of plain text, unless --hec-sourcetype says otherwise
+pub const DEFAULT_SOURCETYPE: &str = "rusty_axe";
+
+/// How often to ask whether events were indexed
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A HEC token, which debug output (and anything else) only says the source of
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token {
+    value: String,
+    /// Where it was read from, like `$SPLUNK_TOKEN`
+    from: String,
+}
+
+impl Token {
+    /// The token in the environment variable `name`
+    pub fn from_env(name: &str) -> Result<Token, String> {
+        let value = std::env::var(name).map_err(|_| format!("${} isn't set", name))?;
+        Token::new(value, format!("${}", name))
+    }
+
+    /// The token in the file at `path`, on its first line
+    pub fn from_file(path: &str) -> Result<Token, String> {
+        let contents =
+            std::fs::read_to_string(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+        let value = contents.lines().next().unwrap_or_default().to_string();
+        Token::new(value, path.to_string())
+    }
+
+    fn new(value: String, from: String) -> Result<Token, String> {
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            return Err(format!("{} has no token", from));
+        }
+        Ok(Token { value, from })
+    }
+
+    /// The Authorization header for it
+    fn header(&self) -> String {
+        format!("Splunk {}", self.value)
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Token {{ from: {:?} }}", self.from)
+    }
+}
+
+/// The HEC's URL, given as the Splunk host (and port) like
+/// https://http-inputs-acme.splunkcloud.com, or its event endpoint
+pub fn parse_url(s: &str) -> Result<String, String> {
+    let url = http::parse_base_url(s, "https://http-inputs-acme.splunkcloud.com")?;
+    Ok(url.strip_suffix(EVENT_PATH).unwrap_or(&url).to_string())
+}
+
+/// Where in Splunk events go, and what they say about themselves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplunkOptions {
+    pub url: String,
+    pub token: Token,
+    pub sourcetype: String,
+    /// The host events are from, if not this one's name
+    pub host: Option<String>,
+    /// The source events are from, if not the file each is from
+    pub source: Option<String>,
+}
+
+/// A HEC, and the fields every event sent to it has
+pub struct Collector {
+    client: HttpsClient,
+    url: String,
+    token: Token,
+    /// The host, source and sourcetype of the events
+    fields: serde_json::Map<String, serde_json::Value>,
+    /// Which channel requests are sent on, as HEC needs to track acknowledgments
+    channel: String,
+    timeout: Duration,
+    ack_interval: Duration,
+}
+
+impl Collector {
+    pub fn new(
+        client: HttpsClient,
+        splunk: &SplunkOptions,
+        host: &str,
+        source: Option<&str>,
+        timeout: Duration,
+    ) -> Collector {
+        let mut fields = serde_json::Map::new();
+        fields.insert(String::from("host"), json!(host));
+        if let Some(source) = source {
+            fields.insert(String::from("source"), json!(source));
+        }
+        fields.insert(String::from("sourcetype"), json!(splunk.sourcetype));
+        Collector {
+            client,
+            url: splunk.url.clone(),
+            token: splunk.token.clone(),
+            fields,
+            channel: channel(&fastrand::Rng::new()),
+            timeout,
+            ack_interval: ACK_INTERVAL,
+        }
+    }
+
+    /// A POST of `body` to `path`, with the token and channel
+    fn request(&self, path: &str, body: Vec<u8>) -> Result<Request<Body>, CallFailed> {
+        Request::post(format!("{}{}", self.url, path))
+            .header(AUTHORIZATION, self.token.header())
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Splunk-Request-Channel", &self.channel)
+            .body(Body::from(body))
+            .map_err(|e| CallFailed {
+                message: e.to_string(),
+                transient: false,
+            })
+    }
+
+    /// Wait for the HEC to say the events it gave `ack` were indexed, for as long as a
+    /// call can take
+    ///
+    /// If it doesn't in that time, they may have been lost, so are to be sent again.
+    async fn wait_for_ack(&self, ack: u64) -> Result<(), CallFailed> {
+        let started = tokio::time::Instant::now();
+        loop {
+            let body = json!({ "acks": [ack] }).to_string().into_bytes();
+            let request = self.request(ACK_PATH, body)?;
+            let (status, body) = http::send(&self.client, request, self.timeout).await?;
+            if !status.is_success() {
+                return Err(CallFailed {
+                    message: format!("HEC answered {} when asked about acknowledgments", status),
+                    transient: true,
+                });
+            }
+            let reply: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            if reply["acks"][ack.to_string()] == json!(true) {
+                return Ok(());
+            }
+            if started.elapsed() + self.ack_interval > self.timeout {
+                return Err(CallFailed {
+                    message: String::from("HEC didn't acknowledge the events"),
+                    transient: true,
+                });
+            }
+            tokio::time::sleep(self.ack_interval).await;
+        }
+    }
+}
+
+impl PutRecords for Collector {
+    // HEC recommends batches of about a megabyte
+    const LIMITS: Limits = Limits {
+        records: 10_000,
+        bytes: 1_000_000,
+    };
+
+    fn describe(&self) -> String {
+        format!("Splunk HEC {}", self.url)
+    }
+
+    /// The event as a HEC event, a JSON object with the collector's fields
+    fn record(&self, event: InputLogEvent) -> Vec<u8> {
+        hec_event(&event, &self.fields).to_string().into_bytes()
+    }
+
+    /// Each record takes a newline after it as well
+    fn size(&self, record: &[u8]) -> usize {
+        record.len() + 1
+    }
+
+    /// Post the records in one request, each on a line of its own
+    ///
+    /// HEC takes all of them or none; if the token needs acknowledgments, they're only
+    /// taken once it says they've been indexed.
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed> {
+        let count = records.len();
+        let request = self.request(EVENT_PATH, records.join(&b'\n'))?;
+        let (status, body) = http::send(&self.client, request, self.timeout).await?;
+        let reply: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if !status.is_success() {
+            let message = match reply["text"].as_str() {
+                Some(text) => format!("HEC answered {}: {}", status, text),
+                None => format!("HEC answered {}", status),
+            };
+            return Err(CallFailed {
+                message,
+                transient: http::RETRYABLE.contains(&status),
+            });
+        }
+        if let Some(ack) = reply["ackId"].as_u64() {
+            self.wait_for_ack(ack).await?;
+        }
+        Ok(vec![None; count])
+    }
+}
+
+/// An event as HEC takes it: its message, its time in seconds, and the fields
+fn hec_event(
+    event: &InputLogEvent,
+    fields: &serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Value {
+    let mut hec_event = fields.clone();
+    if let Some(millis) = event.timestamp() {
+        let time = format!("{}.{:03}", millis.div_euclid(1000), millis.rem_euclid(1000));
+        let time: serde_json::Number = time.parse().expect("valid number");
+        hec_event.insert(String::from("time"), json!(time));
+    }
+    hec_event.insert(String::from("event"), json!(event.message()));
+    serde_json::Value::Object(hec_event)
+}
+
+/// A new channel, a random GUID as HEC wants them
+fn channel(rng: &fastrand::Rng) -> String {
+    let hex = |digits: usize| -> String {
+        (0..digits)
+            .map(|_| char::from_digit(rng.u32(0..16), 16).expect("a hex digit"))
+            .collect()
+    };
+    format!("{}-{}-{}-{}-{}", hex(8), hex(4), hex(4), hex(4), hex(12))
+}
+
+/// Post each set of events to the HEC, one after another, each with the file it's from
+/// (if it's only from one) as its source
+///
+/// The summary and what's returned are as for [`send_logs`](crate::uploader::send_logs).
+pub async fn send_streams(
+    options: &UploadOptions,
+    splunk: &SplunkOptions,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Vec<Option<String>> {
+    let host = match &splunk.host {
+        Some(host) => host.clone(),
+        None => {
+            let template = naming::parse_template("{hostname}").expect("valid template");
+            host_details(options, &[&template]).await.hostname
+        }
+    };
+    debug!(
+        "Sending to Splunk HEC {} with the token from {}",
+        splunk.url, splunk.token.from
+    );
+    let client = http::client(options.client.timeouts);
+    let mut unsent = Vec::new();
+    for stream in streams {
+        let source = splunk.source.as_deref().or(stream.file.as_deref());
+        let timeout = options.client.timeouts.operation;
+        let to = Collector::new(client.clone(), splunk, &host, source, timeout);
+        unsent.extend(records::send_streams(options, &to, vec![stream], summary).await);
+    }
+    unsent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use crate::retry::RetryPolicy;
+    use crate::stub::{Response, Stub};
+
+    /// Stand in for a HEC, answering each request with the next of `answers` (then
+    /// 200 and a success), and keeping what it was sent
+    fn serve(answers: &'static [(u16, &'static str)]) -> Stub {
+        Stub::serve(|n, _| {
+            let (status, body) = answers
+                .get(n)
+                .copied()
+                .unwrap_or((200, r#"{"text":"Success","code":0}"#));
+            Response::new(status, body)
+        })
+    }
+
+    fn splunk(url: &str) -> SplunkOptions {
+        SplunkOptions {
+            url: url.to_string(),
+            token: Token::new(String::from("0000-secret"), String::from("$SPLUNK_TOKEN")).unwrap(),
+            sourcetype: String::from(DEFAULT_SOURCETYPE),
+            host: None,
+            source: None,
+        }
+    }
+
+    fn collector(url: &str) -> Collector {
+        let client = http::client(Default::default());
+        let mut to = Collector::new(
+            client,
+            &splunk(url),
+            "web-1",
+            Some("/var/log/app.log"),
+            Duration::from_secs(5),
+        );
+        to.ack_interval = Duration::from_millis(1);
+        to
+    }
+
+    #[test]
+    fn test_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "0000-secret\n").unwrap();
+        let token = Token::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(token.header(), "Splunk 0000-secret");
+        assert!(!format!("{:?}", token).contains("secret"));
+        assert!(!format!("{:?}", splunk("http://hec")).contains("secret"));
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(Token::from_file(path.to_str().unwrap()).is_err());
+        assert!(Token::from_env("RUSTY_AXE_TEST_NO_SUCH_TOKEN").is_err());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("https://http-inputs-acme.splunkcloud.com/").unwrap(),
+            "https://http-inputs-acme.splunkcloud.com"
+        );
+        assert_eq!(
+            parse_url("https://splunk:8088/services/collector/event").unwrap(),
+            "https://splunk:8088"
+        );
+        assert!(parse_url("splunk:8088").is_err());
+    }
+
+    #[test]
+    fn test_hec_event() {
+        let to = collector("http://hec");
+        let record = to.record(build_event(1_700_000_000_123, String::from("started")));
+        assert_eq!(
+            String::from_utf8(record).unwrap(),
+            r#"{"event":"started","host":"web-1","source":"/var/log/app.log","sourcetype":"rusty_axe","time":1700000000.123}"#
+        );
+    }
+
+    #[test]
+    fn test_channel() {
+        let channel = channel(&fastrand::Rng::with_seed(7));
+        let lengths: Vec<_> = channel.split('-').map(str::len).collect();
+        assert_eq!(lengths, [8, 4, 4, 4, 12]);
+        assert!(channel.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let stub = serve(&[]);
+        let to = collector(stub.url());
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a", "b"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+
+        let received = stub.received().remove(0);
+        assert!(
+            received
+                .head
+                .starts_with("POST /services/collector/event HTTP/1.1\r\n"),
+            "{}",
+            received.head
+        );
+        assert!(received
+            .head
+            .contains("authorization: Splunk 0000-secret\r\n"));
+        assert!(received.head.contains("x-splunk-request-channel: "));
+        let events: Vec<serde_json::Value> = received
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["event"], "b");
+        assert_eq!(events[1]["host"], "web-1");
+        assert_eq!(events[1]["source"], "/var/log/app.log");
+        assert_eq!(events[1]["sourcetype"], "rusty_axe");
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        // Busy, it's sent again
+        let stub = serve(&[(503, r#"{"text":"Server is busy","code":9}"#)]);
+        let to = collector(stub.url());
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stub.received().len(), 2);
+        assert_eq!(summary.records_retried, 1);
+
+        // But not with a token it won't take
+        let stub = serve(&[(403, r#"{"text":"Invalid token","code":4}"#)]);
+        let to = collector(stub.url());
+        let err = records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, "HEC answered 403 Forbidden: Invalid token");
+        assert_eq!(stub.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_acks() {
+        let stub = serve(&[
+            (200, r#"{"text":"Success","code":0,"ackId":3}"#),
+            (200, r#"{"acks":{"3":false}}"#),
+            (200, r#"{"acks":{"3":true}}"#),
+        ]);
+        let to = collector(stub.url());
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let received = stub.received();
+        assert_eq!(received.len(), 3);
+        assert!(received[1]
+            .head
+            .starts_with("POST /services/collector/ack HTTP/1.1\r\n"));
+        assert_eq!(received[1].text(), r#"{"acks":[3]}"#);
+        assert_eq!(summary.records_retried, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ack_never_comes() {
+        let stub = serve(&[
+            (200, r#"{"text":"Success","code":0,"ackId":0}"#),
+            (200, r#"{"acks":{"0":false}}"#),
+            (200, r#"{"acks":{"0":false}}"#),
+        ]);
+        let mut to = collector(stub.url());
+        to.timeout = Duration::from_millis(500);
+        to.ack_interval = Duration::from_millis(300);
+        let mut summary = UploadSummary::default();
+        // Sent again, and the stub doesn't ask for acknowledgment the second time
+        records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.records_retried, 1);
+        let received = stub.received();
+        assert!(received[3]
+            .head
+            .starts_with("POST /services/collector/event "));
+    }
+
+    #[tokio::test]
+    async fn test_send_streams() {
+        let stub = serve(&[]);
+        let options = UploadOptions {
+            imds: crate::metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let splunk = SplunkOptions {
+            host: Some(String::from("web-1")),
+            ..splunk(stub.url())
+        };
+        let streams = vec![
+            StreamEvents {
+                file: Some(String::from("/var/log/app.log")),
+                events: vec![build_event(0, String::from("a"))],
+            },
+            StreamEvents::merged(vec![build_event(0, String::from("b"))]),
+        ];
+        let mut summary = UploadSummary::default();
+        assert!(send_streams(&options, &splunk, streams, &mut summary)
+            .await
+            .is_empty());
+        assert_eq!(summary.streams_sent.len(), 2);
+        let received = stub.received();
+        let events: Vec<serde_json::Value> = received
+            .iter()
+            .map(|received| serde_json::from_slice(&received.body).unwrap())
+            .collect();
+        assert_eq!(events[0]["source"], "/var/log/app.log");
+        assert_eq!(events[0]["host"], "web-1");
+        // Events from every file have no source of their own
+        assert_eq!(events[1].get("source"), None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Remembering how far into each file earlier runs got, so only new lines are sent
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Where reading a file got to, and enough about the file to notice it being rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub inode: u64,
+    /// How big the file was when it was read
+    pub size: u64,
+    /// The offset reading stopped at
+    pub offset: u64,
+}
+
+/// A state file holding a cursor for each file read, locked for as long as it's open
+///
+/// The file has a line per input file, `inode size offset path`, and is replaced
+/// whole when saved so a crash part way through can't leave it half written.
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    cursors: HashMap<String, Cursor>,
+    /// Cursors for the files being read this run, until the upload succeeds
+    pending: HashMap<String, Cursor>,
+    /// Held open (and locked) to keep other runs away
+    _lock: File,
+}
+
+impl StateFile {
+    /// Lock and load the state file, which needn't exist yet
+    ///
+    /// Fails if another run already has the same state file open.
+    pub fn open(path: &Path) -> io::Result<StateFile> {
+        let lock_path = path.with_file_name(format!(
+            "{}.lock",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        match lock.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("{:?} is in use by another run", path),
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        let cursors = match fs::read_to_string(path) {
+            Ok(contents) => parse(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(StateFile {
+            path: path.to_path_buf(),
+            cursors,
+            pending: HashMap::new(),
+            _lock: lock,
+        })
+    }
+
+    /// Where to start reading `file`, which is back at the beginning if it's been
+    /// rotated or truncated since the last run
+    pub fn start_offset(&mut self, file: &str) -> io::Result<u64> {
+        let metadata = fs::metadata(file)?;
+        self.pending.insert(
+            file.to_string(),
+            Cursor {
+                inode: metadata.ino(),
+                size: metadata.len(),
+                offset: 0,
+            },
+        );
+
+        match self.cursors.get(file) {
+            None => Ok(0),
+            Some(cursor) if cursor.inode != metadata.ino() || metadata.len() < cursor.size => {
+                info!(
+                    "{:?} was rotated since the last run, starting from the beginning",
+                    file
+                );
+                Ok(0)
+            }
+            Some(cursor) => Ok(cursor.offset),
+        }
+    }
+
+    /// Note how far reading `file` got, to be saved once the upload succeeds
+    pub fn advance(&mut self, file: &str, offset: u64) {
+        if let Some(cursor) = self.pending.get_mut(file) {
+            cursor.offset = offset;
+            cursor.size = cursor.size.max(offset);
+        }
+    }
+
+    /// Save the cursors for every file read this run
+    pub fn save(&mut self) -> io::Result<()> {
+        self.cursors.extend(self.pending.drain());
+
+        let mut files: Vec<_> = self.cursors.iter().collect();
+        files.sort_by_key(|(file, _)| *file);
+        let contents: String = files
+            .into_iter()
+            .map(|(file, c)| format!("{} {} {} {}\n", c.inode, c.size, c.offset, file))
+            .collect();
+
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, &self.path)
+    }
+}
+
+/// Read the cursors out of a state file's contents
+fn parse(contents: &str) -> io::Result<HashMap<String, Cursor>> {
+    let mut cursors = HashMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("state file line {} is malformed", index + 1),
+            )
+        };
+        let mut fields = line.splitn(4, ' ');
+        let mut number = || {
+            fields
+                .next()
+                .and_then(|n| n.parse::<u64>().ok())
+                .ok_or_else(invalid)
+        };
+        let cursor = Cursor {
+            inode: number()?,
+            size: number()?,
+            offset: number()?,
+        };
+        let file = fields.next().ok_or_else(invalid)?;
+        cursors.insert(file.to_string(), cursor);
+    }
+
+    Ok(cursors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app log");
+        let log_name = log.to_str().unwrap();
+        fs::write(&log, "one\ntwo\n").unwrap();
+        let state_path = dir.path().join("state");
+
+        let mut state = StateFile::open(&state_path).unwrap();
+        assert_eq!(state.start_offset(log_name).unwrap(), 0);
+        state.advance(log_name, 8);
+        state.save().unwrap();
+        drop(state);
+
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        writeln!(file, "three").unwrap();
+
+        let mut state = StateFile::open(&state_path).unwrap();
+        assert_eq!(state.start_offset(log_name).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_state_not_saved_is_not_advanced() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        let log_name = log.to_str().unwrap();
+        fs::write(&log, "one\ntwo\n").unwrap();
+        let state_path = dir.path().join("state");
+
+        let mut state = StateFile::open(&state_path).unwrap();
+        state.start_offset(log_name).unwrap();
+        state.advance(log_name, 8);
+        // As if the upload had failed
+        drop(state);
+
+        let mut state = StateFile::open(&state_path).unwrap();
+        assert_eq!(state.start_offset(log_name).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_state_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        let log_name = log.to_str().unwrap();
+        let state_path = dir.path().join("state");
+
+        let save = |contents: &str| {
+            fs::write(&log, contents).unwrap();
+            let mut state = StateFile::open(&state_path).unwrap();
+            state.start_offset(log_name).unwrap();
+            state.advance(log_name, contents.len() as u64);
+            state.save().unwrap();
+        };
+        let start = || {
+            StateFile::open(&state_path)
+                .unwrap()
+                .start_offset(log_name)
+                .unwrap()
+        };
+
+        // Truncated in place
+        save("one\ntwo\n");
+        fs::write(&log, "1\n").unwrap();
+        assert_eq!(start(), 0);
+
+        // Replaced by a new file, even a bigger one
+        save("one\ntwo\n");
+        fs::rename(&log, dir.path().join("app.log.1")).unwrap();
+        fs::write(&log, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(start(), 0);
+    }
+
+    #[test]
+    fn test_state_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state");
+
+        let state = StateFile::open(&state_path).unwrap();
+        let err = StateFile::open(&state_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        drop(state);
+        assert!(StateFile::open(&state_path).is_ok());
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(parse("12 34 56 /var/log/a b.log\n").is_ok());
+        assert!(parse("12 34 /var/log/app.log\n").is_err());
+        assert!(parse("12 34 56\n").is_err());
+    }
+}
@@ -0,0 +1,862 @@
+//! Writing events to a log stream, a batch at a time
+
+use crate::batch;
+use crate::error;
+use crate::events::{now_millis, AcceptedTimes};
+use crate::group::{self, CreateLogs, GroupSetup};
+use crate::progress::Progress;
+use crate::rate::RateLimit;
+use crate::retry::RetryPolicy;
+use crate::uploader::{OnRejected, RangeCounts, UploadOptions, UploadSummary};
+use crate::verify::GetEvents;
+
+use aws_sdk_cloudwatchlogs::error::ResourceNotFoundException;
+use aws_sdk_cloudwatchlogs::model::{InputLogEvent, RejectedLogEventsInfo};
+use aws_sdk_cloudwatchlogs::{Client as CWL_Client, Error};
+use std::future::Future;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// What CloudWatch Logs said about a batch it took
+#[derive(Debug, Default)]
+pub struct Accepted {
+    /// The sequence token for the next batch
+    pub next_token: Option<String>,
+    /// Which of the batch's events it turned down for their time, if any
+    pub rejected: Option<RejectedLogEventsInfo>,
+}
+
+/// Something that can put a batch of events into a log stream
+pub trait PutEvents {
+    /// Send one batch of events, returning the sequence token for the next and any
+    /// events that were rejected
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - The log group the stream belongs to
+    /// * `stream` - The log stream to write to
+    /// * `events` - The events to send, in chronological order
+    /// * `sequence_token` - The token returned for the previous batch (None for a new
+    ///   stream)
+    ///
+    fn put_events(
+        &self,
+        group: &str,
+        stream: &str,
+        events: Vec<InputLogEvent>,
+        sequence_token: Option<String>,
+    ) -> impl Future<Output = Result<Accepted, Error>> + Send;
+
+    /// The sequence token for the next batch sent to a stream that's already been
+    /// written to (None if it hasn't), failing with ResourceNotFound if the stream
+    /// isn't there
+    fn sequence_token(
+        &self,
+        group: &str,
+        stream: &str,
+    ) -> impl Future<Output = Result<Option<String>, Error>> + Send;
+}
+
+impl PutEvents for CWL_Client {
+    async fn put_events(
+        &self,
+        group: &str,
+        stream: &str,
+        events: Vec<InputLogEvent>,
+        sequence_token: Option<String>,
+    ) -> Result<Accepted, Error> {
+        let resp = self
+            .put_log_events()
+            .log_group_name(group)
+            .log_stream_name(stream)
+            .set_log_events(Some(events))
+            .set_sequence_token(sequence_token)
+            .send()
+            .await?;
+
+        Ok(Accepted {
+            next_token: resp.next_sequence_token,
+            rejected: resp.rejected_log_events_info,
+        })
+    }
+
+    async fn sequence_token(&self, group: &str, stream: &str) -> Result<Option<String>, Error> {
+        let resp = self
+            .describe_log_streams()
+            .log_group_name(group)
+            .log_stream_name_prefix(stream)
+            .send()
+            .await?;
+        let found = resp
+            .log_streams()
+            .unwrap_or_default()
+            .iter()
+            .find(|found| found.log_stream_name() == Some(stream));
+        match found {
+            Some(found) => Ok(found.upload_sequence_token().map(String::from)),
+            None => Err(Error::ResourceNotFoundException(
+                ResourceNotFoundException::builder()
+                    .message("The specified log stream does not exist.")
+                    .build(),
+            )),
+        }
+    }
+}
+
+/// All of CloudWatch Logs that sending a stream calls: putting its events, creating
+/// it (and its log group), and reading it back to verify
+///
+/// The client calls are behind traits so that tests can stand in for the SDK's
+/// client (see `mock::MockClient`); the other sinks' clients are too, the same way.
+/// It's cloned for each stream sent at once, each on a task of its own.
+pub trait CwlApi: PutEvents + CreateLogs + GetEvents + Clone + Send + Sync + 'static {}
+
+impl<C: PutEvents + CreateLogs + GetEvents + Clone + Send + Sync + 'static> CwlApi for C {}
+
+/// Why CloudWatch Logs rejected an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    /// More than 14 days old
+    TooOld,
+    /// Older than the log group keeps events
+    Expired,
+    /// Too far ahead of CloudWatch Logs' clock
+    TooNew,
+}
+
+/// Why each of a batch's `count` events was rejected (None for those that weren't)
+///
+/// CloudWatch Logs gives the rejected events as ranges of the batch: the too old and
+/// expired from the start up to (not including) their end indices, and the too new
+/// from their start index on.  An event in more than one range counts as too old
+/// before expired.
+pub(crate) fn rejections(info: &RejectedLogEventsInfo, count: usize) -> Vec<Option<Rejection>> {
+    let index = |index: Option<i32>| index.map(|index| usize::try_from(index).unwrap_or(0));
+    let too_old_end = index(info.too_old_log_event_end_index()).unwrap_or(0);
+    let expired_end = index(info.expired_log_event_end_index()).unwrap_or(0);
+    let too_new_start = index(info.too_new_log_event_start_index()).unwrap_or(count);
+
+    (0..count)
+        .map(|i| {
+            if i < too_old_end {
+                Some(Rejection::TooOld)
+            } else if i < expired_end {
+                Some(Rejection::Expired)
+            } else if i >= too_new_start {
+                Some(Rejection::TooNew)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Count rejected events by why they were rejected
+fn count_rejections(rejections: &[Option<Rejection>]) -> RangeCounts {
+    let mut counts = RangeCounts::default();
+    for rejection in rejections.iter().flatten() {
+        match rejection {
+            Rejection::TooOld => counts.too_old += 1,
+            Rejection::Expired => counts.past_retention += 1,
+            Rejection::TooNew => counts.too_new += 1,
+        }
+    }
+    counts
+}
+
+/// The rejected events, given the nearest timestamp CloudWatch Logs accepts as of
+/// `now` so they can be sent again
+///
+/// The log group's retention isn't known here, so if any events were expired, the
+/// old ones are all moved to a day ago (no retention period is shorter).  Old events
+/// come before new ones in a batch, so they stay in order.
+fn clamp_rejected(
+    events: Vec<InputLogEvent>,
+    rejections: &[Option<Rejection>],
+    now: i64,
+) -> Vec<InputLogEvent> {
+    let expired = rejections.contains(&Some(Rejection::Expired));
+    let accepted = AcceptedTimes::new(now, expired.then_some(1));
+
+    events
+        .into_iter()
+        .zip(rejections)
+        .filter_map(|(event, rejection)| {
+            let timestamp = match (*rejection)? {
+                Rejection::TooOld | Rejection::Expired => accepted.earliest(),
+                Rejection::TooNew => accepted.latest,
+            };
+            Some(
+                InputLogEvent::builder()
+                    .timestamp(timestamp)
+                    .set_message(event.message)
+                    .build(),
+            )
+        })
+        .collect()
+}
+
+/// How many times a batch is sent again with the token CloudWatch Logs expected,
+/// before giving up on a stream something else keeps writing to
+const MAX_TOKEN_RETRIES: usize = 3;
+
+/// A log stream being written to, which keeps track of its sequence token
+///
+/// Every batch after the first has to carry the token returned for the one before,
+/// so all the writing to a stream goes through one of these.
+pub struct StreamWriter<C> {
+    client: C,
+    group: String,
+    stream: String,
+    /// The token to send with the next batch
+    token: Option<String>,
+    /// How calls that fail are retried
+    retry: RetryPolicy,
+    /// How fast calls can be made, shared with anything else uploading
+    limit: Arc<RateLimit>,
+    /// What to do with events CloudWatch Logs rejects for their time
+    on_rejected: OnRejected,
+    /// How to set up the log group, and whether to create it (and the stream) again
+    /// if it's gone
+    group_setup: GroupSetup,
+    /// Where to say each batch has been sent
+    progress: Progress,
+}
+
+impl<C: PutEvents + CreateLogs> StreamWriter<C> {
+    /// Start writing to a stream in `options.group` that's had nothing written to it
+    /// yet, sending events as `options` says
+    pub fn new(client: C, options: &UploadOptions, stream: &str) -> StreamWriter<C> {
+        StreamWriter {
+            client,
+            group: options.group.clone(),
+            stream: stream.to_string(),
+            token: None,
+            retry: options.retry.clone(),
+            limit: options.rate_limit.clone(),
+            on_rejected: options.on_rejected,
+            group_setup: options.group_setup.clone(),
+            progress: options.progress.clone(),
+        }
+    }
+
+    /// Start adding to a stream in `options.group` that's likely been written to
+    /// before, carrying on from its sequence token
+    ///
+    /// A stream that isn't there is created (as is its log group, with
+    /// --create-group).
+    pub async fn append(
+        client: C,
+        options: &UploadOptions,
+        stream: &str,
+    ) -> Result<StreamWriter<C>, Error> {
+        let mut writer = StreamWriter::new(client, options, stream);
+        match writer.fetch_token().await {
+            Ok(()) => info!("Appending to log stream: {}", stream),
+            Err(Error::ResourceNotFoundException(_)) => {
+                let created = group::open_stream(
+                    &writer.client,
+                    &writer.group,
+                    stream,
+                    &writer.retry,
+                    &writer.group_setup,
+                )
+                .await;
+                match created {
+                    Ok(()) => info!("Created new log stream: {}", stream),
+                    // Something else created it in the meantime
+                    Err(Error::ResourceAlreadyExistsException(_)) => writer.fetch_token().await?,
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(writer)
+    }
+
+    /// Carry on from the sequence token of a stream that's already been written to,
+    /// so the first batch sent doesn't have to be turned down for lacking it
+    pub async fn fetch_token(&mut self) -> Result<(), Error> {
+        self.token = self
+            .retry
+            .run("DescribeLogStreams", || {
+                self.client.sequence_token(&self.group, &self.stream)
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Send one batch of events, which has to fit in a single PutLogEvents call
+    ///
+    /// Events CloudWatch Logs rejects for their time are counted in the summary, and
+    /// dealt with by the --on-rejected policy: sent again once with the nearest
+    /// timestamp it accepts, or failing the batch.
+    pub async fn write_batch(
+        &mut self,
+        events: Vec<InputLogEvent>,
+        summary: &mut UploadSummary,
+    ) -> Result<(), error::Error> {
+        let Some(rejected) = self.send(&events, summary).await? else {
+            return Ok(());
+        };
+        let reasons = rejections(&rejected, events.len());
+        let counts = count_rejections(&reasons);
+        summary.events_rejected.add(&counts);
+
+        match self.on_rejected {
+            OnRejected::Report => Ok(()),
+            OnRejected::Fail => Err(error::Error::Rejected(counts.total())),
+            OnRejected::ClampRetry => {
+                let resend = clamp_rejected(events, &reasons, now_millis());
+                summary.events_resent += resend.len();
+                let count = resend.len();
+                if let Some(rejected) = self.send(&resend, summary).await? {
+                    // Sent the once; CloudWatch Logs' clock must be far from ours
+                    let again = count_rejections(&rejections(&rejected, count));
+                    warn!(
+                        "CloudWatch Logs rejected {} events again after they were given the nearest timestamp accepted",
+                        again.total()
+                    );
+                    summary.events_rejected.add(&again);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Send one batch of events, returning what CloudWatch Logs rejected of it
+    ///
+    /// Calls wait their turn under the rate limit, and those that fail for reasons
+    /// likely to pass, like throttling, are retried by the retry policy.  If
+    /// something else has written to the stream since the last batch, the token is
+    /// stale and CloudWatch Logs says which one it expected, so the batch is sent
+    /// again with that (a few times at most, in case it keeps happening).  If the log
+    /// group has gone, it's created again along with the stream (once, with
+    /// --create-group).  A batch CloudWatch Logs says it already has (from an earlier
+    /// attempt that seemed to fail, but didn't) counts as sent.
+    async fn send(
+        &mut self,
+        events: &[InputLogEvent],
+        summary: &mut UploadSummary,
+    ) -> Result<Option<RejectedLogEventsInfo>, Error> {
+        let size = batch::batch_size(events);
+        let mut retries = 0;
+        let mut recreated = false;
+        loop {
+            let sent = self
+                .retry
+                .run("PutLogEvents", || async {
+                    // Every attempt counts against the rate limit, retries included
+                    self.limit.wait(size).await;
+                    self.client
+                        .put_events(
+                            &self.group,
+                            &self.stream,
+                            events.to_vec(),
+                            self.token.clone(),
+                        )
+                        .await
+                })
+                .await;
+            match sent {
+                Ok(accepted) => {
+                    self.token = accepted.next_token;
+                    return Ok(accepted.rejected);
+                }
+                Err(Error::InvalidSequenceTokenException(e)) if retries < MAX_TOKEN_RETRIES => {
+                    retries += 1;
+                    summary.sequence_token_recoveries += 1;
+                    self.token = e.expected_sequence_token().map(str::to_string);
+                }
+                Err(Error::ResourceNotFoundException(_))
+                    if self.group_setup.create && !recreated =>
+                {
+                    recreated = true;
+                    group::recreate(
+                        &self.client,
+                        &self.group,
+                        &self.stream,
+                        &self.retry,
+                        &self.group_setup,
+                    )
+                    .await?;
+                    self.token = None;
+                }
+                Err(Error::DataAlreadyAcceptedException(e)) => {
+                    info!("A batch was already accepted by an earlier attempt");
+                    self.token = e.expected_sequence_token().map(str::to_string);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Send events in as many batches as it takes
+    ///
+    /// The batches are sent one after another.  If one fails, the error says how
+    /// many made it before it.
+    pub async fn write(
+        &mut self,
+        events: Vec<InputLogEvent>,
+        summary: &mut UploadSummary,
+    ) -> Result<(), error::Error> {
+        let batches = batch::make_batches(events);
+        let count = batches.len();
+
+        for (sent, batch) in batches.into_iter().enumerate() {
+            let (events, size) = (batch.len(), batch::batch_size(&batch));
+            let bytes = crate::events::message_bytes(&batch);
+            if let Err(e) = self.write_batch(batch, summary).await {
+                error!(
+                    "Batch {} of {} ({} events, {} bytes) failed; the {} before it were sent",
+                    sent + 1,
+                    count,
+                    events,
+                    size,
+                    sent
+                );
+                return Err(e);
+            }
+            summary.batches_sent += 1;
+            self.progress.sent(bytes);
+        }
+        if count > 1 {
+            info!("Sent {} batches", count);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{not_found, rejected, stale_token, throttled, MockClient, Put};
+    use aws_sdk_cloudwatchlogs::error::DataAlreadyAcceptedException;
+    use std::sync::Mutex;
+
+    fn options() -> UploadOptions {
+        UploadOptions {
+            group: String::from("group"),
+            retry: RetryPolicy {
+                base_delay: std::time::Duration::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn events(count: usize) -> Vec<InputLogEvent> {
+        (0..count)
+            .map(|i| crate::events::build_event(i as i64, String::from("x")))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_token_threading() {
+        let mut writer = StreamWriter::new(MockClient::default(), &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        writer.write_batch(events(3), &mut summary).await.unwrap();
+
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                (None, 2),
+                (Some(String::from("token-1")), 1),
+                (Some(String::from("token-2")), 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_token() {
+        // A stream that's been written to carries on from its token
+        let client = MockClient {
+            existing_token: Some(String::from("existing")),
+            ..Default::default()
+        };
+        let mut writer = StreamWriter::new(client, &options(), "stream");
+        writer.fetch_token().await.unwrap();
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        assert_eq!(
+            *writer.client.calls.lock().unwrap(),
+            [
+                (Some(String::from("existing")), 2),
+                (Some(String::from("token-1")), 1)
+            ]
+        );
+        assert_eq!(summary.sequence_token_recoveries, 0);
+
+        // One that exists but is empty has no token yet
+        let mut writer = StreamWriter::new(MockClient::default(), &options(), "stream");
+        writer.fetch_token().await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        assert_eq!(*writer.client.calls.lock().unwrap(), [(None, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        // Found, so carried on from its token
+        let client = MockClient {
+            existing_token: Some(String::from("existing")),
+            ..Default::default()
+        };
+        let mut writer = StreamWriter::append(client, &options(), "stream")
+            .await
+            .unwrap();
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        assert_eq!(
+            *writer.client.calls.lock().unwrap(),
+            [(Some(String::from("existing")), 1)]
+        );
+        assert!(writer.client.created.lock().unwrap().is_empty());
+
+        // Not found, so created
+        let client = MockClient {
+            missing: Arc::new(Mutex::new(true)),
+            ..Default::default()
+        };
+        let mut writer = StreamWriter::append(client, &options(), "stream")
+            .await
+            .unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        assert_eq!(*writer.client.calls.lock().unwrap(), [(None, 1)]);
+        assert_eq!(
+            *writer.client.created.lock().unwrap(),
+            ["stream group/stream"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_stale_token() {
+        // Something else writes to the stream between the token being looked up and
+        // the first batch being sent
+        let client = MockClient {
+            existing_token: Some(String::from("existing")),
+            ..MockClient::failing([stale_token("newer")])
+        };
+        let mut writer = StreamWriter::append(client, &options(), "stream")
+            .await
+            .unwrap();
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+        assert_eq!(
+            *writer.client.calls.lock().unwrap(),
+            [
+                (Some(String::from("existing")), 2),
+                (Some(String::from("newer")), 2),
+                (Some(String::from("token-2")), 1)
+            ]
+        );
+        assert_eq!(summary.sequence_token_recoveries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_each_group_gets_the_same_batches() {
+        let options = UploadOptions {
+            more_groups: vec![String::from("group-2")],
+            ..options()
+        };
+        let events = events(batch::MAX_BATCH_EVENTS + 5);
+        let mut summary = UploadSummary::default();
+        let mut sent = Vec::new();
+        for options in options.each_group() {
+            let mut writer = StreamWriter::new(MockClient::default(), &options, "stream");
+            writer.write(events.clone(), &mut summary).await.unwrap();
+            let client = writer.client;
+            sent.push((
+                client.streams.lock().unwrap().clone(),
+                client.batches.lock().unwrap().clone(),
+            ));
+        }
+
+        assert_eq!(sent[0].0, ["group/stream", "group/stream"]);
+        assert_eq!(sent[1].0, ["group-2/stream", "group-2/stream"]);
+        assert_eq!(sent[0].1, sent[1].1);
+        assert_eq!(sent[0].1.concat(), events);
+    }
+
+    #[tokio::test]
+    async fn test_write_in_batches() {
+        let mut writer = StreamWriter::new(MockClient::default(), &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer
+            .write(events(batch::MAX_BATCH_EVENTS + 1), &mut summary)
+            .await
+            .unwrap();
+        // Later writes carry on from the token the last batch got back
+        writer.write(events(1), &mut summary).await.unwrap();
+
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                (None, batch::MAX_BATCH_EVENTS),
+                (Some(String::from("token-1")), 1),
+                (Some(String::from("token-2")), 1)
+            ]
+        );
+        assert_eq!(writer.token.as_deref(), Some("token-3"));
+    }
+
+    #[tokio::test]
+    async fn test_write_progress() {
+        let (progress, updates) = crate::progress::tests::recorded();
+        let options = UploadOptions {
+            progress,
+            ..options()
+        };
+        let mut writer = StreamWriter::new(MockClient::default(), &options, "stream");
+        let events = events(2 * batch::MAX_BATCH_EVENTS + 1);
+        options.progress.sending(batch::count_batches(&events));
+        let mut summary = UploadSummary::default();
+        writer.write(events, &mut summary).await.unwrap();
+        assert_eq!(summary.batches_sent, 3);
+
+        let sends = crate::progress::tests::sends(&updates.lock().unwrap());
+        assert_eq!(
+            sends,
+            [
+                (0, 3, 0),
+                (1, 3, batch::MAX_BATCH_EVENTS as u64),
+                (2, 3, 2 * batch::MAX_BATCH_EVENTS as u64),
+                (3, 3, 2 * batch::MAX_BATCH_EVENTS as u64 + 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_token() {
+        let client = MockClient::failing([stale_token("theirs")]);
+        let mut writer = StreamWriter::new(client, &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                (None, 2),
+                // The same batch again, with the token it was expecting
+                (Some(String::from("theirs")), 2),
+                (Some(String::from("token-2")), 1)
+            ]
+        );
+        assert_eq!(summary.sequence_token_recoveries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_token_gives_up() {
+        let client = MockClient::failing((0..=MAX_TOKEN_RETRIES).map(|_| stale_token("theirs")));
+        let mut writer = StreamWriter::new(client, &options(), "stream");
+        let mut summary = UploadSummary::default();
+        let err = writer
+            .write_batch(events(1), &mut summary)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            error::Error::AwsSdk(Error::InvalidSequenceTokenException(_))
+        ));
+        assert_eq!(
+            writer.client.calls.lock().unwrap().len(),
+            MAX_TOKEN_RETRIES + 1
+        );
+        assert_eq!(summary.sequence_token_recoveries, MAX_TOKEN_RETRIES);
+    }
+
+    #[tokio::test]
+    async fn test_data_already_accepted() {
+        let accepted = Error::DataAlreadyAcceptedException(
+            DataAlreadyAcceptedException::builder()
+                .expected_sequence_token("after-ours")
+                .message("The given batch of log events has already been accepted")
+                .build(),
+        );
+        let mut writer = StreamWriter::new(MockClient::failing([accepted]), &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+
+        // Not sent again, and the next batch carries on from the token it gave
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(*calls, [(None, 2), (Some(String::from("after-ours")), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_throttled() {
+        let client = MockClient::failing([throttled(), throttled()]);
+        let mut writer = StreamWriter::new(client, &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+        writer.write_batch(events(1), &mut summary).await.unwrap();
+
+        // The same batch until it's through, then on to the next with its token
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                (None, 2),
+                (None, 2),
+                (None, 2),
+                (Some(String::from("token-3")), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejections() {
+        use Rejection::*;
+        let reasons = |info| rejections(&info, 5);
+        assert_eq!(reasons(rejected(None, None, None)), [None; 5]);
+        assert_eq!(
+            reasons(rejected(Some(2), None, None)),
+            [Some(TooOld), Some(TooOld), None, None, None]
+        );
+        assert_eq!(
+            reasons(rejected(None, Some(1), None)),
+            [Some(Expired), None, None, None, None]
+        );
+        assert_eq!(
+            reasons(rejected(None, None, Some(3))),
+            [None, None, None, Some(TooNew), Some(TooNew)]
+        );
+        // Too old counts first, where the ranges overlap
+        assert_eq!(
+            reasons(rejected(Some(1), Some(3), Some(4))),
+            [
+                Some(TooOld),
+                Some(Expired),
+                Some(Expired),
+                None,
+                Some(TooNew)
+            ]
+        );
+        // Indices past the end of the batch don't add events
+        assert_eq!(reasons(rejected(Some(9), None, None)), [Some(TooOld); 5]);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_reported() {
+        let client = MockClient::scripted([Put::Rejected(rejected(Some(1), Some(2), Some(4)))]);
+        let mut writer = StreamWriter::new(client, &options(), "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(5), &mut summary).await.unwrap();
+
+        assert_eq!(
+            summary.events_rejected,
+            RangeCounts {
+                too_old: 1,
+                past_retention: 1,
+                too_new: 1
+            }
+        );
+        assert_eq!(writer.client.calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_fail() {
+        let options = UploadOptions {
+            on_rejected: OnRejected::Fail,
+            ..options()
+        };
+        let client = MockClient::scripted([Put::Rejected(rejected(None, None, Some(4)))]);
+        let mut writer = StreamWriter::new(client, &options, "stream");
+        let mut summary = UploadSummary::default();
+        let err = writer
+            .write_batch(events(5), &mut summary)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, error::Error::Rejected(1)), "{:?}", err);
+        assert_eq!(summary.events_rejected.too_new, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_clamp_retry() {
+        let options = UploadOptions {
+            on_rejected: OnRejected::ClampRetry,
+            ..options()
+        };
+        // Rejected again the second time, which isn't tried a third
+        let client = MockClient::scripted([
+            Put::Rejected(rejected(Some(1), None, Some(3))),
+            Put::Rejected(rejected(Some(1), None, None)),
+        ]);
+        let mut writer = StreamWriter::new(client, &options, "stream");
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(4), &mut summary).await.unwrap();
+
+        // Just the rejected events are sent again, with the token from the first send
+        let calls = writer.client.calls.lock().unwrap();
+        assert_eq!(*calls, [(None, 4), (Some(String::from("token-1")), 2)]);
+        let batches = writer.client.batches.lock().unwrap();
+        let now = now_millis();
+        let (old, new) = (&batches[1][0], &batches[1][1]);
+        assert_eq!(old.message(), Some("x"));
+        let old = old.timestamp.unwrap();
+        assert!(
+            old > now - crate::events::MAX_EVENT_AGE
+                && old < now - crate::events::MAX_EVENT_AGE / 2
+        );
+        let new = new.timestamp.unwrap();
+        assert!(new > now && new < now + crate::events::MAX_EVENT_LEAD);
+
+        assert_eq!(summary.events_resent, 2);
+        assert_eq!(summary.events_rejected.too_old, 2);
+        assert_eq!(summary.events_rejected.too_new, 1);
+    }
+
+    #[test]
+    fn test_clamp_expired() {
+        let now = now_millis();
+        let reasons = [Some(Rejection::TooOld), Some(Rejection::Expired), None];
+        let clamped = clamp_rejected(events(3), &reasons, now);
+        // Retention isn't known, but it's at least a day
+        assert_eq!(clamped.len(), 2);
+        let day_ago = now - crate::events::DAY + crate::events::EVENT_AGE_MARGIN;
+        assert!(clamped.iter().all(|event| event.timestamp == Some(day_ago)));
+    }
+
+    #[tokio::test]
+    async fn test_group_gone_while_writing() {
+        let options = UploadOptions {
+            group_setup: GroupSetup {
+                create: true,
+                ..Default::default()
+            },
+            ..options()
+        };
+        let mut writer = StreamWriter::new(MockClient::failing([not_found()]), &options, "stream");
+        writer.token = Some(String::from("token-0"));
+        let mut summary = UploadSummary::default();
+        writer.write_batch(events(2), &mut summary).await.unwrap();
+
+        // Created again from scratch, so the batch goes without a token
+        assert_eq!(
+            *writer.client.created.lock().unwrap(),
+            ["group group", "stream group/stream"]
+        );
+        assert_eq!(
+            *writer.client.calls.lock().unwrap(),
+            [(Some(String::from("token-0")), 2), (None, 2)]
+        );
+
+        // But only once a batch
+        writer
+            .client
+            .script
+            .lock()
+            .unwrap()
+            .extend([Put::Failed(not_found()), Put::Failed(not_found())]);
+        assert!(writer.write_batch(events(1), &mut summary).await.is_err());
+    }
+}
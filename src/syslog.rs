@@ -0,0 +1,454 @@
+//! Forwarding events to a syslog server, like an rsyslog relay, instead of CloudWatch
+//! Logs, each as an RFC 5424 message over UDP, TCP or TLS
+
+use crate::naming;
+use crate::records::{CallFailed, Limits, PutRecords};
+
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// What messages say sent them
+pub const APP_NAME: &str = "rusty_axe";
+
+/// The priority of every message: the user facility, at the informational level
+const PRIORITY: u8 = 8 + 6;
+
+/// The longest hostname a message can have
+const MAX_HOSTNAME: usize = 255;
+
+/// How messages get to the server
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// A datagram each, which may be lost on the way
+    #[default]
+    Udp,
+    /// Over a connection, each message counted out (RFC 6587)
+    Tcp,
+    /// Over a TLS connection, each message counted out (RFC 5425)
+    Tls,
+}
+
+impl Protocol {
+    /// The port servers listen on for it, unless they're told otherwise
+    fn default_port(self) -> u16 {
+        match self {
+            Protocol::Udp | Protocol::Tcp => 514,
+            Protocol::Tls => 6514,
+        }
+    }
+}
+
+/// A server, given as HOST[:PORT] (with an IPv6 address in brackets, if it has a port)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Server {
+    pub fn parse(s: &str) -> Result<Server, String> {
+        let invalid = || format!("{:?} isn't a server like logs.example.com:514", s);
+        let (host, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+                match rest {
+                    "" => (host, None),
+                    rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            }
+            None => match s.rsplit_once(':') {
+                // More than one colon is an IPv6 address without a port
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (s, None),
+            },
+        };
+        let port = match port {
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) if port > 0 => Some(port),
+                _ => return Err(invalid()),
+            },
+            None => None,
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Server {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// Where syslog messages go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogOptions {
+    pub server: Server,
+    pub protocol: Protocol,
+}
+
+impl SyslogOptions {
+    fn port(&self) -> u16 {
+        self.server.port.unwrap_or(self.protocol.default_port())
+    }
+}
+
+impl fmt::Display for SyslogOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.server.host.contains(':') {
+            true => write!(f, "[{}]:{}", self.server.host, self.port()),
+            false => write!(f, "{}:{}", self.server.host, self.port()),
+        }
+    }
+}
+
+/// A way to the server
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// A syslog server, and the connection to it (made when it's first needed, and made
+/// again if the server drops it)
+pub struct SyslogServer {
+    options: SyslogOptions,
+    connect_timeout: Duration,
+    hostname: String,
+    procid: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SyslogServer {
+    pub fn new(options: &SyslogOptions, connect_timeout: Duration) -> SyslogServer {
+        let hostname = naming::hostname()
+            .map(|name| {
+                name.chars()
+                    .filter(char::is_ascii_graphic)
+                    .take(MAX_HOSTNAME)
+                    .collect()
+            })
+            .filter(|name: &String| !name.is_empty())
+            .unwrap_or_else(|| String::from("-"));
+        SyslogServer {
+            options: options.clone(),
+            connect_timeout,
+            hostname,
+            procid: std::process::id().to_string(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> io::Result<Connection> {
+        let host = self.options.server.host.as_str();
+        let address = tokio::net::lookup_host((host, self.options.port()))
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+        if self.options.protocol == Protocol::Udp {
+            let local = match address.is_ipv4() {
+                true => "0.0.0.0:0",
+                false => "[::]:0",
+            };
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(address).await?;
+            return Ok(Connection::Udp(socket));
+        }
+        let tcp = tokio::time::timeout(self.connect_timeout, TcpStream::connect(address))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out connecting"))??;
+        if self.options.protocol == Protocol::Tcp {
+            return Ok(Connection::Tcp(tcp));
+        }
+        let name = ServerName::try_from(host)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let tls = tls_connector()?.connect(name, tcp).await?;
+        Ok(Connection::Tls(Box::new(tls)))
+    }
+}
+
+impl PutRecords for SyslogServer {
+    // Only how much is sent again when the connection drops
+    const LIMITS: Limits = Limits {
+        records: 500,
+        bytes: 1024 * 1024,
+    };
+
+    fn describe(&self) -> String {
+        format!("syslog server {}", self.options)
+    }
+
+    fn record(&self, event: InputLogEvent) -> Vec<u8> {
+        message(&event, &self.hostname, &self.procid).into_bytes()
+    }
+
+    /// Send each record, in order
+    ///
+    /// If the connection fails part way through, the whole batch is sent again over a
+    /// new one, so the server can see some messages twice.
+    async fn put_records(&self, records: Vec<Vec<u8>>) -> Result<Vec<Option<String>>, CallFailed> {
+        let failed = |e: io::Error| CallFailed {
+            message: e.to_string(),
+            transient: true,
+        };
+        let mut connection = self.connection.lock().await;
+        let dropped = match connection.as_ref() {
+            Some(Connection::Tcp(tcp)) => dropped(tcp).await,
+            Some(Connection::Tls(tls)) => dropped(tls.get_ref().0).await,
+            _ => false,
+        };
+        if dropped {
+            *connection = None;
+        }
+        if connection.is_none() {
+            *connection = Some(self.connect().await.map_err(failed)?);
+        }
+        let count = records.len();
+        let sent = match connection.as_mut().expect("connected") {
+            Connection::Udp(socket) => send_datagrams(socket, records).await,
+            Connection::Tcp(tcp) => send_frames(tcp, records).await,
+            Connection::Tls(tls) => send_frames(tls.as_mut(), records).await,
+        };
+        if let Err(e) = sent {
+            *connection = None;
+            return Err(failed(e));
+        }
+        Ok(vec![None; count])
+    }
+}
+
+/// An event as an RFC 5424 message, without structured data
+fn message(event: &InputLogEvent, hostname: &str, procid: &str) -> String {
+    let time = event
+        .timestamp()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .unwrap_or_else(Utc::now);
+    format!(
+        "<{}>1 {} {} {} {} - - {}",
+        PRIORITY,
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        hostname,
+        APP_NAME,
+        procid,
+        event.message().unwrap_or_default()
+    )
+}
+
+/// A message with its length in front, as messages are framed over connections
+fn frame(record: &[u8]) -> Vec<u8> {
+    let mut frame = format!("{} ", record.len()).into_bytes();
+    frame.extend_from_slice(record);
+    frame
+}
+
+async fn send_datagrams(socket: &UdpSocket, records: Vec<Vec<u8>>) -> io::Result<()> {
+    for record in records {
+        socket.send(&record).await?;
+    }
+    Ok(())
+}
+
+async fn send_frames(
+    stream: &mut (impl AsyncWriteExt + Unpin),
+    records: Vec<Vec<u8>>,
+) -> io::Result<()> {
+    for record in records {
+        stream.write_all(&frame(&record)).await?;
+    }
+    stream.flush().await
+}
+
+/// Whether the server has closed the connection, as far as can be told without
+/// waiting (it's otherwise only found out when sending to it fails)
+async fn dropped(tcp: &TcpStream) -> bool {
+    let mut byte = [0];
+    let peeked = tokio::time::timeout(Duration::ZERO, tcp.peek(&mut byte)).await;
+    matches!(peeked, Ok(Ok(0) | Err(_)))
+}
+
+/// Something to make TLS connections with, trusting the certificates the system does
+fn tls_connector() -> io::Result<TlsConnector> {
+    Ok(TlsConnector::from(Arc::new(tls_config()?)))
+}
+
+/// How to make TLS connections, trusting the certificates the system does
+pub fn tls_config() -> io::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_native_certs::load_native_certs()? {
+        // Ones rustls can't make sense of are no use to it anyway
+        let _ = roots.add(&rustls::Certificate(certificate.0));
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::build_event;
+    use crate::records;
+    use crate::retry::RetryPolicy;
+    use crate::uploader::UploadSummary;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn server(port: u16, protocol: Protocol) -> SyslogServer {
+        let options = SyslogOptions {
+            server: Server::parse(&format!("127.0.0.1:{}", port)).unwrap(),
+            protocol,
+        };
+        SyslogServer::new(&options, Duration::from_secs(1))
+    }
+
+    /// The message in a frame read from a connection, or None once it's closed
+    async fn read_frame(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Option<String> {
+        let mut length = Vec::new();
+        reader.read_until(b' ', &mut length).await.ok()?;
+        let length: usize = std::str::from_utf8(&length).ok()?.trim().parse().ok()?;
+        let mut message = vec![0; length];
+        reader.read_exact(&mut message).await.ok()?;
+        String::from_utf8(message).ok()
+    }
+
+    #[test]
+    fn test_message() {
+        let event = build_event(1_700_000_000_000, String::from("started"));
+        assert_eq!(
+            message(&event, "web-1", "4242"),
+            "<14>1 2023-11-14T22:13:20.000Z web-1 rusty_axe 4242 - - started"
+        );
+        assert_eq!(frame(b"started"), b"7 started");
+    }
+
+    #[test]
+    fn test_parse_server() {
+        let server = |s| Server::parse(s).map(|server| (server.host, server.port));
+        assert_eq!(
+            server("relay:1514"),
+            Ok((String::from("relay"), Some(1514)))
+        );
+        assert_eq!(server("relay"), Ok((String::from("relay"), None)));
+        assert_eq!(server("[::1]:514"), Ok((String::from("::1"), Some(514))));
+        assert_eq!(server("::1"), Ok((String::from("::1"), None)));
+        assert!(server("relay:0").is_err());
+        assert!(server("relay:syslog").is_err());
+        assert!(server(":514").is_err());
+
+        let options = |protocol| SyslogOptions {
+            server: Server::parse("::1").unwrap(),
+            protocol,
+        };
+        assert_eq!(options(Protocol::Udp).to_string(), "[::1]:514");
+        assert_eq!(options(Protocol::Tls).to_string(), "[::1]:6514");
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let to = server(socket.local_addr().unwrap().port(), Protocol::Udp);
+        let mut summary = UploadSummary::default();
+        records::send(
+            &to,
+            records::for_messages(&to, &["a", "b"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let mut datagram = [0; 1024];
+            let length = socket.recv(&mut datagram).await.unwrap();
+            received.push(String::from_utf8(datagram[..length].to_vec()).unwrap());
+        }
+        assert!(received[0].starts_with("<14>1 2023-11-14T22:13:20.000Z "));
+        assert!(received[0].contains(" rusty_axe "));
+        assert!(received[0].ends_with(" - - a"));
+        assert!(received[1].ends_with(" - - b"));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let to = server(listener.local_addr().unwrap().port(), Protocol::Tcp);
+        let mut summary = UploadSummary::default();
+
+        // The first connection is dropped once the first messages are read
+        let messages = ["first\nwith a second line", "second"];
+        records::send(
+            &to,
+            records::for_messages(&to, &messages),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let first = read_frame(&mut reader).await.unwrap();
+        assert!(
+            first.ends_with(" - - first\nwith a second line"),
+            "{}",
+            first
+        );
+        assert!(read_frame(&mut reader)
+            .await
+            .unwrap()
+            .ends_with(" - - second"));
+        drop(reader);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // So the next are sent over a new one
+        records::send(
+            &to,
+            records::for_messages(&to, &["third"]),
+            &RetryPolicy::immediate(),
+            &mut summary,
+        )
+        .await
+        .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        assert!(read_frame(&mut reader)
+            .await
+            .unwrap()
+            .ends_with(" - - third"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // Nothing listens on a port that was just let go of
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let to = server(port, Protocol::Tcp);
+        let mut summary = UploadSummary::default();
+        let policy = RetryPolicy {
+            max_retries: 1,
+            ..RetryPolicy::immediate()
+        };
+        assert!(records::send(
+            &to,
+            records::for_messages(&to, &["a"]),
+            &policy,
+            &mut summary
+        )
+        .await
+        .is_err());
+        assert_eq!(summary.records_retried, 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Filling in text like `{instance_id}-{filename}` with values known at upload time
+
+/// Text with `{name}` variables in it, checked against the names allowed when parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+impl Template {
+    /// Parse a template, whose variables all have to be among `names`
+    ///
+    /// A name ending in `:` (like `tag:`) allows any variable starting with it (like
+    /// `{tag:Name}`).  `{{` and `}}` stand for literal braces.
+    pub fn parse(s: &str, names: &[&str]) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("{:?} has a {{ that isn't closed", s)),
+                        }
+                    }
+                    let allowed = |allowed: &&str| match allowed.strip_suffix(':') {
+                        Some(_) => name.len() > allowed.len() && name.starts_with(allowed),
+                        None => name == *allowed,
+                    };
+                    if !names.iter().any(allowed) {
+                        return Err(format!(
+                            "{{{}}} isn't a variable that can be used here (the ones that can are {})",
+                            name,
+                            names
+                                .iter()
+                                .map(|name| match name.ends_with(':') {
+                                    true => format!("{{{}<name>}}", name),
+                                    false => format!("{{{}}}", name),
+                                })
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Variable(name));
+                }
+                '}' => return Err(format!("{:?} has a }} without a {{ before it", s)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Whether the variable `name` is in the template
+    pub fn uses(&self, name: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Variable(variable) if variable == name))
+    }
+
+    /// The variables in the template
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Variable(name) => Some(name.as_str()),
+            Part::Text(_) => None,
+        })
+    }
+
+    /// Fill in the variables, each with the value given for it (or nothing, if it
+    /// isn't given one)
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Variable(name) => values
+                    .iter()
+                    .find(|(variable, _)| variable == name)
+                    .map_or("", |(_, value)| value),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let names = ["host", "file"];
+        let values = [("host", "web-1"), ("file", "syslog")];
+        let render = |s| Template::parse(s, &names).unwrap().render(&values);
+        assert_eq!(render("{host}/{file}"), "web-1/syslog");
+        assert_eq!(render("logs"), "logs");
+        assert_eq!(render(""), "");
+        assert_eq!(render("{{host}} is {host}"), "{host} is web-1");
+        assert_eq!(render("{file}{file}"), "syslogsyslog");
+        // A variable without a value is left empty
+        let template = Template::parse("{host}-{file}", &names).unwrap();
+        assert_eq!(template.render(&[("host", "web-1")]), "web-1-");
+
+        let template = Template::parse("{{file}} {host}", &names).unwrap();
+        assert!(template.uses("host"));
+        assert!(!template.uses("file"));
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = Template::parse("{hostname}", &["host", "file"]).unwrap_err();
+        assert!(err.contains("{hostname}"), "{}", err);
+        assert!(err.contains("{host}, {file}"), "{}", err);
+        assert!(Template::parse("{host", &["host"]).is_err());
+        assert!(Template::parse("host}", &["host"]).is_err());
+    }
+
+    #[test]
+    fn test_prefixed_variables() {
+        let names = ["host", "tag:"];
+        let template = Template::parse("{tag:Name}/{host}/{tag:Service}", &names).unwrap();
+        let variables: Vec<_> = template.variables().collect();
+        assert_eq!(variables, ["tag:Name", "host", "tag:Service"]);
+        let values = [
+            ("tag:Name", "web"),
+            ("host", "web-1"),
+            ("tag:Service", "shop"),
+        ];
+        assert_eq!(template.render(&values), "web/web-1/shop");
+
+        // The prefix has to have something after it
+        let err = Template::parse("{tag:}", &names).unwrap_err();
+        assert!(err.contains("{tag:<name>}"), "{}", err);
+        assert!(Template::parse("{tags}", &names).is_err());
+    }
+}
@@ -0,0 +1,772 @@
+//! Finding out when a line was logged from the line itself
+
+use crate::events::{EpochUnit, MAX_EVENT_AGE, MAX_EVENT_LEAD};
+use crate::uploader::UploadSummary;
+use crate::zone::{Placed, Zone};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use regex::bytes::Regex;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+/// Timestamp layouts recognised anywhere in a line, most specific first
+///
+/// * ISO 8601 / RFC 3339: `2024-05-01T14:25:00.123Z`, `2024-05-01 14:25:00,123+0200`
+/// * Apache and nginx access logs: `01/May/2024:14:25:00 +0000`
+/// * syslog, which leaves out the year: `May  1 14:25:00`
+fn patterns() -> &'static [(Layout, Regex)] {
+    static PATTERNS: OnceLock<Vec<(Layout, Regex)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            (
+                Layout::Iso8601,
+                r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+            ),
+            (
+                Layout::Apache,
+                r"\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}",
+            ),
+            (Layout::Syslog, r"^[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}"),
+        ]
+        .into_iter()
+        .map(|(layout, pattern)| (layout, Regex::new(pattern).unwrap()))
+        .collect()
+    })
+}
+
+/// Timestamp layouts --timestamp auto looks for, in the order they're tried
+///
+/// Each is at the start of the line, apart from the Apache access log layout, which
+/// comes after the client's address.  The timestamp itself is the first group.
+fn prefixes() -> &'static [(Layout, Regex)] {
+    static PREFIXES: OnceLock<Vec<(Layout, Regex)>> = OnceLock::new();
+    PREFIXES.get_or_init(|| {
+        [
+            (
+                Layout::Rfc3339,
+                r"^\[?(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2}))",
+            ),
+            (
+                Layout::Iso8601,
+                r"^\[?(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?)",
+            ),
+            (
+                Layout::Syslog,
+                r"^([A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2})",
+            ),
+            (
+                Layout::Apache,
+                r"^\S+ \S+ \S+ \[(\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4})\]",
+            ),
+            (Layout::EpochMillis, r"^(\d{13})\b"),
+            (Layout::EpochSeconds, r"^(\d{10}(?:\.\d+)?)\b"),
+        ]
+        .into_iter()
+        .map(|(layout, pattern)| (layout, Regex::new(pattern).unwrap()))
+        .collect()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    Rfc3339,
+    Iso8601,
+    Apache,
+    Syslog,
+    EpochSeconds,
+    EpochMillis,
+}
+
+impl Layout {
+    /// What the layout is called, with an example, for telling the user
+    fn describe(&self) -> &'static str {
+        match self {
+            Layout::Rfc3339 => "RFC 3339 (2024-05-01T14:25:00Z)",
+            Layout::Iso8601 => "ISO 8601 (2024-05-01 14:25:00)",
+            Layout::Apache => "Apache access log ([01/May/2024:14:25:00 +0000])",
+            Layout::Syslog => "syslog (May  1 14:25:00)",
+            Layout::EpochSeconds => "seconds since the epoch (1714573500)",
+            Layout::EpochMillis => "milliseconds since the epoch (1714573500000)",
+        }
+    }
+}
+
+/// Look for a timestamp in a line, in any of the layouts logs commonly use
+///
+/// Timestamps without a time zone are taken to be UTC.
+pub fn find(line: &[u8]) -> Option<DateTime<Utc>> {
+    patterns().iter().find_map(|(layout, pattern)| {
+        let found = pattern.find(line)?;
+        let text = std::str::from_utf8(found.as_bytes()).ok()?;
+        parse(*layout, text)
+    })
+}
+
+/// Parse a timestamp that's known to be in `layout`
+fn parse(layout: Layout, text: &str) -> Option<DateTime<Utc>> {
+    match layout {
+        Layout::Rfc3339 => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        Layout::Iso8601 => {
+            let text = text.replacen(' ', "T", 1).replace(',', ".");
+            if let Ok(time) = DateTime::parse_from_rfc3339(&text) {
+                return Some(time.with_timezone(&Utc));
+            }
+            if let Ok(time) = DateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f%z") {
+                return Some(time.with_timezone(&Utc));
+            }
+            NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|time| Utc.from_utc_datetime(&time))
+        }
+        Layout::Apache => DateTime::parse_from_str(text, "%d/%b/%Y:%H:%M:%S %z")
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        Layout::Syslog => {
+            let text = format!("{} {}", Utc::now().year(), text);
+            NaiveDateTime::parse_from_str(&text, "%Y %b %e %H:%M:%S")
+                .ok()
+                .map(|time| Utc.from_utc_datetime(&time))
+        }
+        Layout::EpochSeconds => {
+            let seconds = text.parse::<f64>().ok()?;
+            Utc.timestamp_millis_opt((seconds * 1000.0).round() as i64)
+                .single()
+        }
+        Layout::EpochMillis => Utc.timestamp_millis_opt(text.parse().ok()?).single(),
+    }
+}
+
+/// Where event timestamps come from, other than the lines' own --timestamp-format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Whichever common layout the lines' timestamps turn out to be in
+    Auto,
+    /// When the file was last modified
+    Mtime,
+    /// A given time, in milliseconds since the epoch
+    At(i64),
+}
+
+/// Parse a --timestamp source
+///
+/// Besides `auto` and `mtime`, that's an RFC 3339 timestamp or milliseconds since
+/// the epoch, which has to be a time CloudWatch Logs will accept events from.
+pub fn parse_source(s: &str) -> Result<Source, String> {
+    parse_source_at(s, Utc::now().timestamp_millis())
+}
+
+/// Parse a --timestamp source, as if it were now `now`
+fn parse_source_at(s: &str, now: i64) -> Result<Source, String> {
+    let millis = match s {
+        "auto" => return Ok(Source::Auto),
+        "mtime" => return Ok(Source::Mtime),
+        _ if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => s.parse().ok(),
+        _ => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|time| time.timestamp_millis()),
+    };
+    let millis = millis.ok_or_else(|| {
+        format!(
+            "{:?} isn't a timestamp source (use auto, mtime, an RFC 3339 timestamp like \
+             2024-04-30T22:15:00Z or milliseconds since the epoch)",
+            s
+        )
+    })?;
+
+    if millis < now - MAX_EVENT_AGE {
+        return Err(format!(
+            "{} is more than 14 days ago, and CloudWatch Logs rejects events that old",
+            s
+        ));
+    }
+    if millis > now + MAX_EVENT_LEAD {
+        return Err(format!(
+            "{} is more than 2 hours from now, and CloudWatch Logs rejects events that far ahead",
+            s
+        ));
+    }
+    Ok(Source::At(millis))
+}
+
+/// Works out which layout a file's timestamps are in, for --timestamp auto
+///
+/// The first few lines are checked against each layout in turn, and the first to
+/// match is used for the rest of the file.  If none of the first few lines match any
+/// of them, no timestamps are read at all.
+#[derive(Debug, Default)]
+pub struct AutoFormat {
+    layout: Option<Layout>,
+    lines_checked: usize,
+}
+
+impl AutoFormat {
+    /// How many lines are checked before giving up on finding a layout
+    const LINES_TO_CHECK: usize = 10;
+
+    /// Find the line's timestamp, in the layout found for the file
+    pub fn parse(&mut self, line: &[u8]) -> Option<DateTime<Utc>> {
+        match self.layout {
+            Some(layout) => parse_prefix(layout, line),
+            None if self.lines_checked < Self::LINES_TO_CHECK => {
+                self.lines_checked += 1;
+                let (layout, time) = prefixes()
+                    .iter()
+                    .find_map(|(layout, _)| Some((*layout, parse_prefix(*layout, line)?)))?;
+                info!("Timestamps look like {}", layout.describe());
+                self.layout = Some(layout);
+                Some(time)
+            }
+            None => {
+                if self.lines_checked == Self::LINES_TO_CHECK {
+                    self.lines_checked += 1;
+                    warn!(
+                        "No timestamps recognised in the first {} lines, using the time they were read",
+                        Self::LINES_TO_CHECK
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Parse the timestamp a line starts with, if it's in `layout`
+fn parse_prefix(layout: Layout, line: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, pattern) = prefixes().iter().find(|(l, _)| *l == layout)?;
+    let found = pattern.captures(line)?.get(1)?;
+    parse(layout, std::str::from_utf8(found.as_bytes()).ok()?)
+}
+
+/// Parse a --since or --until bound, either a timestamp or a time ago like `30m`
+///
+/// Relative times count back from now in seconds (`s`), minutes (`m`), hours (`h`)
+/// or days (`d`).
+pub fn parse_bound(s: &str) -> Result<DateTime<Utc>, String> {
+    let relative = s
+        .len()
+        .checked_sub(1)
+        .and_then(|split| Some((s[..split].parse::<i64>().ok()?, &s[split..])));
+    if let Some((count, unit)) = relative {
+        let ago = match unit {
+            "s" => Duration::seconds(count),
+            "m" => Duration::minutes(count),
+            "h" => Duration::hours(count),
+            "d" => Duration::days(count),
+            _ => return Err(format!("{:?} has an unknown unit (use s, m, h or d)", s)),
+        };
+        return Ok(Utc::now() - ago);
+    }
+
+    find(s.as_bytes())
+        .ok_or_else(|| format!("{:?} isn't a timestamp like 2024-05-01T14:25:00Z or 30m", s))
+}
+
+/// Check that a --timestamp-format is something chrono understands
+pub fn parse_format(s: &str) -> Result<String, String> {
+    if s.trim().is_empty() || StrftimeItems::new(s).any(|item| item == Item::Error) {
+        return Err(format!(
+            "{:?} isn't a valid timestamp format (like \"%Y-%m-%d %H:%M:%S\")",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// Reads timestamps out of lines in a layout given as a chrono format string
+///
+/// The timestamp is whatever `regex` matches (or its first group, if it has one).
+/// Without a regex it's at the start of the line, taking up as many words as the
+/// format does.  Formats without a year, like syslog's `%b %e %H:%M:%S`, are taken
+/// to be this year, and times without a zone are taken to be in `zone` (or UTC).
+#[derive(Debug, Clone)]
+pub struct LineFormat {
+    format: String,
+    regex: Option<regex::Regex>,
+    words: usize,
+    zone: Option<Zone>,
+    /// The unit of a timestamp given as a number since the epoch, for the "epoch" format
+    epoch: Option<EpochUnit>,
+}
+
+impl LineFormat {
+    pub fn new(format: &str, regex: Option<regex::Regex>, zone: Option<Zone>) -> LineFormat {
+        LineFormat {
+            format: format.to_string(),
+            regex,
+            words: format.split_whitespace().count(),
+            zone,
+            epoch: None,
+        }
+    }
+
+    /// Read timestamps given as a number of seconds (or smaller units) since the epoch
+    pub fn epoch(unit: EpochUnit, regex: Option<regex::Regex>) -> LineFormat {
+        LineFormat {
+            format: String::from("epoch"),
+            regex,
+            words: 1,
+            zone: None,
+            epoch: Some(unit),
+        }
+    }
+
+    /// Find the line's timestamp, if it has one in this layout
+    ///
+    /// Times that fall in a daylight saving change are counted in `summary`.
+    pub fn parse(&self, line: &str, summary: &mut UploadSummary) -> Option<DateTime<Utc>> {
+        let text = match &self.regex {
+            Some(regex) => {
+                let captures = regex.captures(line)?;
+                captures.get(1).or_else(|| captures.get(0))?.as_str()
+            }
+            None => leading_words(line, self.words),
+        };
+        if let Some(unit) = self.epoch {
+            let millis = epoch_millis(text.trim().parse().ok()?, unit)?;
+            return Utc.timestamp_millis_opt(millis).single();
+        }
+        let time = parse_with_format(text.trim(), &self.format)?;
+        let naive = match time {
+            Written::Zoned(time) => return Some(time),
+            Written::Naive(naive) => naive,
+        };
+        let zone = match &self.zone {
+            Some(zone) => zone,
+            None => return Some(Utc.from_utc_datetime(&naive)),
+        };
+        match zone.place(&naive) {
+            Placed::Exact(time) => Some(time),
+            Placed::Ambiguous(time) => {
+                summary.timestamps_ambiguous += 1;
+                Some(time)
+            }
+            Placed::Skipped(time) => {
+                summary.timestamps_skipped += 1;
+                Some(time)
+            }
+        }
+    }
+}
+
+/// A timestamp as it was logged, with or without its zone
+enum Written {
+    Zoned(DateTime<Utc>),
+    Naive(NaiveDateTime),
+}
+
+/// Read a number of seconds, milliseconds or microseconds since the epoch as
+/// milliseconds
+///
+/// With [`EpochUnit::Auto`] the unit is worked out from how big the number is: seconds
+/// won't pass 10^11 until the year 5138, nor milliseconds 10^14.
+pub fn epoch_millis(number: f64, unit: EpochUnit) -> Option<i64> {
+    const LARGEST_SECONDS: f64 = 1e11;
+    const LARGEST_MILLIS: f64 = 1e14;
+
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    let unit = match unit {
+        EpochUnit::Auto if number < LARGEST_SECONDS => EpochUnit::S,
+        EpochUnit::Auto if number < LARGEST_MILLIS => EpochUnit::Ms,
+        EpochUnit::Auto => EpochUnit::Us,
+        unit => unit,
+    };
+    let millis = match unit {
+        EpochUnit::S => number * 1000.0,
+        EpochUnit::Us => number / 1000.0,
+        _ => number,
+    };
+    Some(millis.round() as i64)
+}
+
+/// The start of a line, up to the end of its `words`th word
+fn leading_words(line: &str, words: usize) -> &str {
+    let mut seen = 0;
+    let mut in_word = false;
+    for (i, c) in line.char_indices() {
+        if !c.is_whitespace() {
+            in_word = true;
+        } else if in_word {
+            seen += 1;
+            in_word = false;
+            if seen == words {
+                return &line[..i];
+            }
+        }
+    }
+    line
+}
+
+/// Parse a timestamp in a chrono format, with or without a zone, date or year
+fn parse_with_format(text: &str, format: &str) -> Option<Written> {
+    if let Ok(time) = DateTime::parse_from_str(text, format) {
+        return Some(Written::Zoned(time.with_timezone(&Utc)));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+        return Some(Written::Naive(time));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(text, format) {
+        return Some(Written::Naive(date.and_hms(0, 0, 0)));
+    }
+
+    let text = format!("{} {}", Utc::now().year(), text);
+    NaiveDateTime::parse_from_str(&text, &format!("%Y {}", format))
+        .ok()
+        .map(Written::Naive)
+}
+
+/// Keeps lines logged between --since and --until
+///
+/// Lines without a timestamp of their own (like the rest of a stack trace) are
+/// taken to have been logged at the same time as the last line that had one.
+/// Lines before the first timestamp can't be placed, so they're left out.
+pub struct TimeWindow {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+impl TimeWindow {
+    pub fn new(since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> TimeWindow {
+        TimeWindow {
+            since,
+            until,
+            last_seen: None,
+        }
+    }
+
+    /// Is the window open at both ends, letting every line through?
+    pub fn is_open(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Should this line be uploaded?  Lines outside the window are counted in the summary.
+    pub fn keep(&mut self, line: &[u8], summary: &mut UploadSummary) -> bool {
+        if self.is_open() {
+            return true;
+        }
+        if let Some(time) = find(line) {
+            self.last_seen = Some(time);
+        }
+
+        let inside = self.last_seen.is_some_and(|time| {
+            self.since.is_none_or(|since| time >= since)
+                && self.until.is_none_or(|until| time <= until)
+        });
+        if !inside {
+            summary.lines_outside_window += 1;
+        }
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+    use std::path::Path;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_find_iso8601() {
+        let expected = Some(utc("2024-05-01T14:25:00Z"));
+        assert_eq!(find(b"2024-05-01T14:25:00Z INFO started"), expected);
+        assert_eq!(find(b"2024-05-01 14:25:00 INFO started"), expected);
+        assert_eq!(find(b"[2024-05-01T16:25:00+02:00] started"), expected);
+        assert_eq!(find(b"2024-05-01T16:25:00+0200 started"), expected);
+        assert_eq!(
+            find(b"2024-05-01 14:25:00,250 INFO started"),
+            Some(utc("2024-05-01T14:25:00.250Z"))
+        );
+    }
+
+    #[test]
+    fn test_find_other_layouts() {
+        assert_eq!(
+            find(b"10.0.0.1 - - [01/May/2024:16:25:00 +0200] \"GET / HTTP/1.1\" 200"),
+            Some(utc("2024-05-01T14:25:00Z"))
+        );
+
+        let syslog = find(b"May  1 14:25:00 web-1 sshd[812]: Accepted publickey").unwrap();
+        assert_eq!((syslog.month(), syslog.day()), (5, 1));
+        assert_eq!(syslog.year(), Utc::now().year());
+    }
+
+    #[test]
+    fn test_find_nothing() {
+        assert_eq!(find(b"    at com.example.Main.run(Main.java:42)"), None);
+        assert_eq!(find(b"version 2024-05-01"), None);
+        assert_eq!(find(b"2024-13-45T99:99:99Z is nonsense"), None);
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        assert_eq!(
+            parse_bound("2024-05-01T14:25:00Z"),
+            Ok(utc("2024-05-01T14:25:00Z"))
+        );
+
+        let ago = Utc::now() - parse_bound("30m").unwrap();
+        assert!((Duration::minutes(29)..Duration::minutes(31)).contains(&ago));
+        let ago = Utc::now() - parse_bound("2d").unwrap();
+        assert!((Duration::hours(47)..Duration::hours(49)).contains(&ago));
+
+        assert!(parse_bound("30y").is_err());
+        assert!(parse_bound("yesterday").is_err());
+        assert!(parse_bound("").is_err());
+    }
+
+    #[test]
+    fn test_auto_format() {
+        let expected = Some(utc("2024-05-01T14:25:00Z"));
+        let lines: [(&[u8], Layout); 7] = [
+            (b"2024-05-01T16:25:00+02:00 INFO started", Layout::Rfc3339),
+            (b"[2024-05-01T14:25:00Z] started", Layout::Rfc3339),
+            (b"2024-05-01 14:25:00,000 INFO started", Layout::Iso8601),
+            (
+                b"10.0.0.1 - frank [01/May/2024:14:25:00 +0000] \"GET / HTTP/1.1\" 200",
+                Layout::Apache,
+            ),
+            (b"1714573500000 started", Layout::EpochMillis),
+            (b"1714573500 started", Layout::EpochSeconds),
+            (b"1714573500.000 started", Layout::EpochSeconds),
+        ];
+        for (line, layout) in lines {
+            let mut auto = AutoFormat::default();
+            assert_eq!(auto.parse(line), expected, "{:?}", line);
+            assert_eq!(auto.layout, Some(layout));
+        }
+
+        let mut auto = AutoFormat::default();
+        assert!(auto
+            .parse(b"May  1 14:25:00 web-1 sshd[812]: started")
+            .is_some());
+        assert_eq!(auto.layout, Some(Layout::Syslog));
+        // Once a layout's found, it's the only one tried
+        assert_eq!(auto.parse(b"2024-05-01T14:25:00Z started"), None);
+    }
+
+    #[test]
+    fn test_auto_format_gives_up() {
+        let mut auto = AutoFormat::default();
+        for _ in 0..AutoFormat::LINES_TO_CHECK {
+            assert_eq!(auto.parse(b"no timestamp here"), None);
+        }
+        assert_eq!(auto.parse(b"2024-05-01T14:25:00Z too late"), None);
+        assert_eq!(auto.layout, None);
+
+        // Lines before the first timestamp don't stop it being found
+        let mut auto = AutoFormat::default();
+        assert_eq!(auto.parse(b"starting up"), None);
+        assert!(auto.parse(b"2024-05-01T14:25:00Z started").is_some());
+    }
+
+    #[test]
+    fn test_parse_source() {
+        let now = utc("2024-05-01T14:25:00Z").timestamp_millis();
+        assert_eq!(parse_source_at("auto", now), Ok(Source::Auto));
+        assert_eq!(parse_source_at("mtime", now), Ok(Source::Mtime));
+        assert_eq!(
+            parse_source_at("2024-04-30T22:15:00Z", now),
+            Ok(Source::At(1714515300000))
+        );
+        assert_eq!(
+            parse_source_at("2024-05-01T00:15:00+02:00", now),
+            Ok(Source::At(1714515300000))
+        );
+        assert_eq!(
+            parse_source_at("1714515300000", now),
+            Ok(Source::At(1714515300000))
+        );
+
+        assert_eq!(
+            parse_source_at("2024-04-01T00:00:00Z", now),
+            Err(String::from(
+                "2024-04-01T00:00:00Z is more than 14 days ago, and CloudWatch Logs rejects \
+                 events that old"
+            ))
+        );
+        assert_eq!(
+            parse_source_at("1714584300000", now),
+            Err(String::from(
+                "1714584300000 is more than 2 hours from now, and CloudWatch Logs rejects \
+                 events that far ahead"
+            ))
+        );
+        assert!(parse_source_at("yesterday", now)
+            .unwrap_err()
+            .starts_with("\"yesterday\" isn't a timestamp source"));
+        assert!(parse_source_at("2024-04-30 22:15:00", now).is_err());
+        assert!(parse_source_at("", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert!(parse_format("%Y-%m-%dT%H:%M:%S%.f%:z").is_ok());
+        assert!(parse_format("%b %e %H:%M:%S").is_ok());
+        assert!(parse_format("%Q").is_err());
+        assert!(parse_format(" ").is_err());
+    }
+
+    #[test]
+    fn test_line_format() {
+        let mut summary = UploadSummary::default();
+        let iso = LineFormat::new("%Y-%m-%dT%H:%M:%S%.f%:z", None, None);
+        assert_eq!(
+            iso.parse("2024-05-01T16:25:00.5+02:00 INFO started", &mut summary),
+            Some(utc("2024-05-01T14:25:00.5Z"))
+        );
+        assert_eq!(
+            iso.parse("    at Main.run(Main.java:42)", &mut summary),
+            None
+        );
+
+        let naive = LineFormat::new("%Y-%m-%d %H:%M:%S", None, None);
+        assert_eq!(
+            naive.parse("2024-05-01 14:25:00 INFO started", &mut summary),
+            Some(utc("2024-05-01T14:25:00Z"))
+        );
+        let date = LineFormat::new("%d.%m.%Y", None, None);
+        assert_eq!(
+            date.parse("01.05.2024 rotated", &mut summary),
+            Some(utc("2024-05-01T00:00:00Z"))
+        );
+
+        let syslog = LineFormat::new("%b %e %H:%M:%S", None, None)
+            .parse(
+                "May  1 14:25:00 web-1 sshd[812]: Accepted publickey",
+                &mut summary,
+            )
+            .unwrap();
+        assert_eq!((syslog.month(), syslog.day(), syslog.hour()), (5, 1, 14));
+        assert_eq!(syslog.year(), Utc::now().year());
+
+        let bracketed = LineFormat::new(
+            "%d/%b/%Y:%H:%M:%S %z",
+            Some(regex::Regex::new(r"\[([^]]+)\]").unwrap()),
+            None,
+        );
+        assert_eq!(
+            bracketed.parse(
+                "10.0.0.1 - - [01/May/2024:16:25:00 +0200] \"GET /\"",
+                &mut summary
+            ),
+            Some(utc("2024-05-01T14:25:00Z"))
+        );
+        assert_eq!(bracketed.parse("no brackets", &mut summary), None);
+        assert_eq!(summary.timestamps_ambiguous + summary.timestamps_skipped, 0);
+    }
+
+    #[test]
+    fn test_epoch_millis() {
+        let auto = |number| epoch_millis(number, EpochUnit::Auto);
+        assert_eq!(auto(1714573500.0), Some(1714573500000));
+        assert_eq!(auto(1714573500.25), Some(1714573500250));
+        assert_eq!(auto(1714573500250.0), Some(1714573500250));
+        assert_eq!(auto(1714573500250123.0), Some(1714573500250));
+        assert_eq!(auto(-1.0), None);
+        assert_eq!(auto(f64::NAN), None);
+
+        // Small numbers are only milliseconds or microseconds when told so
+        assert_eq!(epoch_millis(86400.0, EpochUnit::S), Some(86400000));
+        assert_eq!(epoch_millis(86400.0, EpochUnit::Ms), Some(86400));
+        assert_eq!(epoch_millis(86400.0, EpochUnit::Us), Some(86));
+    }
+
+    #[test]
+    fn test_line_format_epoch() {
+        let mut summary = UploadSummary::default();
+        let epoch = LineFormat::epoch(EpochUnit::Auto, None);
+        for line in [
+            "1714573500 started",
+            "1714573500.000 started",
+            "1714573500000 started",
+            "1714573500000000 started",
+        ] {
+            assert_eq!(
+                epoch.parse(line, &mut summary),
+                Some(utc("2024-05-01T14:25:00Z")),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            epoch.parse("    at Main.run(Main.java:42)", &mut summary),
+            None
+        );
+
+        let bracketed =
+            LineFormat::epoch(EpochUnit::Ms, Some(regex::Regex::new(r"ts=(\d+)").unwrap()));
+        assert_eq!(
+            bracketed.parse("level=info ts=1714573500250 msg=started", &mut summary),
+            Some(utc("2024-05-01T14:25:00.25Z"))
+        );
+    }
+
+    #[test]
+    fn test_line_format_in_zone() {
+        let sydney = Zone::load(Path::new("tests/fixtures/zoneinfo/Australia/Sydney"));
+        let layout = LineFormat::new("%Y-%m-%d %H:%M:%S", None, sydney.clone());
+        let mut summary = UploadSummary::default();
+        let mut parse = |line| layout.parse(line, &mut summary);
+
+        assert_eq!(
+            parse("2024-05-01 14:25:00 winter"),
+            Some(utc("2024-05-01T04:25:00Z"))
+        );
+        assert_eq!(
+            parse("2024-01-15 09:00:00 summer"),
+            Some(utc("2024-01-14T22:00:00Z"))
+        );
+        // The clocks went back from 03:00 to 02:00, so this happened twice
+        assert_eq!(
+            parse("2024-04-07 02:30:00 twice"),
+            Some(utc("2024-04-06T15:30:00Z"))
+        );
+        // The clocks went forward from 02:00 to 03:00, so this never happened
+        assert_eq!(
+            parse("2024-10-06 02:30:00 never"),
+            Some(utc("2024-10-05T16:30:00Z"))
+        );
+        assert_eq!(
+            (summary.timestamps_ambiguous, summary.timestamps_skipped),
+            (1, 1)
+        );
+
+        // Times with their own zone keep it
+        let layout = LineFormat::new("%Y-%m-%d %H:%M:%S%#z", None, sydney);
+        assert_eq!(
+            layout.parse("2024-04-07 02:30:00Z", &mut summary),
+            Some(utc("2024-04-07T02:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_time_window() {
+        let mut window = TimeWindow::new(
+            Some(utc("2024-05-01T14:25:00Z")),
+            Some(utc("2024-05-01T14:40:00Z")),
+        );
+        let mut summary = UploadSummary::default();
+
+        let lines: [(&[u8], bool); 6] = [
+            (b"no timestamp yet", false),
+            (b"2024-05-01T14:24:59Z before", false),
+            (b"2024-05-01T14:25:00Z on the edge", true),
+            (b"    continuation", true),
+            (b"2024-05-01T14:40:01Z after", false),
+            (b"    continuation", false),
+        ];
+        for (line, expected) in lines {
+            assert_eq!(window.keep(line, &mut summary), expected, "{:?}", line);
+        }
+        assert_eq!(summary.lines_outside_window, 4);
+    }
+}
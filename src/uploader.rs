@@ -0,0 +1,1556 @@
+//! Sending events where they're going, and summing up how it went
+
+use crate::events::{
+    check_event_times, clock_millis, message_bytes, BinaryPolicy, OutOfRange, DAY,
+};
+#[cfg(feature = "otlp")]
+use crate::otlp;
+use crate::stream::CwlApi;
+use crate::{
+    batch, dump, endpoints, error, exit, firehose, group, kinesis, metadata, metric, naming,
+    notify, plan, profile, progress, rate, records, region, retry, s3, splunk, state, stream,
+    syslog, template, verify,
+};
+
+use aws_config::RetryConfig;
+use aws_sdk_cloudwatchlogs::model::InputLogEvent;
+use aws_sdk_cloudwatchlogs::{Client as CWL_Client, Endpoint, Error};
+use aws_types::credentials::SharedCredentialsProvider;
+use aws_types::region::Region;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Send the events that were collected, then report how the run went
+///
+/// Events logged at times CloudWatch Logs won't accept are dealt with first, by the
+/// out-of-range policy, if that's where they're going.  The state file (if there is
+/// one) is only moved on for files whose events have been accepted, so nothing is
+/// missed next time if the upload fails, and the metric (with --emit-metric) is only
+/// published if it all succeeded.  The --notify-sns topic is told how it went either
+/// way.  If anything went wrong along the way, the error returned says which
+/// failure it amounts to.
+pub async fn upload(
+    options: &UploadOptions,
+    mut streams: Vec<StreamEvents>,
+    mut summary: UploadSummary,
+    state: Option<state::StateFile>,
+    started: Instant,
+) -> Result<(), error::Error> {
+    if options.sink == Sink::CloudWatchLogs {
+        summary.log_groups = std::iter::once(&options.group)
+            .chain(&options.more_groups)
+            .cloned()
+            .collect();
+    }
+    summary.region = options.client.region.as_ref().map(ToString::to_string);
+    let now = clock_millis()?;
+    // Every retention period is at least a day, so younger events can't be past it
+    let retention_days = match streams
+        .iter()
+        .flat_map(|stream| &stream.events)
+        .any(|e| e.timestamp.is_some_and(|time| time < now - DAY))
+    {
+        true if options.sink == Sink::CloudWatchLogs && options.dry_run.is_none() => {
+            shortest_retention(options).await
+        }
+        _ => None,
+    };
+    let streams_to_check = match options.sink {
+        Sink::CloudWatchLogs | Sink::File(_) => &mut streams[..],
+        _ => &mut [],
+    };
+    for stream in streams_to_check {
+        if let Err(e) = check_event_times(
+            &mut stream.events,
+            options.out_of_range,
+            now,
+            retention_days,
+            &mut summary,
+        ) {
+            if let Some(notify) = &options.notify {
+                notify::publish(options, notify, &summary, Some(e.to_string())).await;
+            }
+            return Err(e);
+        }
+    }
+    streams.retain(|stream| !stream.events.is_empty());
+    let shipped = metric::Shipped::of(&streams);
+    let unsent = if streams.is_empty() {
+        warn!("Nothing to send");
+        Vec::new()
+    } else {
+        match &options.sink {
+            Sink::CloudWatchLogs => send_logs(options, streams, &mut summary).await?,
+            Sink::Kinesis(kinesis) => {
+                let to = kinesis::KinesisStream::new(options, kinesis).await;
+                records::send_streams(options, &to, streams, &mut summary).await
+            }
+            Sink::Firehose(name) => {
+                let to = firehose::DeliveryStream::new(options, name).await;
+                records::send_streams(options, &to, streams, &mut summary).await
+            }
+            Sink::S3(s3) => {
+                let to = s3::Bucket::new(options, s3).await;
+                s3::send_streams(options, s3, &to, streams, &mut summary).await
+            }
+            Sink::Syslog(syslog) => {
+                let to = syslog::SyslogServer::new(syslog, options.client.timeouts.connect);
+                records::send_streams(options, &to, streams, &mut summary).await
+            }
+            Sink::Splunk(splunk) => {
+                splunk::send_streams(options, splunk, streams, &mut summary).await
+            }
+            #[cfg(feature = "otlp")]
+            Sink::Otlp(endpoint) => {
+                otlp::send_streams(options, endpoint, streams, &mut summary).await
+            }
+            Sink::File(path) => dump::send_streams(path, streams, &mut summary),
+        }
+    };
+
+    // A dry run sent nothing, so leaves every file where it was
+    if let Some(mut state) = state.filter(|_| options.dry_run.is_none()) {
+        for (path, offset) in &summary.final_offsets {
+            // A stream for every file that failed leaves them all where they were
+            if !unsent
+                .iter()
+                .any(|file| file.as_ref().is_none_or(|file| file == path))
+            {
+                state.advance(path, *offset);
+            }
+        }
+        state
+            .save()
+            .map_err(|e| error::Error::Other(format!("Couldn't save the state file: {}", e)))?;
+    }
+
+    if let (Some(metric), true) = (&options.metric, summary.is_success()) {
+        metric::publish(options, metric, shipped).await;
+    }
+    if let Some(notify) = &options.notify {
+        notify::publish(options, notify, &summary, None).await;
+    }
+
+    summary.duration = started.elapsed();
+    match (options.output, options.dry_run) {
+        (Output::Text, _) => summary.report(),
+        // The plan is all that's printed, for scripts to read
+        (Output::Json, Some(_)) => (),
+        (Output::Json, None) => println!("{}", summary.json()),
+    }
+    if let Some(failure) = summary.failure() {
+        return Err(error::Error::Unsent(failure));
+    }
+
+    Ok(())
+}
+
+/// Where and how events are sent
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// Where events are sent, when it isn't the log groups
+    pub sink: Sink,
+    /// The log group to send them to
+    pub group: String,
+    /// Other log groups to send the same events to
+    pub more_groups: Vec<String>,
+    /// The log stream to send them to, if it isn't to be a new one with a name of our
+    /// own
+    pub stream: Option<String>,
+    /// How to name new log streams, if not the default way
+    pub stream_template: Option<template::Template>,
+    /// Whether to add to the log stream if it's there already
+    pub append: bool,
+    /// What to do with events logged at times CloudWatch Logs won't accept
+    pub out_of_range: OutOfRange,
+    /// What to do with events CloudWatch Logs rejects for their time anyway
+    pub on_rejected: OnRejected,
+    /// Whether to create the log group if it doesn't exist, and how to set it up
+    pub group_setup: group::GroupSetup,
+    /// Tags for the log group, before their values are filled in for each stream
+    pub tags: Vec<group::Tag>,
+    /// How the instance metadata is asked what stream names and tags say about the
+    /// instance
+    pub imds: metadata::ImdsOptions,
+    /// How calls to CloudWatch Logs that fail are retried
+    pub retry: retry::RetryPolicy,
+    /// How clients for CloudWatch Logs are made
+    pub client: ClientOptions,
+    /// How fast events can be sent
+    pub rate_limit: Arc<rate::RateLimit>,
+    /// How many log streams to upload to at once
+    pub concurrency: usize,
+    /// How to check the events landed once they're sent, if they're to be
+    pub verify: Option<verify::VerifyOptions>,
+    /// How to print what would be sent, if it's only a dry run
+    pub dry_run: Option<Output>,
+    /// How to print the summary of the run
+    pub output: Output,
+    /// Where to publish how much was sent, if anywhere
+    pub metric: Option<metric::MetricOptions>,
+    /// Where to say how the run went, if anywhere
+    pub notify: Option<notify::NotifyOptions>,
+    /// Where to say how many batches have been sent
+    pub progress: progress::Progress,
+}
+
+/// How clients for CloudWatch Logs are made
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// How long calls can take
+    pub timeouts: retry::Timeouts,
+    /// The credentials to send with, if not the profile's or else the ones found
+    /// the usual way
+    pub credentials: Option<SharedCredentialsProvider>,
+    /// The region to send to, if it's been found already
+    pub region: Option<Region>,
+    /// The profile in the AWS config files to send with, if not the credentials
+    /// found the usual way
+    pub profile: Option<String>,
+    /// Where to send calls, if not where the environment says (or else AWS's usual
+    /// endpoint)
+    pub endpoint: Option<Endpoint>,
+    /// Which of CloudWatch Logs's endpoints to call, when it's not given as a URL
+    pub variant: endpoints::Variant,
+    /// Not to ask the instance metadata for the region or credentials
+    pub no_imds: bool,
+}
+
+impl UploadOptions {
+    /// A copy of the options for each log group the events go to, sending to that
+    /// one alone
+    pub(crate) fn each_group(&self) -> Vec<UploadOptions> {
+        let groups = std::iter::once(&self.group).chain(&self.more_groups);
+        groups
+            .map(|group| UploadOptions {
+                group: group.clone(),
+                more_groups: Vec::new(),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Events bound for a log stream of their own
+#[derive(Debug)]
+pub struct StreamEvents {
+    /// The file they were read from, if each file has its own stream (None for events
+    /// from every file at once)
+    pub file: Option<String>,
+    /// The events, in the order they're to be sent
+    pub events: Vec<InputLogEvent>,
+}
+
+impl StreamEvents {
+    /// Events from every input, all going to the one stream
+    pub fn merged(events: Vec<InputLogEvent>) -> StreamEvents {
+        StreamEvents { file: None, events }
+    }
+}
+
+/// Tally of what happened during a run, reported once everything is done
+///
+/// `--output json` prints it as it is, along with a few totals worked out from it.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadSummary {
+    /// The log groups sent to, if the events went to CloudWatch Logs
+    pub log_groups: Vec<String>,
+    /// The region sent to, if it was AWS's
+    pub region: Option<String>,
+    /// How long the run took, up to the summary
+    #[serde(with = "seconds")]
+    pub duration: Duration,
+    /// Files that were read successfully
+    pub files_read: Vec<String>,
+    /// Files that were deliberately left out, along with the reason why
+    pub files_skipped: Vec<(String, String)>,
+    /// Files that couldn't be read, along with the reason why
+    pub files_failed: Vec<(String, String)>,
+    /// Files that looked binary, what was done with them, and how many bytes were read
+    pub binary_files: Vec<(String, BinaryPolicy, u64)>,
+    /// How many bytes of input were read
+    pub bytes_read: u64,
+    /// How long was spent reading input
+    #[serde(with = "seconds")]
+    pub read_time: Duration,
+    /// How far into each file reading got, for picking up from there with --start-offset
+    pub final_offsets: Vec<(String, u64)>,
+    /// Lines (or records, when lines are grouped) picked out of the input to be sent,
+    /// before any are sampled out or given up on
+    pub lines_selected: usize,
+    /// Lines that had invalid UTF-8 swapped for replacement characters
+    pub lines_replaced: usize,
+    /// Lines left out because they weren't valid UTF-8
+    pub lines_skipped_encoding: usize,
+    /// Lines left out because they matched --grep-v
+    pub lines_excluded: usize,
+    /// Lines left out because they were logged before --since or after --until
+    pub lines_outside_window: usize,
+    /// Lines that didn't fit the --format
+    pub lines_invalid: usize,
+    /// Lines without a timestamp of their own, given the one before them
+    pub lines_without_timestamp: usize,
+    /// Timestamps logged during a daylight saving change, taken as the earlier time
+    pub timestamps_ambiguous: usize,
+    /// Timestamps logged in a daylight saving gap, moved forward past it
+    pub timestamps_skipped: usize,
+    /// Events outside the times CloudWatch Logs accepts that were given the nearest
+    /// timestamp it does
+    pub events_clamped: RangeCounts,
+    /// Events outside the times CloudWatch Logs accepts that were left out
+    pub events_skipped: RangeCounts,
+    /// Events left sharing a timestamp, as there were too many to space out
+    pub events_sharing_timestamp: usize,
+    /// Batches sent again because something else had written to the log stream
+    pub sequence_token_recoveries: usize,
+    /// Events CloudWatch Logs rejected for their time, despite the out-of-range policy
+    pub events_rejected: RangeCounts,
+    /// Rejected events sent again with the nearest timestamp accepted
+    pub events_resent: usize,
+    /// Records Kinesis or Firehose failed to put that were put again
+    pub records_retried: usize,
+    /// Log streams every event was sent to, along with how many there were
+    pub streams_sent: Vec<(String, usize)>,
+    /// The bytes of the messages of every event sent (once for each stream)
+    pub bytes_sent: usize,
+    /// The calls it took to send them
+    pub batches_sent: usize,
+    /// Log streams that events couldn't be sent to, along with the reason why
+    pub streams_failed: Vec<(String, String)>,
+    /// How sending to each of those failed, where that's known
+    pub stream_failures: Vec<exit::Failure>,
+    /// How many times each --redact pattern (or --mask preset) replaced something
+    pub redactions: Vec<(String, usize)>,
+    /// Lines left out by sampling
+    pub lines_sampled_out: usize,
+    /// Lines left out to keep under --max-bytes
+    pub lines_capped: usize,
+    /// How many bytes those lines were
+    pub bytes_capped: usize,
+    /// Lines too long for one event that were sent as several
+    pub lines_split: usize,
+    /// Lines too long for one event that had their end cut off
+    pub lines_truncated: usize,
+    /// Lines too long for one event that were left out
+    pub lines_dropped_oversize: usize,
+}
+
+/// (De)serializing a duration as a number of seconds, as --output json has them
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How many events fell outside the times CloudWatch Logs accepts, by which limit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeCounts {
+    /// Older than 14 days
+    pub too_old: usize,
+    /// Older than the log group's retention period
+    pub past_retention: usize,
+    /// More than 2 hours ahead of now
+    pub too_new: usize,
+}
+
+impl RangeCounts {
+    /// How many events were outside each limit, with the limit described
+    pub(crate) fn by_limit(&self) -> [(usize, &'static str); 3] {
+        [
+            (self.too_old, "older than 14 days"),
+            (self.past_retention, "older than the log group's retention"),
+            (self.too_new, "more than 2 hours ahead"),
+        ]
+    }
+
+    /// Add another set of counts to these
+    pub(crate) fn add(&mut self, other: &RangeCounts) {
+        self.too_old += other.too_old;
+        self.past_retention += other.past_retention;
+        self.too_new += other.too_new;
+    }
+
+    /// How many events there are in all
+    pub(crate) fn total(&self) -> usize {
+        self.too_old + self.past_retention + self.too_new
+    }
+
+    pub(crate) fn report(&self, outcome: &str) {
+        for (count, limit) in self.by_limit() {
+            if count > 0 {
+                warn!("Events {} {}: {}", limit, outcome, count);
+            }
+        }
+    }
+}
+
+impl UploadSummary {
+    /// Did every part of the run go to plan?
+    pub fn is_success(&self) -> bool {
+        self.files_failed.is_empty() && self.streams_failed.is_empty()
+    }
+
+    /// How the run failed, if it did, for the status to exit with
+    ///
+    /// A stream that failed for a reason of its own (like the log group not being
+    /// there) says the most, then a file that couldn't be read.  Otherwise, failing
+    /// to send only some of it is told apart from failing to send any.
+    fn failure(&self) -> Option<exit::Failure> {
+        let rejected = self.events_rejected.total() > self.events_resent;
+        let reason = self
+            .stream_failures
+            .iter()
+            .find(|&&failure| failure != exit::Failure::Other);
+        if let Some(&failure) = reason {
+            Some(failure)
+        } else if !self.files_failed.is_empty() {
+            Some(exit::Failure::Input)
+        } else if !self.streams_failed.is_empty() && self.streams_sent.is_empty() {
+            Some(exit::Failure::Other)
+        } else if !self.streams_failed.is_empty() || rejected {
+            Some(exit::Failure::Partial)
+        } else {
+            None
+        }
+    }
+
+    /// The summary as --output json prints it, with what became of each file and the
+    /// totals scripts are likeliest to want added
+    fn json(&self) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).expect("a summary is always valid JSON");
+        let read = self.files_read.iter().map(|path| {
+            let offset = self
+                .final_offsets
+                .iter()
+                .rev()
+                .find(|(file, _)| file == path);
+            serde_json::json!({
+                "path": path,
+                "status": "read",
+                "final_offset": offset.map(|(_, offset)| offset),
+            })
+        });
+        let mut files: Vec<_> = read.collect();
+        for (status, left_out) in [
+            ("skipped", &self.files_skipped),
+            ("failed", &self.files_failed),
+        ] {
+            files.extend(left_out.iter().map(|(path, reason)| {
+                serde_json::json!({"path": path, "status": status, "reason": reason})
+            }));
+        }
+        json["files"] = files.into();
+        json["events_sent"] = self
+            .streams_sent
+            .iter()
+            .map(|(_, count)| count)
+            .sum::<usize>()
+            .into();
+        json["exit_status"] = self.failure().map_or(0, exit::Failure::code).into();
+        json
+    }
+
+    /// Print the summary for whoever is watching
+    pub(crate) fn report(&self) {
+        info!("Files read: {}", self.files_read.len());
+        info!(
+            "Bytes read: {} in {:.3}s",
+            self.bytes_read,
+            self.read_time.as_secs_f64()
+        );
+        for (path, offset) in &self.final_offsets {
+            info!("Final byte offset of {}: {}", path, offset);
+        }
+        if !self.files_skipped.is_empty() {
+            info!("Files skipped: {}", self.files_skipped.len());
+            for (path, reason) in &self.files_skipped {
+                info!("  {}: {}", path, reason);
+            }
+        }
+        for (path, policy, bytes) in &self.binary_files {
+            info!(
+                "  {}: binary, uploaded as {:?} ({} bytes)",
+                path, policy, bytes
+            );
+        }
+        if self.lines_replaced > 0 {
+            warn!("Lines with invalid UTF-8 replaced: {}", self.lines_replaced);
+        }
+        if self.lines_skipped_encoding > 0 {
+            warn!(
+                "Lines with invalid UTF-8 skipped: {}",
+                self.lines_skipped_encoding
+            );
+        }
+        if self.lines_excluded > 0 {
+            info!("Lines left out by --grep-v: {}", self.lines_excluded);
+        }
+        if self.lines_outside_window > 0 {
+            info!(
+                "Lines left out by --since/--until: {}",
+                self.lines_outside_window
+            );
+        }
+        if self.lines_invalid > 0 {
+            warn!("Lines not matching --format: {}", self.lines_invalid);
+        }
+        if self.lines_without_timestamp > 0 {
+            info!(
+                "Lines without a timestamp of their own: {}",
+                self.lines_without_timestamp
+            );
+        }
+        if self.timestamps_ambiguous > 0 {
+            warn!(
+                "Timestamps in a daylight saving change, taken as the earlier: {}",
+                self.timestamps_ambiguous
+            );
+        }
+        if self.timestamps_skipped > 0 {
+            warn!(
+                "Timestamps in a daylight saving gap, moved forward: {}",
+                self.timestamps_skipped
+            );
+        }
+        self.events_clamped
+            .report("given the nearest timestamp accepted");
+        self.events_skipped.report("left out");
+        self.events_rejected.report("rejected by CloudWatch Logs");
+        if self.events_resent > 0 {
+            warn!(
+                "Rejected events sent again with the nearest timestamp accepted: {}",
+                self.events_resent
+            );
+        }
+        if self.records_retried > 0 {
+            warn!("Records put again: {}", self.records_retried);
+        }
+        if self.sequence_token_recoveries > 0 {
+            info!(
+                "Batches sent again after something else wrote to the stream: {}",
+                self.sequence_token_recoveries
+            );
+        }
+        if self.events_sharing_timestamp > 0 {
+            warn!(
+                "Events too many to keep in order (sharing a timestamp): {}",
+                self.events_sharing_timestamp
+            );
+        }
+        for (rule, count) in &self.redactions {
+            info!("Redactions by {}: {}", rule, count);
+        }
+        if self.lines_sampled_out > 0 {
+            info!("Lines left out by sampling: {}", self.lines_sampled_out);
+        }
+        if self.lines_capped > 0 {
+            info!(
+                "Lines left out by --max-bytes: {} ({} bytes)",
+                self.lines_capped, self.bytes_capped
+            );
+        }
+        if self.lines_split > 0 {
+            info!("Oversized lines split: {}", self.lines_split);
+        }
+        if self.lines_truncated > 0 {
+            warn!("Oversized lines truncated: {}", self.lines_truncated);
+        }
+        if self.lines_dropped_oversize > 0 {
+            warn!("Oversized lines dropped: {}", self.lines_dropped_oversize);
+        }
+        if !self.files_failed.is_empty() {
+            warn!("Files failed: {}", self.files_failed.len());
+            for (path, reason) in &self.files_failed {
+                warn!("  {}: {}", path, reason);
+            }
+        }
+        if self.streams_sent.len() + self.streams_failed.len() > 1 {
+            info!("Streams sent: {}", self.streams_sent.len());
+            for (name, events) in &self.streams_sent {
+                info!("  {}: {} events", name, events);
+            }
+        }
+        if !self.streams_failed.is_empty() {
+            warn!("Streams failed: {}", self.streams_failed.len());
+            for (name, reason) in &self.streams_failed {
+                warn!("  {}: {}", name, reason);
+            }
+        }
+    }
+}
+
+/// How what's printed or sent (by --notify-sns) is laid out
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Plain text (a table, for what's listed), for reading
+    #[default]
+    #[clap(alias = "table")]
+    Text,
+    /// A JSON object, for scripts
+    Json,
+}
+
+/// Where events are sent, and how it's set up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Sink {
+    /// Log streams in the log groups
+    #[default]
+    CloudWatchLogs,
+    /// A Kinesis data stream
+    Kinesis(kinesis::KinesisOptions),
+    /// A Firehose delivery stream, by name
+    Firehose(String),
+    /// An S3 bucket, a whole object a file
+    S3(s3::S3Options),
+    /// A syslog server
+    Syslog(syslog::SyslogOptions),
+    /// A Splunk HTTP Event Collector
+    Splunk(splunk::SplunkOptions),
+    /// An OpenTelemetry collector, by its endpoint
+    #[cfg(feature = "otlp")]
+    Otlp(String),
+    /// A local file, written as the events would be sent to CloudWatch Logs
+    File(PathBuf),
+}
+
+/// What to do with events CloudWatch Logs rejects for their time, despite any
+/// out-of-range policy (as when the clock here is off)
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnRejected {
+    /// Count them in the summary, and carry on
+    #[default]
+    Report,
+    /// Give them the nearest timestamp that's accepted and send them again (once)
+    ClampRetry,
+    /// Stop sending to the stream, and exit with an error
+    Fail,
+}
+
+/// Send each set of events to a brand new log stream in the log group (and the same
+/// stream in each of the others, if there are more)
+///
+/// Up to `options.concurrency` streams are uploaded to at once, each one's batches
+/// still sent one after another.  A stream that fails doesn't stop the others, in
+/// its own log group or another; the summary says how each one went, and the files
+/// of those that failed are returned (None for a stream of every file), as they are
+/// from each of the other destinations' `send_streams`.  A dry run only prints what
+/// would be sent, calling nothing.
+///
+/// It's only an error if sending stopped short of trying every stream.
+///
+/// ```
+/// use rusty_axe::events::build_event;
+/// use rusty_axe::metadata::ImdsOptions;
+/// use rusty_axe::uploader::{self, Output, StreamEvents, UploadOptions, UploadSummary};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), rusty_axe::error::Error> {
+/// let options = UploadOptions {
+///     group: String::from("app"),
+///     stream: Some(String::from("deploy-42")),
+///     // Print what would be sent as JSON, rather than sending it
+///     dry_run: Some(Output::Json),
+///     imds: ImdsOptions {
+///         enabled: false,
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let events = vec![build_event(1_714_573_500_000, String::from("deployed"))];
+/// let mut summary = UploadSummary::default();
+/// let streams = vec![StreamEvents::merged(events)];
+/// let unsent = uploader::send_logs(&options, streams, &mut summary).await?;
+/// assert!(unsent.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn send_logs(
+    options: &UploadOptions,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Result<Vec<Option<String>>, error::Error> {
+    if let Some(output) = options.dry_run {
+        print_plan(options, streams, output).await;
+        return Ok(Vec::new());
+    }
+    // Every stream shares one client
+    let cwlogs = new_client(&options.client).await;
+    send_logs_with(options, cwlogs, streams, summary).await
+}
+
+/// Send each set of events as [`send_logs`] does, with `cwlogs` (not a dry run)
+pub(crate) async fn send_logs_with<C: CwlApi>(
+    options: &UploadOptions,
+    cwlogs: C,
+    streams: Vec<StreamEvents>,
+    summary: &mut UploadSummary,
+) -> Result<Vec<Option<String>>, error::Error> {
+    // And one lookup of the host's details
+    let host = lookup_host(options).await;
+    let permits = Arc::new(tokio::sync::Semaphore::new(options.concurrency.max(1)));
+    let groups = options.each_group();
+    let mut tasks = Vec::new();
+    for stream in streams {
+        let file = stream.file.as_deref();
+        let name = stream_name(options, &host, file, file.is_some());
+        for options in &groups {
+            let options = with_tags(options, &host, file);
+            // With more than one group, say which this stream is in
+            let label = match groups.len() {
+                1 => name.clone(),
+                _ => format!("{} in {}", name, options.group),
+            };
+            options
+                .progress
+                .sending(batch::count_batches(&stream.events));
+            let (name, events) = (name.clone(), stream.events.clone());
+            let (file, cwlogs, permits) = (stream.file.clone(), cwlogs.clone(), permits.clone());
+            tasks.push(tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("never closed");
+                let mut summary = UploadSummary::default();
+                let (count, bytes) = (events.len(), message_bytes(&events));
+                let sent = send_stream(&options, cwlogs, &name, events, &mut summary).await;
+                (label, file, count, bytes, sent, summary)
+            }));
+        }
+    }
+
+    let mut unsent = Vec::new();
+    for task in tasks {
+        let (name, file, count, bytes, sent, stream_summary) = task
+            .await
+            .map_err(|e| error::Error::Other(format!("Sending stopped short: {}", e)))?;
+        summary.sequence_token_recoveries += stream_summary.sequence_token_recoveries;
+        summary.events_rejected.add(&stream_summary.events_rejected);
+        summary.events_resent += stream_summary.events_resent;
+        summary.batches_sent += stream_summary.batches_sent;
+        match sent {
+            Ok(()) => {
+                summary.streams_sent.push((name, count));
+                summary.bytes_sent += bytes;
+            }
+            Err(e) => {
+                let reason = e.to_string();
+                error!("Couldn't send events to {}: {}", name, reason);
+                summary.streams_failed.push((name, reason));
+                summary.stream_failures.push(e.failure());
+                unsent.push(file);
+            }
+        }
+    }
+
+    Ok(unsent)
+}
+
+/// Print what would be sent to which streams, without making a client at all
+async fn print_plan(options: &UploadOptions, streams: Vec<StreamEvents>, output: Output) {
+    let host = lookup_host(options).await;
+    let streams = streams
+        .into_iter()
+        .map(|stream| plan::StreamPlan {
+            name: stream_name(
+                options,
+                &host,
+                stream.file.as_deref(),
+                stream.file.is_some(),
+            ),
+            file: stream.file,
+            events: stream.events,
+        })
+        .collect();
+    let groups = std::iter::once(&options.group).chain(&options.more_groups);
+    let plan = plan::Plan {
+        groups: groups.cloned().collect(),
+        streams,
+    };
+    match output {
+        Output::Text => print!("{}", plan.text()),
+        Output::Json => println!("{}", plan.json()),
+    }
+}
+
+/// Send events to a new stream called `name`, then read them back if --verify says to
+async fn send_stream<C: CwlApi>(
+    options: &UploadOptions,
+    cwlogs: C,
+    name: &str,
+    events: Vec<InputLogEvent>,
+    summary: &mut UploadSummary,
+) -> Result<(), error::Error> {
+    let expected = verify::Expected::new(&events);
+    let mut writer = create_log_stream(options, cwlogs.clone(), name).await?;
+    writer.write(events, summary).await?;
+    match &options.verify {
+        Some(verify) => {
+            let group = &options.group;
+            verify::verify(&cwlogs, group, name, &expected, verify, &options.retry).await?;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Look up the host's details that stream names and tags use, once for them all
+pub(crate) async fn lookup_host(options: &UploadOptions) -> naming::Host {
+    let mut templates: Vec<_> = options.tags.iter().map(|tag| &tag.value).collect();
+    // The template for a stream of its own file uses all the other one does
+    let template = naming::template(options.stream_template.as_ref(), true);
+    if options.stream.is_none() {
+        templates.push(&template);
+    }
+    host_details(options, &templates).await
+}
+
+/// Look up the host's details that `templates` use
+pub(crate) async fn host_details(
+    options: &UploadOptions,
+    templates: &[&template::Template],
+) -> naming::Host {
+    let client = &options.client;
+    // There's no instance metadata to ask when sending with a profile
+    let imds = metadata::Imds::new(metadata::ImdsOptions {
+        enabled: options.imds.enabled && client.profile.is_none(),
+        ..options.imds
+    });
+    let host = naming::Host::lookup(templates, client.region.as_ref(), &imds).await;
+    let source = host.instance_id_source;
+    if !host.instance_id.is_empty() {
+        let naming = format!(
+            "Naming streams by {}, for {{instance_id}}: {}",
+            source.describe(),
+            host.instance_id
+        );
+        // The instance's own id is what's expected, so only worth saying with -v
+        match source {
+            naming::IdSource::Imds => debug!("{}", naming),
+            _ => info!("{}", naming),
+        }
+    }
+    host
+}
+
+/// The name of the stream for events from `file` (None for every file): the --stream,
+/// or else a new one from the --stream-template
+///
+/// `own_file` says whether the stream is the file's alone, for the default template.
+pub(crate) fn stream_name(
+    options: &UploadOptions,
+    host: &naming::Host,
+    file: Option<&str>,
+    own_file: bool,
+) -> String {
+    options.stream.clone().unwrap_or_else(|| {
+        let template = naming::template(options.stream_template.as_ref(), own_file);
+        naming::stream_name(&template, host, file, Utc::now())
+    })
+}
+
+/// The options for uploading to one stream, with the --tag values filled in for it
+///
+/// `{filename}` is the name of the stream's file, or empty for a stream of every file.
+pub(crate) fn with_tags(
+    options: &UploadOptions,
+    host: &naming::Host,
+    file: Option<&str>,
+) -> UploadOptions {
+    let values = [
+        ("instance_id", host.instance_id.as_str()),
+        ("filename", file.map_or("", naming::basename)),
+        ("version", env!("CARGO_PKG_VERSION")),
+    ];
+    let mut options = options.clone();
+    options.group_setup.tags = group::render_tags(&options.tags, &values);
+    options
+}
+
+/// A CloudWatch Logs client, configured from the environment
+///
+/// The SDK's own retries are turned off, since calls are retried by our own policy.
+pub async fn new_client(options: &ClientOptions) -> CWL_Client {
+    CWL_Client::new(&sdk_config(options).await)
+}
+
+/// The configuration CloudWatch Logs clients are made with, sending with the
+/// credentials and to the endpoint in `options` if they're given (or else the ones
+/// found the usual way)
+async fn sdk_config(options: &ClientOptions) -> aws_config::SdkConfig {
+    service_config(options, "logs", "CloudWatch Logs").await
+}
+
+/// The configuration clients for `service` (like `kinesis`, called `name`) are made
+/// with, as for CloudWatch Logs
+///
+/// With -v, where calls go is said when it isn't the usual endpoint, so it can be
+/// checked.
+pub(crate) async fn service_config(
+    options: &ClientOptions,
+    service: &'static str,
+    name: &str,
+) -> aws_config::SdkConfig {
+    let region = match &options.region {
+        Some(region) => region.clone(),
+        None => region::resolve(None, options.profile.as_deref(), !options.no_imds).await,
+    };
+    let mut loader = aws_config::from_env()
+        .configure(metadata::provider_config(!options.no_imds))
+        .region(region)
+        .retry_config(RetryConfig::disabled())
+        .timeout_config(options.timeouts.config());
+    match (&options.credentials, &options.profile) {
+        (Some(credentials), _) => loader = loader.credentials_provider(credentials.clone()),
+        (None, Some(profile)) => {
+            loader = loader.credentials_provider(profile::credentials(profile))
+        }
+        (None, None) => {}
+    }
+    let resolver = endpoints::Resolver::new(service, options.endpoint.as_ref(), options.variant);
+    if let Some(resolver) = resolver.clone() {
+        loader = loader.endpoint_resolver(resolver);
+    }
+    let config = loader.load().await;
+    if let (Some(resolver), Some(region)) = (resolver, config.region()) {
+        match resolver.url(region) {
+            Ok(url) => debug!(
+                "Calling {} at {} ({} endpoint)",
+                name,
+                url,
+                resolver.describe()
+            ),
+            Err(e) => debug!("Couldn't find {}'s endpoint: {}", name, e),
+        }
+    }
+    config
+}
+
+/// The fewest days any of the log groups keeps events for, if they don't all keep
+/// them forever
+async fn shortest_retention(options: &UploadOptions) -> Option<i32> {
+    let mut shortest = None;
+    for options in options.each_group() {
+        if let Some(days) = group_retention(&options).await {
+            shortest = Some(shortest.map_or(days, |shortest: i32| shortest.min(days)));
+        }
+    }
+    shortest
+}
+
+/// How many days `options.group` keeps events for, if it doesn't keep them forever
+///
+/// A group that can't be looked up (or doesn't exist yet) is taken to keep them
+/// forever.
+async fn group_retention(options: &UploadOptions) -> Option<i32> {
+    let group = options.group.as_str();
+    let cwlogs = new_client(&options.client).await;
+    match cwlogs
+        .describe_log_groups()
+        .log_group_name_prefix(group)
+        .send()
+        .await
+    {
+        Ok(resp) => resp
+            .log_groups()?
+            .iter()
+            .find(|found| found.log_group_name() == Some(group))?
+            .retention_in_days(),
+        Err(e) => {
+            warn!(
+                "Couldn't look up the log group's retention period: {}",
+                retry::describe(&Error::from(e))
+            );
+            None
+        }
+    }
+}
+
+/// Create a new log stream called `log_stream_name` in the log group
+///
+/// Returns a writer for sending events to the stream.  If the stream's already
+/// there, the writer carries on from its sequence token.
+pub(crate) async fn create_log_stream<C: CwlApi>(
+    options: &UploadOptions,
+    cwlogs: C,
+    log_stream_name: &str,
+) -> Result<stream::StreamWriter<C>, Error> {
+    let group = &options.group;
+
+    // Posting to a log stream takes the sequence token the last post returned (except
+    // for the first time).  With --append the stream's most likely there already, so
+    // its token is looked up first; otherwise the stream's most likely new, so it's
+    // created first, and its token only looked up if it turns out to be there.
+    if options.append {
+        let appended = stream::StreamWriter::append(cwlogs, options, log_stream_name).await;
+        if let Err(e) = &appended {
+            error!("Couldn't open log stream: {}", retry::describe(e));
+        }
+        return appended;
+    }
+    let created = group::open_stream(
+        &cwlogs,
+        group,
+        log_stream_name,
+        &options.retry,
+        &options.group_setup,
+    )
+    .await;
+    match created {
+        Ok(_) => info!("Created new log stream: {}", log_stream_name),
+        Err(Error::ResourceAlreadyExistsException(_)) => {
+            info!("Adding to existing log stream: {}", log_stream_name);
+            let mut writer = stream::StreamWriter::new(cwlogs, options, log_stream_name);
+            writer.fetch_token().await?;
+            return Ok(writer);
+        }
+        Err(e) => {
+            error!("Couldn't create log stream: {}", retry::describe(&e));
+            return Err(e);
+        }
+    }
+
+    Ok(stream::StreamWriter::new(cwlogs, options, log_stream_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::tests::parse_push;
+    use crate::cli::Args;
+    use crate::events::{build_event, now_millis};
+    use crate::mock::{not_found, rejected, stale_token, throttled, MockClient, Put};
+    use crate::stub::{Response, Stub};
+    use aws_types::credentials::ProvideCredentials;
+    use clap::Parser;
+    use std::ffi::OsString;
+    use std::fs::{self};
+
+    #[test]
+    fn test_stream_name() {
+        let host = naming::Host {
+            instance_id: String::from("i-0123"),
+            ..Default::default()
+        };
+        let options = UploadOptions::default();
+        let name = stream_name(&options, &host, None, false);
+        assert!(name.starts_with("i-0123-"));
+        let name = stream_name(&options, &host, Some("/var/log/app:1*.log"), true);
+        assert!(name.starts_with("i-0123-"));
+        assert!(name.ends_with("-app_1_.log"), "{}", name);
+        assert_eq!(
+            stream_name(&options, &host, Some("-"), true)
+                .rsplit_once('-')
+                .unwrap()
+                .1,
+            ""
+        );
+
+        let options = UploadOptions {
+            stream: Some(String::from("incident-4821/syslog")),
+            ..Default::default()
+        };
+        assert_eq!(
+            stream_name(&options, &host, Some("/var/log/syslog"), true),
+            "incident-4821/syslog"
+        );
+        let options = UploadOptions {
+            stream_template: Some(naming::parse_template("{instance_id}/{filename}").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            stream_name(&options, &host, Some("/var/log/syslog"), true),
+            "i-0123/syslog"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_role_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let arn = "arn:aws:iam::123456789012:role/central-logs-writer";
+        let args = parse(&[
+            "--role-arn",
+            arn,
+            "--external-id",
+            "x",
+            "--session-name",
+            "web-1",
+        ])
+        .unwrap();
+        assert_eq!(args.role_arn.as_deref(), Some(arn));
+        assert_eq!(args.external_id.as_deref(), Some("x"));
+        assert!(parse(&["--role-arn", "central-logs-writer"]).is_err());
+        assert!(parse(&["--external-id", "x"]).is_err());
+        assert!(parse(&["--role-arn", arn, "--session-name", "has space"]).is_err());
+
+        // Clients send with the role's credentials when there's a role
+        let role = aws_sdk_cloudwatchlogs::Credentials::new("ROLE", "secret", None, None, "test");
+        let role = SharedCredentialsProvider::new(role);
+        let options = ClientOptions {
+            credentials: Some(SharedCredentialsProvider::new(role)),
+            ..Default::default()
+        };
+        let config = sdk_config(&options).await;
+        let provider = config.credentials_provider().unwrap();
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "ROLE");
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_url() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        assert!(parse(&["--endpoint-url", "http://localhost:4566"]).is_ok());
+        assert!(parse(&["--endpoint-url", "localhost:4566"]).is_err());
+        assert!(parse(&["--endpoint-url", "http://localhost:4566", "--use-fips"]).is_err());
+
+        // Clients call the endpoint given
+        let url = "https://vpce-0123.logs.us-east-1.vpce.amazonaws.com";
+        let options = ClientOptions {
+            endpoint: Some(endpoints::parse_endpoint_url(url).unwrap()),
+            ..Default::default()
+        };
+        let config = sdk_config(&options).await;
+        let resolved = config
+            .endpoint_resolver()
+            .unwrap()
+            .resolve_endpoint(&Region::new("us-east-1"))
+            .unwrap();
+        let mut uri = "/".parse().unwrap();
+        resolved.endpoint().set_endpoint(&mut uri, None);
+        assert_eq!(uri.to_string(), format!("{}/", url));
+    }
+
+    #[tokio::test]
+    async fn test_region_args() {
+        let parse = |args: &[&str]| {
+            let args = [&["rusty-axe", "push", "-g", "g"], args].concat();
+            parse_push(args.into_iter().map(OsString::from).collect())
+        };
+        let (global, _) = parse(&["--region", "eu-central-1"]).unwrap();
+        assert_eq!(global.region, Some(Region::new("eu-central-1")));
+        assert!(parse(&["--region", "Frankfurt"]).is_err());
+
+        // Clients send to the region given, whatever the environment says
+        let options = ClientOptions {
+            region: global.region,
+            ..Default::default()
+        };
+        let config = sdk_config(&options).await;
+        assert_eq!(config.region(), Some(&Region::new("eu-central-1")));
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_variant() {
+        let args = Args::try_parse_from(["rusty-axe", "-g", "g", "--use-fips", "--use-dualstack"])
+            .unwrap();
+        assert!(args.use_fips && args.use_dualstack);
+
+        // The config carries the variant, resolving it in whatever its region is
+        let options = ClientOptions {
+            variant: endpoints::Variant {
+                fips: true,
+                dualstack: false,
+            },
+            ..Default::default()
+        };
+        let config = sdk_config(&options).await;
+        let resolver = config.endpoint_resolver().unwrap();
+        let resolved = resolver
+            .resolve_endpoint(&Region::new("us-gov-west-1"))
+            .unwrap();
+        let mut uri = "/".parse().unwrap();
+        resolved.endpoint().set_endpoint(&mut uri, None);
+        assert_eq!(uri.to_string(), "https://logs.us-gov-west-1.amazonaws.com/");
+        let resolved = resolver
+            .resolve_endpoint(&Region::new("us-east-1"))
+            .unwrap();
+        let mut uri = "/".parse().unwrap();
+        resolved.endpoint().set_endpoint(&mut uri, None);
+        assert_eq!(
+            uri.to_string(),
+            "https://logs-fips.us-east-1.amazonaws.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let parse = |args: &[&str]| {
+            let args = [&["rusty-axe", "push", "-g", "g"], args].concat();
+            parse_push(args.into_iter().map(OsString::from).collect())
+        };
+        let (global, args) = parse(&["--dry-run", "--output", "json"]).unwrap();
+        assert!(args.dry_run);
+        assert_eq!(global.output, Output::Json);
+        // Without --dry-run, it's the summary that's printed as JSON
+        assert_eq!(parse(&["--output", "json"]).unwrap().0.output, Output::Json);
+        assert!(parse(&["--dry-run", "--verify"]).is_err());
+
+        // Nothing is called, not even to make the stream
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let credentials =
+            aws_sdk_cloudwatchlogs::Credentials::new("test", "test", None, None, "test");
+        let options = UploadOptions {
+            group: String::from("g"),
+            stream: Some(String::from("planned")),
+            imds: metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            client: ClientOptions {
+                credentials: Some(SharedCredentialsProvider::new(credentials)),
+                endpoint: Some(endpoints::parse_endpoint_url(&url).unwrap()),
+                ..Default::default()
+            },
+            dry_run: Some(Output::Json),
+            ..Default::default()
+        };
+        let events = vec![build_event(now_millis(), String::from("started"))];
+        let mut summary = UploadSummary::default();
+        let unsent = send_logs(&options, vec![StreamEvents::merged(events)], &mut summary)
+            .await
+            .unwrap();
+        assert!(unsent.is_empty());
+        assert!(summary.streams_sent.is_empty() && summary.is_success());
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err(), "dry run connected to CloudWatch Logs");
+    }
+
+    /// Answer every call with a 400 and `body`, as CloudWatch Logs turns one down
+    fn serve_error(body: &'static str) -> Stub {
+        Stub::serve(|_, _| {
+            Response::new(400, body).header("Content-Type", "application/x-amz-json-1.1")
+        })
+    }
+
+    #[tokio::test]
+    async fn test_sdk_error() {
+        let stub = serve_error(
+            r#"{"__type":"ResourceNotFoundException","message":"The specified log group does not exist."}"#,
+        );
+        let credentials =
+            aws_sdk_cloudwatchlogs::Credentials::new("test", "test", None, None, "test");
+        let options = UploadOptions {
+            group: String::from("missing"),
+            stream: Some(String::from("run")),
+            imds: metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            client: ClientOptions {
+                credentials: Some(SharedCredentialsProvider::new(credentials)),
+                region: Some(Region::new("us-east-1")),
+                endpoint: Some(endpoints::parse_endpoint_url(stub.url()).unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let events = || vec![build_event(now_millis(), String::from("started"))];
+
+        let client = new_client(&options.client).await;
+        let mut summary = UploadSummary::default();
+        let err = send_stream(&options, client, "run", events(), &mut summary)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                error::Error::AwsSdk(Error::ResourceNotFoundException(_))
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.failure(), exit::Failure::NotFound);
+
+        // Sending to every stream carries on, with the summary saying how this one failed
+        let streams = vec![StreamEvents::merged(events())];
+        let unsent = send_logs(&options, streams, &mut summary).await.unwrap();
+        assert_eq!(unsent, [None]);
+        assert_eq!(summary.stream_failures, [exit::Failure::NotFound]);
+        assert!(summary.streams_failed[0].1.contains("--create-group"));
+    }
+
+    /// Options for sending to `run` in `group` and `group-2` with the mock, a stream
+    /// at a time, retrying at once
+    fn mock_options() -> UploadOptions {
+        UploadOptions {
+            group: String::from("group"),
+            more_groups: vec![String::from("group-2")],
+            stream: Some(String::from("run")),
+            imds: metadata::ImdsOptions {
+                enabled: false,
+                ..Default::default()
+            },
+            retry: retry::RetryPolicy {
+                base_delay: Duration::ZERO,
+                ..Default::default()
+            },
+            concurrency: 1,
+            ..Default::default()
+        }
+    }
+
+    fn started(count: usize) -> Vec<StreamEvents> {
+        let now = now_millis();
+        let events = (0..count as i64)
+            .map(|i| build_event(now + i, format!("started {}", i)))
+            .collect();
+        vec![StreamEvents::merged(events)]
+    }
+
+    #[tokio::test]
+    async fn test_send_logs_recovers() {
+        let options = UploadOptions {
+            verify: Some(verify::VerifyOptions {
+                timeout: Duration::ZERO,
+                interval: Duration::ZERO,
+                content: true,
+            }),
+            ..mock_options()
+        };
+        // Throttled, then given a stale token, then fine
+        let client = MockClient::scripted([
+            Put::Failed(throttled()),
+            Put::Failed(stale_token("theirs")),
+            Put::Accepted,
+        ]);
+        let mut summary = UploadSummary::default();
+        let unsent = send_logs_with(&options, client.clone(), started(3), &mut summary)
+            .await
+            .unwrap();
+
+        assert!(unsent.is_empty());
+        assert!(summary.is_success());
+        assert_eq!(
+            summary.streams_sent,
+            [
+                (String::from("run in group"), 3),
+                (String::from("run in group-2"), 3)
+            ]
+        );
+        assert_eq!(summary.batches_sent, 2);
+        assert_eq!(summary.sequence_token_recoveries, 1);
+        assert_eq!(
+            *client.created.lock().unwrap(),
+            ["stream group/run", "stream group-2/run"]
+        );
+        assert_eq!(
+            *client.calls.lock().unwrap(),
+            [
+                (None, 3),
+                (None, 3),
+                (Some(String::from("theirs")), 3),
+                (None, 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_logs_failures() {
+        // The first stream's events are rejected in part, and the second's group
+        // isn't there
+        let client = MockClient::scripted([
+            Put::Rejected(rejected(Some(1), None, None)),
+            Put::Failed(not_found()),
+        ]);
+        let mut summary = UploadSummary::default();
+        let unsent = send_logs_with(&mock_options(), client, started(2), &mut summary)
+            .await
+            .unwrap();
+
+        assert_eq!(unsent, [None]);
+        assert_eq!(summary.streams_sent, [(String::from("run in group"), 2)]);
+        assert_eq!(summary.events_rejected.too_old, 1);
+        assert_eq!(summary.streams_failed[0].0, "run in group-2");
+        assert_eq!(summary.stream_failures, [exit::Failure::NotFound]);
+        assert!(!summary.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_send_logs_verify_short() {
+        // Rejected events don't land, so they can't be read back
+        let options = UploadOptions {
+            more_groups: Vec::new(),
+            verify: Some(verify::VerifyOptions {
+                timeout: Duration::ZERO,
+                interval: Duration::ZERO,
+                content: false,
+            }),
+            ..mock_options()
+        };
+        let client = MockClient::scripted([Put::Rejected(rejected(None, None, Some(2)))]);
+        let mut summary = UploadSummary::default();
+        let unsent = send_logs_with(&options, client, started(3), &mut summary)
+            .await
+            .unwrap();
+
+        assert_eq!(unsent, [None]);
+        assert!(
+            summary.streams_failed[0].1.contains("only 2 of the 3"),
+            "{:?}",
+            summary.streams_failed
+        );
+    }
+
+    #[test]
+    fn test_each_group() {
+        let args = Args::try_parse_from(["rusty-axe", "-g", "team", "-g", "security"]).unwrap();
+        assert_eq!(args.group, ["team", "security"]);
+        assert!(Args::try_parse_from(["rusty-axe", "-f", "x"]).is_err());
+
+        let options = UploadOptions {
+            group: String::from("team"),
+            more_groups: vec![String::from("security")],
+            ..Default::default()
+        };
+        let groups: Vec<_> = options
+            .each_group()
+            .into_iter()
+            .map(|options| (options.group, options.more_groups))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (String::from("team"), Vec::new()),
+                (String::from("security"), Vec::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tag_args() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from([&["rusty-axe", "-g", "g"], args].concat());
+        let args = parse(&["--tag", "team=infra", "--tag", "host={instance_id}"]).unwrap();
+        assert_eq!(args.tag.len(), 2);
+        assert!(!args.force_tags);
+        assert!(parse(&["--tag", "team"]).is_err());
+        assert!(parse(&["--tag", "host={hostname}"]).is_err());
+        assert!(parse(&["--force-tags"]).is_err());
+
+        let options = UploadOptions {
+            tags: args.tag,
+            ..Default::default()
+        };
+        let host = naming::Host {
+            instance_id: String::from("i-0123"),
+            ..Default::default()
+        };
+        let tags = with_tags(&options, &host, Some("/var/log/syslog"))
+            .group_setup
+            .tags;
+        assert_eq!(tags["team"], "infra");
+        assert_eq!(tags["host"], "i-0123");
+    }
+
+    #[test]
+    fn test_summary_with_failed_stream() {
+        let mut summary = UploadSummary::default();
+        summary.streams_sent.push((String::from("a"), 3));
+        assert!(summary.is_success());
+        summary
+            .streams_failed
+            .push((String::from("b"), String::from("access denied")));
+        assert!(!summary.is_success());
+    }
+
+    /// A summary of a run that sent two files, but not a third
+    fn sample_summary() -> UploadSummary {
+        UploadSummary {
+            log_groups: vec![String::from("app")],
+            region: Some(String::from("us-east-1")),
+            duration: Duration::from_millis(2500),
+            files_read: vec![String::from("a.log"), String::from("b.log")],
+            files_failed: vec![(String::from("c.log"), String::from("not found"))],
+            binary_files: vec![(String::from("b.log"), BinaryPolicy::Hexdump, 64)],
+            bytes_read: 1024,
+            read_time: Duration::from_millis(125),
+            final_offsets: vec![(String::from("a.log"), 960), (String::from("b.log"), 64)],
+            lines_selected: 12,
+            lines_excluded: 3,
+            events_rejected: RangeCounts {
+                too_old: 1,
+                ..Default::default()
+            },
+            streams_sent: vec![
+                (String::from("i-0123-a.log"), 8),
+                (String::from("i-0123-b.log"), 4),
+            ],
+            bytes_sent: 900,
+            batches_sent: 2,
+            redactions: vec![(String::from("email"), 2)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary_round_trip() {
+        let summary = sample_summary();
+        let json = summary.json().to_string();
+        let read: UploadSummary = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, summary);
+        let read: UploadSummary = serde_json::from_str("{}").unwrap();
+        assert_eq!(read, UploadSummary::default());
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let golden = fs::read_to_string("tests/fixtures/summary.json").unwrap();
+        let golden: serde_json::Value = serde_json::from_str(&golden).unwrap();
+        assert_eq!(
+            sample_summary().json(),
+            golden,
+            "{:#}",
+            sample_summary().json()
+        );
+    }
+
+    #[test]
+    fn test_summary_failure() {
+        let mut summary = UploadSummary::default();
+        summary.streams_sent.push((String::from("a"), 3));
+        assert_eq!(summary.failure(), None);
+        summary.events_rejected.too_old = 2;
+        summary.events_resent = 2;
+        assert_eq!(summary.failure(), None);
+        summary.events_resent = 1;
+        assert_eq!(summary.failure(), Some(exit::Failure::Partial));
+
+        let failed = |failure| {
+            let mut summary = UploadSummary::default();
+            summary
+                .streams_failed
+                .push((String::from("b"), String::from("it failed")));
+            summary.stream_failures.push(failure);
+            summary
+        };
+        assert_eq!(
+            failed(exit::Failure::Other).failure(),
+            Some(exit::Failure::Other)
+        );
+        let mut summary = failed(exit::Failure::Other);
+        summary.streams_sent.push((String::from("a"), 3));
+        assert_eq!(summary.failure(), Some(exit::Failure::Partial));
+        summary
+            .files_failed
+            .push((String::from("gone.log"), String::from("not found")));
+        assert_eq!(summary.failure(), Some(exit::Failure::Input));
+        let mut summary = failed(exit::Failure::NotFound);
+        summary
+            .files_failed
+            .push((String::from("gone.log"), String::from("not found")));
+        assert_eq!(summary.failure(), Some(exit::Failure::NotFound));
+    }
+}
@@ -0,0 +1,281 @@
+//! Reading events back after they're sent, to check they all landed
+
+use crate::retry::RetryPolicy;
+
+use aws_sdk_cloudwatchlogs::model::{InputLogEvent, OutputLogEvent};
+use aws_sdk_cloudwatchlogs::{Client as CWL_Client, Error};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How sent events are checked
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// How long to keep looking for events that haven't shown up yet, since they can
+    /// take a while to
+    pub timeout: Duration,
+    /// How long to wait between looks
+    pub interval: Duration,
+    /// Check that the first and last messages are the ones sent, too
+    pub content: bool,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> VerifyOptions {
+        VerifyOptions {
+            timeout: Duration::from_secs(30),
+            interval: Duration::from_secs(2),
+            content: false,
+        }
+    }
+}
+
+/// A page of events, and the token for the next page
+pub type EventsPage = (Vec<OutputLogEvent>, Option<String>);
+
+/// Something that can read the events in a log stream a page at a time
+pub trait GetEvents {
+    /// The page of events logged in `range`, from its start up to (not including)
+    /// its end, that `next_token` points to (the first, for None), oldest first
+    ///
+    /// The last page is the one whose next token is the one it was asked for with.
+    fn get_events(
+        &self,
+        group: &str,
+        stream: &str,
+        range: (i64, i64),
+        next_token: Option<String>,
+    ) -> impl Future<Output = Result<EventsPage, Error>> + Send;
+}
+
+impl GetEvents for CWL_Client {
+    async fn get_events(
+        &self,
+        group: &str,
+        stream: &str,
+        (start, end): (i64, i64),
+        next_token: Option<String>,
+    ) -> Result<EventsPage, Error> {
+        let resp = self
+            .get_log_events()
+            .log_group_name(group)
+            .log_stream_name(stream)
+            .start_time(start)
+            .end_time(end)
+            .start_from_head(true)
+            .set_next_token(next_token)
+            .send()
+            .await?;
+        Ok((resp.events.unwrap_or_default(), resp.next_forward_token))
+    }
+}
+
+/// What should be found in a stream once events have been sent to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expected {
+    count: usize,
+    /// When the events were logged, from the first up to (not including) just after
+    /// the last
+    range: (i64, i64),
+    first: Option<String>,
+    last: Option<String>,
+}
+
+impl Expected {
+    /// What sending `events` should leave in the stream
+    pub fn new(events: &[InputLogEvent]) -> Expected {
+        let times = events.iter().filter_map(|event| event.timestamp);
+        let start = times.clone().min().unwrap_or(0);
+        let end = times.max().unwrap_or(0) + 1;
+        let message = |event: Option<&InputLogEvent>| event.and_then(|e| e.message.clone());
+        Expected {
+            count: events.len(),
+            range: (start, end),
+            first: message(events.first()),
+            last: message(events.last()),
+        }
+    }
+}
+
+/// What's been found in the stream
+#[derive(Debug, Default)]
+struct Found {
+    count: usize,
+    first: Option<String>,
+    last: Option<String>,
+}
+
+/// Count the stream's events in the time the expected ones were logged, noting the
+/// first and last
+async fn read_back<C: GetEvents>(
+    client: &C,
+    group: &str,
+    stream: &str,
+    expected: &Expected,
+    retry: &RetryPolicy,
+) -> Result<Found, Error> {
+    let mut found = Found::default();
+    let mut next_token = None;
+    loop {
+        let (events, next) = retry
+            .run("GetLogEvents", || {
+                client.get_events(group, stream, expected.range, next_token.clone())
+            })
+            .await?;
+        if let Some(event) = events.first().filter(|_| found.count == 0) {
+            found.first = event.message.clone();
+        }
+        if let Some(event) = events.last() {
+            found.last = event.message.clone();
+        }
+        found.count += events.len();
+        match next {
+            Some(next) if next_token.as_ref() != Some(&next) => next_token = Some(next),
+            _ => return Ok(found),
+        }
+    }
+}
+
+/// Check that the events sent to a stream have all landed, looking again every so
+/// often until they have or `options.timeout` runs out
+///
+/// Only the stream's events logged in the time the sent ones were are counted, so
+/// a stream with events from before doesn't throw the count out (unless they were
+/// logged in that time too).
+pub async fn verify<C: GetEvents>(
+    client: &C,
+    group: &str,
+    stream: &str,
+    expected: &Expected,
+    options: &VerifyOptions,
+    retry: &RetryPolicy,
+) -> Result<(), Error> {
+    let deadline = Instant::now() + options.timeout;
+    let found = loop {
+        let found = read_back(client, group, stream, expected, retry).await?;
+        if found.count == expected.count || Instant::now() >= deadline {
+            break found;
+        }
+        tokio::time::sleep(options.interval).await;
+    };
+
+    let failed = |reason: String| Err(Error::Unhandled(reason.into()));
+    if found.count != expected.count {
+        return failed(format!(
+            "only {} of the {} events sent could be read back after {:?} (more may yet \
+             show up; raise --verify-timeout to wait longer)",
+            found.count, expected.count, options.timeout
+        ));
+    }
+    if options.content && (found.first != expected.first || found.last != expected.last) {
+        return failed(String::from(
+            "the first or last event read back isn't the one that was sent",
+        ));
+    }
+    info!("Read all {} events back from {}", found.count, stream);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockClient;
+    use std::sync::{Arc, Mutex};
+
+    /// The events sent with `messages`, a millisecond apart
+    fn events(messages: &[&str]) -> Vec<InputLogEvent> {
+        messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| crate::events::build_event(i as i64, message.to_string()))
+            .collect()
+    }
+
+    /// Has a stream whose events show up a few more at a time, each time it's read
+    ///
+    /// The first read finds `visible` of them, and each read after `step` more.
+    /// Events come a page of two at a time.
+    fn stream(messages: &[&str], visible: usize, step: usize) -> MockClient {
+        MockClient {
+            page_size: 2,
+            visible: Arc::new(Mutex::new(Some(visible))),
+            step,
+            ..MockClient::holding("group/stream", events(messages))
+        }
+    }
+
+    fn sent(messages: &[&str]) -> Expected {
+        Expected::new(&events(messages))
+    }
+
+    fn options(content: bool) -> VerifyOptions {
+        VerifyOptions {
+            timeout: Duration::from_millis(200),
+            interval: Duration::ZERO,
+            content,
+        }
+    }
+
+    async fn check(client: &MockClient, expected: &Expected, content: bool) -> Result<(), Error> {
+        let retry = RetryPolicy::default();
+        verify(
+            client,
+            "group",
+            "stream",
+            expected,
+            &options(content),
+            &retry,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_verify_match() {
+        let messages = ["a", "b", "c", "d", "e"];
+        let client = stream(&messages, 5, 0);
+        check(&client, &sent(&messages), true).await.unwrap();
+        // Read in pages, until one comes back empty
+        assert_eq!(client.reads.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_verify_until_visible() {
+        // Nothing at first, then a couple more events each time
+        let messages = ["a", "b", "c", "d", "e"];
+        let client = stream(&messages, 0, 2);
+        check(&client, &sent(&messages), true).await.unwrap();
+        let reads = client.reads.lock().unwrap();
+        assert!(reads.iter().filter(|token| token.is_none()).count() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_mismatch() {
+        // Two of the events never show up
+        let client = stream(&["a", "b", "c"], 3, 0);
+        let err = check(&client, &sent(&["a", "b", "c", "d", "e"]), false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("only 3 of the 5"), "{}", err);
+
+        // Or one does, but not as it was sent
+        let client = stream(&["a", "b", "x"], 3, 0);
+        let expected = sent(&["a", "b", "c"]);
+        check(&client, &expected, false).await.unwrap();
+        let err = check(&client, &expected, true).await.unwrap_err();
+        assert!(err.to_string().contains("isn't the one"), "{}", err);
+    }
+
+    #[test]
+    fn test_expected() {
+        let expected = sent(&["a", "b", "c"]);
+        assert_eq!(
+            expected,
+            Expected {
+                count: 3,
+                range: (0, 3),
+                first: Some(String::from("a")),
+                last: Some(String::from("c")),
+            }
+        );
+    }
+}
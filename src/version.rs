@@ -0,0 +1,138 @@
+//! The `version` command, saying exactly which build of rusty-axe this is
+
+use crate::cli::Global;
+use crate::uploader::Output;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde_json::json;
+
+/// Which build this is, as build.rs found it
+struct Build {
+    version: &'static str,
+    commit: &'static str,
+    /// Whether there were changes on top of the commit, if git could say
+    dirty: Option<bool>,
+    /// When it was built, in seconds since the epoch
+    built: i64,
+    target: &'static str,
+    sdk: &'static str,
+}
+
+impl Build {
+    fn this() -> Build {
+        Build {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("RUSTY_AXE_COMMIT"),
+            dirty: env!("RUSTY_AXE_DIRTY").parse().ok(),
+            built: env!("RUSTY_AXE_BUILT").parse().unwrap_or_default(),
+            target: env!("RUSTY_AXE_TARGET"),
+            sdk: aws_sdk_cloudwatchlogs::PKG_VERSION,
+        }
+    }
+
+    fn built(&self) -> String {
+        Utc.timestamp_opt(self.built, 0)
+            .single()
+            .map_or_else(String::new, |time| {
+                time.to_rfc3339_opts(SecondsFormat::Secs, true)
+            })
+    }
+
+    /// The version, then (if `verbose`) the rest, a line each
+    fn text(&self, verbose: bool) -> String {
+        let mut text = format!("rusty-axe {}\n", self.version);
+        if verbose {
+            let dirty = match self.dirty {
+                Some(true) => " (with uncommitted changes)",
+                Some(false) => "",
+                None => " (maybe with uncommitted changes)",
+            };
+            text.push_str(&format!(
+                "commit:                 {}{}\n",
+                self.commit, dirty
+            ));
+            text.push_str(&format!("built:                  {}\n", self.built()));
+            text.push_str(&format!("target:                 {}\n", self.target));
+            text.push_str(&format!("aws-sdk-cloudwatchlogs: {}\n", self.sdk));
+        }
+        text
+    }
+
+    fn json(&self) -> serde_json::Value {
+        json!({
+            "version": self.version,
+            "commit": self.commit,
+            "dirty": self.dirty,
+            "built": self.built(),
+            "target": self.target,
+            "aws_sdk_cloudwatchlogs": self.sdk,
+        })
+    }
+}
+
+/// Run the `version` command
+pub fn print(global: &Global) {
+    let build = Build::this();
+    match global.output {
+        Output::Text => print!("{}", build.text(global.verbosity.verbose > 0)),
+        Output::Json => println!("{}", build.json()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build() -> Build {
+        Build {
+            version: "1.2.3",
+            commit: "0123abcd",
+            dirty: Some(true),
+            built: 1_660_000_000,
+            target: "x86_64-unknown-linux-gnu",
+            sdk: "0.16.0",
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(build().text(false), "rusty-axe 1.2.3\n");
+        let verbose = build().text(true);
+        assert!(
+            verbose.contains("commit:                 0123abcd (with uncommitted changes)\n"),
+            "{}",
+            verbose
+        );
+        assert!(verbose.contains("built:                  2022-08-08T23:06:40Z\n"));
+        assert!(verbose.contains("target:                 x86_64-unknown-linux-gnu\n"));
+        assert!(verbose.ends_with("aws-sdk-cloudwatchlogs: 0.16.0\n"));
+        let clean = Build {
+            dirty: Some(false),
+            ..build()
+        };
+        assert!(clean.text(true).contains("0123abcd\n"));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = build().json();
+        assert_eq!(json["version"], "1.2.3");
+        assert_eq!(json["dirty"], true);
+        assert_eq!(json["built"], "2022-08-08T23:06:40Z");
+        assert_eq!(json["aws_sdk_cloudwatchlogs"], "0.16.0");
+        let unknown = Build {
+            dirty: None,
+            ..build()
+        };
+        assert!(unknown.json()["dirty"].is_null());
+    }
+
+    #[test]
+    fn test_this() {
+        let this = Build::this();
+        assert_eq!(this.version, env!("CARGO_PKG_VERSION"));
+        assert!(this.built > 0);
+        assert!(!this.target.is_empty());
+        assert!(this.sdk.starts_with("0."));
+    }
+}
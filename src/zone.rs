@@ -0,0 +1,521 @@
+//! Time zones from the system's zone database, for timestamps logged without one
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where the zone database lives, unless $TZDIR says otherwise
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// The zone the system is set to, unless $TZ says otherwise
+const LOCALTIME_PATH: &str = "/etc/localtime";
+
+/// A time zone, as described by a TZif file from the zone database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    /// When the offset from UTC changes (in seconds since the epoch), and which of
+    /// `offsets` applies from then on
+    transitions: Vec<(i64, usize)>,
+    /// The offsets from UTC (in seconds, east positive) the zone has used, the first
+    /// of which applies before the first transition
+    offsets: Vec<i32>,
+    /// How the offset changes after the last transition
+    rule: Option<Rule>,
+}
+
+/// Where a time logged without a zone falls, once the zone is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placed {
+    /// Exactly one moment
+    Exact(DateTime<Utc>),
+    /// The clocks went back, so the time happened twice; this is the earlier one
+    Ambiguous(DateTime<Utc>),
+    /// The clocks went forward past the time, so it's moved forward by the gap
+    Skipped(DateTime<Utc>),
+}
+
+impl Zone {
+    /// Parse a --timezone, which is a name from the zone database (like
+    /// `Australia/Sydney`) or `local` for the zone the system is set to
+    pub fn parse(name: &str) -> Result<Zone, String> {
+        let zone = if name == "local" {
+            Zone::local()
+        } else if name.is_empty() || name.starts_with('/') || name.split('/').any(|p| p == "..") {
+            None
+        } else {
+            Zone::load(&zoneinfo_dir().join(name))
+        };
+        zone.ok_or_else(|| {
+            format!(
+                "{:?} isn't a time zone in the zone database (use a name like Australia/Sydney, or local)",
+                name
+            )
+        })
+    }
+
+    /// The zone the system is set to, from $TZ or /etc/localtime
+    fn local() -> Option<Zone> {
+        match env::var("TZ") {
+            Ok(tz) if !tz.is_empty() => {
+                let tz = tz.strip_prefix(':').unwrap_or(&tz);
+                let path = Path::new(tz);
+                let path = if path.is_absolute() {
+                    path.to_path_buf()
+                } else {
+                    zoneinfo_dir().join(path)
+                };
+                Zone::load(&path).or_else(|| {
+                    Some(Zone {
+                        transitions: Vec::new(),
+                        offsets: Vec::new(),
+                        rule: Some(Rule::parse(tz)?),
+                    })
+                })
+            }
+            _ => Zone::load(Path::new(LOCALTIME_PATH)),
+        }
+    }
+
+    /// Read a zone from a TZif file
+    pub fn load(path: &Path) -> Option<Zone> {
+        Zone::from_tzif(&fs::read(path).ok()?)
+    }
+
+    /// Make sense of the contents of a TZif file (as described by RFC 8536)
+    fn from_tzif(data: &[u8]) -> Option<Zone> {
+        let mut data = Cursor(data);
+        let (version, counts) = tzif_header(&mut data)?;
+
+        // Version 1 files only have 32-bit times; later ones repeat everything with
+        // 64-bit times after them, followed by a rule for after the last transition
+        let (time_size, counts) = if version == 0 {
+            (4, counts)
+        } else {
+            data.skip(block_len(&counts, 4))?;
+            (8, tzif_header(&mut data)?.1)
+        };
+        let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = counts;
+
+        let mut times = Vec::with_capacity(timecnt);
+        for _ in 0..timecnt {
+            times.push(match time_size {
+                4 => i32::from_be_bytes(data.take()?) as i64,
+                _ => i64::from_be_bytes(data.take()?),
+            });
+        }
+        let indices = data.bytes(timecnt)?.to_vec();
+        let mut offsets = Vec::with_capacity(typecnt);
+        for _ in 0..typecnt {
+            offsets.push(i32::from_be_bytes(data.take()?));
+            data.skip(2)?;
+        }
+        if offsets.is_empty() || indices.iter().any(|&i| i as usize >= offsets.len()) {
+            return None;
+        }
+        data.skip(charcnt + leapcnt * (time_size + 4) + isstdcnt + isutcnt)?;
+
+        let rule = match version {
+            0 => None,
+            _ => {
+                let footer = std::str::from_utf8(data.0).ok()?;
+                let footer = footer.strip_prefix('\n')?.lines().next()?;
+                match footer {
+                    "" => None,
+                    footer => Some(Rule::parse(footer)?),
+                }
+            }
+        };
+
+        Some(Zone {
+            transitions: times
+                .into_iter()
+                .zip(indices.into_iter().map(usize::from))
+                .collect(),
+            offsets,
+            rule,
+        })
+    }
+
+    /// The offset from UTC (in seconds) at a moment, given in seconds since the epoch
+    fn offset_at(&self, time: i64) -> i32 {
+        let passed = self.transitions.partition_point(|&(at, _)| at <= time);
+        match (passed, &self.rule) {
+            (passed, Some(rule)) if passed == self.transitions.len() => rule.offset_at(time),
+            (0, _) => self.offsets.first().copied().unwrap_or_default(),
+            (passed, _) => self.offsets[self.transitions[passed - 1].1],
+        }
+    }
+
+    /// Work out when a time logged in this zone (without saying so) happened
+    ///
+    /// A time the clocks went back over is taken to be the earlier of the two, and a
+    /// time the clocks went forward over is moved forward by as much as they did.
+    pub fn place(&self, local: &NaiveDateTime) -> Placed {
+        const DAY: i64 = 24 * 60 * 60;
+        let seconds = local.timestamp();
+        let utc = |offset: i32| Utc.from_utc_datetime(&(*local - Duration::seconds(offset.into())));
+
+        // The offsets either side of any change, which are the only ones that can apply
+        let before = self.offset_at(seconds - DAY);
+        let after = self.offset_at(seconds + DAY);
+        let fits = |offset: i32| self.offset_at(seconds - offset as i64) == offset;
+
+        match (fits(before), fits(after)) {
+            _ if before == after => Placed::Exact(utc(before)),
+            (true, true) => Placed::Ambiguous(utc(before.max(after))),
+            (true, false) => Placed::Exact(utc(before)),
+            (false, true) => Placed::Exact(utc(after)),
+            (false, false) => Placed::Skipped(utc(before)),
+        }
+    }
+}
+
+/// Where to find the zone database
+fn zoneinfo_dir() -> PathBuf {
+    env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(ZONEINFO_DIR))
+}
+
+/// Read a TZif header, returning the version (0 for the first) and the counts
+/// describing the data after it
+fn tzif_header(data: &mut Cursor) -> Option<(u8, [usize; 6])> {
+    if data.bytes(4)? != b"TZif" {
+        return None;
+    }
+    let version = match data.bytes(1)?[0] {
+        0 => 0,
+        version => version.checked_sub(b'0')?,
+    };
+    data.skip(15)?;
+    let mut counts = [0; 6];
+    for count in &mut counts {
+        *count = u32::from_be_bytes(data.take()?) as usize;
+    }
+    Some((version, counts))
+}
+
+/// The length of the data block after a TZif header, with times `time_size` bytes long
+fn block_len(counts: &[usize; 6], time_size: usize) -> usize {
+    let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = *counts;
+    timecnt * (time_size + 1)
+        + typecnt * 6
+        + charcnt
+        + leapcnt * (time_size + 4)
+        + isstdcnt
+        + isutcnt
+}
+
+/// Reads its way through a slice of bytes
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+}
+
+/// A POSIX TZ rule, like `AEST-10AEDT,M10.1.0,M4.1.0/3`, for the years after a
+/// zone's last listed transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// The standard offset from UTC, in seconds
+    standard: i32,
+    /// When daylight saving applies, if ever
+    daylight: Option<Daylight>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Daylight {
+    /// The offset from UTC during daylight saving, in seconds
+    offset: i32,
+    /// When daylight saving starts (in standard time)
+    start: (Day, i32),
+    /// When daylight saving ends (in daylight saving time)
+    end: (Day, i32),
+}
+
+/// A day of the year in a POSIX TZ rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Day {
+    /// `Jn`: 1 to 365, never counting 29 February
+    Julian(u32),
+    /// `n`: 0 to 365, counting 29 February
+    Ordinal(u32),
+    /// `Mm.w.d`: day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`
+    Weekday(u32, u32, u32),
+}
+
+impl Rule {
+    fn parse(s: &str) -> Option<Rule> {
+        let mut s = s;
+        zone_abbreviation(&mut s)?;
+        let standard = -zone_offset(&mut s)?;
+        if s.is_empty() {
+            return Some(Rule {
+                standard,
+                daylight: None,
+            });
+        }
+
+        zone_abbreviation(&mut s)?;
+        let offset = if s.starts_with(',') {
+            standard + 3600
+        } else {
+            -zone_offset(&mut s)?
+        };
+        let mut s = s.strip_prefix(',')?;
+        let start = rule_date(&mut s)?;
+        let mut s = s.strip_prefix(',')?;
+        let end = rule_date(&mut s)?;
+        if !s.is_empty() {
+            return None;
+        }
+
+        Some(Rule {
+            standard,
+            daylight: Some(Daylight { offset, start, end }),
+        })
+    }
+
+    fn offset_at(&self, time: i64) -> i32 {
+        let daylight = match &self.daylight {
+            Some(daylight) => daylight,
+            None => return self.standard,
+        };
+        let year = match NaiveDateTime::from_timestamp_opt(time + self.standard as i64, 0) {
+            Some(local) => local.year(),
+            None => return self.standard,
+        };
+
+        let start = transition(year, daylight.start, self.standard);
+        let end = transition(year, daylight.end, daylight.offset);
+        let in_daylight = match (start, end) {
+            (Some(start), Some(end)) if start < end => start <= time && time < end,
+            // Daylight saving runs over the new year, as it does south of the equator
+            (Some(start), Some(end)) => !(end <= time && time < start),
+            _ => false,
+        };
+        if in_daylight {
+            daylight.offset
+        } else {
+            self.standard
+        }
+    }
+}
+
+/// When a rule's transition happens in a year, in seconds since the epoch
+fn transition(year: i32, (day, time): (Day, i32), offset: i32) -> Option<i64> {
+    let date = match day {
+        Day::Julian(n) => {
+            let date = NaiveDate::from_yo_opt(year, n)?;
+            let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+            if leap && n >= 60 {
+                date.succ_opt()?
+            } else {
+                date
+            }
+        }
+        Day::Ordinal(n) => NaiveDate::from_yo_opt(year, n + 1)?,
+        Day::Weekday(month, week, weekday) => {
+            let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+            let first_weekday = first.weekday().num_days_from_sunday();
+            let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+            while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                day -= 7;
+            }
+            NaiveDate::from_ymd_opt(year, month, day)?
+        }
+    };
+    Some(date.and_hms_opt(0, 0, 0)?.timestamp() + time as i64 - offset as i64)
+}
+
+/// Move past a zone abbreviation, like `AEST` or `<+0530>`
+fn zone_abbreviation(s: &mut &str) -> Option<()> {
+    let len = match s.strip_prefix('<') {
+        Some(quoted) => quoted.find('>')? + 2,
+        None => s
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(s.len()),
+    };
+    if len < 3 {
+        return None;
+    }
+    *s = &s[len..];
+    Some(())
+}
+
+/// Read an offset (or time of day) like `-10`, `5:30` or `2:00:00`, in seconds
+///
+/// POSIX offsets are west of UTC, so they're the other way round to everything else.
+fn zone_offset(s: &mut &str) -> Option<i32> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, *s),
+    };
+    let len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(rest.len());
+    let mut seconds = 0;
+    let mut scale = 3600;
+    for part in rest[..len].split(':') {
+        if scale == 0 || part.is_empty() {
+            return None;
+        }
+        seconds += part.parse::<i32>().ok()? * scale;
+        scale /= 60;
+    }
+    *s = &rest[len..];
+    Some(sign * seconds)
+}
+
+/// Read when a rule's transition happens, like `M10.1.0` or `M4.1.0/3`
+fn rule_date(s: &mut &str) -> Option<(Day, i32)> {
+    let len = s.find([',', '/']).unwrap_or(s.len());
+    let (date, rest) = s.split_at(len);
+    let day = if let Some(date) = date.strip_prefix('M') {
+        let mut parts = date.split('.').map(|part| part.parse::<u32>().ok());
+        let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return None;
+        }
+        Day::Weekday(month, week, weekday)
+    } else if let Some(n) = date.strip_prefix('J') {
+        Day::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+    } else {
+        Day::Ordinal(date.parse().ok().filter(|&n| n <= 365)?)
+    };
+
+    *s = rest;
+    let time = match s.strip_prefix('/') {
+        Some(time) => {
+            *s = time;
+            zone_offset(s)?
+        }
+        None => 2 * 3600,
+    };
+    Some((day, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sydney() -> Zone {
+        Zone::load(Path::new("tests/fixtures/zoneinfo/Australia/Sydney")).unwrap()
+    }
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_place() {
+        let zone = sydney();
+        // Standard time (+10) and daylight saving (+11)
+        assert_eq!(
+            zone.place(&local("2024-05-01 14:25:00")),
+            Placed::Exact(utc("2024-05-01T04:25:00Z"))
+        );
+        assert_eq!(
+            zone.place(&local("2024-01-15 09:00:00")),
+            Placed::Exact(utc("2024-01-14T22:00:00Z"))
+        );
+
+        // The clocks went back from 3am to 2am on 7 April 2024
+        assert_eq!(
+            zone.place(&local("2024-04-07 02:30:00")),
+            Placed::Ambiguous(utc("2024-04-06T15:30:00Z"))
+        );
+        // And forward from 2am to 3am on 6 October 2024
+        assert_eq!(
+            zone.place(&local("2024-10-06 02:30:00")),
+            Placed::Skipped(utc("2024-10-05T16:30:00Z"))
+        );
+        assert_eq!(
+            zone.place(&local("2024-10-06 03:00:00")),
+            Placed::Exact(utc("2024-10-05T16:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_rule_after_last_transition() {
+        let zone = sydney();
+        let (last, _) = *zone.transitions.last().unwrap();
+        let year = NaiveDateTime::from_timestamp_opt(last, 0).unwrap().year() + 5;
+
+        // The rule takes over, with the same changes on the first Sundays of April
+        // and October
+        let zone_only_rule = Zone {
+            transitions: Vec::new(),
+            offsets: Vec::new(),
+            rule: zone.rule.clone(),
+        };
+        for zone in [zone, zone_only_rule] {
+            let winter = format!("{}-07-01 12:00:00", year);
+            let summer = format!("{}-12-01 12:00:00", year);
+            let offset = |s: &str| match zone.place(&local(s)) {
+                Placed::Exact(time) => (local(s) - time.naive_utc()).num_hours(),
+                placed => panic!("{:?}", placed),
+            };
+            assert_eq!((offset(&winter), offset(&summer)), (10, 11));
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(rule.standard, 36000);
+        let daylight = rule.daylight.unwrap();
+        assert_eq!(daylight.offset, 39600);
+        assert_eq!(daylight.start, (Day::Weekday(10, 1, 0), 7200));
+        assert_eq!(daylight.end, (Day::Weekday(4, 1, 0), 10800));
+
+        let rule = Rule::parse("<+0530>-5:30").unwrap();
+        assert_eq!((rule.standard, rule.daylight), (19800, None));
+        let rule = Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(rule.standard, -18000);
+        assert_eq!(rule.daylight.unwrap().offset, -14400);
+        let rule = Rule::parse("XXX3YYY,J60/-1,300/26")
+            .unwrap()
+            .daylight
+            .unwrap();
+        assert_eq!(rule.start, (Day::Julian(60), -3600));
+        assert_eq!(rule.end, (Day::Ordinal(300), 26 * 3600));
+
+        assert_eq!(Rule::parse("AB-1"), None);
+        assert_eq!(Rule::parse("AEST"), None);
+        assert_eq!(Rule::parse("AEST-10AEDT,M13.1.0,M4.1.0"), None);
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert!(Zone::parse("../../etc/passwd").is_err());
+        assert!(Zone::parse("/etc/localtime").is_err());
+        assert_eq!(
+            Zone::parse("Nowhere/Special").unwrap_err(),
+            "\"Nowhere/Special\" isn't a time zone in the zone database (use a name like \
+             Australia/Sydney, or local)"
+        );
+        assert!(Zone::from_tzif(b"not a zone file").is_none());
+    }
+}
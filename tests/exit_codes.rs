@@ -0,0 +1,244 @@
+//! Run the program itself, and check the status it exits with says what went wrong,
+//! and what it prints says what happened
+
+#[path = "support/stub.rs"]
+mod stub;
+
+use std::io::Write;
+use std::process::Command;
+use stub::{Response, Stub};
+
+/// The program, with no credentials or region to find but these, and no instance
+/// metadata to ask
+fn rusty_axe(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rusty-axe"));
+    command
+        .args(args)
+        .args(["--no-imds", "--no-progress", "--max-retries", "0"])
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .env("AWS_REGION", "us-east-1")
+        .env("AWS_CONFIG_FILE", "/nonexistent")
+        .env("AWS_SHARED_CREDENTIALS_FILE", "/nonexistent")
+        .env_remove("AWS_PROFILE")
+        .env_remove("RUST_LOG");
+    command
+}
+
+fn status(command: &mut Command) -> i32 {
+    let output = command.output().unwrap();
+    output.status.code().unwrap_or_else(|| {
+        panic!("killed: {}", String::from_utf8_lossy(&output.stderr));
+    })
+}
+
+/// Answer calls to CloudWatch Logs with `answer`, given each operation's name,
+/// returning the endpoint it's at
+fn serve(answer: fn(&str) -> (u16, &'static str)) -> String {
+    let stub = Stub::serve(move |_, request| {
+        let target = request.header("x-amz-target").unwrap_or_default();
+        let (status, body) = answer(target.rsplit('.').next().unwrap_or_default());
+        Response::new(status, body).header("content-type", "application/x-amz-json-1.1")
+    });
+    stub.url().to_string()
+}
+
+fn upload(endpoint: &str) -> Command {
+    let file = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/lorem-ipsum-5.txt"
+    );
+    rusty_axe(&[
+        "push",
+        "-g",
+        "app",
+        "--stream",
+        "run",
+        "-f",
+        file,
+        "--endpoint-url",
+        endpoint,
+    ])
+}
+
+#[test]
+fn test_usage_error() {
+    assert_eq!(status(&mut rusty_axe(&["-g", "app", "--head", "many"])), 2);
+}
+
+#[test]
+fn test_missing_file() {
+    let command = &mut rusty_axe(&["-g", "app", "-f", "does-not-exist.txt", "--dry-run"]);
+    assert_eq!(status(command), 6);
+}
+
+#[test]
+fn test_follow_missing_file() {
+    let output = rusty_axe(&["push", "-g", "app", "-f", "does-not-exist.txt", "--follow"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    // Said on one line, naming the file
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let said: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.contains("ERROR"))
+        .collect();
+    assert_eq!(said.len(), 1, "{}", stderr);
+    assert!(said[0].contains("\"does-not-exist.txt\""), "{}", stderr);
+}
+
+#[test]
+fn test_legacy_invocation() {
+    // The same either way, but for saying the old way is deprecated
+    let legacy = rusty_axe(&["-g", "app", "-f", "does-not-exist.txt", "--dry-run"])
+        .output()
+        .unwrap();
+    let push = rusty_axe(&["push", "-g", "app", "-f", "does-not-exist.txt", "--dry-run"])
+        .output()
+        .unwrap();
+    assert_eq!(legacy.status.code(), Some(6));
+    assert_eq!(push.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&legacy.stderr).contains("deprecated"));
+    assert!(!String::from_utf8_lossy(&push.stderr).contains("deprecated"));
+}
+
+#[test]
+fn test_version() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rusty-axe"))
+        .args(["version", "--output", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let build: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    for field in [
+        "version",
+        "commit",
+        "built",
+        "target",
+        "aws_sdk_cloudwatchlogs",
+    ] {
+        let value = build[field].as_str().unwrap_or_default();
+        assert!(!value.is_empty(), "{} is empty: {}", field, build);
+    }
+    assert!(build.get("dirty").is_some(), "{}", build);
+}
+
+#[test]
+fn test_group_not_found() {
+    let endpoint = serve(|_| {
+        (
+            400,
+            r#"{"__type":"ResourceNotFoundException","message":"The specified log group does not exist."}"#,
+        )
+    });
+    assert_eq!(status(&mut upload(&endpoint)), 4);
+}
+
+#[test]
+fn test_access_denied() {
+    let endpoint = serve(|_| {
+        (
+            400,
+            r#"{"__type":"AccessDeniedException","message":"not authorized to perform: logs:CreateLogStream"}"#,
+        )
+    });
+    assert_eq!(status(&mut upload(&endpoint)), 3);
+}
+
+#[test]
+fn test_events_rejected() {
+    let endpoint = serve(|operation| match operation {
+        "PutLogEvents" => (
+            200,
+            r#"{"nextSequenceToken":"1","rejectedLogEventsInfo":{"tooOldLogEventEndIndex":1}}"#,
+        ),
+        _ => (200, "{}"),
+    });
+    assert_eq!(status(&mut upload(&endpoint)), 5);
+    // Failing the stream for them is still only some of it not sent
+    let fail = ["--on-rejected", "fail"];
+    assert_eq!(status(upload(&endpoint).args(fail)), 5);
+}
+
+#[test]
+fn test_success() {
+    let endpoint = serve(|operation| match operation {
+        "PutLogEvents" => (200, r#"{"nextSequenceToken":"1"}"#),
+        _ => (200, "{}"),
+    });
+    assert_eq!(status(&mut upload(&endpoint)), 0);
+}
+
+#[test]
+fn test_too_large() {
+    let endpoint = serve(|operation| match operation {
+        "PutLogEvents" => (200, r#"{"nextSequenceToken":"1"}"#),
+        _ => (200, "{}"),
+    });
+    // There's no terminal to ask at, so it stops without --force
+    let output = upload(&endpoint)
+        .args(["--max-file-size", "1KB"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(8));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("over --max-file-size (1 KB): about"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--force"), "{}", stderr);
+    let forced = ["--max-file-size", "1KB", "--force"];
+    assert_eq!(status(upload(&endpoint).args(forced)), 0);
+    assert_eq!(
+        status(upload(&endpoint).args(["--max-file-size", "1MB"])),
+        0
+    );
+}
+
+#[test]
+fn test_too_large_tail() {
+    let endpoint = serve(|operation| match operation {
+        "PutLogEvents" => (200, r#"{"nextSequenceToken":"1"}"#),
+        _ => (200, "{}"),
+    });
+    // A file of about 1 MB, only 50 lines of which (about 6 KB) are sent
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    for _ in 0..10_000 {
+        writeln!(file, "{}", "x".repeat(99)).unwrap();
+    }
+    let path = file.path().to_str().unwrap();
+    let push = |args: &[&str]| {
+        let mut command = rusty_axe(&["push", "-g", "app", "--stream", "run", "-f", path]);
+        command.args(["--endpoint-url", &endpoint, "--max-file-size", "100KB"]);
+        command.args(args);
+        command
+    };
+    assert_eq!(status(&mut push(&["--tail", "50"])), 0);
+    assert_eq!(status(&mut push(&["--head", "50"])), 0);
+    // Though not when what's picked out is still too much
+    assert_eq!(status(&mut push(&["--tail", "5000"])), 8);
+    assert_eq!(status(&mut push(&[])), 8);
+}
+
+#[test]
+fn test_json_summary() {
+    let endpoint = serve(|operation| match operation {
+        "PutLogEvents" => (200, r#"{"nextSequenceToken":"1"}"#),
+        _ => (200, "{}"),
+    });
+    let output = upload(&endpoint)
+        .args(["--output", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    // Nothing else is printed
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["events_sent"], 55);
+    assert_eq!(summary["batches_sent"], 1);
+    assert_eq!(summary["log_groups"], serde_json::json!(["app"]));
+    assert_eq!(summary["region"], "us-east-1");
+    assert_eq!(summary["files"][0]["status"], "read");
+    assert_eq!(summary["exit_status"], 0);
+}
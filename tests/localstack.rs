@@ -0,0 +1,181 @@
+//! Push files to LocalStack with the program itself, then read them back with
+//! GetLogEvents, to check the whole way from a file to the events in its log stream
+//!
+//! These need LocalStack running (like `docker run -p 4566:4566 localstack/localstack`)
+//! at AWS_ENDPOINT_URL_LOGS, or else http://localhost:4566, so they only run when
+//! asked for: `cargo test --test localstack -- --ignored`.  Even then, they pass
+//! without checking anything if nothing's listening there.
+
+use aws_sdk_cloudwatchlogs::{Client, Config, Credentials, Endpoint, Region};
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// LocalStack's endpoint, if something's listening there
+fn localstack() -> Option<String> {
+    let url = std::env::var("AWS_ENDPOINT_URL_LOGS")
+        .or_else(|_| std::env::var("AWS_ENDPOINT_URL"))
+        .unwrap_or_else(|_| String::from("http://localhost:4566"));
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", &url));
+    let host = rest.split('/').next().unwrap_or_default();
+    let address = match (host.contains(':'), scheme) {
+        (true, _) => host.to_string(),
+        (false, "https") => format!("{}:443", host),
+        (false, _) => format!("{}:80", host),
+    };
+    let connects = |address| TcpStream::connect_timeout(&address, Duration::from_secs(1)).is_ok();
+    let listening = address
+        .to_socket_addrs()
+        .is_ok_and(|mut addresses| addresses.any(connects));
+    if !listening {
+        eprintln!(
+            "Nothing's listening at {}, so not testing against LocalStack",
+            url
+        );
+        return None;
+    }
+    Some(url)
+}
+
+/// A client for reading back what was sent, with the same made up credentials
+fn client(endpoint: &str) -> Client {
+    let config = Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(endpoint.parse().unwrap()))
+        .build();
+    Client::from_conf(config)
+}
+
+fn now_millis() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_millis() as i64
+}
+
+/// Push `file` to the `push` stream of a new log group, its lines a millisecond apart
+/// from `start`, returning the summary printed
+fn push(endpoint: &str, group: &str, file: &Path, start: i64) -> Value {
+    push_with(endpoint, group, file, start, &[])
+}
+
+/// Push `file` as [`push`] does, with the options `args` too
+fn push_with(endpoint: &str, group: &str, file: &Path, start: i64, args: &[&str]) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_rusty-axe"))
+        .args(["push", "-g", group, "--create-group", "--stream", "push"])
+        .args(args)
+        .args(["--timestamp", &start.to_string(), "--output", "json"])
+        .args(["--endpoint-url", endpoint, "--no-imds", "--no-progress"])
+        .arg("-f")
+        .arg(file)
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .env("AWS_REGION", "us-east-1")
+        .env("AWS_CONFIG_FILE", "/nonexistent")
+        .env("AWS_SHARED_CREDENTIALS_FILE", "/nonexistent")
+        .env_remove("AWS_PROFILE")
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+/// Every message in a log stream, oldest first, however many pages it takes
+async fn read_back(client: &Client, group: &str, stream: &str) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut next_token = None;
+    loop {
+        let resp = client
+            .get_log_events()
+            .log_group_name(group)
+            .log_stream_name(stream)
+            .start_from_head(true)
+            .set_next_token(next_token.clone())
+            .send()
+            .await
+            .unwrap();
+        let events = resp.events.unwrap_or_default();
+        messages.extend(events.into_iter().filter_map(|event| event.message));
+        // The last page is the one whose next token is the one it was asked for with
+        if resp.next_forward_token.is_none() || resp.next_forward_token == next_token {
+            return messages;
+        }
+        next_token = resp.next_forward_token;
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs LocalStack running, like `docker run -p 4566:4566 localstack/localstack`"]
+async fn test_push_fixture() {
+    let Some(endpoint) = localstack() else {
+        return;
+    };
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lorem-ipsum-5.txt");
+    let start = now_millis();
+    let group = format!("rusty-axe-test-{}", start);
+    let summary = push(&endpoint, &group, &file, start);
+
+    // Blank lines are sent as a space, which CloudWatch Logs takes
+    let lines: Vec<_> = fs::read_to_string(&file)
+        .unwrap()
+        .lines()
+        .map(|line| match line {
+            "" => String::from(" "),
+            line => line.to_string(),
+        })
+        .collect();
+    assert_eq!(summary["events_sent"], lines.len());
+    assert_eq!(read_back(&client(&endpoint), &group, "push").await, lines);
+}
+
+#[tokio::test]
+#[ignore = "needs LocalStack running, like `docker run -p 4566:4566 localstack/localstack`"]
+async fn test_push_batches() {
+    let Some(endpoint) = localstack() else {
+        return;
+    };
+    // More lines than one PutLogEvents call takes
+    let lines: Vec<_> = (0..10_005).map(|i| format!("line {}", i)).collect();
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "{}", lines.join("\n")).unwrap();
+    let start = now_millis();
+    let group = format!("rusty-axe-test-{}", start);
+    let summary = push(&endpoint, &group, file.path(), start);
+
+    assert_eq!(summary["events_sent"], lines.len());
+    assert_eq!(summary["batches_sent"], 2);
+    assert_eq!(read_back(&client(&endpoint), &group, "push").await, lines);
+}
+
+#[tokio::test]
+#[ignore = "needs LocalStack running, like `docker run -p 4566:4566 localstack/localstack`"]
+async fn test_push_append() {
+    let Some(endpoint) = localstack() else {
+        return;
+    };
+    // Sent more than one batch, then added to by a second run, which has to pick up
+    // where the first left off
+    let start = now_millis();
+    let group = format!("rusty-axe-test-{}", start);
+    let mut sent = Vec::new();
+    for run in 0..2 {
+        let lines: Vec<_> = (0..10_010)
+            .map(|i| format!("run {} line {}", run, i))
+            .collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "{}", lines.join("\n")).unwrap();
+        let from = start + sent.len() as i64;
+        let summary = push_with(&endpoint, &group, file.path(), from, &["--append"]);
+        assert_eq!(summary["events_sent"], lines.len(), "run {}", run);
+        sent.extend(lines);
+    }
+    assert_eq!(read_back(&client(&endpoint), &group, "push").await, sent);
+}
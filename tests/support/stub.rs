@@ -0,0 +1,158 @@
+//! A stand-in HTTP server for tests, which answers each request it's sent as it's
+//! told to, and keeps them all to be looked at afterwards
+//!
+//! Both the library's own tests and the tests that run the program use it, so it
+//! only needs the standard library, serving every connection from a thread of its
+//! own.
+
+// Each set of tests only uses some of it
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A request the stub was sent
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The request line and the headers, as they were sent
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The method and path, like `POST` and `/v1/logs`
+    pub fn line(&self) -> (&str, &str) {
+        let mut parts = self.head.split(' ');
+        (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        )
+    }
+
+    /// The value of the header `name`, if it was sent
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            header.eq_ignore_ascii_case(name).then_some(value.trim())
+        })
+    }
+
+    /// The body, as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// What the stub answers a request with
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    /// The same response with another header
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {} Stub\r\n", self.status);
+        for (name, value) in &self.headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        response.push_str(&self.body);
+        response.into_bytes()
+    }
+}
+
+/// How the stub answers the request it's been sent so many of before
+type Answer = dyn FnMut(usize, &Request) -> Response + Send;
+
+/// A stub that's being served, for as long as the test runs
+pub struct Stub {
+    url: String,
+    received: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Stub {
+    /// Serve on a port of its own, answering the `n`th request (counting from 0)
+    /// with whatever `answer` gives
+    pub fn serve(answer: impl FnMut(usize, &Request) -> Response + Send + 'static) -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let answer: Arc<Mutex<Box<Answer>>> = Arc::new(Mutex::new(Box::new(answer)));
+        let kept = received.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (kept, answer) = (kept.clone(), answer.clone());
+                std::thread::spawn(move || answer_all(stream, &kept, &answer));
+            }
+        });
+        Stub { url, received }
+    }
+
+    /// Where the stub is, like `http://127.0.0.1:41234`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Every request the stub's been sent so far, oldest first
+    pub fn received(&self) -> MutexGuard<'_, Vec<Request>> {
+        self.received.lock().unwrap()
+    }
+}
+
+/// Answer the requests sent over one connection, which a client may keep open for
+/// more than one
+fn answer_all(stream: TcpStream, kept: &Mutex<Vec<Request>>, answer: &Mutex<Box<Answer>>) {
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader) {
+        let response = {
+            let mut kept = kept.lock().unwrap();
+            kept.push(request.clone());
+            (answer.lock().unwrap())(kept.len() - 1, &request)
+        };
+        if reader.get_mut().write_all(&response.to_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Read the next request, or None once the client's done with the connection
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        if line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let mut request = Request {
+        head,
+        body: Vec::new(),
+    };
+    let length = request
+        .header("content-length")
+        .map_or(0, |length| length.parse().unwrap());
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}